@@ -0,0 +1,77 @@
+// Times the interpreter on a couple of workloads, counting heap allocations along the way.
+// Run with `cargo bench`; times are only comparable on the same machine, but allocation counts are exact.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ir441::ir441::exec::*;
+use ir441::ir441::parsing::*;
+
+struct Counting;
+static ALLOCS: AtomicU64 = AtomicU64::new(0);
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// Counts down from 200000, taking a three-way branch on each trip through the loop: mostly moving between blocks
+const BRANCHY: &str = concat!("data:\n",
+                              "code:\n",
+                              "main:\n",
+                              "  %n0 = 200000\n",
+                              "  jump head\n",
+                              "head:\n",
+                              "  %n = phi(main, %n0, even, %e, odd, %o)\n",
+                              "  if %n then step else done\n",
+                              "step:\n",
+                              "  %bit = %n & 1\n",
+                              "  if %bit then odd else even\n",
+                              "odd:\n",
+                              "  %o = %n - 1\n",
+                              "  jump head\n",
+                              "even:\n",
+                              "  %e = %n - 1\n",
+                              "  jump head\n",
+                              "done:\n",
+                              "  ret 0\n");
+
+// Naive recursive fib(20): mostly calls and returns
+const RECURSIVE: &str = concat!("data:\n",
+                                "code:\n",
+                                "main:\n",
+                                "  %r = call(fib, 0, 20)\n",
+                                "  ret %r\n",
+                                "fib(this, n):\n",
+                                "  %small = %n < 2\n",
+                                "  if %small then base else rec\n",
+                                "base:\n",
+                                "  ret %n\n",
+                                "rec:\n",
+                                "  %n1 = %n - 1\n",
+                                "  %n2 = %n - 2\n",
+                                "  %a = call(fib, 0, %n1)\n",
+                                "  %b = call(fib, 0, %n2)\n",
+                                "  %s = %a + %b\n",
+                                "  ret %s\n");
+
+fn bench(name: &str, src: &str) {
+    let (_,prog) = parse_program(src.as_bytes()).unwrap();
+    let runs = 10;
+    let allocs_before = ALLOCS.load(Ordering::Relaxed);
+    let report = bench_run(&prog, runs);
+    assert!(report.result.is_ok());
+    let allocs = (ALLOCS.load(Ordering::Relaxed) - allocs_before) / runs as u64;
+    println!("{}: {} steps in {:?} per run (min {:?}, max {:?}; {:.1} ns/step), {} heap allocations per run",
+             name, report.steps, report.median, report.min, report.max, report.median.as_nanos() as f64 / report.steps as f64, allocs);
+}
+
+fn main() {
+    bench("branchy loop", BRANCHY);
+    bench("recursive fib", RECURSIVE);
+}
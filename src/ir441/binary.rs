@@ -0,0 +1,564 @@
+// Compact binary encoding of IRProgram, for caching parsed programs.
+//
+// Everything is little-endian. Strings are a u32 byte length followed by UTF-8 bytes, and every list is a u32
+// count followed by its elements. A file is the magic bytes, a version byte, the pragmas, the globals, then the blocks
+// (sorted by name so encoding is deterministic). Each global, expression, statement, and control transfer starts with a
+// one-byte tag picking the variant, followed by its fields in declaration order. Operators are stored as their
+// source symbol. Globals are tagged 0 for an array (its initializers, then an optional declared size) and 1 for a
+// constant.
+//
+// Decoding hands back an OwnedIRProgram, so the decoded program doesn't keep the input buffer alive; call view() on
+// it to run it.
+use std::collections::{HashMap};
+use std::str::{from_utf8};
+
+use crate::ir441::nodes::*;
+use crate::ir441::owned::OwnedIRProgram;
+
+const MAGIC: &[u8] = b"IR441B";
+// 2 added pragmas, 3 declared global sizes, 4 default arguments, 5 pure declarations, 6 block costs, 7 constants
+const VERSION: u8 = 7;
+
+#[derive(Debug,PartialEq)]
+pub enum DecodeError {
+    BadMagic,
+    UnsupportedVersion { version: u8 },
+    UnexpectedEof { pos: usize },
+    BadTag { what: &'static str, tag: u8, pos: usize },
+    BadUtf8 { pos: usize },
+    UnknownOperator { pos: usize },
+    TrailingBytes { pos: usize },
+    // A number too big for the field it decodes into
+    ValueOutOfRange { what: &'static str, pos: usize },
+    // A block with more default arguments than formals to give them to
+    TooManyDefaults { pos: usize },
+}
+
+pub fn to_bytes(prog: &IRProgram) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    put_pragmas(&mut out, &prog.pragmas);
+    put_u32(&mut out, prog.globals.len());
+    for g in prog.globals.iter() {
+        match g {
+            GlobalStatic::Array { name, vals, slots } => {
+                out.push(0);
+                put_str(&mut out, name);
+                put_u32(&mut out, vals.len());
+                for v in vals.iter() {
+                    put_expr(&mut out, v);
+                }
+                match slots {
+                    None => out.push(0),
+                    Some(n) => { out.push(1); put_u64(&mut out, *n); }
+                }
+            },
+            GlobalStatic::Const { name, val } => { out.push(1); put_str(&mut out, name); put_u64(&mut out, *val); },
+        }
+    }
+    let mut names : Vec<&&str> = prog.blocks.keys().collect();
+    names.sort();
+    put_u32(&mut out, names.len());
+    for n in names {
+        let b = &prog.blocks[*n];
+        put_str(&mut out, b.name);
+        put_u32(&mut out, b.formals.len());
+        for f in b.formals.iter() {
+            put_str(&mut out, f);
+        }
+        put_u32(&mut out, b.defaults.len());
+        for d in b.defaults.iter() {
+            put_expr(&mut out, d);
+        }
+        put_u32(&mut out, b.instrs.len());
+        for i in b.instrs.iter() {
+            put_stmt(&mut out, i);
+        }
+        put_control(&mut out, &b.next);
+    }
+    out
+}
+
+pub fn from_bytes(data: &[u8]) -> Result<OwnedIRProgram,DecodeError> {
+    decode(data).map(|prog| prog.to_owned())
+}
+
+// The decoded program borrows its identifiers from data; from_bytes copies them out
+fn decode(data: &[u8]) -> Result<IRProgram<'_>,DecodeError> {
+    if !data.starts_with(MAGIC) {
+        return Err(DecodeError::BadMagic);
+    }
+    let mut d = Decoder { data, pos: MAGIC.len() };
+    let version = d.u8()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion { version });
+    }
+    let pragmas = d.pragmas()?;
+    let mut globals = vec![];
+    for _ in 0..d.u32()? {
+        match d.tag()? {
+            (0,_) => {
+                let name = d.str()?;
+                let mut vals = vec![];
+                for _ in 0..d.u32()? {
+                    vals.push(d.expr()?);
+                }
+                let slots = match d.tag()? {
+                    (0,_) => None,
+                    (1,_) => Some(d.u64()?),
+                    (tag,pos) => return Err(DecodeError::BadTag { what: "global size", tag, pos })
+                };
+                globals.push(GlobalStatic::Array { name, vals, slots });
+            },
+            (1,_) => globals.push(GlobalStatic::Const { name: d.str()?, val: d.u64()? }),
+            (tag,pos) => return Err(DecodeError::BadTag { what: "global", tag, pos })
+        }
+    }
+    let mut blocks = HashMap::new();
+    for _ in 0..d.u32()? {
+        let name = d.str()?;
+        let mut formals = vec![];
+        for _ in 0..d.u32()? {
+            formals.push(d.str()?);
+        }
+        let mut defaults = vec![];
+        let pos = d.pos;
+        let ndefaults = d.u32()?;
+        if ndefaults > formals.len() {
+            return Err(DecodeError::TooManyDefaults { pos });
+        }
+        for _ in 0..ndefaults {
+            defaults.push(d.expr()?);
+        }
+        let mut instrs = vec![];
+        for _ in 0..d.u32()? {
+            instrs.push(d.stmt()?);
+        }
+        let next = d.control()?;
+        blocks.insert(name, BasicBlock { name, formals, defaults, instrs, next });
+    }
+    if d.pos != data.len() {
+        return Err(DecodeError::TrailingBytes { pos: d.pos });
+    }
+    Ok(IRProgram { globals, vars: VarIds::new(&blocks), blocks, pragmas })
+}
+
+fn put_u32(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+fn put_u64(out: &mut Vec<u8>, n: u64) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+// Mode and cap are each a presence byte (0 absent, otherwise the mode tag + 1, or 1 for a cap) and their value
+fn put_pragmas(out: &mut Vec<u8>, p: &Pragmas) {
+    out.push(match p.mode {
+        None => 0,
+        Some(PragmaMode::Unlimited) => 1,
+        Some(PragmaMode::MemCap) => 2,
+        Some(PragmaMode::GC) => 3,
+        Some(PragmaMode::LoggingGC) => 4,
+    });
+    match p.cap {
+        None => out.push(0),
+        Some(n) => { out.push(1); put_u64(out, n); }
+    }
+    put_u32(out, p.unknown.len());
+    for u in p.unknown.iter() {
+        put_str(out, u);
+    }
+    put_u32(out, p.pure.len());
+    for n in p.pure.iter() {
+        put_str(out, n);
+    }
+    put_u32(out, p.costs.len());
+    for (n,c) in p.costs.iter() {
+        put_str(out, n);
+        put_u64(out, *c);
+    }
+}
+fn put_expr(out: &mut Vec<u8>, e: &IRExpr) {
+    match e {
+        IRExpr::IntLit { val } => { out.push(0); put_u64(out, *val); },
+        IRExpr::GlobalRef { name } => { out.push(1); put_str(out, name); },
+        IRExpr::Var { id } => { out.push(2); put_str(out, id); },
+        IRExpr::BlockRef { bname } => { out.push(3); put_str(out, bname); },
+        IRExpr::ConstRef { id } => { out.push(4); put_str(out, id); },
+    }
+}
+fn put_stmt(out: &mut Vec<u8>, i: &IRStatement) {
+    match i {
+        IRStatement::VarAssign { lhs, rhs } => { out.push(0); put_str(out, lhs); put_expr(out, rhs); },
+        IRStatement::Op { lhs, arg1, op, arg2 } => { out.push(1); put_str(out, lhs); put_expr(out, arg1); put_str(out, op.symbol()); put_expr(out, arg2); },
+        IRStatement::Call { lhs, code, receiver, args } => {
+            out.push(2);
+            put_str(out, lhs);
+            put_expr(out, code);
+            put_expr(out, receiver);
+            put_u32(out, args.len());
+            for a in args.iter() {
+                put_expr(out, a);
+            }
+        },
+        IRStatement::Phi { lhs, opts } => {
+            out.push(3);
+            put_str(out, lhs);
+            put_u32(out, opts.len());
+            for (bname,src) in opts.iter() {
+                put_str(out, bname);
+                put_expr(out, src);
+            }
+        },
+        IRStatement::Alloc { lhs, slots } => { out.push(4); put_str(out, lhs); put_u64(out, u64::from(*slots)); },
+        IRStatement::Rand { lhs, bound } => { out.push(5); put_str(out, lhs); put_expr(out, bound); },
+        IRStatement::Print { out: e } => { out.push(6); put_expr(out, e); },
+        IRStatement::GetElt { lhs, base, offset } => { out.push(7); put_str(out, lhs); put_expr(out, base); put_expr(out, offset); },
+        IRStatement::SetElt { base, offset, val } => { out.push(8); put_expr(out, base); put_expr(out, offset); put_expr(out, val); },
+        IRStatement::GetEltNarrow { lhs, bits, base, offset } => { out.push(18); put_str(out, lhs); put_u32(out, *bits as usize); put_expr(out, base); put_expr(out, offset); },
+        IRStatement::SetEltNarrow { bits, base, offset, val } => { out.push(19); put_u32(out, *bits as usize); put_expr(out, base); put_expr(out, offset); put_expr(out, val); },
+        IRStatement::Load { lhs, base } => { out.push(9); put_str(out, lhs); put_expr(out, base); },
+        IRStatement::Store { base, val } => { out.push(10); put_expr(out, base); put_expr(out, val); },
+        IRStatement::MemCopy { dst, src, slots } => { out.push(11); put_expr(out, dst); put_expr(out, src); put_expr(out, slots); },
+        IRStatement::MemFill { dst, val, slots } => { out.push(12); put_expr(out, dst); put_expr(out, val); put_expr(out, slots); },
+        IRStatement::DivMod { quot, rem, arg1, arg2 } => { out.push(13); put_str(out, quot); put_str(out, rem); put_expr(out, arg1); put_expr(out, arg2); },
+        IRStatement::Checkpoint { label } => { out.push(14); put_str(out, label); },
+        IRStatement::PtrToInt { lhs, ptr } => { out.push(15); put_str(out, lhs); put_expr(out, ptr); },
+        IRStatement::IntToPtr { lhs, int } => { out.push(16); put_str(out, lhs); put_expr(out, int); },
+        IRStatement::ReadCycles { lhs } => { out.push(20); put_str(out, lhs); },
+        IRStatement::AssertHeap { msg, block, receiver } => { out.push(21); put_str(out, msg); put_str(out, block); put_expr(out, receiver); },
+        IRStatement::Halt { val } => { out.push(22); put_expr(out, val); },
+        IRStatement::Cas { result, base, expected, new } => { out.push(23); put_str(out, result); put_expr(out, base); put_expr(out, expected); put_expr(out, new); },
+        IRStatement::CallMulti { lhs, code, receiver, args } => {
+            out.push(17);
+            put_u32(out, lhs.len());
+            for l in lhs.iter() {
+                put_str(out, l);
+            }
+            put_expr(out, code);
+            put_expr(out, receiver);
+            put_u32(out, args.len());
+            for a in args.iter() {
+                put_expr(out, a);
+            }
+        },
+    }
+}
+fn put_control(out: &mut Vec<u8>, c: &ControlXfer) {
+    match c {
+        ControlXfer::Jump { block } => { out.push(0); put_str(out, block); },
+        ControlXfer::If { cond, tblock, fblock } => { out.push(1); put_expr(out, cond); put_str(out, tblock); put_str(out, fblock); },
+        ControlXfer::IfSigned { cond, neg_block, zero_block, pos_block } => {
+            out.push(2);
+            put_expr(out, cond);
+            put_str(out, neg_block);
+            put_str(out, zero_block);
+            put_str(out, pos_block);
+        },
+        ControlXfer::Ret { val } => { out.push(3); put_expr(out, val); },
+        ControlXfer::Fail { reason } => {
+            out.push(4);
+            out.push(match reason {
+                Reason::NotAPointer => 0,
+                Reason::NotANumber => 1,
+                Reason::NoSuchField => 2,
+                Reason::NoSuchMethod => 3,
+            });
+        },
+        ControlXfer::RetVoid => out.push(5),
+        ControlXfer::JumpIndirect { target } => { out.push(6); put_expr(out, target); },
+        ControlXfer::RetMulti { vals } => {
+            out.push(7);
+            put_u32(out, vals.len());
+            for v in vals.iter() {
+                put_expr(out, v);
+            }
+        },
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+impl <'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8],DecodeError> {
+        if self.data.len() - self.pos < n {
+            return Err(DecodeError::UnexpectedEof { pos: self.pos });
+        }
+        let bytes = &self.data[self.pos..self.pos+n];
+        self.pos += n;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8,DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<usize,DecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+    fn u64(&mut self) -> Result<u64,DecodeError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+    fn str(&mut self) -> Result<&'a str,DecodeError> {
+        let len = self.u32()?;
+        let start = self.pos;
+        from_utf8(self.take(len)?).map_err(|_| DecodeError::BadUtf8 { pos: start })
+    }
+    // Operators are stored as their source symbol, so the encoding doesn't depend on BinOp's variant order
+    fn op(&mut self) -> Result<BinOp,DecodeError> {
+        let pos = self.pos;
+        BinOp::from_symbol(self.str()?).ok_or(DecodeError::UnknownOperator { pos })
+    }
+    // Narrow accesses only come in the widths the parser accepts
+    fn narrow_bits(&mut self) -> Result<u32,DecodeError> {
+        let pos = self.pos;
+        match self.u32()? {
+            16 => Ok(16),
+            32 => Ok(32),
+            _ => Err(DecodeError::ValueOutOfRange { what: "narrow access width", pos })
+        }
+    }
+    fn tag(&mut self) -> Result<(u8,usize),DecodeError> {
+        let pos = self.pos;
+        Ok((self.u8()?, pos))
+    }
+    fn pragmas(&mut self) -> Result<Pragmas,DecodeError> {
+        let mode = match self.tag()? {
+            (0,_) => None,
+            (1,_) => Some(PragmaMode::Unlimited),
+            (2,_) => Some(PragmaMode::MemCap),
+            (3,_) => Some(PragmaMode::GC),
+            (4,_) => Some(PragmaMode::LoggingGC),
+            (tag,pos) => return Err(DecodeError::BadTag { what: "pragma mode", tag, pos })
+        };
+        let cap = match self.tag()? {
+            (0,_) => None,
+            (1,_) => Some(self.u64()?),
+            (tag,pos) => return Err(DecodeError::BadTag { what: "pragma cap", tag, pos })
+        };
+        let mut unknown = vec![];
+        for _ in 0..self.u32()? {
+            unknown.push(self.str()?.to_string());
+        }
+        let mut pure = vec![];
+        for _ in 0..self.u32()? {
+            pure.push(self.str()?.to_string());
+        }
+        let mut costs = vec![];
+        for _ in 0..self.u32()? {
+            costs.push((self.str()?.to_string(), self.u64()?));
+        }
+        Ok(Pragmas { mode, cap, unknown, pure, costs })
+    }
+    fn expr(&mut self) -> Result<IRExpr<'a>,DecodeError> {
+        match self.tag()? {
+            (0,_) => Ok(IRExpr::IntLit { val: self.u64()? }),
+            (1,_) => Ok(IRExpr::GlobalRef { name: self.str()? }),
+            (2,_) => Ok(IRExpr::Var { id: self.str()? }),
+            (3,_) => Ok(IRExpr::BlockRef { bname: self.str()? }),
+            (4,_) => Ok(IRExpr::ConstRef { id: self.str()? }),
+            (tag,pos) => Err(DecodeError::BadTag { what: "expression", tag, pos })
+        }
+    }
+    fn stmt(&mut self) -> Result<IRStatement<'a>,DecodeError> {
+        match self.tag()? {
+            (0,_) => Ok(IRStatement::VarAssign { lhs: self.str()?, rhs: self.expr()? }),
+            (1,_) => Ok(IRStatement::Op { lhs: self.str()?, arg1: self.expr()?, op: self.op()?, arg2: self.expr()? }),
+            (2,_) => {
+                let lhs = self.str()?;
+                let code = self.expr()?;
+                let receiver = self.expr()?;
+                let mut args = vec![];
+                for _ in 0..self.u32()? {
+                    args.push(self.expr()?);
+                }
+                Ok(IRStatement::Call { lhs, code, receiver, args })
+            },
+            (3,_) => {
+                let lhs = self.str()?;
+                let mut opts = vec![];
+                for _ in 0..self.u32()? {
+                    opts.push((self.str()?, self.expr()?));
+                }
+                Ok(IRStatement::Phi { lhs, opts })
+            },
+            (4,_) => {
+                let lhs = self.str()?;
+                let pos = self.pos;
+                let slots = u32::try_from(self.u64()?).map_err(|_| DecodeError::ValueOutOfRange { what: "alloc size", pos })?;
+                Ok(IRStatement::Alloc { lhs, slots })
+            },
+            (5,_) => Ok(IRStatement::Rand { lhs: self.str()?, bound: self.expr()? }),
+            (6,_) => Ok(IRStatement::Print { out: self.expr()? }),
+            (7,_) => Ok(IRStatement::GetElt { lhs: self.str()?, base: self.expr()?, offset: self.expr()? }),
+            (8,_) => Ok(IRStatement::SetElt { base: self.expr()?, offset: self.expr()?, val: self.expr()? }),
+            (9,_) => Ok(IRStatement::Load { lhs: self.str()?, base: self.expr()? }),
+            (10,_) => Ok(IRStatement::Store { base: self.expr()?, val: self.expr()? }),
+            (11,_) => Ok(IRStatement::MemCopy { dst: self.expr()?, src: self.expr()?, slots: self.expr()? }),
+            (12,_) => Ok(IRStatement::MemFill { dst: self.expr()?, val: self.expr()?, slots: self.expr()? }),
+            (13,_) => Ok(IRStatement::DivMod { quot: self.str()?, rem: self.str()?, arg1: self.expr()?, arg2: self.expr()? }),
+            (14,_) => Ok(IRStatement::Checkpoint { label: self.str()? }),
+            (15,_) => Ok(IRStatement::PtrToInt { lhs: self.str()?, ptr: self.expr()? }),
+            (16,_) => Ok(IRStatement::IntToPtr { lhs: self.str()?, int: self.expr()? }),
+            (20,_) => Ok(IRStatement::ReadCycles { lhs: self.str()? }),
+            (21,_) => Ok(IRStatement::AssertHeap { msg: self.str()?, block: self.str()?, receiver: self.expr()? }),
+            (22,_) => Ok(IRStatement::Halt { val: self.expr()? }),
+            (23,_) => Ok(IRStatement::Cas { result: self.str()?, base: self.expr()?, expected: self.expr()?, new: self.expr()? }),
+            (17,_) => {
+                let mut lhs = vec![];
+                for _ in 0..self.u32()? {
+                    lhs.push(self.str()?);
+                }
+                let code = self.expr()?;
+                let receiver = self.expr()?;
+                let mut args = vec![];
+                for _ in 0..self.u32()? {
+                    args.push(self.expr()?);
+                }
+                Ok(IRStatement::CallMulti { lhs, code, receiver, args })
+            },
+            (18,_) => Ok(IRStatement::GetEltNarrow { lhs: self.str()?, bits: self.narrow_bits()?, base: self.expr()?, offset: self.expr()? }),
+            (19,_) => Ok(IRStatement::SetEltNarrow { bits: self.narrow_bits()?, base: self.expr()?, offset: self.expr()?, val: self.expr()? }),
+            (tag,pos) => Err(DecodeError::BadTag { what: "statement", tag, pos })
+        }
+    }
+    fn control(&mut self) -> Result<ControlXfer<'a>,DecodeError> {
+        match self.tag()? {
+            (0,_) => Ok(ControlXfer::Jump { block: self.str()? }),
+            (1,_) => Ok(ControlXfer::If { cond: self.expr()?, tblock: self.str()?, fblock: self.str()? }),
+            (2,_) => Ok(ControlXfer::IfSigned { cond: self.expr()?, neg_block: self.str()?, zero_block: self.str()?, pos_block: self.str()? }),
+            (3,_) => Ok(ControlXfer::Ret { val: self.expr()? }),
+            (4,_) => {
+                let reason = match self.tag()? {
+                    (0,_) => Reason::NotAPointer,
+                    (1,_) => Reason::NotANumber,
+                    (2,_) => Reason::NoSuchField,
+                    (3,_) => Reason::NoSuchMethod,
+                    (tag,pos) => return Err(DecodeError::BadTag { what: "failure reason", tag, pos })
+                };
+                Ok(ControlXfer::Fail { reason })
+            },
+            (5,_) => Ok(ControlXfer::RetVoid),
+            (6,_) => Ok(ControlXfer::JumpIndirect { target: self.expr()? }),
+            (7,_) => {
+                let mut vals = vec![];
+                for _ in 0..self.u32()? {
+                    vals.push(self.expr()?);
+                }
+                Ok(ControlXfer::RetMulti { vals })
+            },
+            (tag,pos) => Err(DecodeError::BadTag { what: "control transfer", tag, pos })
+        }
+    }
+}
+
+#[cfg(test)]
+mod binary_tests {
+    use crate::ir441::binary::*;
+    use crate::ir441::parsing::*;
+
+    #[test]
+    fn check_round_trip() {
+        let src = std::fs::read("examples/basicoo.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        let bytes = to_bytes(&prog);
+        assert_eq!(from_bytes(&bytes), Ok(prog.to_owned()));
+        // Encoding is deterministic despite blocks living in a HashMap
+        let (_,again) = parse_program(&src[..]).unwrap();
+        assert_eq!(to_bytes(&again), bytes);
+        // The decoded program outlives the buffer it came from
+        let decoded = { let copy = bytes.clone(); from_bytes(&copy).unwrap() };
+        assert_eq!(decoded.view(), prog);
+
+        let src = std::fs::read("examples/globalrefs.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/shortglobal.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/jumptable.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/divmod.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/defaults.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/multiret.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/narrow.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/voidmain.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = std::fs::read("examples/consts.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+
+        let src = b";@ mode gc\n;@ cap 64\n;@ something else\n;@ pure main\n;@ cost main 20\ndata:\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&prog)), Ok(prog.to_owned()));
+    }
+
+    #[test]
+    fn check_bad_input() {
+        let src = std::fs::read("examples/countdown.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        let bytes = to_bytes(&prog);
+        assert_eq!(from_bytes(b"IR441"), Err(DecodeError::BadMagic));
+        assert!(matches!(from_bytes(&bytes[..bytes.len()-1]), Err(DecodeError::UnexpectedEof { .. })));
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(from_bytes(&extra), Err(DecodeError::TrailingBytes { pos: bytes.len() }));
+        let mut wrong_version = bytes.clone();
+        wrong_version[MAGIC.len()] = 99;
+        assert_eq!(from_bytes(&wrong_version), Err(DecodeError::UnsupportedVersion { version: 99 }));
+
+        let (_,prog) = parse_program(b"data:\ncode:\nmain:\n  %v = 3 + 4\n  ret %v\n").unwrap();
+        let bytes = to_bytes(&prog);
+        let at = bytes.windows(5).position(|w| w == [1,0,0,0,b'+']).unwrap();
+        let mut bad_op = bytes.clone();
+        bad_op[at+4] = b'%';
+        assert_eq!(from_bytes(&bad_op), Err(DecodeError::UnknownOperator { pos: at }));
+
+        // Alloc sizes are stored in 64 bits but only 32 fit
+        let (_,prog) = parse_program(b"data:\ncode:\nmain:\n  %a = alloc(7)\n  ret 0\n").unwrap();
+        let bytes = to_bytes(&prog);
+        let at = bytes.windows(6).position(|w| w == [4,1,0,0,0,b'a']).unwrap() + 6;
+        let mut huge = bytes.clone();
+        huge[at+4] = 1;
+        assert_eq!(from_bytes(&huge), Err(DecodeError::ValueOutOfRange { what: "alloc size", pos: at }));
+
+        // Defaults belong to trailing formals, so there can't be more of them than formals
+        let (_,prog) = parse_program(b"data:\ncode:\nmain:\n  ret 0\nf(this, n = 5):\n  ret %n\n").unwrap();
+        let bytes = to_bytes(&prog);
+        let at = bytes.windows(5).position(|w| w == [1,0,0,0,b'n']).unwrap() + 5;
+        let mut extra_defaults = bytes.clone();
+        extra_defaults[at] = 3;
+        assert_eq!(from_bytes(&extra_defaults), Err(DecodeError::TooManyDefaults { pos: at }));
+
+        // Narrow accesses are 16 or 32 bits wide, nothing else
+        let (_,prog) = parse_program(b"data:\ncode:\nmain:\n  %a = alloc(1)\n  setelt16(%a, 0, 7)\n  ret 0\n").unwrap();
+        let bytes = to_bytes(&prog);
+        let at = bytes.windows(5).position(|w| w == [19,16,0,0,0]).unwrap() + 1;
+        let mut odd_width = bytes.clone();
+        odd_width[at] = 8;
+        assert_eq!(from_bytes(&odd_width), Err(DecodeError::ValueOutOfRange { what: "narrow access width", pos: at }));
+    }
+}
@@ -0,0 +1,172 @@
+// Build an IRProgram directly, for tools that generate IR and would rather not print text just to parse it again.
+//
+//     let mut b = ProgramBuilder::new();
+//     b.block("main", vec![]);
+//     b.instr(IRStatement::VarAssign { lhs: "x", rhs: IRExpr::IntLit { val: 3 } });
+//     b.set_control(ControlXfer::Ret { val: IRExpr::Var { id: "x" } });
+//     let prog = b.finish()?;
+//
+// Instructions and control transfers go to the most recently started block. Mistakes in the calls themselves are
+// remembered and reported by finish(), which also runs the static validator, so callers only check once.
+use std::collections::{HashMap};
+
+use crate::ir441::nodes::*;
+use crate::ir441::validate::*;
+
+#[derive(Debug,PartialEq)]
+pub enum BuildError<'a> {
+    DuplicateBlock { name: &'a str },
+    DuplicateGlobal { name: &'a str },
+    NoCurrentBlock,
+    MissingControl { block: &'a str },
+    TooManyDefaults { block: &'a str },
+    // Only the validator's errors; its warnings don't stop a build
+    Invalid { issues: Vec<ValidationIssue<'a>> },
+}
+
+// A block whose control transfer may not have been set yet
+struct PartialBlock<'a> {
+    name: &'a str,
+    formals: Vec<&'a str>,
+    defaults: Vec<IRExpr<'a>>,
+    instrs: Vec<IRStatement<'a>>,
+    next: Option<ControlXfer<'a>>,
+}
+
+pub struct ProgramBuilder<'a> {
+    globals: Vec<GlobalStatic<'a>>,
+    // Blocks in the order they were started
+    blocks: Vec<PartialBlock<'a>>,
+    pragmas: Pragmas,
+    // First misuse, reported by finish()
+    error: Option<BuildError<'a>>,
+}
+
+impl <'a> ProgramBuilder<'a> {
+    pub fn new() -> ProgramBuilder<'a> {
+        ProgramBuilder { globals: vec![], blocks: vec![], pragmas: Pragmas::default(), error: None }
+    }
+
+    fn fail(&mut self, e: BuildError<'a>) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+    }
+
+    pub fn global(&mut self, name: &'a str, vals: Vec<IRExpr<'a>>) -> &mut Self {
+        if self.globals.iter().any(|g| g.name() == name) {
+            self.fail(BuildError::DuplicateGlobal { name });
+        }
+        self.globals.push(GlobalStatic::Array { name, vals, slots: None });
+        self
+    }
+
+    pub fn pragmas(&mut self, pragmas: Pragmas) -> &mut Self {
+        self.pragmas = pragmas;
+        self
+    }
+
+    // Start a new block; following instr() and set_control() calls apply to it
+    pub fn block(&mut self, name: &'a str, formals: Vec<&'a str>) -> &mut Self {
+        if self.blocks.iter().any(|b| b.name == name) {
+            self.fail(BuildError::DuplicateBlock { name });
+        }
+        self.blocks.push(PartialBlock { name, formals, defaults: vec![], instrs: vec![], next: None });
+        self
+    }
+
+    // Defaults for the current block's trailing formals, one per formal from the end
+    pub fn defaults(&mut self, defaults: Vec<IRExpr<'a>>) -> &mut Self {
+        match self.blocks.last_mut() {
+            Some(b) if defaults.len() <= b.formals.len() => b.defaults = defaults,
+            Some(b) => { let block = b.name; self.fail(BuildError::TooManyDefaults { block }) },
+            None => self.fail(BuildError::NoCurrentBlock)
+        }
+        self
+    }
+
+    pub fn instr(&mut self, i: IRStatement<'a>) -> &mut Self {
+        match self.blocks.last_mut() {
+            Some(b) => b.instrs.push(i),
+            None => self.fail(BuildError::NoCurrentBlock)
+        }
+        self
+    }
+
+    // Set (or replace) how the current block ends
+    pub fn set_control(&mut self, c: ControlXfer<'a>) -> &mut Self {
+        match self.blocks.last_mut() {
+            Some(b) => b.next = Some(c),
+            None => self.fail(BuildError::NoCurrentBlock)
+        }
+        self
+    }
+
+    pub fn finish(self) -> Result<IRProgram<'a>,BuildError<'a>> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut blocks = HashMap::new();
+        for PartialBlock { name, formals, defaults, instrs, next } in self.blocks {
+            match next {
+                None => return Err(BuildError::MissingControl { block: name }),
+                Some(next) => { blocks.insert(name, BasicBlock { name, formals, defaults, instrs, next }); }
+            }
+        }
+        let prog = IRProgram { globals: self.globals, vars: VarIds::new(&blocks), blocks, pragmas: self.pragmas };
+        let errors : Vec<ValidationIssue<'a>> = validate(&prog).into_iter().filter(|i| i.is_error()).collect();
+        if !errors.is_empty() {
+            return Err(BuildError::Invalid { issues: errors });
+        }
+        Ok(prog)
+    }
+}
+impl <'a> Default for ProgramBuilder<'a> {
+    fn default() -> ProgramBuilder<'a> {
+        ProgramBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod builder_tests {
+    use crate::ir441::builder::*;
+    use crate::ir441::exec::*;
+
+    #[test]
+    fn check_build_and_run() {
+        // Same as: data: global array k: { 40 } / code: main: %k = load(@k) / %r = call(add2, 0, %k) / ret %r
+        //          add2(this, n): %r = %n + 2 / ret %r
+        let mut b = ProgramBuilder::new();
+        b.global("k", vec![IRExpr::IntLit { val: 40 }]);
+        b.block("main", vec![])
+            .instr(IRStatement::Load { lhs: "k", base: IRExpr::GlobalRef { name: "k" } })
+            .instr(IRStatement::Call { lhs: "r", code: IRExpr::BlockRef { bname: "add2" }, receiver: IRExpr::IntLit { val: 0 }, args: vec![IRExpr::Var { id: "k" }] })
+            .set_control(ControlXfer::Ret { val: IRExpr::Var { id: "r" } });
+        b.block("add2", vec!["this", "n"])
+            .instr(IRStatement::Op { lhs: "r", arg1: IRExpr::Var { id: "n" }, op: BinOp::Add, arg2: IRExpr::IntLit { val: 2 } })
+            .set_control(ControlXfer::Ret { val: IRExpr::Var { id: "r" } });
+        let prog = b.finish().unwrap();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 42 })));
+    }
+
+    #[test]
+    fn check_build_errors() {
+        let mut b = ProgramBuilder::new();
+        b.instr(IRStatement::Print { out: IRExpr::IntLit { val: 1 } });
+        assert_eq!(b.finish().err(), Some(BuildError::NoCurrentBlock));
+
+        let mut b = ProgramBuilder::new();
+        b.block("main", vec![]);
+        assert_eq!(b.finish().err(), Some(BuildError::MissingControl { block: "main" }));
+
+        let mut b = ProgramBuilder::new();
+        b.block("main", vec![]).set_control(ControlXfer::RetVoid);
+        b.block("main", vec![]).set_control(ControlXfer::RetVoid);
+        assert_eq!(b.finish().err(), Some(BuildError::DuplicateBlock { name: "main" }));
+
+        let mut b = ProgramBuilder::new();
+        b.block("main", vec![]).set_control(ControlXfer::Jump { block: "nowhere" });
+        assert_eq!(b.finish().err(), Some(BuildError::Invalid { issues: vec![ValidationIssue::MissingSuccessor { block: "main", target: "nowhere" }] }));
+    }
+}
@@ -1,8 +1,13 @@
-use std::collections::{HashMap,BTreeMap,HashSet};
+use std::collections::{HashMap,BTreeMap,BTreeSet,HashSet};
+use std::fmt;
+use std::fmt::Write;
+use std::time::{Duration,Instant};
 
 use crate::ir441::nodes::*;
+use crate::ir441::validate::purity_violation;
+use crate::ir441::json::{to_json_trace,from_json_trace,JsonError,TraceLine};
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
 pub enum ExecMode {
     Unlimited,
     MemCap { limit: u64 },
@@ -31,39 +36,100 @@ impl ExecMode {
             _ => false
         }
     }
+    /// The mode a program actually runs in once its own pragmas are taken into account. A pragma overrides
+    /// only what it mentions: `cap` keeps the requested kind of mode (turning Unlimited into MemCap, since a
+    /// cap means nothing otherwise), and `mode` keeps the requested limit (100 if there wasn't one).
+    pub fn with_pragmas(self, p: &Pragmas) -> ExecMode {
+        let limit = match (p.cap, &self) {
+            (Some(n), _) => n,
+            (None, ExecMode::Unlimited) => 100,
+            (None, _) => self.effective_cap()
+        };
+        match (p.mode, self) {
+            (Some(PragmaMode::Unlimited), _) => ExecMode::Unlimited,
+            (Some(PragmaMode::MemCap), _) => ExecMode::MemCap { limit },
+            (Some(PragmaMode::GC), _) => ExecMode::GC { limit },
+            (Some(PragmaMode::LoggingGC), _) => ExecMode::LoggingGC { limit },
+            (None, ExecMode::Unlimited) if p.cap.is_none() => ExecMode::Unlimited,
+            (None, ExecMode::Unlimited) | (None, ExecMode::MemCap{..}) => ExecMode::MemCap { limit },
+            (None, ExecMode::GC{..}) => ExecMode::GC { limit },
+            (None, ExecMode::LoggingGC{..}) => ExecMode::LoggingGC { limit },
+        }
+    }
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone)]
 pub enum RuntimeError<'a> {
     AccessingCodeInMemory { bname: &'a str },
     AccessingDeallocatedAddress { addr: u64 },
+    // An object of this many slots would run past the end of the address space (or RunOptions::max_address)
+    AllocationTooLarge { slots: u64 },
     BadCallArity { instr: &'a IRStatement<'a> },
+    BadEntryArity { bname: &'a str, got: usize },
     BadGCField,
     BadPhiPredecessor { instr: &'a IRStatement<'a>, actual_predecessor: &'a str },
+    // RunOptions::block_fuel: block was about to run for the (limit+1)th time
+    BlockFuelExceeded { block: &'a str, limit: u64 },
+    CallToNonEntry { bname: &'a str },
     CallingNonCode,
     CodeAddressArithmetic { bname: &'a str },
     CorruptGCMetadata { val: VirtualVal<'a> },
+    DivisionByZero { instr: &'a IRStatement<'a> },
+    EmptyRandomRange,
+    Failed { reason: Reason },
     GCRequired,
+    GuardZoneAccess { addr: u64 },
+    // An assertheap predicate returned 0
+    HeapAssertionFailed { msg: &'a str, bname: &'a str },
     InvalidBlock { bname: &'a str },
+    IndirectJumpDisabled,
     InvalidBlockInControl { instr: &'a ControlXfer<'a>, bname: &'a str },
+    JumpToNonCode,
     MissingMain,
+    // RunOptions::memoize_pure found a `;@ pure` block with an effect (see validate::purity_violation)
+    NotPure { bname: &'a str, at: &'a str, index: usize },
     NullPointer,
+    // Scaling offset and adding it to base overflows a 64-bit address
+    OffsetOverflow { base: u64, offset: u64 },
     OutOfMemory,
+    LiveObjectLimitExceeded { limit: u64 },
+    OverwroteGCHeader { addr: u64 },
+    // An Allocator placed an object over addr, a word it hadn't reserved or released (see Memory::place_object)
+    PlacementNotFree { addr: u64 },
+    // An InitialHeap value referring to object index, when the heap has only `objects` objects
+    UnknownHeapObject { index: usize, objects: usize },
+    // An InitialHeap object with a reference in field, past the 64 fields a slotmap can mark
+    UntraceableHeapField { object: usize, field: usize },
     PhiInFirstBlock { instr: &'a IRStatement<'a> },
+    PhiNotAtBlockStart { instr: &'a IRStatement<'a> },
     UnalignedAccess { addr: u64 },
     UnallocatedAddressRead { addr: u64 },
     UnallocatedAddressWrite { addr: u64 },
     UninitializedVariable { name: &'a str },
+    // An UninitializedVariable where the calling frame does have a variable of that name
+    VariableFromCaller { name: &'a str },
+    // A global declaring more slots than fit under the cap or RunOptions::max_address, or than any object could have
+    GlobalTooLarge { name: &'a str, slots: u64 },
+    // A getelt from a global at index, past the slots the global declares
+    GlobalOutOfBounds { name: &'a str, index: u64, slots: u64 },
     UndefinedGlobal { name: &'a str },
+    UndefinedConst { name: &'a str },
     ReadFromGCedData,
+    ReturnedDanglingReference { addr: u64 },
+    SlotmapSizeMismatch { addr: u64, slots: u64, slotmap: u64 },
     WriteToGCedData { addr: u64, val: VirtualVal<'a> },
     WriteToImmutableData,
+    WrongReturnCount { expected: usize, got: usize },
+    UseBeforeHeaderInit { addr: u64 },
+    // RunOptions::check_code_stores: code pointer bname stored (or already sitting) at addr, a field the slotmap traces
+    StoringCodePtrInTracedField { addr: u64, bname: &'a str },
     NYI,
 }
 
 
 // Memory is a map from u64 to u64. Lookup will fail for unaligned accesses for now
-struct Memory<'a> {
+#[derive(Debug,Clone)]
+pub struct Memory<'a> {
     /// Underlying storage for memory
     map: BTreeMap<u64,VirtualVal<'a>>,
     /// First address in the current allocation space
@@ -76,29 +142,361 @@ struct Memory<'a> {
     slot_cap: ExecMode,
     /// How many slots *are* allocated in the current allocation space
     slots_alloced: u64,
+    /// Slots of globals charged against slot_cap, if the cap covers globals too (RunOptions::cap_includes_globals)
+    global_slots: u64,
     /// Allocated object addresses, used to filter GC roots without a stack map.
     /// This does result in semi-conservative GC since we can occasionally mistake an int for a valid pointer, but it's unlikely to persist beyond a single GC cycle.
     allocations: HashSet<u64>,
+    /// Every object in the current allocation space (address of first field -> number of fields), whether or not it is still reachable
+    objects: BTreeMap<u64,u64>,
+    /// Objects relocated by the collection in progress, as (old address, allocation size including metadata)
+    relocated: Vec<(u64,u64)>,
+    /// Fragmentation the most recent collection would have left if it had not moved anything
+    last_fragmentation: Option<FragmentationReport>,
+    /// Whether to reject slotmaps that don't fit their object (RunOptions::check_slotmaps)
+    check_slotmaps: bool,
+    /// Unused words left in front of each allocation, to catch some memory errors (RunOptions::guard_words)
+    guard_words: u64,
+    /// Guard zones in the current allocation space (first address -> length in words)
+    guards: BTreeMap<u64,u64>,
+    /// Where each global starts, to keep indexing from one global from running on into the next
+    global_starts: BTreeMap<u64,&'a str>,
+    /// Collections run so far
+    collections: u64,
+    /// Whether to visit GC roots in name order rather than hash order (RunOptions::deterministic)
+    deterministic: bool,
+    /// Whether to record births (RunOptions::track_ages)
+    track_ages: bool,
+    /// For each object in the current allocation space, how many collections had run when it was allocated
+    births: BTreeMap<u64,u64>,
+    /// Whether to reject touching an object's fields before its slotmap is written (RunOptions::check_header_init)
+    check_header_init: bool,
+    /// Objects in the current allocation space whose slotmap the program has written
+    header_inited: BTreeSet<u64>,
+    /// Whether slotmaps have a weak half (RunOptions::weak_refs)
+    weak_refs: bool,
+    /// Weak fields copied by the collection in progress, as (new field address, old target address)
+    weak_fields: Vec<(u64,u64)>,
+    /// Whether to watch for stores that are never read (RunOptions::track_dead_stores)
+    track_dead_stores: bool,
+    /// Object fields whose stored value hasn't been read yet, with the block and instruction that stored it
+    unread_stores: BTreeMap<u64,(&'a str,&'a IRStatement<'a>)>,
+    /// Stores found dead so far, in the order they were found
+    dead_stores: Vec<DeadStore<'a>>,
+    /// Field counts of objects the collector has moved, not yet added to the run's ExecStats
+    copied_sizes: Vec<u64>,
+    /// Most objects that may be live at once (RunOptions::max_live_objects)
+    max_live_objects: Option<u64>,
+    /// What each field of a newly allocated object starts out holding (RunOptions::fill_word)
+    fill_word: u64,
+    /// In capped modes with a nonzero fill word, the object fields still holding it, which the collector treats as null
+    filled: BTreeSet<u64>,
+    /// Whether to reject code pointers in fields the slotmap marks for tracing (RunOptions::check_code_stores)
+    check_code_stores: bool,
+    /// Highest address an allocation may reach (RunOptions::max_address)
+    max_address: Option<u64>,
+    /// Whether to watch for fields that are never written (RunOptions::track_uninit_fields)
+    track_uninit_fields: bool,
+    /// Object fields not yet written since their object was allocated, each with its index in the object
+    unwritten: BTreeMap<u64,u64>,
+    /// Fields found never written so far, in the order they were found
+    uninit_fields: Vec<UninitField>,
+    /// Words of the current allocation space an Allocator has reserved or released but not yet placed an object in
+    free_words: BTreeSet<u64>,
+}
+
+/// A store whose value was never read: overwritten by another store, or still unread when its object was
+/// collected or the run ended
+#[derive(Debug,PartialEq,Clone)]
+pub struct DeadStore<'a> {
+    /// Where the value was when it was found dead (objects move when collected)
+    pub addr: u64,
+    pub block: &'a str,
+    pub instr: &'a IRStatement<'a>,
+    /// Whether a later store replaced it, rather than it going unread until its object died or the run ended
+    pub overwritten: bool,
+}
+/// An object field the program never wrote, from the object's allocation until the object was collected or the run
+/// ended
+#[derive(Debug,PartialEq,Eq,Clone)]
+pub struct UninitField {
+    /// The object's address when the field was found unwritten (objects move when collected)
+    pub object: u64,
+    pub field: u64,
+}
+/// Something suspicious a run noticed but carried on past, as opposed to a RuntimeError, which ends the run
+#[derive(Debug,PartialEq,Clone)]
+pub enum Warning<'a> {
+    /// RunOptions::warn_pointer_confusion: pointer ptr to an object used in arithmetic that isn't plausibly address math
+    PointerArithmetic { ptr: u64, block: &'a str, instr: &'a IRStatement<'a> },
+    /// RunOptions::warn_pointer_confusion: addr, which points into no global or object, dereferenced
+    IntegerAsPointer { addr: u64, block: &'a str, instr: &'a IRStatement<'a> },
+    /// RunOptions::warn_pointer_ordering: objects at left and right compared with < or >
+    PointerOrdering { left: u64, right: u64, block: &'a str, instr: &'a IRStatement<'a> },
+    /// RunOptions::track_dead_stores
+    DeadStore(DeadStore<'a>),
+    /// RunOptions::track_uninit_fields
+    UninitField(UninitField),
+    /// RunOptions::best_effort: error, raised in block, was logged in ExecResult::recovered and read as 0
+    Recovered { error: RuntimeError<'a>, block: &'a str },
+}
+impl <'a> fmt::Display for Warning<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::PointerArithmetic { ptr, instr, .. } => write!(f, "pointer {} used in arithmetic: {}", ptr, instr),
+            Warning::IntegerAsPointer { addr, instr, .. } => write!(f, "integer {} used as a pointer: {}", addr, instr),
+            Warning::PointerOrdering { left, right, instr, .. } => write!(f, "pointers {} and {} compared by address order: {}", left, right, instr),
+            Warning::DeadStore(d) if d.overwritten => write!(f, "value stored at {} was overwritten unread: {}", d.addr, d.instr),
+            Warning::DeadStore(d) => write!(f, "value stored at {} was never read: {}", d.addr, d.instr),
+            Warning::UninitField(u) => write!(f, "field {} of the object at {} was never written", u.field, u.object),
+            Warning::Recovered { error, block } => write!(f, "{} recovered from {:?}, reading 0", block, error),
+        }
+    }
+}
+/// Peak heap use over a run (RunOptions::sample_live_every), in slots including GC headers. Without a collector
+/// nothing is ever freed, so allocated only grows, but live drops as the program lets go of objects.
+#[derive(Debug,PartialEq,Eq,Clone,Copy,Default)]
+pub struct LivePeak {
+    /// Most slots allocated at once, checked after every alloc
+    pub allocated: u64,
+    /// Most slots reachable from the stack at once, among the samples taken
+    pub live: u64,
+}
+/// One object in the heap, as listed by Memory::heap_objects
+#[derive(Debug,PartialEq,Clone)]
+pub struct HeapObject {
+    /// Address of the first field
+    pub addr: u64,
+    pub fields: u64,
+    /// Number of collections run before it was allocated, if ages are being tracked
+    pub born: Option<u64>,
+    /// Number of collections it has survived so far, if ages are being tracked
+    pub age: Option<u64>,
+}
+/// Free space left between surviving objects in a collected region, as a non-moving (mark-sweep) collector would see it.
+/// The copying collector compacts survivors, so this is what it saves us from.
+#[derive(Debug,PartialEq,Clone)]
+pub struct FragmentationReport {
+    /// First address of the collected region
+    pub region_start: u64,
+    /// First address past the end of the collected region
+    pub region_end: u64,
+    pub live_objects: u64,
+    /// Free gaps between live objects, as (first address, size in slots)
+    pub gaps: Vec<(u64,u64)>,
+    pub free_slots: u64,
+    /// Largest contiguous free run in slots, i.e., the largest allocation (metadata included) that could still be placed
+    pub largest_free_run: u64,
+}
+impl FragmentationReport {
+    // live is a list of (object address, allocation size including the 3 metadata slots)
+    fn compute(region_start: u64, region_end: u64, live: &[(u64,u64)]) -> FragmentationReport {
+        let mut extents : Vec<(u64,u64)> = live.iter().map(|(addr,size)| (addr - 3*8, addr - 3*8 + size*8)).collect();
+        extents.sort();
+        let mut gaps = vec![];
+        let mut cursor = region_start;
+        for (start,end) in extents.iter() {
+            if *start > cursor {
+                gaps.push((cursor, (start - cursor) / 8));
+            }
+            cursor = cursor.max(*end);
+        }
+        if region_end > cursor {
+            gaps.push((cursor, (region_end - cursor) / 8));
+        }
+        FragmentationReport {
+            region_start,
+            region_end,
+            live_objects: extents.len() as u64,
+            free_slots: gaps.iter().map(|(_,n)| n).sum(),
+            largest_free_run: gaps.iter().map(|(_,n)| *n).max().unwrap_or(0),
+            gaps,
+        }
+    }
+}
+
+/// A call frame's variables. The interpreter keeps each in the slot for its id in the program (see VarIds), along
+/// with its name for the by-name methods, which are for looking at a frame from outside (a snapshot's, say). A
+/// variable set by name from outside is kept apart, and moves to its slot once the interpreter sets it.
+#[derive(Clone,Default)]
+pub struct Locals<'a> {
+    slots: Vec<Option<(&'a str,VirtualVal<'a>)>>,
+    named: Vec<(&'a str,VirtualVal<'a>)>,
+}
+impl <'a> Locals<'a> {
+    pub fn new() -> Locals<'a> {
+        Locals::default()
+    }
+    // The variable, from its slot if it has an id and otherwise (or if it was set by name) by its name
+    fn lookup(&self, id: Option<VarId>, name: &str) -> Option<&VirtualVal<'a>> {
+        match id.and_then(|id| self.slots.get(id as usize)) {
+            Some(Some((_,v))) => Some(v),
+            _ => self.named.iter().find(|(n,_)| *n == name).map(|(_,v)| v)
+        }
+    }
+    fn assign(&mut self, id: Option<VarId>, name: &'a str, val: VirtualVal<'a>) -> Option<VirtualVal<'a>> {
+        let Some(id) = id else { return self.insert(name, val) };
+        let id = id as usize;
+        if id >= self.slots.len() {
+            self.slots.resize(id + 1, None);
+        }
+        let moved = self.named.iter().position(|(n,_)| *n == name).map(|i| self.named.swap_remove(i).1);
+        self.slots[id].replace((name, val)).map(|(_,v)| v).or(moved)
+    }
+    pub fn get(&self, name: &str) -> Option<&VirtualVal<'a>> {
+        self.iter().find(|(n,_)| *n == name).map(|(_,v)| v)
+    }
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut VirtualVal<'a>> {
+        self.slots.iter_mut().flatten().chain(self.named.iter_mut()).find(|(n,_)| *n == name).map(|(_,v)| v)
+    }
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+    /// Set name to val, returning what it held before
+    pub fn insert(&mut self, name: &'a str, val: VirtualVal<'a>) -> Option<VirtualVal<'a>> {
+        match self.get_mut(name) {
+            Some(old) => Some(std::mem::replace(old, val)),
+            None => {
+                self.named.push((name, val));
+                None
+            }
+        }
+    }
+    /// The value of name, or UninitializedVariable if this frame hasn't set it
+    pub fn read(&self, name: &'a str) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+        self.get(name).copied().ok_or(RuntimeError::UninitializedVariable { name })
+    }
+    /// Variables by id, then those set by name from outside, in the order they were set
+    pub fn iter(&self) -> impl Iterator<Item=(&'a str,&VirtualVal<'a>)> {
+        self.slots.iter().flatten().chain(self.named.iter()).map(|(n,v)| (*n, v))
+    }
+    pub fn keys(&self) -> impl Iterator<Item=&'a str> + '_ {
+        self.iter().map(|(n,_)| n)
+    }
+    pub fn values(&self) -> impl Iterator<Item=&VirtualVal<'a>> {
+        self.iter().map(|(_,v)| v)
+    }
+    pub fn len(&self) -> usize {
+        self.slots.iter().flatten().count() + self.named.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.named.clear();
+    }
+}
+// Just the variables that are set, as a map from name to value
+impl <'a> fmt::Debug for Locals<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+// The same variables with the same values, in whatever order
+impl <'a> PartialEq for Locals<'a> {
+    fn eq(&self, other: &Locals<'a>) -> bool {
+        self.len() == other.len() && self.iter().all(|(n,v)| other.get(n) == Some(v))
+    }
+}
+impl <'a> FromIterator<(&'a str,VirtualVal<'a>)> for Locals<'a> {
+    fn from_iter<I: IntoIterator<Item=(&'a str,VirtualVal<'a>)>>(iter: I) -> Locals<'a> {
+        let mut l = Locals::new();
+        for (name, val) in iter {
+            l.insert(name, val);
+        }
+        l
+    }
+}
+/// Global names and their addresses
+pub type Globals<'a> = HashMap<&'a str, u64>;
+// What running some code produces: a value, nothing (ret void), or an error
+type Outcome<'a> = Result<Option<VirtualVal<'a>>,RuntimeError<'a>>;
+
+/// Why a run ended before main returned, though nothing went wrong (see ExecResult::stopped)
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum Stop<'a> {
+    /// eval_prog_cancellable's should_cancel returned true
+    Cancelled,
+    /// RunOptions::run_until: an instruction set name to the target value, at step
+    AtCondition { name: &'a str, step: u64 },
+    /// RunOptions::stop_at_step: the first instruction boundary at or past the limit, at step
+    AtStep { step: u64 },
+}
+
+// How run_code left its code when nothing went wrong. Halt and Stop end the whole run, so like an error they unwind
+// every frame and leave it on the stack.
+#[derive(Debug,PartialEq)]
+enum Flow<'a> {
+    // A ret, with the values returned
+    Return(Vec<VirtualVal<'a>>),
+    // A halt instruction, ending the run as if main had returned this
+    Halt(VirtualVal<'a>),
+    Stop(Stop<'a>),
 }
-type Locals<'a> = HashMap<&'a str, VirtualVal<'a>>;
-type Globals<'a> = HashMap<&'a str, u64>;
+
+// Address of the first global; everything below is unused so a null pointer (or one near it) never hits real data
+const GLOBALS_START: u64 = 32;
 
 impl <'a> Memory<'a> {
     // Okay, a little weird for this to also allocate the globals, but whatever
-    fn new(prog: &'a IRProgram, slot_cap: ExecMode) -> (Memory<'a>,Globals<'a>) {
-        let mut next_free : u64 = 32;
+    fn new(prog: &'a IRProgram, slot_cap: ExecMode, count_globals: bool, max_address: Option<u64>) -> Result<(Memory<'a>,Globals<'a>),RuntimeError<'a>> {
+        let mut next_free : u64 = GLOBALS_START;
         let mut m : BTreeMap<u64,VirtualVal<'a>> = BTreeMap::new();
         let mut globs : Globals = HashMap::new();
 
+        // Two passes, so initializers can refer to globals declared after them:
+        // first lay out every global's address, then fill in the values
+        let mut global_starts = BTreeMap::new();
         for g in prog.globals.iter() {
-            let GlobalStatic::Array { name: n, vals: vs } = g;
+            // Constants take no memory
+            let GlobalStatic::Array { name: n, vals: vs, slots } = g else { continue };
             globs.insert(n, next_free);
+            let size = slots.unwrap_or(vs.len() as u64);
+            // Checked before anything is laid out. Like an object, a global must end below max_address; a declared
+            // size is a literal like an alloc's, so it also can't pass alloc's limit, or the cap when globals count
+            let end = size.checked_mul(8).and_then(|bytes| next_free.checked_add(bytes));
+            let capped = count_globals && slot_cap != ExecMode::Unlimited;
+            let fits = end.is_some_and(|end| max_address.is_none_or(|max| end <= max))
+                && (slots.is_none() || size <= u32::MAX as u64 && (!capped || size <= slot_cap.effective_cap()));
+            let Some(end) = end.filter(|_| fits) else { return Err(RuntimeError::GlobalTooLarge { name: n, slots: size }) };
+            // An empty global shares its address with the next one, but owns none of its slots
+            if size > 0 {
+                global_starts.insert(next_free, *n);
+            }
+            next_free = end;
+        }
+        for g in prog.globals.iter() {
+            let GlobalStatic::Array { name: n, vals: vs, slots } = g else { continue };
+            let mut loc = globs[n];
+            // Globals are read-only, so slots past the initializers are zero for good
+            for pad in vs.len() as u64..slots.unwrap_or(0) {
+                m.insert(loc + 8 * pad, VirtualVal::Data { val: 0 });
+            }
             for v in vs.iter() {
-                m.insert(next_free, v.clone());
-                next_free = next_free + 8;
+                let init = match v {
+                    IRExpr::IntLit { val } => VirtualVal::Data { val: *val },
+                    IRExpr::BlockRef { bname } => VirtualVal::CodePtr { val: bname },
+                    IRExpr::GlobalRef { name } => match globs.get(name) {
+                        Some(addr) => VirtualVal::Data { val: *addr },
+                        None => return Err(RuntimeError::UndefinedGlobal { name })
+                    },
+                    IRExpr::ConstRef { id } => match prog.constant(id) {
+                        Some(val) => VirtualVal::Data { val },
+                        None => return Err(RuntimeError::UndefinedConst { name: id })
+                    },
+                    // The parser never produces these in an initializer
+                    IRExpr::Var { id } => return Err(RuntimeError::UninitializedVariable { name: id })
+                };
+                m.insert(loc, init);
+                loc += 8;
             }
         }
 
+        let global_slots = if count_globals { (next_free - GLOBALS_START) / 8 } else { 0 };
+        if global_slots > slot_cap.effective_cap() {
+            return Err(RuntimeError::OutOfMemory);
+        }
         let mem = Memory {
                     map: m,
                     first_writable: next_free,
@@ -106,17 +504,49 @@ impl <'a> Memory<'a> {
                     next_alloc: next_free,
                     slot_cap,
                     slots_alloced: 0,
-                    allocations: HashSet::new()
+                    global_slots,
+                    allocations: HashSet::new(),
+                    objects: BTreeMap::new(),
+                    relocated: vec![],
+                    last_fragmentation: None,
+                    check_slotmaps: false,
+                    guard_words: 1,
+                    guards: BTreeMap::new(),
+                    global_starts,
+                    collections: 0,
+                    deterministic: false,
+                    track_ages: false,
+                    births: BTreeMap::new(),
+                    check_header_init: false,
+                    header_inited: BTreeSet::new(),
+                    weak_refs: false,
+                    weak_fields: vec![],
+                    track_dead_stores: false,
+                    unread_stores: BTreeMap::new(),
+                    dead_stores: vec![],
+                    copied_sizes: vec![],
+                    max_live_objects: None,
+                    fill_word: 0,
+                    filled: BTreeSet::new(),
+                    check_code_stores: false,
+                    max_address,
+                    track_uninit_fields: false,
+                    unwritten: BTreeMap::new(),
+                    uninit_fields: vec![],
+                    free_words: BTreeSet::new()
                 };
-        (mem,globs)
+        Ok((mem,globs))
     }
 
-    fn gc(&mut self, stack: &mut Vec<Locals<'a>>) -> Result<(),RuntimeError<'a>> {
+    /// Collect garbage, treating every variable in every frame of the stack as a root (and updating them to the moved objects).
+    /// Only valid in the GC modes.
+    pub fn gc(&mut self, stack: &mut Vec<Locals<'a>>) -> Result<(),RuntimeError<'a>> {
         if !self.slot_cap.is_gc() {
             panic!("Error: GC triggered in a mode where GC header is not allocated!");
         } else if self.slot_cap.is_logging_gc() {
             println!("Beginning GC")
         }
+        self.collections += 1;
         // Simple copying collector.
         // Hard limit of 64 slots (including vtbl) due to width of slotmap
         // TODO: Hmm, I need *all* locals... I need this to handle stuff from earlier stack frames....
@@ -127,8 +557,15 @@ impl <'a> Memory<'a> {
         self.allocations.clear();
         self.allocations = HashSet::new();
         self.slots_alloced = 0;
+        self.relocated.clear();
         for locals in stack.iter_mut() {
-            for (x,v) in locals.iter_mut() {
+            // Roots are moved in the order they're visited, so that order decides where the survivors end up
+            let mut roots : Vec<&'a str> = locals.keys().collect();
+            if self.deterministic {
+                roots.sort();
+            }
+            for x in roots {
+                let v = locals.get_mut(x).unwrap();
                 if self.slot_cap.is_logging_gc() {
                     println!("Tracing from root: {}={}", x, v);
                 }
@@ -152,20 +589,68 @@ impl <'a> Memory<'a> {
                 }
             }
         }
+        // Weak references follow their target if something else kept it alive, and become null otherwise.
+        // Anything that wasn't an object to begin with (0, an integer, a global) is left as it was.
+        for (field, target) in std::mem::take(&mut self.weak_fields) {
+            if !old_allocations.contains(&target) {
+                continue;
+            }
+            let now = match self.map.get(&(target - 16)) {
+                Some(VirtualVal::Data { val }) => *val,
+                _ => 0
+            };
+            if self.slot_cap.is_logging_gc() {
+                println!("Weak reference at {} to {} now {}", field, target, now);
+            }
+            self.mem_store(field, VirtualVal::Data { val: now })?;
+        }
+        // Before discarding the old semispace, record what a non-moving collector would have left behind
+        let report = FragmentationReport::compute(self.base, new_base, &self.relocated);
+        if self.slot_cap.is_logging_gc() {
+            println!("In-place collection would leave {} free slots in {} gaps, largest run {}", report.free_slots, report.gaps.len(), report.largest_free_run);
+        }
+        self.last_fragmentation = Some(report);
         // After relocating, wipe everything from the old base to the start of the new "semispace" with tombstones for debugging
         // TODO: Eventually, actually remove these and adjust lookup to automatically return tombstone for anything in the GC'ed range (i.e., between first_writable and )
         for loc in (self.base..new_base).step_by(8) {
             self.map.insert(loc, VirtualVal::GCTombstone);
         }
         self.base = new_base;
+        self.objects = self.objects.split_off(&new_base);
+        self.guards = self.guards.split_off(&new_base);
+        self.births = self.births.split_off(&new_base);
+        self.header_inited = self.header_inited.split_off(&new_base);
+        // Free words were all in the old space, so an Allocator's free list is stale after a collection
+        self.free_words.clear();
+        // Unread stores into objects that didn't survive never will be read
+        let survivors = self.unread_stores.split_off(&new_base);
+        for (addr,(block,instr)) in std::mem::replace(&mut self.unread_stores, survivors) {
+            self.dead_stores.push(DeadStore { addr, block, instr, overwritten: false });
+        }
+        // Likewise fields never written in objects that didn't survive
+        let survivors = self.unwritten.split_off(&new_base);
+        for (addr,field) in std::mem::replace(&mut self.unwritten, survivors) {
+            self.uninit_fields.push(UninitField { object: addr - 8*field, field });
+        }
+        self.filled = self.filled.split_off(&new_base);
         if self.slot_cap.is_logging_gc() {
             println!("Updated semispace base to {}, next alloc at {}", self.base, self.next_alloc);
             println!("Reduced memory consumption from {} to {} slots", was_alloced, self.slots_alloced);
         }
+        debug_assert!(self.accounting_consistent());
         Ok(())
     }
+    // Every word from base up to next_alloc is a guard zone, a free word, or one of the slots_alloced (headers
+    // included), whether alloc, an Allocator, or the collector's reserve put it there. Guard zones are deliberately not
+    // charged to the cap. Only meaningful between collections: mid-collection, base still points at the space being
+    // evacuated.
+    fn accounting_consistent(&self) -> bool {
+        let guard_slots : u64 = self.guards.values().sum();
+        self.next_alloc - self.base == 8 * (self.slots_alloced + guard_slots + self.free_words.len() as u64)
+    }
     fn reserve(&mut self, slots_including_metadata: u64) -> Result<u64,RuntimeError<'a>> {
-        if self.slots_alloced + slots_including_metadata > self.slot_cap.effective_cap() {
+        self.check_alloc_end(slots_including_metadata, slots_including_metadata)?;
+        if self.slots_alloced + slots_including_metadata > self.heap_cap() {
             return Err(RuntimeError::OutOfMemory)
         }
         let metadata_base = self.next_alloc;
@@ -199,24 +684,48 @@ impl <'a> Memory<'a> {
                     let allocsizev = *self.map.get(&allocsize_loc).ok_or_else(|| RuntimeError::UnallocatedAddressRead { addr })?;
                     let allocsize = allocsizev.as_u64_or_else(|v| RuntimeError::CorruptGCMetadata {val:*v })?;
                     let slotmapv = *self.map.get(&slotmap_loc).ok_or_else(|| RuntimeError::UnallocatedAddressRead { addr })?;
-                    let mut slotmap = slotmapv.as_u64_or_else(|v| RuntimeError::CorruptGCMetadata {val:*v })?;
+                    let slotmap = slotmapv.as_u64_or_else(|v| RuntimeError::CorruptGCMetadata {val:*v })?;
+                    self.check_slotmap(addr, allocsize - 3, slotmap)?;
                     if self.slot_cap.is_logging_gc() {
                         println!("Tracing {} with alloc size {} and slotmap {:X}", addr, allocsize, slotmap);
                     }
                     let new_metadata_loc = self.reserve(allocsize)?;
+                    self.relocated.push((addr, allocsize));
+                    self.copied_sizes.push(allocsize - 3);
                     self.mem_store(new_metadata_loc, allocsizev)?;
                     // Set new forwarding pointer to 0
                     self.mem_store(new_metadata_loc+8, VirtualVal::Data{val:0})?;
                     self.mem_store(new_metadata_loc+16, slotmapv)?;
                     // Compute new program address for object's moved version
                     let new_obj_base = new_metadata_loc + 24;
+                    self.objects.insert(new_obj_base, allocsize - 3);
+                    // Moving doesn't make an object any younger
+                    if let Some(born) = self.births.get(&addr).copied() {
+                        self.births.insert(new_obj_base, born);
+                    }
+                    // The copy's header was written by the collector from the original's
+                    if self.header_inited.contains(&addr) {
+                        self.header_inited.insert(new_obj_base);
+                    }
                     // Set forwarding pointer
                     self.mem_store(fwd_ptr_loc, VirtualVal::Data { val: new_obj_base })?;
                     // Iterate through the fields and slot map in parallel
+                    let (mut strong, mut weak) = self.split_slotmap(slotmap);
                     for i in 0..(allocsize - 3) {
                         // recursively copy or trace from addr[i] to new_obj_base[i]
                         let orig = self.mem_lookup(addr + i*8)?;
-                        if slotmap & 0x1 == 1 {
+                        if let Some(store) = self.unread_stores.remove(&(addr + i*8)) {
+                            self.unread_stores.insert(new_obj_base + i*8, store);
+                        }
+                        if let Some(field) = self.unwritten.remove(&(addr + i*8)) {
+                            self.unwritten.insert(new_obj_base + i*8, field);
+                        }
+                        // A traced field the program never wrote holds the fill word, not a pointer
+                        let filled = self.filled.remove(&(addr + i*8));
+                        if filled {
+                            self.filled.insert(new_obj_base + i*8);
+                        }
+                        if strong & 0x1 == 1 && !filled {
                             // trace
                             let to_trace = match orig {
                                             VirtualVal::GCTombstone => Err(RuntimeError::CorruptGCMetadata { val: orig }),
@@ -234,10 +743,14 @@ impl <'a> Memory<'a> {
                                 }
                             }
                         } else {
-                            // blind copy
+                            // blind copy; a weak reference is patched up once we know whether its target survived
                             self.mem_store(new_obj_base + i*8, orig)?;
+                            if let (1, VirtualVal::Data { val: target }) = (weak & 0x1, orig) {
+                                self.weak_fields.push((new_obj_base + i*8, target));
+                            }
                         }
-                        slotmap = slotmap >> 1;
+                        strong >>= 1;
+                        weak >>= 1;
                     }
                     Ok(new_obj_base)
                 }
@@ -246,8 +759,33 @@ impl <'a> Memory<'a> {
         }
 
     }
-    fn alloc(&mut self, n:u64) -> Result<u64,RuntimeError<'a>> {
-        if self.slot_cap != ExecMode::Unlimited && self.slots_alloced + n + 1 > self.slot_cap.effective_cap() {
+    // Slots the heap may use, once any globals counted against the cap are set aside
+    fn heap_cap(&self) -> u64 {
+        self.slot_cap.effective_cap() - self.global_slots
+    }
+    // The words about to be bump-allocated must end at an address that fits in 64 bits and is within
+    // RunOptions::max_address; slots is what to report if not
+    fn check_alloc_end(&self, words:u64, slots:u64) -> Result<(),RuntimeError<'a>> {
+        match words.checked_mul(8).and_then(|bytes| self.next_alloc.checked_add(bytes)) {
+            Some(end) if self.max_address.is_none_or(|max| end <= max) => Ok(()),
+            _ => Err(RuntimeError::AllocationTooLarge { slots })
+        }
+    }
+    /// Bump-allocate an object of n fields, each holding the fill word (0 unless RunOptions::fill_word says otherwise),
+    /// plus a guard zone and, in capped modes, a GC header, returning the address of its first field. Fails with GCRequired in the GC modes when the cap is reached; this never collects by itself.
+    pub fn alloc(&mut self, n:u64) -> Result<u64,RuntimeError<'a>> {
+        let header = if self.slot_cap == ExecMode::Unlimited { 0 } else { 3 };
+        match n.checked_add(self.guard_words + header) {
+            Some(words) => self.check_alloc_end(words, n)?,
+            None => return Err(RuntimeError::AllocationTooLarge { slots: n })
+        }
+        if let Some(limit) = self.max_live_objects {
+            if self.live_objects() >= limit {
+                // A collection may free some, as with slots
+                return Err(if self.slot_cap.is_gc() { RuntimeError::GCRequired } else { RuntimeError::LiveObjectLimitExceeded { limit } });
+            }
+        }
+        if self.slot_cap != ExecMode::Unlimited && self.slots_alloced + self.free_words.len() as u64 + n + 1 > self.heap_cap() {
             match self.slot_cap {
                 ExecMode::Unlimited => {return Ok(0)}, // unreachable since we checked it's not unlimited
                 ExecMode::MemCap{..} => {return Err(RuntimeError::OutOfMemory)},
@@ -256,33 +794,341 @@ impl <'a> Memory<'a> {
             }
         } else {
             if self.slot_cap.is_logging_gc() {
-                println!("Alloc'ing {} slots on top of {} with cap {}", n, self.slots_alloced, self.slot_cap.effective_cap());
+                println!("Alloc'ing {} slots on top of {} with cap {}", n, self.slots_alloced, self.heap_cap());
             }
         }
 
-        // Skip 8 bytes to catch some memory errors
-        self.next_alloc = self.next_alloc + 8;
-        if self.slot_cap != ExecMode::Unlimited {
-            // Reserve GC header space
+        // Skip a guard zone to catch some memory errors
+        if self.guard_words > 0 {
+            self.guards.insert(self.next_alloc, self.guard_words);
+        }
+        self.next_alloc = self.next_alloc + 8 * self.guard_words;
+        let start = self.next_alloc;
+        self.next_alloc += 8 * self.object_words(n);
+        let result = self.lay_out(start, n);
+        debug_assert!(self.accounting_consistent());
+        Ok(result)
+    }
+    // Write an object of n fields (with a GC header, in capped modes) into the words from start, which the caller has
+    // already accounted for, and start tracking it. Returns the address of its first field.
+    fn lay_out(&mut self, start: u64, n: u64) -> u64 {
+        let header = self.object_words(0);
+        if header > 0 {
             // Technically unnecessary when running with limits but without GC
-            self.map.insert(self.next_alloc, VirtualVal::Data { val: n+3 });
-            self.map.insert(self.next_alloc+8, VirtualVal::Data { val: 0 });
-            self.map.insert(self.next_alloc+16, VirtualVal::Data { val: 0 });
-            self.next_alloc = self.next_alloc + 24;
-            self.slots_alloced = self.slots_alloced + 3;
-        }
-        let result = self.next_alloc;
-        let mut allocd = 0;
-        while allocd < n {
+            self.map.insert(start, VirtualVal::Data { val: n+3 });
+            self.map.insert(start+8, VirtualVal::Data { val: 0 });
+            self.map.insert(start+16, VirtualVal::Data { val: 0 });
+        }
+        let result = start + 8 * header;
+        for i in 0..n {
             // Must insert directly to side-step allocation checks
-            self.map.insert(self.next_alloc, VirtualVal::Data { val: 0 });
-            self.next_alloc = self.next_alloc + 8;
-            allocd = allocd + 1;
+            self.map.insert(result + 8*i, VirtualVal::Data { val: self.fill_word });
+            if self.track_uninit_fields {
+                self.unwritten.insert(result + 8*i, i);
+            }
+            if header > 0 && self.fill_word != 0 {
+                self.filled.insert(result + 8*i);
+            }
         }
-        self.slots_alloced = self.slots_alloced + allocd;
+        self.slots_alloced += header + n;
         self.allocations.insert(result);
+        self.objects.insert(result, n);
+        if self.track_ages {
+            self.births.insert(result, self.collections);
+        }
+        result
+    }
+
+    /// Words an object of n fields takes up, counting its GC header in the capped modes but not any guard zone
+    pub fn object_words(&self, n: u64) -> u64 {
+        n + if self.slot_cap == ExecMode::Unlimited { 0 } else { 3 }
+    }
+    /// For Allocators: bump-allocate words free words at the end of the heap, for place_object to put objects in,
+    /// returning the address of the first. Free words count against the cap just as objects do; in the GC modes,
+    /// running out is GCRequired.
+    pub fn reserve_words(&mut self, words: u64) -> Result<u64,RuntimeError<'a>> {
+        self.check_alloc_end(words, words)?;
+        if self.slot_cap != ExecMode::Unlimited && self.slots_alloced + self.free_words.len() as u64 + words > self.heap_cap() {
+            return Err(if self.slot_cap.is_gc() { RuntimeError::GCRequired } else { RuntimeError::OutOfMemory });
+        }
+        let start = self.next_alloc;
+        for i in 0..words {
+            self.map.insert(start + 8*i, VirtualVal::GCTombstone);
+            self.free_words.insert(start + 8*i);
+        }
+        self.next_alloc += 8 * words;
+        debug_assert!(self.accounting_consistent());
+        Ok(start)
+    }
+    /// For Allocators: put an object of n fields in the object_words(n) free words starting at start, returning the
+    /// address of its first field, as alloc would. The words must all be free (from reserve_words or release_object).
+    pub fn place_object(&mut self, start: u64, n: u64) -> Result<u64,RuntimeError<'a>> {
+        let words = self.object_words(n);
+        if let Some(addr) = (0..words).map(|i| start + 8*i).find(|a| !self.free_words.contains(a)) {
+            return Err(RuntimeError::PlacementNotFree { addr });
+        }
+        if let Some(limit) = self.max_live_objects {
+            if self.live_objects() >= limit {
+                return Err(if self.slot_cap.is_gc() { RuntimeError::GCRequired } else { RuntimeError::LiveObjectLimitExceeded { limit } });
+            }
+        }
+        for i in 0..words {
+            self.free_words.remove(&(start + 8*i));
+        }
+        let result = self.lay_out(start, n);
+        debug_assert!(self.accounting_consistent());
         Ok(result)
     }
+    /// For Allocators: stop tracking the object whose first field is at addr and free its words (header included), as
+    /// the collector would if it were garbage. Returns the freed run as (first word, number of words). Reading the
+    /// object afterwards is AccessingDeallocatedAddress, until an object is placed there again; it's up to the
+    /// allocator to only release objects nothing can reach (see reachable_objects).
+    pub fn release_object(&mut self, addr: u64) -> Result<(u64,u64),RuntimeError<'a>> {
+        let n = self.objects.remove(&addr).ok_or(RuntimeError::UnallocatedAddressWrite { addr })?;
+        let words = self.object_words(n);
+        let start = addr - 8 * (words - n);
+        self.allocations.remove(&addr);
+        self.births.remove(&addr);
+        self.header_inited.remove(&addr);
+        for a in (start..start + 8*words).step_by(8) {
+            self.map.insert(a, VirtualVal::GCTombstone);
+            self.free_words.insert(a);
+            if let Some((block,instr)) = self.unread_stores.remove(&a) {
+                self.dead_stores.push(DeadStore { addr: a, block, instr, overwritten: false });
+            }
+            if let Some(field) = self.unwritten.remove(&a) {
+                self.uninit_fields.push(UninitField { object: addr, field });
+            }
+            self.filled.remove(&a);
+        }
+        self.slots_alloced -= words;
+        debug_assert!(self.accounting_consistent());
+        Ok((start, words))
+    }
+
+    /// How many objects, and how many slots, are reachable from the given addresses, following each object's slotmap
+    /// the same way the collector does, but without moving or changing anything. Slots include the GC header, so right
+    /// after a collection from the same roots this matches what the collector kept. Roots that aren't object addresses
+    /// are ignored. Without a cap there are no headers and so no slotmaps: only the roots themselves are counted.
+    pub fn reachable_count(&self, roots: &[u64]) -> (u64, u64) {
+        let seen = self.reachable_objects(roots);
+        (seen.len() as u64, seen.iter().map(|addr| self.object_words(self.objects[addr])).sum())
+    }
+    /// The objects reachable from the given addresses, as reachable_count finds them: fields are only followed where
+    /// the object's slotmap marks them traced, so objects without a header (ExecMode::Unlimited) are leaves
+    pub fn reachable_objects(&self, roots: &[u64]) -> BTreeSet<u64> {
+        let header = self.object_words(0);
+        let mut seen = BTreeSet::new();
+        let mut worklist : Vec<u64> = roots.to_vec();
+        while let Some(addr) = worklist.pop() {
+            let fields = match self.objects.get(&addr) {
+                Some(n) if seen.insert(addr) => *n,
+                _ => continue
+            };
+            if header == 0 {
+                continue;
+            }
+            let (strong, _) = self.split_slotmap(match self.map.get(&(addr - 8)) {
+                Some(VirtualVal::Data { val }) => *val,
+                _ => 0
+            });
+            for i in 0..fields.min(64) {
+                if (strong >> i) & 1 == 1 {
+                    if let Some(VirtualVal::Data { val }) = self.map.get(&(addr + i*8)) {
+                        worklist.push(*val);
+                    }
+                }
+            }
+        }
+        seen
+    }
+
+    /// Allocate the objects of an InitialHeap in order, fill in their fields, and (with a cap) set each slotmap to mark
+    /// the fields holding HeapValue::Object, so the collector traces them. Returns each object's address. With a cap,
+    /// references can only go in the first 64 fields, the ones a slotmap has bits for.
+    pub fn place_objects(&mut self, objects: &[Vec<HeapValue>]) -> Result<Vec<u64>,RuntimeError<'a>> {
+        let mut addrs = vec![];
+        for fields in objects.iter() {
+            // Nothing has been allocated yet, so there's no garbage a collection could free
+            addrs.push(self.alloc(fields.len() as u64).map_err(|e| match e {
+                RuntimeError::GCRequired => self.out_of_room(),
+                e => e
+            })?);
+        }
+        for (n, (addr, fields)) in addrs.iter().zip(objects.iter()).enumerate() {
+            let mut slotmap = 0u64;
+            for (i, field) in fields.iter().enumerate() {
+                if let HeapValue::Object(_) = field {
+                    if i >= 64 && self.slot_cap != ExecMode::Unlimited {
+                        return Err(RuntimeError::UntraceableHeapField { object: n, field: i });
+                    }
+                    slotmap |= 1u64.checked_shl(i as u32).unwrap_or(0);
+                }
+                self.map.insert(addr + 8 * i as u64, VirtualVal::Data { val: field.resolve(&addrs)? });
+                self.unwritten.remove(&(addr + 8 * i as u64));
+                self.filled.remove(&(addr + 8 * i as u64));
+            }
+            if self.slot_cap != ExecMode::Unlimited {
+                self.map.insert(addr - 8, VirtualVal::Data { val: slotmap });
+                self.header_inited.insert(*addr);
+            }
+        }
+        Ok(addrs)
+    }
+
+    // RunOptions::check_header_init: addr is in an object's fields, but the program hasn't yet written that object's slotmap
+    fn check_header_init(&self, addr:u64) -> Result<(),RuntimeError<'a>> {
+        if !self.check_header_init || !self.slot_cap.is_gc() {
+            return Ok(());
+        }
+        match self.object_containing(addr) {
+            Some(start) if !self.header_inited.contains(&start) => Err(RuntimeError::UseBeforeHeaderInit { addr: start }),
+            _ => Ok(())
+        }
+    }
+
+    // The object whose fields include addr, if any
+    fn object_containing(&self, addr:u64) -> Option<u64> {
+        match self.objects.range(..=addr).next_back() {
+            Some((start,fields)) if addr < start + 8*fields => Some(*start),
+            _ => None
+        }
+    }
+
+    // RunOptions::track_dead_stores: the program wrote an object field (header writes aren't tracked)
+    fn note_write(&mut self, addr:u64, block: &'a str, instr: &'a IRStatement<'a>) {
+        self.unwritten.remove(&addr);
+        self.filled.remove(&addr);
+        if !self.track_dead_stores || self.object_containing(addr).is_none() {
+            return;
+        }
+        if let Some((b,i)) = self.unread_stores.insert(addr, (block, instr)) {
+            self.dead_stores.push(DeadStore { addr, block: b, instr: i, overwritten: true });
+        }
+    }
+    // RunOptions::track_dead_stores: the program read a word, so whatever was last stored there was used
+    fn note_read(&mut self, addr:u64) {
+        if self.track_dead_stores {
+            self.unread_stores.remove(&addr);
+        }
+    }
+
+    /// With RunOptions::track_uninit_fields, every object field the program never wrote: first those in objects
+    /// collected so far, in the order that happened, then those in objects still around, by address
+    pub fn uninit_fields(&self) -> Vec<UninitField> {
+        let mut all = self.uninit_fields.clone();
+        all.extend(self.unwritten.iter().map(|(addr,field)| UninitField { object: addr - 8*field, field: *field }));
+        all
+    }
+
+    /// With RunOptions::track_dead_stores, every store to an object field whose value the program never read: first
+    /// those overwritten or collected so far, in the order that happened, then those still unread, by address
+    pub fn dead_stores(&self) -> Vec<DeadStore<'a>> {
+        let mut all = self.dead_stores.clone();
+        all.extend(self.unread_stores.iter().map(|(addr,(block,instr))| DeadStore { addr: *addr, block, instr, overwritten: false }));
+        all
+    }
+
+    // RunOptions::check_code_stores: storing val at addr would leave a code pointer where the collector will trace
+    fn check_code_store(&self, addr:u64, val: VirtualVal<'a>) -> Result<(),RuntimeError<'a>> {
+        let bname = match val {
+            VirtualVal::CodePtr { val: b } if self.check_code_stores && self.slot_cap.is_gc() => b,
+            _ => return Ok(())
+        };
+        let start = match self.object_containing(addr) {
+            Some(start) => start,
+            None => return Ok(())
+        };
+        match self.map.get(&(start - 8)) {
+            Some(VirtualVal::Data { val: slotmap }) if (self.split_slotmap(*slotmap).0 >> ((addr - start) / 8)) & 1 == 1 =>
+                Err(RuntimeError::StoringCodePtrInTracedField { addr, bname }),
+            _ => Ok(())
+        }
+    }
+    // RunOptions::check_code_stores: the slotmap about to be stored for the object at start marks a field that already
+    // holds a code pointer
+    fn check_code_fields(&self, start:u64, slotmap:u64) -> Result<(),RuntimeError<'a>> {
+        if !self.check_code_stores || !self.slot_cap.is_gc() {
+            return Ok(());
+        }
+        let (strong, _) = self.split_slotmap(slotmap);
+        for i in 0..self.objects.get(&start).copied().unwrap_or(0).min(64) {
+            if let (1, Some(VirtualVal::CodePtr { val: bname })) = ((strong >> i) & 1, self.map.get(&(start + 8*i))) {
+                return Err(RuntimeError::StoringCodePtrInTracedField { addr: start + 8*i, bname });
+            }
+        }
+        Ok(())
+    }
+
+    // Whether addr is exactly where an allocation returned an object (i.e., looks like a fresh pointer)
+    fn is_object(&self, addr:u64) -> bool {
+        self.objects.contains_key(&addr)
+    }
+
+    // A slotmap has one bit per field, so it can't mark anything past the object's end, and can't describe more than 64 fields
+    // With weak references on, the halves must each fit the object, and can't both claim a field
+    fn check_slotmap(&self, addr:u64, slots:u64, slotmap:u64) -> Result<(),RuntimeError<'a>> {
+        if !self.check_slotmaps {
+            return Ok(());
+        }
+        let (strong, weak) = self.split_slotmap(slotmap);
+        let width = if self.weak_refs { 32 } else { 64 };
+        if slots > width || (slots < width && (strong | weak) >> slots != 0) || strong & weak != 0 {
+            return Err(RuntimeError::SlotmapSizeMismatch { addr, slots, slotmap });
+        }
+        Ok(())
+    }
+
+    // A slotmap's (strong, weak) pointer bits. Without weak references every marked field is strong; with them
+    // (RunOptions::weak_refs) the low 32 bits mark strong fields and the high 32 bits weak ones.
+    fn split_slotmap(&self, slotmap:u64) -> (u64,u64) {
+        if self.weak_refs {
+            (slotmap & 0xFFFF_FFFF, slotmap >> 32)
+        } else {
+            (slotmap, 0)
+        }
+    }
+
+    // If addr is one of the GC header slots in front of an object, which one: 0 allocsize, 1 forwarding pointer, 2 slotmap
+    fn header_slot(&self, addr:u64) -> Option<u64> {
+        if self.slot_cap == ExecMode::Unlimited {
+            return None;
+        }
+        match self.objects.range(addr+1..).next() {
+            Some((start,_)) if *start - addr <= 3*8 && (*start - addr).is_multiple_of(8) => Some(3 - (*start - addr)/8),
+            _ => None
+        }
+    }
+
+    // Whether addr lands in a global or somewhere in an object (including the GC header slots in front of it, if any)
+    fn is_known_address(&self, addr:u64) -> bool {
+        if addr >= GLOBALS_START && addr < self.first_writable {
+            return true;
+        }
+        let header = if self.slot_cap == ExecMode::Unlimited { 0 } else { 3*8 };
+        match self.objects.range(..=addr + header).next_back() {
+            Some((start,fields)) => addr < start + 8*fields,
+            None => false
+        }
+    }
+
+    fn in_guard(&self, addr:u64) -> bool {
+        match self.guards.range(..=addr).next_back() {
+            Some((start,words)) => addr < start + 8*words,
+            None => false
+        }
+    }
+
+    // A value returned from a call must not point into space the collector has already reclaimed.
+    // This only sees what the collector left behind: values are untyped, so an integer that happens to fall in
+    // reclaimed space is reported too, and nothing is ever reclaimed in modes without GC.
+    fn check_returned(&self, v: &VirtualVal<'a>) -> Result<(),RuntimeError<'a>> {
+        match v {
+            VirtualVal::Data { val } if self.map.get(val) == Some(&VirtualVal::GCTombstone) =>
+                Err(RuntimeError::ReturnedDanglingReference { addr: *val }),
+            _ => Ok(())
+        }
+    }
 
     fn mem_lookup(&mut self, addr:u64) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
         if addr == 0 {
@@ -291,6 +1137,7 @@ impl <'a> Memory<'a> {
             Err(RuntimeError::ReadFromGCedData)
         } else if addr % 8 == 0 {
             match self.map.get(&addr) {
+                None if self.in_guard(addr) => Err(RuntimeError::GuardZoneAccess { addr }),
                 None => Err(RuntimeError::UnallocatedAddressRead { addr }),
                 Some(VirtualVal::GCTombstone) => Err(RuntimeError::AccessingDeallocatedAddress {addr}),
                 Some(&v) => Ok(v)
@@ -309,67 +1156,304 @@ impl <'a> Memory<'a> {
             Err(RuntimeError::WriteToGCedData { addr, val })
         } else if addr % 8 == 0 {
             match self.map.get(&addr) {
+                None if self.in_guard(addr) => Err(RuntimeError::GuardZoneAccess { addr }),
                 None => Err(RuntimeError::UnallocatedAddressWrite { addr }),
                 Some(VirtualVal::GCTombstone) => Err(RuntimeError::AccessingDeallocatedAddress {addr}),
-                Some(_) => { Ok(self.map.insert(addr, val).unwrap()) }
+                Some(_) => {
+                    if self.check_header_init && self.header_slot(addr) == Some(2) {
+                        self.header_inited.insert(addr + 8);
+                    }
+                    Ok(self.map.insert(addr, val).unwrap())
+                }
             }
         } else {
             Err(RuntimeError::UnalignedAccess { addr })
         }
     }
 
+    // Read a bits-wide element at byte address addr, out of the word containing it
+    fn mem_lookup_narrow(&mut self, addr:u64, bits:u32) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+        if !addr.is_multiple_of(u64::from(bits / 8)) {
+            return Err(RuntimeError::UnalignedAccess { addr });
+        }
+        let shift = (addr % 8) * 8;
+        match self.mem_lookup(addr - addr % 8)? {
+            VirtualVal::Data { val } => Ok(VirtualVal::Data { val: (val >> shift) & ((1u64 << bits) - 1) }),
+            VirtualVal::CodePtr { val } => Err(RuntimeError::AccessingCodeInMemory { bname: val }),
+            VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData)
+        }
+    }
+
+    // Overwrite a bits-wide element at byte address addr with the low bits of val, keeping the rest of its word
+    fn mem_store_narrow(&mut self, addr:u64, bits:u32, val:u64) -> Result<(),RuntimeError<'a>> {
+        if !addr.is_multiple_of(u64::from(bits / 8)) {
+            return Err(RuntimeError::UnalignedAccess { addr });
+        }
+        let word = addr - addr % 8;
+        let shift = (addr % 8) * 8;
+        let mask = ((1u64 << bits) - 1) << shift;
+        match self.mem_lookup(word)? {
+            VirtualVal::Data { val: old } => self.mem_store(word, VirtualVal::Data { val: (old & !mask) | ((val << shift) & mask) }).map(|_| ()),
+            VirtualVal::CodePtr { val } => Err(RuntimeError::AccessingCodeInMemory { bname: val }),
+            VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData)
+        }
+    }
+
     fn print(&self, _prog: &'a IRProgram, globs: &'a Globals<'a>) {
-        println!("Global Addresses:");
-        for (name,addr) in globs.iter() {
-            println!("\t@{} -> {}", name, addr)
+        print!("{}", self.dump(globs));
+    }
+
+    // Render the memory dump
+    fn dump(&self, globs: &Globals<'a>) -> String {
+        self.snapshot(globs).to_string()
+    }
+
+    /// Objects allocated and not yet reclaimed. Without a collector that's every object ever allocated; with one, objects
+    /// that became unreachable still count until the next collection.
+    pub fn live_objects(&self) -> u64 {
+        self.objects.len() as u64
+    }
+
+    // Why an allocation that a collection couldn't make room for fails
+    fn out_of_room(&self) -> RuntimeError<'a> {
+        match self.max_live_objects {
+            Some(limit) if self.live_objects() >= limit => RuntimeError::LiveObjectLimitExceeded { limit },
+            _ => RuntimeError::OutOfMemory
+        }
+    }
+
+    /// Every object in the current allocation space, in address order. Right after a collection these are exactly the
+    /// survivors; between collections, objects that became unreachable are still listed until the next one.
+    pub fn heap_objects(&self) -> Vec<HeapObject> {
+        self.objects.iter().map(|(addr,fields)| {
+            let born = self.births.get(addr).copied();
+            HeapObject { addr: *addr, fields: *fields, born, age: born.map(|b| self.collections - b) }
+        }).collect()
+    }
+
+    /// Every slot of every global, in address order: name, index within the global, and value (slots past the
+    /// initializer hold 0). Globals with no slots don't appear.
+    pub fn global_slots(&self) -> Vec<(&'a str,u64,Option<VirtualVal<'a>>)> {
+        let mut slots = vec![];
+        for (start,name) in self.global_starts.iter() {
+            for addr in (*start..self.global_end(*start)).step_by(8) {
+                slots.push((*name, (addr - start) / 8, self.map.get(&addr).copied()));
+            }
+        }
+        slots
+    }
+
+    // Just past the last slot of the global starting at start: globals are laid out back to back
+    fn global_end(&self, start: u64) -> u64 {
+        self.global_starts.range(start+1..).next().map_or(self.first_writable, |(next,_)| *next)
+    }
+    // Indexing from the start of a global (base) must land within that global's declared slots
+    fn check_global_index(&self, base: u64, addr: u64) -> Result<(),RuntimeError<'a>> {
+        let Some(name) = self.global_starts.get(&base) else { return Ok(()) };
+        let end = self.global_end(base);
+        if addr < base || addr >= end {
+            return Err(RuntimeError::GlobalOutOfBounds { name, index: (addr.wrapping_sub(base) as i64 / 8) as u64, slots: (end - base) / 8 });
+        }
+        Ok(())
+    }
+
+    /// Copy out the current contents and layout
+    pub fn snapshot(&self, globs: &Globals<'a>) -> MemorySnapshot<'a> {
+        let mut globals : Vec<(&'a str,u64)> = globs.iter().map(|(name,addr)| (*name,*addr)).collect();
+        globals.sort_by_key(|(name,addr)| (*addr, *name));
+        let free_slots = if self.slot_cap == ExecMode::Unlimited { None } else { Some(self.heap_cap().saturating_sub(self.slots_alloced)) };
+        MemorySnapshot { contents: self.map.clone(), globals, first_writable: self.first_writable, base: self.base, next_alloc: self.next_alloc, free_slots }
+    }
+}
+
+/// A copy of memory at one point in a run, for callers that want to inspect or render it themselves.
+/// Display gives the same dump a crashing run_prog prints; the alternate form (`{:#}`) shows addresses and Data values
+/// in hex, which makes pointers easier to follow. Sizes stay in decimal words either way.
+#[derive(Debug,PartialEq,Clone)]
+pub struct MemorySnapshot<'a> {
+    /// Every word with a value, by address
+    pub contents: BTreeMap<u64,VirtualVal<'a>>,
+    /// Global names and addresses, in address order
+    pub globals: Vec<(&'a str,u64)>,
+    /// First address of mutable memory; everything below is globals
+    pub first_writable: u64,
+    /// First address of the current allocation space; anything from first_writable up to here was reclaimed by GC
+    pub base: u64,
+    /// Next unallocated address
+    pub next_alloc: u64,
+    /// Slots that can still be allocated before reaching the cap, or None when uncapped
+    pub free_slots: Option<u64>,
+}
+// Globals are listed in address order so dumps can be diffed between runs
+impl <'a> fmt::Display for MemorySnapshot<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = f.alternate();
+        let num = |n: u64| if hex { format!("{:#x}", n) } else { n.to_string() };
+        writeln!(f, "Global Addresses:")?;
+        for (name,addr) in self.globals.iter() {
+            writeln!(f, "\t@{} -> {}", name, num(*addr))?;
+        }
+        // Sizes are in words
+        writeln!(f, "Memory Map:")?;
+        writeln!(f, "\tglobals   [{}, {}) size {}", num(GLOBALS_START), num(self.first_writable), (self.first_writable - GLOBALS_START) / 8)?;
+        writeln!(f, "\tGC'ed     [{}, {}) size {}", num(self.first_writable), num(self.base), (self.base - self.first_writable) / 8)?;
+        writeln!(f, "\tsemispace [{}, {}) size {}", num(self.base), num(self.next_alloc), (self.next_alloc - self.base) / 8)?;
+        match self.free_slots {
+            Some(n) => writeln!(f, "\tfree until cap: {}", n)?,
+            None => writeln!(f, "\tfree until cap: unlimited")?
         }
-        println!("Memory Contents:");
+        writeln!(f, "Memory Contents:")?;
         let mut split_globals = false;
         let mut split_gcspace = false;
-        for (addr,val) in self.map.iter() {
-            if !split_globals && *addr > self.first_writable {
-                println!("\t---------------- <end of globals, start of mutable memory>");
+        for (addr,val) in self.contents.iter() {
+            if !split_globals && *addr >= self.first_writable {
+                writeln!(f, "\t---------------- <end of globals, start of mutable memory>")?;
                 split_globals = true;
             }
-            if !split_gcspace && *addr > self.base {
-                println!("\t---------------- <end of GC'ed memory, start of current \"semispace\">");
+            if !split_gcspace && *addr >= self.base {
+                writeln!(f, "\t---------------- <end of GC'ed memory, start of current \"semispace\">")?;
                 split_gcspace = true;
             }
-            println!("\t{}: {}", addr, val);
+            match val {
+                VirtualVal::Data { val } => writeln!(f, "\t{}: {}", num(*addr), num(*val))?,
+                // Block names and tombstones print as themselves, so they can't be mistaken for numbers
+                _ => writeln!(f, "\t{}: {}", num(*addr), val)?
+            }
         }
+        Ok(())
     }
 }
 
 
-fn read_var<'a>(l:&Locals<'a>, v:&'a str) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
-    match l.get(v) {
+/// Compare the object graphs reachable from two root lists, up to relabeling of addresses, since two runs
+/// (or one run before and after a moving collection) can build the same structure at different addresses.
+/// Roots are compared pairwise. A Data value is treated as a pointer exactly when it is the address of an object
+/// in that heap, so an integer that happens to coincide with an object address in only one heap makes the heaps differ.
+pub fn heaps_equal<'a,'b>(a: &Memory<'a>, a_roots: &[VirtualVal<'a>], b: &Memory<'b>, b_roots: &[VirtualVal<'b>]) -> bool {
+    if a_roots.len() != b_roots.len() {
+        return false;
+    }
+    // The address correspondence discovered so far, in both directions so it stays a bijection
+    let mut a_to_b : HashMap<u64,u64> = HashMap::new();
+    let mut b_to_a : HashMap<u64,u64> = HashMap::new();
+    let mut worklist : Vec<(VirtualVal<'a>,VirtualVal<'b>)> = a_roots.iter().copied().zip(b_roots.iter().copied()).collect();
+    while let Some((va,vb)) = worklist.pop() {
+        match (va,vb) {
+            (VirtualVal::Data { val: x }, VirtualVal::Data { val: y }) => {
+                match (a.objects.get(&x), b.objects.get(&y)) {
+                    (None, None) => if x != y { return false; },
+                    (Some(&na), Some(&nb)) => {
+                        match (a_to_b.get(&x), b_to_a.get(&y)) {
+                            (Some(&y2), Some(&x2)) => if y2 != y || x2 != x { return false; },
+                            (None, None) => {
+                                if na != nb {
+                                    return false;
+                                }
+                                a_to_b.insert(x, y);
+                                b_to_a.insert(y, x);
+                                for i in 0..na {
+                                    match (a.map.get(&(x + i*8)), b.map.get(&(y + i*8))) {
+                                        (Some(&fa), Some(&fb)) => worklist.push((fa,fb)),
+                                        _ => return false
+                                    }
+                                }
+                            },
+                            // One side already corresponds to a different object
+                            _ => return false
+                        }
+                    },
+                    _ => return false
+                }
+            },
+            (VirtualVal::CodePtr { val: x }, VirtualVal::CodePtr { val: y }) => if x != y { return false; },
+            (VirtualVal::GCTombstone, VirtualVal::GCTombstone) => (),
+            _ => return false
+        }
+    }
+    true
+}
+
+fn read_var<'a>(l:&Locals<'a>, vars:&VarIds, v:&'a str) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+    match l.lookup(vars.id(v), v) {
         Some(&x) => Ok(x),
         None => Err(RuntimeError::UninitializedVariable { name : v})
     }
 }
-fn set_var<'a>(l:&mut Locals<'a>, x:&'a str, val:VirtualVal<'a>) -> Result<(),RuntimeError<'a>> {
-    l.insert(x, val);
+fn set_var<'a>(l:&mut Locals<'a>, vars:&VarIds, x:&'a str, val:VirtualVal<'a>) -> Result<(),RuntimeError<'a>> {
+    l.assign(vars.id(x), x, val);
     Ok(())
 }
 
-#[derive(Debug,PartialEq)]
+/// Every value in the stack's frames that could be a pointer: the roots a collection (or Memory::reachable_objects)
+/// starts from
+pub fn stack_roots(stack: &[Locals<'_>]) -> Vec<u64> {
+    stack.iter().flat_map(|l| l.values()).filter_map(|v| match v {
+        VirtualVal::Data { val } => Some(*val),
+        _ => None
+    }).collect()
+}
+
+/// Decides where each alloc instruction's object goes. Implementations place objects through Memory's own primitives,
+/// so the result is still laid out the way the rest of the interpreter expects; they choose what to ask for and when.
+/// Memory::alloc bump-allocates and Memory::gc collects using the stack as roots. An allocator managing its own space
+/// instead takes free words with Memory::reserve_words, puts objects in them with Memory::place_object (which writes the
+/// header and slotmap as alloc would), and hands back unreachable objects' words with Memory::release_object.
+pub trait Allocator<'a> {
+    /// Find room for an object of n fields, returning the address of its first field. The stack holds every frame's
+    /// locals, the roots for any collection.
+    fn alloc(&mut self, mem: &mut Memory<'a>, stack: &mut Vec<Locals<'a>>, n: u64) -> Result<u64,RuntimeError<'a>>;
+}
+
+/// The standard policy: bump-allocate, and in the GC modes collect once and retry when the cap is reached
+pub struct BumpAllocator;
+impl <'a> Allocator<'a> for BumpAllocator {
+    fn alloc(&mut self, mem: &mut Memory<'a>, stack: &mut Vec<Locals<'a>>, n: u64) -> Result<u64,RuntimeError<'a>> {
+        match mem.alloc(n) {
+            Err(RuntimeError::GCRequired) => {
+                if mem.slot_cap.is_logging_gc() {
+                    println!("Triggering GC");
+                }
+                mem.gc(stack)?;
+                match mem.alloc(n) {
+                    Err(RuntimeError::GCRequired) => Err(mem.out_of_room()),
+                    result => result
+                }
+            },
+            result => result
+        }
+    }
+}
+
+#[derive(Debug,PartialEq,Clone)]
 pub struct ExecStats {
     // + - & | << >> ^ and also register copies
     pub fast_alu_ops: u64,
-    // * /
+    // * / divmod
     pub slow_alu_ops: u64,
     pub conditional_branches: u64,
     pub unconditional_branches: u64,
-    // Currently we'll "ammortize" argument passing into a general call cost
+    // By default we "ammortize" argument passing into a general call cost;
+    // with RunOptions::charge_arg_passing each argument (receiver included) also costs a fast op, counted here
     pub calls: u64,
+    pub arg_passes: u64,
     pub rets: u64,
     pub mem_reads: u64,
     pub mem_writes: u64,
+    // Volume of memory traffic; every access is currently a full 8-byte word
+    pub bytes_read: u64,
+    pub bytes_written: u64,
     // TODO: In the future we may want to track sizes of individual allocations
     pub allocs: u64,
     // Recall: we only print ints, not strings, so it's fixed-cost
     pub prints: u64,
-    pub phis: u64
+    pub phis: u64,
+    // Garbage collections run; not charged as cycles, since the collector isn't the program's own work
+    pub gcs: u64,
+    // Objects the collector relocated, keyed by number of fields, to see whether big or small objects dominate copying
+    pub gc_copies_by_size: BTreeMap<u64,u64>,
+    // Cycles charged by `;@ cost` declarations on entering blocks. Not an event, so it doesn't count toward steps, and
+    // the cost model doesn't scale it. Declared costs can be anything up to u64::MAX, so this saturates rather than wrap.
+    pub block_cost: u64
 }
 impl ExecStats {
     fn fast_op(&mut self) {
@@ -378,6 +1462,12 @@ impl ExecStats {
     fn slow_op(&mut self) {
         self.slow_alu_ops = self.slow_alu_ops + 1
     }
+    fn op(&mut self, class: OpClass) {
+        match class {
+            OpClass::Fast => self.fast_op(),
+            OpClass::Slow => self.slow_op()
+        }
+    }
     fn cond(&mut self) {
         self.conditional_branches = self.conditional_branches + 1
     }
@@ -387,14 +1477,19 @@ impl ExecStats {
     fn call(&mut self) {
         self.calls = self.calls + 1
     }
+    fn arg_pass(&mut self) {
+        self.arg_passes = self.arg_passes + 1
+    }
     fn ret(&mut self) {
         self.rets = self.rets + 1
     }
     fn read(&mut self) {
-        self.mem_reads = self.mem_reads + 1
+        self.mem_reads = self.mem_reads + 1;
+        self.bytes_read = self.bytes_read + 8
     }
     fn write(&mut self) {
-        self.mem_writes = self.mem_writes + 1
+        self.mem_writes = self.mem_writes + 1;
+        self.bytes_written = self.bytes_written + 8
     }
     fn alloc(&mut self) {
         self.allocs = self.allocs + 1
@@ -405,18 +1500,752 @@ impl ExecStats {
     fn phi(&mut self) {
         self.phis = self.phis + 1
     }
+    fn gc(&mut self) {
+        self.gcs += 1
+    }
+    fn gc_copy(&mut self, fields: u64) {
+        *self.gc_copies_by_size.entry(fields).or_insert(0) += 1
+    }
     pub fn new() -> ExecStats {
-        ExecStats { allocs: 0, calls: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, prints: 0, rets: 0 }
+        ExecStats { allocs: 0, calls: 0, arg_passes: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, bytes_read: 0, bytes_written: 0, prints: 0, rets: 0, gcs: 0, gc_copies_by_size: BTreeMap::new(), block_cost: 0 }
+    }
+}
+// One counter per line, as the trace labels them
+impl fmt::Display for ExecStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (label, n) in self.counters() {
+            writeln!(f, "{}: {}", label, n)?;
+        }
+        writeln!(f, "block_cost: {}", self.block_cost)?;
+        writeln!(f, "gc: {}", self.gcs)?;
+        write!(f, "gc_copies_by_size:")?;
+        if self.gc_copies_by_size.is_empty() {
+            write!(f, " none")?;
+        }
+        for (fields, n) in self.gc_copies_by_size.iter() {
+            write!(f, " {}x{} fields", n, fields)?;
+        }
+        writeln!(f)
+    }
+}
+impl Default for ExecStats {
+    fn default() -> ExecStats {
+        ExecStats::new()
+    }
+}
+impl ExecStats {
+    // Every counter, labelled, in a fixed order. Labels are what the annotated trace prints.
+    fn counters(&self) -> [(&'static str,u64); 12] {
+        [("fast_op", self.fast_alu_ops), ("slow_op", self.slow_alu_ops),
+         ("cond_branch", self.conditional_branches), ("uncond_branch", self.unconditional_branches),
+         ("call", self.calls), ("arg_pass", self.arg_passes), ("ret", self.rets),
+         ("mem_read", self.mem_reads), ("mem_write", self.mem_writes),
+         ("alloc", self.allocs), ("print", self.prints), ("phi", self.phis)]
+    }
+    /// Every counted event summed, as a rough count of steps taken: an instruction that both computes and touches
+    /// memory counts once for each
+    pub fn steps(&self) -> u64 {
+        self.counters().iter().map(|(_,n)| n).sum()
+    }
+    /// Counts accumulated since an earlier snapshot of the same run
+    pub fn since(&self, earlier: &ExecStats) -> ExecStats {
+        ExecStats {
+            fast_alu_ops: self.fast_alu_ops - earlier.fast_alu_ops,
+            slow_alu_ops: self.slow_alu_ops - earlier.slow_alu_ops,
+            conditional_branches: self.conditional_branches - earlier.conditional_branches,
+            unconditional_branches: self.unconditional_branches - earlier.unconditional_branches,
+            calls: self.calls - earlier.calls,
+            arg_passes: self.arg_passes - earlier.arg_passes,
+            rets: self.rets - earlier.rets,
+            mem_reads: self.mem_reads - earlier.mem_reads,
+            mem_writes: self.mem_writes - earlier.mem_writes,
+            bytes_read: self.bytes_read - earlier.bytes_read,
+            bytes_written: self.bytes_written - earlier.bytes_written,
+            allocs: self.allocs - earlier.allocs,
+            prints: self.prints - earlier.prints,
+            phis: self.phis - earlier.phis,
+            gcs: self.gcs - earlier.gcs,
+            gc_copies_by_size: self.gc_copies_by_size.iter()
+                .map(|(size,n)| (*size, n - earlier.gc_copies_by_size.get(size).copied().unwrap_or(0)))
+                .filter(|(_,n)| *n > 0).collect(),
+            block_cost: self.block_cost - earlier.block_cost,
+        }
+    }
+    /// Check every counter (and the collection count, block costs, byte counts, and objects copied of each size) is
+    /// within frac of the expected one, e.g. 0.1 for "within 10%". Bounds are inclusive, so an expected 0 only accepts 0.
+    /// On failure, lists each counter outside its band, with the same labels as the annotated trace; copies are labelled
+    /// gc_copies[size], for every size copied in either run.
+    pub fn within_tolerance(&self, expected: &ExecStats, frac: f64) -> Result<(), Vec<String>> {
+        let graded = |s: &ExecStats| -> Vec<(String,u64)> {
+            let totals = [("gc", s.gcs), ("block_cost", s.block_cost), ("bytes_read", s.bytes_read), ("bytes_written", s.bytes_written)];
+            let sizes : BTreeSet<u64> = self.gc_copies_by_size.keys().chain(expected.gc_copies_by_size.keys()).copied().collect();
+            s.counters().into_iter().chain(totals).map(|(label, n)| (label.to_string(), n))
+                .chain(sizes.into_iter().map(|size| (format!("gc_copies[{}]", size), s.gc_copies_by_size.get(&size).copied().unwrap_or(0))))
+                .collect()
+        };
+        let outside : Vec<String> = graded(self).into_iter().zip(graded(expected)).filter_map(|((label, got), (_, want))| {
+            let slack = want as f64 * frac;
+            if (got as f64 - want as f64).abs() > slack {
+                Some(format!("{}: {} is outside {} \u{b1} {}%", label, got, want, frac * 100.0))
+            } else {
+                None
+            }
+        }).collect();
+        if outside.is_empty() { Ok(()) } else { Err(outside) }
+    }
+    /// Total cost of the counted events, weighted by a cost model
+    pub fn cycles(&self, model: &CostModel) -> u64 {
+        (self.fast_alu_ops * model.fast_op
+            + self.slow_alu_ops * model.slow_op
+            + self.conditional_branches * model.cond_branch
+            + self.unconditional_branches * model.uncond_branch
+            + self.calls * model.call
+            + self.arg_passes * model.arg_pass
+            + self.rets * model.ret
+            + self.mem_reads * model.mem_read
+            + self.mem_writes * model.mem_write
+            + self.allocs * model.alloc
+            + self.prints * model.print
+            + self.phis * model.phi)
+            .saturating_add(self.block_cost)
     }
 }
 
-fn expr_val<'a>(l:&Locals<'a>, globs:&Globals<'a>, prog:&IRProgram<'a>, e:&IRExpr<'a>) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
-    // TODO need globals and program to detect invalid block and global references,
-    // and to map global names to locations
+/// Cycle cost of each kind of event ExecStats counts. These are rough, teaching-oriented numbers,
+/// not a model of any real machine.
+#[derive(Debug,PartialEq,Clone)]
+pub struct CostModel {
+    pub fast_op: u64,
+    pub slow_op: u64,
+    pub cond_branch: u64,
+    pub uncond_branch: u64,
+    pub call: u64,
+    // Each argument passed is also charged as a fast op, so this is only the surcharge on top of that
+    pub arg_pass: u64,
+    pub ret: u64,
+    pub mem_read: u64,
+    pub mem_write: u64,
+    pub alloc: u64,
+    pub print: u64,
+    pub phi: u64,
+}
+impl CostModel {
+    pub fn new() -> CostModel {
+        CostModel { fast_op: 1, slow_op: 3, cond_branch: 2, uncond_branch: 1, call: 5, arg_pass: 0, ret: 5,
+                    mem_read: 4, mem_write: 4, alloc: 10, print: 1, phi: 1 }
+    }
+}
+impl Default for CostModel {
+    fn default() -> CostModel {
+        CostModel::new()
+    }
+}
+
+/// Which ExecStats counter a binary operator is charged to
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+pub enum OpClass {
+    Fast,
+    Slow,
+}
+impl OpClass {
+    /// The usual class: multiply and divide are slow, everything else is fast
+    pub fn default_for(op: BinOp) -> OpClass {
+        match op {
+            BinOp::Mul | BinOp::Div => OpClass::Slow,
+            BinOp::Add | BinOp::Sub | BinOp::Shl | BinOp::Shr | BinOp::And | BinOp::Or | BinOp::Xor
+            | BinOp::Lt | BinOp::Gt | BinOp::Eq => OpClass::Fast,
+        }
+    }
+    // The class op is charged as under opts
+    fn of(opts: &RunOptions, op: BinOp) -> OpClass {
+        if opts.op_classes.is_empty() {
+            return OpClass::default_for(op);
+        }
+        opts.op_classes.get(&op).copied().unwrap_or_else(|| OpClass::default_for(op))
+    }
+}
+
+/// Knobs for a single run. Start from RunOptions::new() and override the fields you care about.
+#[derive(Debug,Clone)]
+pub struct RunOptions {
+    pub tracing: bool,
+    /// Seed for the generator behind rand()
+    pub seed: u64,
+    /// Charge a fast op per argument moved into a callee instead of folding it into the call cost
+    pub charge_arg_passing: bool,
+    /// Charge a memory read for every `$K` operand, as loading from a constant pool would, instead of treating
+    /// constants as free as immediates
+    pub charge_const_loads: bool,
+    /// If set, the only blocks `call` may target. Anything else is assumed to be the middle of a function,
+    /// reachable only by jumps that set up its phis. None allows calls to any block.
+    pub entry_blocks: Option<HashSet<String>>,
+    /// Which instructions produce trace lines when tracing
+    pub trace_filter: TraceFilter,
+    /// Diagnostic mode: recoverable errors (reading an uninitialized variable or unallocated memory) are
+    /// recorded and read as 0 instead of stopping the run, so one run can report every such mistake
+    pub best_effort: bool,
+    /// With best_effort, a `fail` ends the run the way returning from main would, so end-of-run reporting such as
+    /// print_final_globals still happens. Nothing is recovered: the result is Err(Failed), since there's no value to
+    /// hand back, alongside the stats and warnings gathered up to the fail.
+    pub continue_past_fail: bool,
+    /// Weights used to annotate trace lines with the cycles each instruction cost
+    pub cost_model: CostModel,
+    /// Operators to charge differently from OpClass::default_for, e.g. shifts as slow ops for a target without a
+    /// barrel shifter. Operators not listed keep their usual class; divmod is always slow.
+    pub op_classes: HashMap<BinOp,OpClass>,
+    /// Emit trace warnings (whether or not tracing is on) when a freshly allocated pointer is used in arithmetic,
+    /// or an address outside every global and object is dereferenced. These are heuristics: an integer that
+    /// happens to equal an object address looks like a pointer.
+    pub warn_pointer_confusion: bool,
+    /// Warn when < or > compares two object addresses. Which object comes first is down to allocation order, and a
+    /// collection can reorder objects, so the answer isn't something a program can rely on. == is fine.
+    pub warn_pointer_ordering: bool,
+    /// Record the sequence of blocks entered, keeping at most this many entries. None records nothing.
+    pub record_path: Option<usize>,
+    /// Stop with OverwroteGCHeader as soon as a write lands on an object's GC metadata, rather than letting it
+    /// corrupt a later collection. A store straight to the slotmap is still allowed, since that's how programs set it.
+    pub guard_gc_headers: bool,
+    /// Check each slotmap against its object's size, both when a program stores it and when the collector reads it.
+    /// Bits past the last field, or an object too big for a 64-bit slotmap, are reported as SlotmapSizeMismatch
+    /// instead of silently mis-tracing.
+    pub check_slotmaps: bool,
+    /// Words of unallocated guard zone left in front of every allocation; touching one is a GuardZoneAccess.
+    /// Bigger zones catch bigger overruns; 0 turns guards off and packs objects back to back (header, then fields), for
+    /// the densest possible heap. Without guards an off-by-one read or write silently lands in the neighboring object.
+    /// Guard words never count against the slot cap, only against the address space.
+    pub guard_words: u64,
+    /// Whether to reject calls that return a pointer into memory the GC has reclaimed
+    pub check_returned_refs: bool,
+    /// Whether globals count against the memory cap along with the heap, making it a ceiling on all memory.
+    /// Globals alone exceeding the cap is then an OutOfMemory before main starts.
+    pub cap_includes_globals: bool,
+    /// When tracing, follow each instruction that writes locals with a `%x: <old> => <new>` line per variable.
+    /// Variables with no earlier value show as <unset>.
+    pub trace_assignments: bool,
+    /// Allow code pointers to be compared with == and jumped to indirectly (jump %t), for jump and function tables.
+    /// Arithmetic on code pointers is still an error.
+    pub code_pointer_tables: bool,
+    /// Guarantee that repeated runs produce identical results, stats, and trace text, by doing anything whose order
+    /// shows up in them (listing globals, visiting GC roots) in sorted order instead of hash order
+    pub deterministic: bool,
+    /// Stamp each object with the number of collections run before it was allocated, so Memory::heap_objects can
+    /// report how many collections each has survived (e.g., for generational-GC experiments)
+    pub track_ages: bool,
+    /// In the GC modes, stop with UseBeforeHeaderInit when a program reads or writes an object's fields before it has
+    /// stored the object's slotmap. Objects with no pointer fields need an explicit slotmap of 0.
+    pub check_header_init: bool,
+    /// Split each slotmap into strong and weak halves: bit i (for i < 32) marks field i as a pointer the collector
+    /// traces, and bit 32 + i marks it as a weak reference, which doesn't keep its target alive. After a collection a
+    /// weak field points to its target's new copy if something else kept the target alive, and is 0 if not.
+    /// Objects can then describe at most 32 fields.
+    pub weak_refs: bool,
+    /// Stop the run with Stop::AtCondition as soon as an instruction sets this variable (in whatever frame is
+    /// running) to this value. It carries the step count at that point.
+    pub run_until: Option<(String,u64)>,
+    /// Record every store to an object field whose value is never read, either because another store replaces it or
+    /// because nothing reads it before the object is collected or the run ends. See ExecResult::dead_stores.
+    /// Costs a map update on every memory access, so it's off by default.
+    pub track_dead_stores: bool,
+    /// Record every object field the program never writes between allocating the object and the object being
+    /// collected or the run ending. The fill word a field starts with doesn't count as a write. See
+    /// ExecResult::uninit_fields.
+    pub track_uninit_fields: bool,
+    /// Every this many alloc instructions (1 for every one), once the new object is assigned, count the slots reachable
+    /// from every frame's variables (see Memory::reachable_count) and keep the largest count in ExecResult::peak_live.
+    /// Each sample walks everything live, so this is slow on big heaps; sample less often to trade accuracy for speed.
+    /// Slotmaps are only followed with a cap, so an unlimited run counts just the objects variables point to directly.
+    pub sample_live_every: Option<u64>,
+    /// Instructions to stop at, each recorded in ExecResult::breakpoint_hits (and traced, when tracing) just before it runs
+    pub breakpoints: HashSet<Breakpoint>,
+    /// Treat getelt/setelt offsets (narrow forms included) as signed 64-bit numbers, so a negative offset indexes
+    /// backwards from the base, e.g. -1 (written 18446744073709551615) reaches the slotmap in front of an object.
+    /// The address must still be allocated and in bounds, as for any access.
+    pub signed_offsets: bool,
+    /// Fail with LiveObjectLimitExceeded on an allocation that would make more than this many objects live at once,
+    /// counting objects from allocation until the collector reclaims them (see Memory::live_objects). In the GC modes,
+    /// reaching the limit triggers a collection first, as running out of slots does.
+    pub max_live_objects: Option<u64>,
+    /// After a successful run, print every global slot's final value as `@name[index] = value` (see
+    /// Memory::global_slots), or add those lines to ExecResult::trace when evaluating. Tracing needn't be on.
+    pub print_final_globals: bool,
+    /// Cache calls to blocks declared `;@ pure` by their arguments, so a repeat call returns the earlier result without
+    /// running the body. A cache hit costs one fast op instead of a call. Each declared block must pass
+    /// validate::purity_violation, or the run stops with NotPure before it starts.
+    pub memoize_pure: bool,
+    /// What each field of a newly allocated object holds until the program writes it, e.g. 0xDEADBEEF so a field read
+    /// too early stands out in a trace or dump. Headers are still set up as usual. In the GC modes, the collector treats a
+    /// field the slotmap marks as a pointer as null until the program writes it, copying the fill word without following it.
+    pub fill_word: u64,
+    /// Record a Snapshot in ExecResult::snapshots at the start of the run and then every this many steps. Instructions
+    /// can cost several steps, so each lands on the first instruction boundary at or past its multiple.
+    pub snapshot_every: Option<u64>,
+    /// Stop the run with Stop::AtStep at the first instruction boundary at or past this many steps, recording the
+    /// state there as the last of ExecResult::snapshots
+    pub stop_at_step: Option<u64>,
+    /// Fail with BlockFuelExceeded when any one block is entered more than this many times, to catch a loop that never
+    /// ends without also capping how long a program that does finish may run
+    pub block_fuel: Option<u64>,
+    /// In the GC modes, stop with StoringCodePtrInTracedField when a code pointer is stored into a field the object's
+    /// slotmap marks as a pointer, or a slotmap is stored that marks a field already holding one, instead of leaving
+    /// the collector to fail later with BadGCField
+    pub check_code_stores: bool,
+    /// Collect what the program prints in ExecResult::output instead of writing it to stdout, e.g. so an autograder
+    /// can compare it exactly. Traces and warnings are unaffected.
+    pub capture_output: bool,
+    /// Refuse, with AllocationTooLarge, any allocation that would reach past this address, so a runaway alloc fails
+    /// fast instead of filling the host's memory. Allocations that would wrap around the address space are always
+    /// refused.
+    pub max_address: Option<u64>,
+}
+impl RunOptions {
+    pub fn new() -> RunOptions {
+        RunOptions { tracing: false, seed: 0, charge_arg_passing: false, charge_const_loads: false, entry_blocks: None, trace_filter: TraceFilter::new(), best_effort: false, continue_past_fail: false, cost_model: CostModel::new(), op_classes: HashMap::new(), warn_pointer_confusion: false, warn_pointer_ordering: false, record_path: None, guard_gc_headers: false, check_slotmaps: false, guard_words: 1, check_returned_refs: false, cap_includes_globals: false, trace_assignments: false, code_pointer_tables: false, deterministic: false, track_ages: false, check_header_init: false, weak_refs: false, run_until: None, track_dead_stores: false, breakpoints: HashSet::new(), signed_offsets: false, max_live_objects: None, print_final_globals: false, memoize_pure: false, fill_word: 0, snapshot_every: None, stop_at_step: None, check_code_stores: false, capture_output: false, max_address: None, track_uninit_fields: false, sample_live_every: None, block_fuel: None }
+    }
+}
+impl Default for RunOptions {
+    fn default() -> RunOptions {
+        RunOptions::new()
+    }
+}
+
+/// A point to stop at: the instruction at a 0-based index within a block. The index one past the last statement
+/// names the block's control transfer.
+#[derive(Debug,PartialEq,Eq,Hash,Clone)]
+pub struct Breakpoint {
+    pub block: String,
+    pub index: usize,
+}
+impl Breakpoint {
+    pub fn new(block: &str, index: usize) -> Breakpoint {
+        Breakpoint { block: block.to_string(), index }
+    }
+    /// Read the `block:index` form, e.g. `loop_body:3`
+    pub fn parse(s: &str) -> Option<Breakpoint> {
+        let (block, index) = s.rsplit_once(':')?;
+        if block.is_empty() {
+            return None;
+        }
+        Some(Breakpoint::new(block, index.parse().ok()?))
+    }
+}
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.block, self.index)
+    }
+}
+
+/// A breakpoint reached during a run, with the state just before the instruction there ran
+#[derive(Debug,PartialEq,Clone)]
+pub struct BreakpointHit<'a> {
+    pub block: &'a str,
+    pub index: usize,
+    /// ExecStats::steps() so far
+    pub step: u64,
+    /// The current frame's variables
+    pub locals: Locals<'a>,
+}
+
+/// The state of a run just before the instruction at block:index ran (index instrs.len() is the control transfer),
+/// as recorded by RunOptions::snapshot_every and stop_at_step
+#[derive(Debug,PartialEq,Clone)]
+pub struct Snapshot<'a> {
+    pub block: &'a str,
+    pub index: usize,
+    /// ExecStats::steps() so far
+    pub step: u64,
+    /// Frames on the stack, 1 in the entry block
+    pub depth: usize,
+    /// The current frame's variables
+    pub locals: Locals<'a>,
+    pub memory: MemorySnapshot<'a>,
+}
+
+// A frame's place in the run: its block, the index of the instruction it's at, and the block it was entered from
+type FramePos<'a> = (&'a str, usize, Option<&'a str>);
+
+// Everything TimeTravel needs to carry a run on from one of its snapshots, rather than replaying it from the start
+#[derive(Debug,Clone)]
+struct ResumePoint<'a> {
+    // Whether the snapshot was one of the every-K-steps boundaries, rather than just where the run was stopped
+    boundary: bool,
+    // Each frame's position, outermost first; all but the last are at the call they're waiting on
+    frames: Vec<FramePos<'a>>,
+    stack: Vec<Locals<'a>>,
+    memory: Memory<'a>,
+    globals: Globals<'a>,
+    stats: ExecStats,
+    rng: u64,
+    block_entries: HashMap<&'a str,u64>,
+    cast_vars: HashSet<(usize,&'a str)>,
+    memo: HashMap<(&'a str,Vec<VirtualVal<'a>>),Vec<VirtualVal<'a>>>,
+    next_snapshot: u64,
+}
+
+/// Restricts tracing to instructions in particular blocks and/or of particular kinds, and/or to the first few times
+/// each block runs. A None field doesn't filter on that criterion; an instruction is traced only if it passes them all.
+#[derive(Debug,Clone,Default)]
+pub struct TraceFilter {
+    pub blocks: Option<HashSet<String>>,
+    pub kinds: Option<HashSet<InstrKind>>,
+    /// Trace only this many entries into each block, so long loops don't flood the trace. The first entry past the
+    /// limit traces a note that the block's tracing is suppressed from then on.
+    pub first_entries: Option<u64>,
+}
+impl TraceFilter {
+    pub fn new() -> TraceFilter {
+        TraceFilter { blocks: None, kinds: None, first_entries: None }
+    }
+    fn is_active(&self) -> bool {
+        self.blocks.is_some() || self.kinds.is_some()
+    }
+    fn admits_block(&self, bname: &str) -> bool {
+        self.blocks.as_ref().is_none_or(|bs| bs.contains(bname))
+    }
+    fn admits(&self, bname: &str, kind: InstrKind) -> bool {
+        self.admits_block(bname) && self.kinds.as_ref().is_none_or(|ks| ks.contains(&kind))
+    }
+}
+
+// Block names interned to small ids for a run, with every jump, if, and ifsigned target resolved ahead of time, so
+// taking a branch is an index rather than a string hash. An empty table (or an unresolved target) just means falling
+// back to looking the name up in the program, which is also where a missing block gets reported.
+#[derive(Default)]
+struct BlockTable<'a> {
+    ids: HashMap<&'a str,usize>,
+    blocks: Vec<&'a BasicBlock<'a>>,
+    // Each block's static targets, in the order its control transfer names them
+    succs: Vec<Vec<Option<usize>>>,
+}
+impl <'a> BlockTable<'a> {
+    fn new(prog: &'a IRProgram<'a>) -> BlockTable<'a> {
+        let mut blocks : Vec<&'a BasicBlock<'a>> = prog.blocks.values().collect();
+        blocks.sort_by_key(|b| b.name);
+        let ids : HashMap<&'a str,usize> = blocks.iter().enumerate().map(|(id,b)| (b.name, id)).collect();
+        let succs = blocks.iter().map(|b| {
+            let targets = match &b.next {
+                ControlXfer::Jump { block } => vec![*block],
+                ControlXfer::If { tblock, fblock, .. } => vec![*tblock, *fblock],
+                ControlXfer::IfSigned { neg_block, zero_block, pos_block, .. } => vec![*neg_block, *zero_block, *pos_block],
+                _ => vec![]
+            };
+            targets.into_iter().map(|t| ids.get(t).copied()).collect()
+        }).collect();
+        BlockTable { ids, blocks, succs }
+    }
+    fn id(&self, bname: &str) -> Option<usize> {
+        self.ids.get(bname).copied()
+    }
+    // Target `which` of block `cur`'s control transfer, if both are known
+    fn succ(&self, cur: Option<usize>, which: usize) -> Option<(usize,&'a BasicBlock<'a>)> {
+        let id = (*self.succs.get(cur?)?.get(which)?)?;
+        Some((id, self.blocks[id]))
+    }
+}
+
+// What every frame of a run shares: the program, how it's being run, and what it has done so far
+struct Ctx<'a,'r> {
+    prog: &'a IRProgram<'a>,
+    opts: &'r RunOptions,
+    st: &'r mut RunState<'a>,
+    cycles: &'r mut ExecStats,
+}
+
+// Mutable state that lives for a whole run, across all stack frames
+struct RunState<'a> {
+    // Interned block and variable names (filled in by exec_entry once the program is known)
+    blocks: BlockTable<'a>,
+    // SplitMix64 state
+    rng: u64,
+    // Where trace lines go: None prints them as they happen, otherwise they're collected for the caller
+    trace: Option<Vec<String>>,
+    // Errors papered over in best-effort mode, in the order they happened
+    recovered: Vec<RuntimeError<'a>>,
+    // Trace line for the instruction in progress, held back until we know what it cost, with the stats from before it ran
+    pending_trace: Option<(String,ExecStats)>,
+    // Warnings and assignment notes raised by the instruction in progress, emitted right after its trace line
+    pending_warnings: Vec<String>,
+    // Warnings raised so far, in order (dead stores and unwritten fields are left to Memory until the end)
+    warnings: Vec<Warning<'a>>,
+    // Blocks entered so far, and whether we stopped recording because the path hit its length limit
+    path: Vec<&'a str>,
+    path_truncated: bool,
+    // Stats as of each checkpoint instruction reached, in order
+    checkpoints: Vec<(&'a str,ExecStats)>,
+    // Variables (by frame depth) that hold integers explicitly cast from pointers, exempt from pointer-confusion warnings
+    cast_vars: HashSet<(usize,&'a str)>,
+    // Block of the instruction in progress, and for each collected trace line the block it came from
+    cur_block: Option<&'a str>,
+    // Index of the instruction in progress in cur_block, and the block control arrived at cur_block from
+    cur_at: (usize,Option<&'a str>),
+    trace_blocks: Vec<Option<&'a str>>,
+    // Decides where each alloc puts its object
+    allocator: Box<dyn Allocator<'a> + 'a>,
+    // Asked before every instruction whether the host wants the run stopped
+    should_cancel: Option<&'a dyn Fn() -> bool>,
+    // Breakpoints reached so far, in order
+    breakpoint_hits: Vec<BreakpointHit<'a>>,
+    // Times each block has been entered, for TraceFilter::first_entries and RunOptions::block_fuel
+    block_entries: HashMap<&'a str,u64>,
+    // Emptied frames from calls that have returned, kept so the next call reuses their storage instead of allocating
+    spare_frames: Vec<Locals<'a>>,
+    // Snapshots taken so far, and the step at or after which the next one is due (RunOptions::snapshot_every)
+    snapshots: Vec<Snapshot<'a>>,
+    next_snapshot: u64,
+    // For TimeTravel: a ResumePoint alongside each snapshot (None when not wanted), and for that the position of every
+    // frame waiting on a call, outermost first
+    resume_points: Option<Vec<ResumePoint<'a>>>,
+    call_sites: Vec<FramePos<'a>>,
+    // Frames a resumed run has still to re-enter, innermost first (see exec_resumed)
+    resuming: Vec<FramePos<'a>>,
+    // RunOptions::memoize_pure: the blocks whose calls are cached, and the results so far by block and arguments
+    pure_blocks: HashSet<&'a str>,
+    memo: HashMap<(&'a str,Vec<VirtualVal<'a>>),Vec<VirtualVal<'a>>>,
+    // RunOptions::capture_output: everything printed so far, one value per line
+    output: Option<String>,
+    // Value of every constant, resolved once by prepare_run so ConstRef operands don't search prog.globals
+    consts: HashMap<&'a str,u64>,
+    // Extra cycles charged on entering each block with a `;@ cost` declaration
+    block_costs: HashMap<&'a str,u64>,
+    // RunOptions::sample_live_every: peaks so far, and allocs since the last sample
+    peak_live: Option<LivePeak>,
+    allocs_unsampled: u64,
+    // Why the run ended early, if it was stopped rather than returning or failing
+    stopped: Option<Stop<'a>>,
+}
+impl <'a> RunState<'a> {
+    fn new(opts: &RunOptions) -> RunState<'a> {
+        RunState { blocks: BlockTable::default(), rng: opts.seed, trace: None, recovered: vec![], pending_trace: None, pending_warnings: vec![], warnings: vec![], path: vec![], path_truncated: false, checkpoints: vec![], cast_vars: HashSet::new(), cur_block: None, cur_at: (0, None), trace_blocks: vec![], allocator: Box::new(BumpAllocator), should_cancel: None, breakpoint_hits: vec![], block_entries: HashMap::new(), spare_frames: vec![], snapshots: vec![], next_snapshot: 0, resume_points: None, call_sites: vec![], resuming: vec![], pure_blocks: HashSet::new(), memo: HashMap::new(), output: if opts.capture_output { Some(String::new()) } else { None }, consts: HashMap::new(), block_costs: HashMap::new(), peak_live: if opts.sample_live_every.is_some() { Some(LivePeak::default()) } else { None }, allocs_unsampled: 0, stopped: None }
+    }
+    // After an alloc: note how much is allocated, and every so often how much of it the stack can still reach
+    fn sample_live(&mut self, m: &Memory<'a>, stack: &[Locals<'a>], every: u64) {
+        let Some(peak) = self.peak_live.as_mut() else { return };
+        peak.allocated = peak.allocated.max(m.slots_alloced);
+        self.allocs_unsampled += 1;
+        if self.allocs_unsampled < every {
+            return;
+        }
+        self.allocs_unsampled = 0;
+        peak.live = peak.live.max(m.reachable_count(&stack_roots(stack)).1);
+    }
+    // An empty frame for a call's locals
+    fn new_frame(&mut self) -> Locals<'a> {
+        self.spare_frames.pop().unwrap_or_default()
+    }
+    // The key a call to target with these bound formals is cached under, if its calls are memoized
+    fn memo_key(&self, vars: &VarIds, target: &'a BasicBlock<'a>, calleevars: &Locals<'a>) -> Option<(&'a str,Vec<VirtualVal<'a>>)> {
+        if !self.pure_blocks.contains(target.name) {
+            return None;
+        }
+        Some((target.name, target.formals.iter().filter_map(|f| calleevars.lookup(vars.id(f), f).copied()).collect()))
+    }
+    // Hand back a returned call's frame. Its bindings are dropped here, so nothing in it is visible to the next call.
+    fn recycle_frame(&mut self, mut frame: Locals<'a>) {
+        frame.clear();
+        self.spare_frames.push(frame);
+    }
+    fn check_cancelled(&self) -> Option<Stop<'a>> {
+        match self.should_cancel {
+            Some(f) if f() => Some(Stop::Cancelled),
+            _ => None
+        }
+    }
+    // Record a hit if a breakpoint is set on this instruction (index instrs.len() is the control transfer)
+    fn check_breakpoint(&mut self, opts: &RunOptions, bname: &'a str, index: usize, locals: &Locals<'a>, cycles: &ExecStats) {
+        if !opts.breakpoints.iter().any(|b| b.block == bname && b.index == index) {
+            return;
+        }
+        let step = cycles.steps();
+        if opts.tracing {
+            self.emit_trace(format!("Breakpoint {}:{} at step {}", bname, index, step));
+        }
+        self.breakpoint_hits.push(BreakpointHit { block: bname, index, step, locals: locals.clone() });
+    }
+    // Take a snapshot here if one is due, and stop if the run has gone as far as it was asked to
+    fn check_snapshot(&mut self, opts: &RunOptions, (bname, index): (&'a str, usize), locs: &[Locals<'a>], m: &Memory<'a>, globs: &Globals<'a>, cycles: &ExecStats) -> Option<Stop<'a>> {
+        let step = cycles.steps();
+        let due = opts.snapshot_every.is_some() && step >= self.next_snapshot;
+        let stop = opts.stop_at_step.is_some_and(|limit| step >= limit);
+        if due || stop {
+            let locals = locs.last().cloned().unwrap_or_default();
+            self.snapshots.push(Snapshot { block: bname, index, step, depth: locs.len(), locals, memory: m.snapshot(globs) });
+        }
+        if let (true, Some(every)) = (due, opts.snapshot_every) {
+            let every = every.max(1);
+            self.next_snapshot = (step / every + 1) * every;
+        }
+        if let Some(points) = self.resume_points.as_mut().filter(|_| due || stop) {
+            let mut frames = self.call_sites.clone();
+            frames.push((bname, index, self.cur_at.1));
+            points.push(ResumePoint {
+                boundary: due, frames, stack: locs.to_vec(), memory: m.clone(), globals: globs.clone(), stats: cycles.clone(),
+                rng: self.rng, block_entries: self.block_entries.clone(), cast_vars: self.cast_vars.clone(),
+                memo: self.memo.clone(), next_snapshot: self.next_snapshot
+            });
+        }
+        if stop {
+            return Some(Stop::AtStep { step });
+        }
+        None
+    }
+    fn enter_block(&mut self, opts: &RunOptions, bname: &'a str) -> Result<(),RuntimeError<'a>> {
+        if let Some(limit) = opts.record_path {
+            if self.path.len() < limit {
+                self.path.push(bname);
+            } else {
+                self.path_truncated = true;
+            }
+        }
+        if opts.trace_filter.first_entries.is_none() && opts.block_fuel.is_none() {
+            return Ok(());
+        }
+        let entries = self.block_entries.entry(bname).or_insert(0);
+        *entries += 1;
+        let entries = *entries;
+        if let Some(limit) = opts.trace_filter.first_entries {
+            if entries == limit + 1 && opts.tracing && opts.trace_filter.admits_block(bname) {
+                self.emit_trace(format!("... {} suppressed after {} entries", bname, limit));
+            }
+        }
+        match opts.block_fuel {
+            Some(limit) if entries > limit => Err(RuntimeError::BlockFuelExceeded { block: bname, limit }),
+            _ => Ok(())
+        }
+    }
+    // Whether to trace an instruction of this kind in this block
+    fn traces(&self, opts: &RunOptions, bname: &str, kind: InstrKind) -> bool {
+        opts.tracing && opts.trace_filter.admits(bname, kind)
+            && opts.trace_filter.first_entries.is_none_or(|limit| self.block_entries.get(bname).is_none_or(|n| *n <= limit))
+    }
+    // Start tracing an instruction. The line goes out (annotated) at the next flush_trace.
+    fn begin_trace(&mut self, opts: &RunOptions, line: String, cycles: &ExecStats) {
+        self.flush_trace(opts, cycles);
+        self.pending_trace = Some((line, cycles.clone()));
+    }
+    // Emit the pending trace line, if any, annotated with everything charged since it began.
+    // Called once the instruction is done, or just before anything else would be printed
+    // (a call's callee running, a print's output), so lines stay in execution order.
+    fn flush_trace(&mut self, opts: &RunOptions, cycles: &ExecStats) {
+        if let Some((line, before)) = self.pending_trace.take() {
+            let mut note = String::new();
+            for ((label, now), (_, then)) in cycles.counters().iter().zip(before.counters().iter()) {
+                match now - then {
+                    0 => (),
+                    1 => write!(note, "{}, ", label).unwrap(),
+                    n => write!(note, "{} x{}, ", label, n).unwrap()
+                }
+            }
+            let cost = cycles.cycles(&opts.cost_model) - before.cycles(&opts.cost_model);
+            self.emit_trace(format!("{}    [{}+{} cycles]", line, note, cost));
+        }
+        for w in std::mem::take(&mut self.pending_warnings) {
+            self.emit_trace(w);
+        }
+    }
+    // RunOptions::guard_gc_headers: refuse a write onto header metadata. Only store may touch the slotmap.
+    fn check_header_write(&self, opts: &RunOptions, m: &Memory<'a>, addr: u64, i: &IRStatement<'a>) -> Result<(),RuntimeError<'a>> {
+        if !opts.guard_gc_headers {
+            return Ok(());
+        }
+        match (m.header_slot(addr), i) {
+            (None, _) => Ok(()),
+            (Some(2), IRStatement::Store { .. }) => Ok(()),
+            (Some(_), _) => Err(RuntimeError::OverwroteGCHeader { addr })
+        }
+    }
+    // Soft check (RunOptions::warn_pointer_confusion): a fresh pointer used in arithmetic (an Op) that isn't plausibly
+    // address math. Comparisons, masking (tag tests), and adding or subtracting whole words are all fine.
+    // Operands that came from ptrtoint are integers on purpose.
+    fn check_arith(&mut self, opts: &RunOptions, m: &Memory<'a>, depth: usize, (e1,n1): (&IRExpr<'a>,u64), (e2,n2): (&IRExpr<'a>,u64), i: &'a IRStatement<'a>) {
+        if !opts.warn_pointer_confusion {
+            return;
+        }
+        let is_cast = |e: &IRExpr<'a>| match e {
+            IRExpr::Var { id } => self.cast_vars.contains(&(depth, *id)),
+            _ => false
+        };
+        let (n1, n2) = (if is_cast(e1) { 0 } else { n1 }, if is_cast(e2) { 0 } else { n2 });
+        let op = match i {
+            IRStatement::Op { op, .. } => *op,
+            _ => return
+        };
+        let suspect = match op {
+            BinOp::Lt | BinOp::Gt | BinOp::Eq | BinOp::And => None,
+            BinOp::Add if m.is_object(n1) && !n2.is_multiple_of(8) => Some(n1),
+            BinOp::Add if m.is_object(n2) && !n1.is_multiple_of(8) => Some(n2),
+            BinOp::Add => None,
+            BinOp::Sub if m.is_object(n1) && (m.is_object(n2) || n2.is_multiple_of(8)) => None,
+            BinOp::Sub if m.is_object(n1) => Some(n1),
+            BinOp::Sub if m.is_object(n2) => Some(n2),
+            BinOp::Sub => None,
+            _ if m.is_object(n1) => Some(n1),
+            _ if m.is_object(n2) => Some(n2),
+            _ => None
+        };
+        if let Some(ptr) = suspect {
+            self.warn(Warning::PointerArithmetic { ptr, block: self.cur_block.unwrap_or_default(), instr: i });
+        }
+    }
+    // Soft check (RunOptions::warn_pointer_ordering): ordering two objects by address
+    fn check_order(&mut self, opts: &RunOptions, m: &Memory<'a>, op: BinOp, n1: u64, n2: u64, i: &'a IRStatement<'a>) {
+        if opts.warn_pointer_ordering && matches!(op, BinOp::Lt | BinOp::Gt) && m.is_object(n1) && m.is_object(n2) {
+            self.warn(Warning::PointerOrdering { left: n1, right: n2, block: self.cur_block.unwrap_or_default(), instr: i });
+        }
+    }
+    // Soft check (RunOptions::warn_pointer_confusion): dereferencing something that points into no global or object
+    fn check_deref(&mut self, opts: &RunOptions, m: &Memory<'a>, addr: u64, i: &'a IRStatement<'a>) {
+        if opts.warn_pointer_confusion && !m.is_known_address(addr) {
+            self.warn(Warning::IntegerAsPointer { addr, block: self.cur_block.unwrap_or_default(), instr: i });
+        }
+    }
+    // Record a warning, and trace it after the instruction that raised it
+    fn warn(&mut self, w: Warning<'a>) {
+        self.pending_warnings.push(format!("WARNING: {}", w));
+        self.warnings.push(w);
+    }
+    // In best-effort mode, log a recoverable error and substitute 0 for the value that couldn't be read
+    fn recover(&mut self, opts: &RunOptions, r: Result<VirtualVal<'a>,RuntimeError<'a>>) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+        match r {
+            Err(e @ RuntimeError::UninitializedVariable { .. }) | Err(e @ RuntimeError::UnallocatedAddressRead { .. }) if opts.best_effort => {
+                self.warn(Warning::Recovered { error: e.clone(), block: self.cur_block.unwrap_or_default() });
+                self.recovered.push(e);
+                Ok(VirtualVal::Data { val: 0 })
+            },
+            _ => r
+        }
+    }
+    // What a print instruction prints, to stdout or (RunOptions::capture_output) the run's output buffer
+    fn emit_output(&mut self, v: VirtualVal<'a>) {
+        match &mut self.output {
+            None => println!("{}", v),
+            Some(out) => writeln!(out, "{}", v).unwrap()
+        }
+    }
+    fn emit_trace(&mut self, line: String) {
+        match &mut self.trace {
+            None => println!("{}", line),
+            Some(lines) => {
+                lines.push(line);
+                self.trace_blocks.push(self.cur_block);
+            }
+        }
+    }
+    // SplitMix64 (Steele, Lea, and Flood), fixed so seeded runs reproduce exactly on every platform
+    fn next_random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+fn expr_val<'a>(l:&Locals<'a>, globs:&Globals<'a>, prog:&IRProgram<'a>, e:&IRExpr<'a>, opts:&RunOptions, st:&mut RunState<'a>) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+    // TODO need globals and program to detect invalid block and global references,
+    // and to map global names to locations
     match e {
         IRExpr::IntLit { val: v } => Ok(VirtualVal::Data { val: u64::from(*v) }),
         // TODO: for now we assume we have infinite registers, so this is "constant"
-        IRExpr::Var { id: n } => read_var(l, n),
+        IRExpr::Var { id: n } => st.recover(opts, read_var(l, &prog.vars, n)),
         IRExpr::BlockRef { bname: b } =>
             match prog.blocks.get(b) {
                 None => Err(RuntimeError::InvalidBlock { bname: b}),
@@ -428,119 +2257,350 @@ fn expr_val<'a>(l:&Locals<'a>, globs:&Globals<'a>, prog:&IRProgram<'a>, e:&IRExp
                 Some(v) => Ok(VirtualVal::Data { val: v.clone()} )
             }
         },
+        IRExpr::ConstRef { id } =>
+            // Outside a run (eval_expr) nothing has been resolved, so fall back to the declarations
+            match st.consts.get(id).copied().or_else(|| prog.constant(id)) {
+                None => Err(RuntimeError::UndefinedConst { name: id }),
+                Some(val) => Ok(VirtualVal::Data { val })
+            },
+    }
+}
+
+// With RunOptions::charge_const_loads, one memory read per constant among an instruction's operands
+fn charge_const_loads(cycles: &mut ExecStats, operands: Vec<&IRExpr>) {
+    for e in operands {
+        if let IRExpr::ConstRef { .. } = e {
+            cycles.read();
+        }
     }
 }
 
+/// Evaluate a single expression against the given variables and globals, as an instruction operand would be.
+/// prog is only consulted to check that block references name real blocks.
+pub fn eval_expr<'a>(e: &IRExpr<'a>, locals: &Locals<'a>, globals: &Globals<'a>, prog: &IRProgram<'a>) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+    let opts = RunOptions::new();
+    expr_val(locals, globals, prog, e, &opts, &mut RunState::new(&opts))
+}
+
+// Bind the defaults of the formals a call didn't supply (it supplied the first `supplied`). Defaults are evaluated
+// left to right after every argument has been bound, in the callee's frame: a default can use the receiver, the
+// other arguments, and earlier defaults, but none of the caller's variables.
+fn bind_defaults<'a>(target: &'a BasicBlock<'a>, calleevars: &mut Locals<'a>, supplied: usize, globs: &Globals<'a>, prog: &'a IRProgram<'a>, opts: &RunOptions, st: &mut RunState<'a>) -> Result<(),RuntimeError<'a>> {
+    let skip = supplied - target.required_formals();
+    for (formal,default) in target.formals[supplied..].iter().zip(target.defaults[skip..].iter()) {
+        let v = expr_val(calleevars, globs, prog, default, opts, st)?;
+        set_var(calleevars, &prog.vars, formal, v)?;
+    }
+    Ok(())
+}
+
+// The block a call or callmulti's code operand names
+fn call_target<'a>(prog: &'a IRProgram<'a>, opts: &RunOptions, vcode: VirtualVal<'a>) -> Result<&'a BasicBlock<'a>,RuntimeError<'a>> {
+    let bname = match vcode {
+        VirtualVal::CodePtr { val: b } => b,
+        VirtualVal::Data { .. } | VirtualVal::GCTombstone => return Err(RuntimeError::CallingNonCode)
+    };
+    let target = prog.blocks.get(bname).ok_or(RuntimeError::InvalidBlock { bname })?;
+    if let Some(entries) = &opts.entry_blocks {
+        if !entries.contains(bname) {
+            return Err(RuntimeError::CallToNonEntry { bname });
+        }
+    }
+    Ok(target)
+}
+
+// Call target on behalf of instr (a call, callmulti, or assertheap) and return what it returned. The receiver and
+// arguments are evaluated in the caller's frame, left to right, before defaults are bound in the callee's. Every
+// calling instruction goes through here; they differ only in how they find target and what they do with the results.
+fn call_block<'a>(cx: &mut Ctx<'a,'_>,
+                  target: &'a BasicBlock<'a>,
+                  instr: &'a IRStatement<'a>,
+                  locs: &mut Vec<Locals<'a>>,
+                  globs: &mut Globals<'a>,
+                  m: &mut Memory<'a>
+            ) -> Result<Flow<'a>,RuntimeError<'a>> {
+    let (prog, opts) = (cx.prog, cx.opts);
+    let (rec, args) : (&'a IRExpr<'a>, &'a [IRExpr<'a>]) = match instr {
+        IRStatement::Call { receiver, args, .. } | IRStatement::CallMulti { receiver, args, .. } => (receiver, args),
+        IRStatement::AssertHeap { receiver, .. } => (receiver, &[]),
+        _ => unreachable!("call_block on a non-call instruction")
+    };
+    // A resumed run waiting on this call when it was snapshotted already has the callee's frame on the stack, and
+    // the call charged, so it carries on inside. The result goes unmemoized, since the arguments the call was made
+    // with may since have been overwritten.
+    let mut memo_key = None;
+    let mut cached = None;
+    if cx.st.resuming.is_empty() {
+        if !target.accepts_args(args.len() + 1) {
+            return Err(RuntimeError::BadCallArity { instr });
+        }
+        let caller = locs.len() - 1;
+        let mut calleevars = cx.st.new_frame();
+        // Receiver, then args left to right
+        for (formal,arg) in target.formals.iter().zip(std::iter::once(rec).chain(args.iter())) {
+            let v = expr_val(&locs[caller], globs, prog, arg, opts, cx.st)?;
+            set_var(&mut calleevars, &prog.vars, formal, v)?;
+        }
+        bind_defaults(target, &mut calleevars, args.len() + 1, globs, prog, opts, cx.st)?;
+        memo_key = cx.st.memo_key(&prog.vars, target, &calleevars);
+        cached = memo_key.as_ref().and_then(|k| cx.st.memo.get(k)).cloned();
+        if cached.is_some() {
+            cx.st.recycle_frame(calleevars);
+            cx.cycles.fast_op();
+        } else {
+            cx.cycles.call();
+            // Only what the caller passed moves; defaults are filled in by the callee
+            if opts.charge_arg_passing {
+                for _ in 0..args.len() + 1 {
+                    cx.cycles.fast_op();
+                    cx.cycles.arg_pass();
+                }
+            }
+            locs.push(calleevars);
+            cx.st.flush_trace(opts, cx.cycles);
+        }
+    }
+    let callresult = match cached {
+        Some(cached) => cached,
+        None => {
+            // TimeTravel needs to know where the caller is waiting, should it want to resume from inside
+            let tracking = cx.st.resume_points.is_some();
+            if tracking {
+                cx.st.call_sites.push((cx.st.cur_block.unwrap_or_default(), cx.st.cur_at.0, cx.st.cur_at.1));
+            }
+            // A halt or stop leaves the callee's frame where it is, as an error would
+            let callresult = match run_code(cx, target, locs, globs, m)? {
+                Flow::Return(vals) => vals,
+                flow => return Ok(flow)
+            };
+            if tracking {
+                cx.st.call_sites.pop();
+            }
+            let caller = locs.len() - 2;
+            if let Some(frame) = locs.pop() {
+                cx.st.recycle_frame(frame);
+            }
+            cx.st.cast_vars.retain(|(depth,_)| *depth <= caller);
+            if let Some(k) = memo_key {
+                cx.st.memo.insert(k, callresult.clone());
+            }
+            callresult
+        }
+    };
+    if opts.check_returned_refs {
+        for v in callresult.iter() {
+            m.check_returned(v)?;
+        }
+    }
+    Ok(Flow::Return(callresult))
+}
+
+// Address of element offset (of scale bytes each) from base, honoring RunOptions::signed_offsets
+fn elt_addr<'a>(opts: &RunOptions, base: u64, offset: u64, scale: u64) -> Result<u64,RuntimeError<'a>> {
+    let addr = if opts.signed_offsets {
+        (offset as i64).checked_mul(scale as i64).and_then(|bytes| base.checked_add_signed(bytes))
+    } else {
+        offset.checked_mul(scale).and_then(|bytes| base.checked_add(bytes))
+    };
+    addr.ok_or(RuntimeError::OffsetOverflow { base, offset })
+}
+
+// A run that fails leaves every frame on the stack, so an unset variable that the caller has was probably expected to
+// carry over into the callee
+fn blame_caller<'a>(err: RuntimeError<'a>, stack: &[Locals<'a>]) -> RuntimeError<'a> {
+    match (err, stack) {
+        (RuntimeError::UninitializedVariable { name }, [.., caller, _]) if caller.contains_key(name) => RuntimeError::VariableFromCaller { name },
+        (err, _) => err
+    }
+}
+
+// RunOptions::run_until: stop once the instruction just run has set the watched variable to the target value
+fn check_run_until<'a>(opts: &RunOptions, i: &'a IRStatement<'a>, locals: &Locals<'a>, cycles: &ExecStats) -> Option<Stop<'a>> {
+    if let Some((var, target)) = &opts.run_until {
+        for x in i.defs() {
+            if x == var && locals.get(x) == Some(&VirtualVal::Data { val: *target }) {
+                return Some(Stop::AtCondition { name: x, step: cycles.steps() });
+            }
+        }
+    }
+    None
+}
+
+// The block a jump, if, or ifsigned goes to: target `which` of the current block's control transfer, named `bname`
+fn resolve_target<'a>(prog: &'a IRProgram<'a>, st: &RunState<'a>, cur_id: Option<usize>, which: usize, instr: &'a ControlXfer<'a>, bname: &'a str) -> Result<(Option<usize>,&'a BasicBlock<'a>),RuntimeError<'a>> {
+    if let Some((id, b)) = st.blocks.succ(cur_id, which) {
+        return Ok((Some(id), b));
+    }
+    match prog.blocks.get(bname) {
+        Some(b) => Ok((st.blocks.id(bname), b)),
+        None => Err(RuntimeError::InvalidBlockInControl { instr, bname })
+    }
+}
 // Run one basic block to completion. We abuse the Rust stack to encode the target code stack.
-fn run_code<'a>(prog: &'a IRProgram<'a>, 
-                mut cur_block: &'a BasicBlock<'a>, 
+// Flow::Return(vec![]) means the code returned via ret void.
+fn run_code<'a>(cx: &mut Ctx<'a,'_>,
+                mut cur_block: &'a BasicBlock<'a>,
                 locs: &mut Vec<Locals<'a>>,
                 globs: &mut Globals<'a>,
-                m: &mut Memory<'a>,
-                tracing: bool,
-                mut cycles: &mut ExecStats
-            ) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
-    let localsindex = locs.len() - 1;
+                m: &mut Memory<'a>
+            ) -> Result<Flow<'a>,RuntimeError<'a>> {
+    let (prog, opts) = (cx.prog, cx.opts);
+    // A resumed run's frames are all on the stack already; this one picks up where it was
+    let localsindex = locs.len() - cx.st.resuming.len().max(1);
     // on entry no previous block
     let mut prevblock : Option<&'a str> = None;
+    let mut resume_at = None;
+    if let Some((bname, index, prev)) = cx.st.resuming.pop() {
+        cur_block = &prog.blocks[bname];
+        prevblock = prev;
+        resume_at = Some(index);
+    }
     let mut finalresult = None;
+    let mut cur_id = cx.st.blocks.id(cur_block.name);
     while let None = finalresult {
-        for i in cur_block.instrs.iter() {
-            if tracing {
-                println!("Executing: {}", i);
+        // Where a resumed frame left off, it has already entered the block and been snapshotted
+        let start = resume_at.take();
+        if start.is_none() {
+            cx.st.enter_block(opts, cur_block.name)?;
+            if let Some(cost) = cx.st.block_costs.get(cur_block.name) {
+                cx.cycles.block_cost = cx.cycles.block_cost.saturating_add(*cost);
+            }
+        }
+        for (idx,i) in cur_block.instrs.iter().enumerate().skip(start.unwrap_or(0)) {
+            if let Some(stop) = cx.st.check_cancelled() {
+                return Ok(Flow::Stop(stop));
+            }
+            cx.st.cur_block = Some(cur_block.name);
+            cx.st.cur_at = (idx, prevblock);
+            if start != Some(idx) {
+                cx.st.check_breakpoint(opts, cur_block.name, idx, &locs[localsindex], cx.cycles);
+                if let Some(stop) = cx.st.check_snapshot(opts, (cur_block.name, idx), locs, m, globs, cx.cycles) {
+                    return Ok(Flow::Stop(stop));
+                }
+            }
+            let traced = cx.st.traces(opts, cur_block.name, i.kind());
+            if traced {
+                cx.st.begin_trace(opts, format!("Executing: {}", i), cx.cycles);
             }
+            // A phi only reads the arm for the edge taken, so it charges that one itself
+            if opts.charge_const_loads && i.kind() != InstrKind::Phi {
+                charge_const_loads(cx.cycles, i.exprs());
+            }
+            // Values the instruction is about to overwrite
+            let old_vals : Vec<(&'a str,Option<VirtualVal<'a>>)> = if traced && opts.trace_assignments {
+                i.defs().into_iter().map(|x| (x, locs[localsindex].get(x).copied())).collect()
+            } else {
+                vec![]
+            };
             let _step =
             match i {
                 IRStatement::Print { out: e } => {
-                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &e)?;
-                    println!("{}",v);
-                    cycles.print();
+                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &e, opts, cx.st)?;
+                    cx.cycles.print();
+                    cx.st.flush_trace(opts, cx.cycles);
+                    cx.st.emit_output(v);
                     Ok(())
                 },
-                IRStatement::Alloc { lhs: v, slots: n } => {
-                    let result = m.alloc((*n).into());
-                    if result.is_ok() {
-                        cycles.alloc();
-                        set_var(&mut locs[localsindex], v, VirtualVal::Data { val: result.unwrap() })
-                    } else if result == Err(RuntimeError::GCRequired) {
-                        // GC, then try again
-                        if m.slot_cap.is_logging_gc() {
-                            println!("Triggering GC");
-                        }
-                        m.gc(locs)?;
-                        let result = m.alloc((*n).into());
-                        match result {
-                            Err(RuntimeError::GCRequired) => Err(RuntimeError::OutOfMemory),
-                            Err(_) => result.map(|_| ()),
-                            Ok(result) => {
-                                set_var(&mut locs[localsindex], v, VirtualVal::Data { val: result })?;
-                                Ok(())
-                            }
+                IRStatement::Rand { lhs: dest, bound } => {
+                    let vbound = expr_val(&locs[localsindex], globs, prog, bound, opts, cx.st)?;
+                    match vbound {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::CodeAddressArithmetic { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        VirtualVal::Data { val: 0 } => Err(RuntimeError::EmptyRandomRange),
+                        VirtualVal::Data { val: n } => {
+                            // Plain modulo; the bias is negligible for the bounds programs actually use
+                            cx.cycles.slow_op();
+                            let r = cx.st.next_random() % n;
+                            set_var(&mut locs[localsindex], &prog.vars, dest, VirtualVal::Data { val: r })
                         }
-                    } else {
-                        result.map(|_| ())
                     }
                 },
+                IRStatement::Alloc { lhs: v, slots: n } => {
+                    // The allocator may collect, which logs as it goes
+                    if m.slot_cap.is_logging_gc() {
+                        cx.st.flush_trace(opts, cx.cycles);
+                    }
+                    let collections = m.collections;
+                    let result = cx.st.allocator.alloc(m, locs, (*n).into());
+                    for _ in collections..m.collections {
+                        cx.cycles.gc();
+                    }
+                    for fields in std::mem::take(&mut m.copied_sizes) {
+                        cx.cycles.gc_copy(fields);
+                    }
+                    let addr = result?;
+                    cx.cycles.alloc();
+                    set_var(&mut locs[localsindex], &prog.vars, v, VirtualVal::Data { val: addr })?;
+                    if let Some(every) = opts.sample_live_every {
+                        cx.st.sample_live(m, locs, every);
+                    }
+                    Ok(())
+                },
                 IRStatement::VarAssign { lhs: var, rhs: e } => {
-                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &e)?;
-                    cycles.fast_op();
-                    set_var(&mut locs[localsindex], var, v)
+                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &e, opts, cx.st)?;
+                    cx.cycles.fast_op();
+                    set_var(&mut locs[localsindex], &prog.vars, var, v)
                 },
-                IRStatement::Phi { lhs: dest, opts } => {
+                IRStatement::Phi { lhs: dest, opts: srcs } => {
+                    // Phis read the values as of the block's entry, which only makes sense before anything else runs
+                    if idx > 0 && cur_block.instrs[idx-1].kind() != InstrKind::Phi {
+                        return Err(RuntimeError::PhiNotAtBlockStart { instr: i });
+                    }
                     if prevblock.is_none() {
                         return Err(RuntimeError::PhiInFirstBlock { instr: i });
                     }
                     let pred = prevblock.unwrap();
                     let mut done = false;
-                    for (bname,src) in opts {
+                    for (bname,src) in srcs {
                         if pred.eq(*bname) {
-                            let v = expr_val(&locs[locs.len()-1], &globs, &prog, &src)?;
-                            set_var(&mut locs[localsindex], &dest, v)?;
+                            if opts.charge_const_loads {
+                                charge_const_loads(cx.cycles, vec![src]);
+                            }
+                            let v = expr_val(&locs[locs.len()-1], &globs, &prog, &src, opts, cx.st)?;
+                            set_var(&mut locs[localsindex], &prog.vars, &dest, v)?;
                             done = true;
                             break;
                         }
                     }
-                    cycles.phi();
+                    cx.cycles.phi();
                     if done {
                         Ok(())
                     } else {
                         Err(RuntimeError::BadPhiPredecessor { instr: i, actual_predecessor: pred })
                     }
                 },
-                IRStatement::Call { lhs: dest, code, receiver: rec, args } => {
-                    let mut calleevars = HashMap::new();
-                    let vcode = expr_val(&locs[locs.len()-1], &globs, &prog, &code)?;
-                    let target_block_name = match vcode {
-                        VirtualVal::CodePtr { val: b } => Ok(b),
-                        VirtualVal::Data { .. } => Err(RuntimeError::CallingNonCode),
-                        VirtualVal::GCTombstone { .. } => Err(RuntimeError::CallingNonCode)
-                    }?;
-                    let target_block = match prog.blocks.get(target_block_name) {
-                        Some(b) => Ok(b),
-                        None => Err(RuntimeError::InvalidBlock { bname: target_block_name })
-                    }?;
-                    let vrec = expr_val(&locs[locs.len()-1], &globs, &prog, &rec)?;
-                    set_var(&mut calleevars, target_block.formals[0], vrec)?;
-                    if args.len() + 1 != target_block.formals.len() {
-                        return Err(RuntimeError::BadCallArity { instr: i });
-                    }
-                    // args are in left-to-right order. Receiver is idx 0.
-                    let mut argidx = 1;
-                    for arg in args.iter() {
-                        let varg = expr_val(&locs[locs.len()-1], &globs, &prog, &arg)?;
-                        set_var(&mut calleevars, target_block.formals[argidx], varg)?;
-                        argidx = argidx + 1;
-                    }
-                    cycles.call();
-                    locs.push(calleevars);
-                    let callresult = run_code(prog, target_block, locs, globs, m, tracing, &mut cycles)?;
-                    locs.pop();
-                    set_var(&mut locs[localsindex], dest, callresult)
+                IRStatement::Call { lhs: dest, code, .. } => {
+                    let vcode = expr_val(&locs[localsindex], globs, prog, code, opts, cx.st)?;
+                    let target_block = call_target(prog, opts, vcode)?;
+                    let callresult = match call_block(cx, target_block, i, locs, globs, m)? {
+                        Flow::Return(vals) => vals,
+                        flow => return Ok(flow)
+                    };
+                    match callresult[..] {
+                        [v] => set_var(&mut locs[localsindex], &prog.vars, dest, v),
+                        [] => Ok(()),
+                        _ => Err(RuntimeError::WrongReturnCount { expected: 1, got: callresult.len() })
+                    }
+                },
+                IRStatement::CallMulti { lhs: dests, code, .. } => {
+                    let vcode = expr_val(&locs[localsindex], globs, prog, code, opts, cx.st)?;
+                    let target_block = call_target(prog, opts, vcode)?;
+                    let callresult = match call_block(cx, target_block, i, locs, globs, m)? {
+                        Flow::Return(vals) => vals,
+                        flow => return Ok(flow)
+                    };
+                    if callresult.len() != dests.len() {
+                        return Err(RuntimeError::WrongReturnCount { expected: dests.len(), got: callresult.len() });
+                    }
+                    for (dest,v) in dests.iter().zip(callresult) {
+                        set_var(&mut locs[localsindex], &prog.vars, dest, v)?;
+                    }
+                    Ok(())
                 },
                 IRStatement::SetElt { base, offset: off, val: v } => {
-                    let vbase = expr_val(&locs[locs.len()-1], &globs, &prog, &base)?;
-                    let offv = expr_val(&locs[locs.len()-1], &globs, &prog, &off)?;
-                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &v)?;
+                    let vbase = expr_val(&locs[locs.len()-1], &globs, &prog, &base, opts, cx.st)?;
+                    let offv = expr_val(&locs[locs.len()-1], &globs, &prog, &off, opts, cx.st)?;
+                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &v, opts, cx.st)?;
                     match vbase {
                         VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
                         VirtualVal::GCTombstone => Err(RuntimeError::WriteToGCedData { addr: 0, val: v }),
@@ -549,18 +2609,27 @@ fn run_code<'a>(prog: &'a IRProgram<'a>,
                                 // TODO: should be different error
                                 VirtualVal::CodePtr { val: offb } => Err(RuntimeError::AccessingCodeInMemory { bname: offb }),
                                 VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                                // Storing a dead value would just move the problem somewhere harder to find
+                                VirtualVal::Data { .. } if v == VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
                                 VirtualVal::Data { val: offset } => {
-                                    cycles.slow_op(); // multiplication
-                                    cycles.fast_op(); // addition
-                                    cycles.write(); // memory access
-                                    m.mem_store(n+(8*offset), v).map(|_| ())
+                                    let addr = elt_addr(opts, n, offset, 8)?;
+                                    cx.st.check_deref(opts, m, n, i);
+                                    cx.st.check_header_write(opts, m, addr, i)?;
+                                    m.check_header_init(addr)?;
+                                    m.check_code_store(addr, v)?;
+                                    cx.cycles.slow_op(); // multiplication
+                                    cx.cycles.fast_op(); // addition
+                                    cx.cycles.write(); // memory access
+                                    m.mem_store(addr, v)?;
+                                    m.note_write(addr, cur_block.name, i);
+                                    Ok(())
                                 }
                             }
                     }
                 },
                 IRStatement::GetElt { lhs: dest, base: e, offset: off } => {
-                    let v = expr_val(&locs[localsindex], &globs, &prog, &e)?;
-                    let offv = expr_val(&locs[localsindex], &globs, &prog, &off)?;
+                    let v = expr_val(&locs[localsindex], &globs, &prog, &e, opts, cx.st)?;
+                    let offv = expr_val(&locs[localsindex], &globs, &prog, &off, opts, cx.st)?;
                     match v {
                         VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
                         VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
@@ -570,130 +2639,1923 @@ fn run_code<'a>(prog: &'a IRProgram<'a>,
                                 VirtualVal::CodePtr { val: offb } => Err(RuntimeError::AccessingCodeInMemory { bname: offb }),
                                 VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
                                 VirtualVal::Data { val: offset } => {
-                                    cycles.slow_op(); // multiplication
-                                    cycles.fast_op(); // addition
-                                    cycles.read(); // memory access
-                                    let mval = m.mem_lookup(n+(8*offset))?;
-                                    set_var(&mut locs[localsindex], dest, mval)
+                                    let addr = elt_addr(opts, n, offset, 8)?;
+                                    cx.st.check_deref(opts, m, n, i);
+                                    m.check_global_index(n, addr)?;
+                                    m.check_header_init(addr)?;
+                                    cx.cycles.slow_op(); // multiplication
+                                    cx.cycles.fast_op(); // addition
+                                    cx.cycles.read(); // memory access
+                                    let mval = cx.st.recover(opts, m.mem_lookup(addr))?;
+                                    m.note_read(addr);
+                                    set_var(&mut locs[localsindex], &prog.vars, dest, mval)
                                 }
                             }
                     }
                 },
+                IRStatement::GetEltNarrow { lhs: dest, bits, base, offset } => {
+                    let vbase = expr_val(&locs[localsindex], globs, prog, base, opts, cx.st)?;
+                    let offv = expr_val(&locs[localsindex], globs, prog, offset, opts, cx.st)?;
+                    match (vbase, offv) {
+                        (VirtualVal::CodePtr { val: b }, _) | (_, VirtualVal::CodePtr { val: b }) => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
+                        (VirtualVal::GCTombstone, _) | (_, VirtualVal::GCTombstone) => Err(RuntimeError::ReadFromGCedData),
+                        (VirtualVal::Data { val: n }, VirtualVal::Data { val: offset }) => {
+                            let addr = elt_addr(opts, n, offset, u64::from(bits / 8))?;
+                            cx.st.check_deref(opts, m, n, i);
+                            m.check_global_index(n, addr - addr % 8)?;
+                            m.check_header_init(addr)?;
+                            cx.cycles.slow_op(); // multiplication
+                            cx.cycles.fast_op(); // addition
+                            cx.cycles.read(); // memory access
+                            cx.cycles.fast_op(); // shift and mask
+                            let mval = cx.st.recover(opts, m.mem_lookup_narrow(addr, *bits))?;
+                            m.note_read(addr - addr % 8);
+                            set_var(&mut locs[localsindex], &prog.vars, dest, mval)
+                        }
+                    }
+                },
+                IRStatement::SetEltNarrow { bits, base, offset, val } => {
+                    let vbase = expr_val(&locs[localsindex], globs, prog, base, opts, cx.st)?;
+                    let offv = expr_val(&locs[localsindex], globs, prog, offset, opts, cx.st)?;
+                    let v = expr_val(&locs[localsindex], globs, prog, val, opts, cx.st)?;
+                    match (vbase, offv, v) {
+                        (VirtualVal::CodePtr { val: b }, _, _) | (_, VirtualVal::CodePtr { val: b }, _) | (_, _, VirtualVal::CodePtr { val: b }) =>
+                            Err(RuntimeError::AccessingCodeInMemory { bname: b }),
+                        (VirtualVal::GCTombstone, _, _) => Err(RuntimeError::WriteToGCedData { addr: 0, val: v }),
+                        (_, VirtualVal::GCTombstone, _) | (_, _, VirtualVal::GCTombstone) => Err(RuntimeError::ReadFromGCedData),
+                        (VirtualVal::Data { val: n }, VirtualVal::Data { val: offset }, VirtualVal::Data { val: v }) => {
+                            let addr = elt_addr(opts, n, offset, u64::from(bits / 8))?;
+                            cx.st.check_deref(opts, m, n, i);
+                            cx.st.check_header_write(opts, m, addr - addr % 8, i)?;
+                            m.check_header_init(addr)?;
+                            cx.cycles.slow_op(); // multiplication
+                            cx.cycles.fast_op(); // addition
+                            cx.cycles.read(); // the rest of the word
+                            cx.cycles.fast_op(); // merge
+                            cx.cycles.write(); // memory access
+                            m.mem_store_narrow(addr, *bits, v)?;
+                            // Only part of the word changes, so the rest of what was stored there may still be used
+                            m.note_read(addr - addr % 8);
+                            m.note_write(addr - addr % 8, cur_block.name, i);
+                            Ok(())
+                        }
+                    }
+                },
                 IRStatement::Load { lhs: dest, base: e } => {
-                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &e)?;
+                    let v = expr_val(&locs[locs.len()-1], &globs, &prog, &e, opts, cx.st)?;
                     match v {
                         VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
                         VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
                         VirtualVal::Data { val: n } => {
-                            cycles.read(); // memory access
-                            let memval = m.mem_lookup(n)?;
-                            set_var(&mut locs[localsindex], dest, memval)
+                            cx.st.check_deref(opts, m, n, i);
+                            m.check_header_init(n)?;
+                            cx.cycles.read(); // memory access
+                            let memval = cx.st.recover(opts, m.mem_lookup(n))?;
+                            m.note_read(n);
+                            set_var(&mut locs[localsindex], &prog.vars, dest, memval)
                         }
                     }
                 },
                 IRStatement::Store { base: e, val: ve } => {
-                    let bv = expr_val(&locs[localsindex], &globs, &prog, &e)?;
-                    let vv = expr_val(&locs[localsindex], &globs, &prog, &ve)?;
+                    let bv = expr_val(&locs[localsindex], &globs, &prog, &e, opts, cx.st)?;
+                    let vv = expr_val(&locs[localsindex], &globs, &prog, &ve, opts, cx.st)?;
                     match bv {
                         VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
                         VirtualVal::GCTombstone => Err(RuntimeError::WriteToGCedData { addr: 0, val: vv }),
+                        VirtualVal::Data { .. } if vv == VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
                         VirtualVal::Data { val: n } => {
-                            cycles.write(); // memory access
-                            m.mem_store(n, vv).map(|_| ())
+                            cx.st.check_deref(opts, m, n, i);
+                            cx.st.check_header_write(opts, m, n, i)?;
+                            m.check_header_init(n)?;
+                            m.check_code_store(n, vv)?;
+                            if m.header_slot(n) == Some(2) {
+                                if let VirtualVal::Data { val: slotmap } = vv {
+                                    m.check_slotmap(n + 8, m.objects[&(n + 8)], slotmap)?;
+                                    m.check_code_fields(n + 8, slotmap)?;
+                                }
+                            }
+                            cx.cycles.write(); // memory access
+                            m.mem_store(n, vv)?;
+                            m.note_write(n, cur_block.name, i);
+                            Ok(())
+                        }
+                    }
+                },
+                IRStatement::Cas { result, base, expected, new } => {
+                    let bv = expr_val(&locs[localsindex], globs, prog, base, opts, cx.st)?;
+                    let ev = expr_val(&locs[localsindex], globs, prog, expected, opts, cx.st)?;
+                    let nv = expr_val(&locs[localsindex], globs, prog, new, opts, cx.st)?;
+                    let n = match bv {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        VirtualVal::Data { .. } if ev == VirtualVal::GCTombstone || nv == VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        VirtualVal::Data { val: n } => Ok(n)
+                    }?;
+                    cx.st.check_deref(opts, m, n, i);
+                    m.check_header_init(n)?;
+                    cx.cycles.read(); // memory access
+                    let old = cx.st.recover(opts, m.mem_lookup(n))?;
+                    m.note_read(n);
+                    // Only a successful swap writes, so only it pays for the write
+                    let swapped = old == ev;
+                    if swapped {
+                        cx.st.check_header_write(opts, m, n, i)?;
+                        m.check_code_store(n, nv)?;
+                        cx.cycles.write(); // memory access
+                        m.mem_store(n, nv)?;
+                        m.note_write(n, cur_block.name, i);
+                    }
+                    set_var(&mut locs[localsindex], &prog.vars, result, VirtualVal::Data { val: u64::from(swapped) })
+                },
+                IRStatement::MemCopy { dst, src, slots } => {
+                    let vdst = expr_val(&locs[localsindex], globs, prog, dst, opts, cx.st)?;
+                    let vsrc = expr_val(&locs[localsindex], globs, prog, src, opts, cx.st)?;
+                    let vslots = expr_val(&locs[localsindex], globs, prog, slots, opts, cx.st)?;
+                    let d = match vdst {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::WriteToGCedData { addr: 0, val: vdst }),
+                        VirtualVal::Data { val: n } => Ok(n)
+                    }?;
+                    let s = match vsrc {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        VirtualVal::Data { val: n } => Ok(n)
+                    }?;
+                    let n = match vslots {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::CodeAddressArithmetic { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        VirtualVal::Data { val: n } => Ok(n)
+                    }?;
+                    cx.st.check_deref(opts, m, d, i);
+                    cx.st.check_deref(opts, m, s, i);
+                    // Read the whole source range before writing anything, so overlapping copies see the original contents
+                    let mut words = vec![];
+                    for i in 0..n {
+                        m.check_header_init(s + 8*i)?;
+                        cx.cycles.read();
+                        words.push(cx.st.recover(opts, m.mem_lookup(s + 8*i))?);
+                        m.note_read(s + 8*i);
+                    }
+                    for (k,w) in words.iter().enumerate() {
+                        let k = k as u64;
+                        cx.st.check_header_write(opts, m, d + 8*k, i)?;
+                        m.check_header_init(d + 8*k)?;
+                        m.check_code_store(d + 8*k, *w)?;
+                    }
+                    for (k,w) in words.into_iter().enumerate() {
+                        cx.cycles.write();
+                        m.mem_store(d + 8*(k as u64), w)?;
+                        m.note_write(d + 8*(k as u64), cur_block.name, i);
+                    }
+                    Ok(())
+                },
+                IRStatement::MemFill { dst, val, slots } => {
+                    let vdst = expr_val(&locs[localsindex], globs, prog, dst, opts, cx.st)?;
+                    let vv = expr_val(&locs[localsindex], globs, prog, val, opts, cx.st)?;
+                    let vslots = expr_val(&locs[localsindex], globs, prog, slots, opts, cx.st)?;
+                    let d = match vdst {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::AccessingCodeInMemory { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::WriteToGCedData { addr: 0, val: vv }),
+                        VirtualVal::Data { .. } if vv == VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        VirtualVal::Data { val: n } => Ok(n)
+                    }?;
+                    let n = match vslots {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::CodeAddressArithmetic { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        VirtualVal::Data { val: n } => Ok(n)
+                    }?;
+                    cx.st.check_deref(opts, m, d, i);
+                    for k in 0..n {
+                        cx.st.check_header_write(opts, m, d + 8*k, i)?;
+                        m.check_header_init(d + 8*k)?;
+                        m.check_code_store(d + 8*k, vv)?;
+                    }
+                    for k in 0..n {
+                        cx.cycles.write();
+                        m.mem_store(d + 8*k, vv)?;
+                        m.note_write(d + 8*k, cur_block.name, i);
+                    }
+                    Ok(())
+                },
+                IRStatement::PtrToInt { lhs, ptr: e } | IRStatement::IntToPtr { lhs, int: e } => {
+                    match expr_val(&locs[localsindex], globs, prog, e, opts, cx.st)? {
+                        VirtualVal::CodePtr { val: b } => Err(RuntimeError::CodeAddressArithmetic { bname: b }),
+                        VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                        v @ VirtualVal::Data { .. } => {
+                            cx.cycles.fast_op();
+                            if let IRStatement::PtrToInt { .. } = i {
+                                cx.st.cast_vars.insert((localsindex, lhs));
+                            } else {
+                                cx.st.cast_vars.remove(&(localsindex, *lhs));
+                            }
+                            set_var(&mut locs[localsindex], &prog.vars, lhs, v)
+                        }
+                    }
+                },
+                IRStatement::Checkpoint { label } => {
+                    cx.st.checkpoints.push((label, cx.cycles.clone()));
+                    Ok(())
+                },
+                IRStatement::AssertHeap { msg, block, .. } => {
+                    let target_block = match prog.blocks.get(block) {
+                        Some(b) => Ok(b),
+                        None => Err(RuntimeError::InvalidBlock { bname: block })
+                    }?;
+                    let callresult = match call_block(cx, target_block, i, locs, globs, m)? {
+                        Flow::Return(vals) => vals,
+                        flow => return Ok(flow)
+                    };
+                    match callresult[..] {
+                        [VirtualVal::Data { val: 0 }] => Err(RuntimeError::HeapAssertionFailed { msg, bname: block }),
+                        [_] => Ok(()),
+                        _ => Err(RuntimeError::WrongReturnCount { expected: 1, got: callresult.len() })
+                    }
+                },
+                IRStatement::Halt { val } => {
+                    let v = expr_val(&locs[localsindex], globs, prog, val, opts, cx.st)?;
+                    return Ok(Flow::Halt(v));
+                },
+                IRStatement::ReadCycles { lhs } => {
+                    cx.cycles.fast_op();
+                    set_var(&mut locs[localsindex], &prog.vars, lhs, VirtualVal::Data { val: cx.cycles.steps() })
+                },
+                IRStatement::DivMod { quot, rem, arg1: e1, arg2: e2 } => {
+                    let v1 = expr_val(&locs[localsindex], globs, prog, e1, opts, cx.st)?;
+                    let v2 = expr_val(&locs[localsindex], globs, prog, e2, opts, cx.st)?;
+                    match (v1,v2) {
+                        (VirtualVal::CodePtr{ val: b },_) => Err(RuntimeError::CodeAddressArithmetic { bname: b}),
+                        (_,VirtualVal::CodePtr{ val: b }) => Err(RuntimeError::CodeAddressArithmetic { bname: b}),
+                        (VirtualVal::GCTombstone,_) => Err(RuntimeError::ReadFromGCedData),
+                        (_,VirtualVal::GCTombstone) => Err(RuntimeError::ReadFromGCedData),
+                        (VirtualVal::Data { .. }, VirtualVal::Data { val: 0 }) => Err(RuntimeError::DivisionByZero { instr: i }),
+                        (VirtualVal::Data { val: n1 }, VirtualVal::Data { val: n2 }) => {
+                            // One divide yields both results
+                            cx.cycles.slow_op();
+                            set_var(&mut locs[localsindex], &prog.vars, quot, VirtualVal::Data { val: n1/n2 })?;
+                            set_var(&mut locs[localsindex], &prog.vars, rem, VirtualVal::Data { val: n1%n2 })
                         }
                     }
                 },
                 IRStatement::Op { lhs: v, arg1: e1, op: o, arg2: e2} => {
-                    let v1 = expr_val(&locs[localsindex], &globs, &prog, &e1)?;
-                    let v2 = expr_val(&locs[localsindex], &globs, &prog, &e2)?;
+                    let v1 = expr_val(&locs[localsindex], &globs, &prog, &e1, opts, cx.st)?;
+                    let v2 = expr_val(&locs[localsindex], &globs, &prog, &e2, opts, cx.st)?;
                     match (v1,v2) {
+                        // Code pointers are equal when they name the same block, and never equal to data
+                        (VirtualVal::CodePtr{ .. },_) | (_,VirtualVal::CodePtr{ .. }) if opts.code_pointer_tables && *o == BinOp::Eq => {
+                            cx.cycles.op(OpClass::of(opts, *o));
+                            set_var(&mut locs[localsindex], &prog.vars, v, VirtualVal::Data { val: if v1 == v2 {1} else {0} })
+                        },
                         (VirtualVal::CodePtr{ val: b },_) => Err(RuntimeError::CodeAddressArithmetic { bname: b}),
                         (_,VirtualVal::CodePtr{ val: b }) => Err(RuntimeError::CodeAddressArithmetic { bname: b}),
                         (VirtualVal::GCTombstone,_) => Err(RuntimeError::ReadFromGCedData),
                         (_,VirtualVal::GCTombstone) => Err(RuntimeError::ReadFromGCedData),
-                        (VirtualVal::Data { val: n1 }, VirtualVal::Data { val: n2 }) => 
-                            // We've ruled out computing with code addresses, which we don't plan to allow
-                            match *o {
-                                "+"  => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1+n2 }) },
-                                "<<" => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1<<n2 }) },
-                                ">>" => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1>>n2 }) },
-                                "-"  => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1-n2 }) },
-                                "/"  => { cycles.slow_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1/n2 }) },
-                                "*"  => { cycles.slow_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1*n2 }) },
-                                "&"  => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1&n2 }) },
-                                "|"  => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1|n2 }) },
-                                "^"  => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: n1^n2 }) },
-                                "<"  => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: if n1<n2 { 1 } else {0} }) },
-                                ">"  => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: if n1>n2 {1} else {0} }) },
-                                "==" => { cycles.fast_op(); set_var(&mut locs[localsindex], v, VirtualVal::Data { val: if n1==n2 {1} else {0}}) },
-                                _ => Err(RuntimeError::NYI) 
-                            }
-                        
+                        (VirtualVal::Data { .. }, VirtualVal::Data { val: 0 }) if *o == BinOp::Div => Err(RuntimeError::DivisionByZero { instr: i }),
+                        (VirtualVal::Data { val: n1 }, VirtualVal::Data { val: n2 }) => {
+                            cx.st.check_arith(opts, m, localsindex, (e1,n1), (e2,n2), i);
+                            cx.st.check_order(opts, m, *o, n1, n2, i);
+                            // We've ruled out computing with code addresses, which we don't plan to allow.
+                            // Arithmetic wraps like a 64-bit register, and shifting by 64 or more clears every bit
+                            let result = match *o {
+                                BinOp::Add => n1.wrapping_add(n2),
+                                BinOp::Shl => u32::try_from(n2).ok().and_then(|n| n1.checked_shl(n)).unwrap_or(0),
+                                BinOp::Shr => u32::try_from(n2).ok().and_then(|n| n1.checked_shr(n)).unwrap_or(0),
+                                BinOp::Sub => n1.wrapping_sub(n2),
+                                BinOp::Div => n1/n2,
+                                BinOp::Mul => n1.wrapping_mul(n2),
+                                BinOp::And => n1&n2,
+                                BinOp::Or => n1|n2,
+                                BinOp::Xor => n1^n2,
+                                BinOp::Lt => if n1<n2 { 1 } else {0},
+                                BinOp::Gt => if n1>n2 {1} else {0},
+                                BinOp::Eq => if n1==n2 {1} else {0},
+                            };
+                            cx.cycles.op(OpClass::of(opts, *o));
+                            set_var(&mut locs[localsindex], &prog.vars, v, VirtualVal::Data { val: result })
+                        }
                     }
                 },
             }?;
+            for (x, old) in old_vals {
+                // A void call leaves its destination alone
+                if let Some(new) = locs[localsindex].get(x) {
+                    let old = old.map_or("<unset>".to_string(), |v| v.to_string());
+                    cx.st.pending_warnings.push(format!("%{}: {} => {}", x, old, new));
+                }
+            }
+            cx.st.flush_trace(opts, cx.cycles);
+            if let Some(stop) = check_run_until(opts, i, &locs[localsindex], cx.cycles) {
+                return Ok(Flow::Stop(stop));
+            }
         }
-        if tracing {
-            println!("Transfering via: {}", &cur_block.next);
+        if let Some(stop) = cx.st.check_cancelled() {
+            return Ok(Flow::Stop(stop));
+        }
+        cx.st.cur_block = Some(cur_block.name);
+        cx.st.cur_at = (cur_block.instrs.len(), prevblock);
+        if start != Some(cur_block.instrs.len()) {
+            cx.st.check_breakpoint(opts, cur_block.name, cur_block.instrs.len(), &locs[localsindex], cx.cycles);
+            if let Some(stop) = cx.st.check_snapshot(opts, (cur_block.name, cur_block.instrs.len()), locs, m, globs, cx.cycles) {
+                return Ok(Flow::Stop(stop));
+            }
+        }
+        if cx.st.traces(opts, cur_block.name, cur_block.next.kind()) {
+            cx.st.begin_trace(opts, format!("Transfering via: {}", &cur_block.next), cx.cycles);
+        }
+        if opts.charge_const_loads {
+            charge_const_loads(cx.cycles, cur_block.next.exprs());
         }
         match &cur_block.next {
-            ControlXfer::Fail {reason: r} => { panic!("Failure: {:?}", r )},
+            ControlXfer::Fail {reason: r} => {
+                return Err(RuntimeError::Failed { reason: *r })
+            },
             ControlXfer::Ret { val: e } => {
-                let result = expr_val(&locs[locs.len()-1], &globs, &prog, &e)?;
-                cycles.ret();
-                finalresult = Some(result);
+                let result = expr_val(&locs[locs.len()-1], &globs, &prog, &e, opts, cx.st)?;
+                cx.cycles.ret();
+                finalresult = Some(vec![result]);
+            },
+            ControlXfer::RetVoid => {
+                cx.cycles.ret();
+                finalresult = Some(vec![]);
+            },
+            ControlXfer::RetMulti { vals } => {
+                let mut results = vec![];
+                for e in vals.iter() {
+                    results.push(expr_val(&locs[localsindex], globs, prog, e, opts, cx.st)?);
+                }
+                // Returning the first value costs the same as a plain ret; each further one is a register move
+                cx.cycles.ret();
+                for _ in 1..results.len() {
+                    cx.cycles.fast_op();
+                }
+                finalresult = Some(results);
             },
             ControlXfer::Jump { block: b } => {
-                let target_block = match prog.blocks.get(b) {
+                let (target_id, target_block) = resolve_target(prog, cx.st, cur_id, 0, &cur_block.next, b)?;
+                cx.cycles.uncond();
+                prevblock = Some(cur_block.name);
+                cur_block = target_block;
+                cur_id = target_id;
+            },
+            ControlXfer::JumpIndirect { target } => {
+                if !opts.code_pointer_tables {
+                    return Err(RuntimeError::IndirectJumpDisabled);
+                }
+                let target_block_name = match expr_val(&locs[localsindex], globs, prog, target, opts, cx.st)? {
+                    VirtualVal::CodePtr { val: b } => Ok(b),
+                    VirtualVal::Data { .. } | VirtualVal::GCTombstone => Err(RuntimeError::JumpToNonCode)
+                }?;
+                // Computed at run time, so there's nothing to resolve ahead of time
+                let target_block = match prog.blocks.get(target_block_name) {
                         Some(b) => Ok(b),
-                        None => Err(RuntimeError::InvalidBlockInControl { instr: &cur_block.next, bname: b })
+                        None => Err(RuntimeError::InvalidBlockInControl { instr: &cur_block.next, bname: target_block_name })
                 }?;
-                cycles.uncond();
+                cx.cycles.uncond();
                 prevblock = Some(cur_block.name);
                 cur_block = target_block;
+                cur_id = cx.st.blocks.id(target_block_name);
             },
             ControlXfer::If { cond, tblock, fblock } => {
-                let vcond = expr_val(&locs[locs.len()-1], &globs, &prog, &cond)?;
+                let vcond = expr_val(&locs[locs.len()-1], &globs, &prog, &cond, opts, cx.st)?;
                 // TODO: Reconsider if we really want global addresses to count as true instead of errors
-                let target_block_name = match vcond {
-                    VirtualVal::Data { val: 0 } => fblock,
-                    _ => tblock
+                let (which, target_block_name) = match vcond {
+                    VirtualVal::Data { val: 0 } => (1, fblock),
+                    _ => (0, tblock)
                 };
-                let target_block = match prog.blocks.get(target_block_name) {
-                        Some(b) => Ok(b),
-                        None => Err(RuntimeError::InvalidBlockInControl { instr: &cur_block.next, bname: target_block_name })
+                let (target_id, target_block) = resolve_target(prog, cx.st, cur_id, which, &cur_block.next, target_block_name)?;
+                cx.cycles.cond();
+                prevblock = Some(cur_block.name);
+                cur_block = target_block;
+                cur_id = target_id;
+            },
+            ControlXfer::IfSigned { cond, neg_block, zero_block, pos_block } => {
+                let vcond = expr_val(&locs[localsindex], globs, prog, cond, opts, cx.st)?;
+                let (which, target_block_name) = match vcond {
+                    VirtualVal::CodePtr { val: b } => Err(RuntimeError::CodeAddressArithmetic { bname: b }),
+                    VirtualVal::GCTombstone => Err(RuntimeError::ReadFromGCedData),
+                    VirtualVal::Data { val } => Ok(match (val as i64).signum() {
+                        -1 => (0, neg_block),
+                        0 => (1, zero_block),
+                        _ => (2, pos_block)
+                    })
                 }?;
-                cycles.cond();
+                let (target_id, target_block) = resolve_target(prog, cx.st, cur_id, which, &cur_block.next, target_block_name)?;
+                cx.cycles.cond();
                 prevblock = Some(cur_block.name);
                 cur_block = target_block;
+                cur_id = target_id;
             }
         }
+        cx.st.flush_trace(opts, cx.cycles);
     }
-    Ok(finalresult.unwrap())
+    Ok(Flow::Return(finalresult.unwrap()))
+}
+/// Everything a run produces besides its stats, for callers that want to inspect the outcome rather than read stdout
+#[derive(Debug)]
+pub struct ExecResult<'a> {
+    /// What main returned: Ok(None) if it ended with ret void, or if the run was stopped before it returned
+    pub result: Outcome<'a>,
+    /// Why the run was stopped early, if it was (a run stopped on purpose hasn't failed)
+    pub stopped: Option<Stop<'a>>,
+    /// Fragmentation report from the most recent garbage collection, if any ran
+    pub fragmentation: Option<FragmentationReport>,
+    /// Final state of memory, if execution got far enough to lay it out
+    pub memory: Option<Memory<'a>>,
+    /// Trace lines, in order, if tracing was enabled (and the final globals, if RunOptions::print_final_globals asked for them)
+    pub trace: Vec<String>,
+    /// The block the run was in as it wrote each trace line (None before it entered main), parallel to trace
+    pub trace_blocks: Vec<Option<&'a str>>,
+    /// Errors a best-effort run recovered from, in order. Nonempty means the result is not trustworthy.
+    pub recovered: Vec<RuntimeError<'a>>,
+    /// Every block entered, in order and with repeats, if RunOptions::record_path asked for it
+    pub path: Vec<&'a str>,
+    /// Whether the path stopped short because it reached the recording limit
+    pub path_truncated: bool,
+    /// Stats as of each checkpoint instruction executed, in order; subtract two (ExecStats::since) to cost the phase between them
+    pub checkpoints: Vec<(&'a str,ExecStats)>,
+    /// Memory as it stood when the run failed, if it failed after memory was laid out
+    pub crash_snapshot: Option<MemorySnapshot<'a>>,
+    /// Each RunOptions::breakpoints entry reached, in order, once per time it was reached
+    pub breakpoint_hits: Vec<BreakpointHit<'a>>,
+    /// Stores never read, if RunOptions::track_dead_stores asked for them (see Memory::dead_stores)
+    pub dead_stores: Vec<DeadStore<'a>>,
+    /// Fields never written, if RunOptions::track_uninit_fields asked for them (see Memory::uninit_fields)
+    pub uninit_fields: Vec<UninitField>,
+    /// Every warning the run raised: those raised as it went, in order, then its dead stores and unwritten fields.
+    /// A run can finish Ok with warnings.
+    pub warnings: Vec<Warning<'a>>,
+    /// States recorded by RunOptions::snapshot_every and stop_at_step, in order
+    pub snapshots: Vec<Snapshot<'a>>,
+    /// With RunOptions::sample_live_every, the most slots allocated and the most found reachable at once
+    pub peak_live: Option<LivePeak>,
+    /// With RunOptions::capture_output, what the program printed (one value per line, each ending in a newline), up
+    /// to where it stopped; otherwise None, and it went to stdout
+    pub output: Option<String>,
+}
+impl <'a> ExecResult<'a> {
+    /// Whether the run only got where it did by substituting values for errors (see RunOptions::best_effort)
+    pub fn is_degraded(&self) -> bool {
+        !self.recovered.is_empty()
+    }
+    /// The trace as JSON (see json::to_json_trace), for recording a run to replay later
+    pub fn trace_json(&self) -> String {
+        to_json_trace(&self.trace, &self.trace_blocks)
+    }
+    /// One greppable line for the run, e.g. `OK result=42 steps=1234 allocs=7 gc=1`, `FAIL reason=NullPointer steps=56`
+    /// or `STOPPED reason=Cancelled steps=10`.
+    /// The stats should be the ones the run was given.
+    pub fn summary(&self, stats: &ExecStats) -> String {
+        match (&self.result, &self.stopped) {
+            (Ok(_), Some(stop)) => format!("STOPPED reason={} steps={}", error_name(stop), stats.steps()),
+            (Ok(v), None) => format!("OK result={} steps={} allocs={} gc={}", v.as_ref().map_or("void".to_string(), |v| v.to_string()), stats.steps(), stats.allocs, stats.gcs),
+            (Err(e), _) => format!("FAIL reason={} steps={}", error_name(e), stats.steps())
+        }
+    }
+    /// The outcome and every stat as one line of key=value pairs separated by spaces, for CI logs: grep for a key, or
+    /// split with awk. Every key is always present, in this order:
+    /// - status: ok, fail, or stopped
+    /// - result: what main returned, void, or none if the run failed or was stopped
+    /// - reason: the error's or Stop's variant name, or none if the run succeeded
+    /// - steps (ExecStats::steps)
+    /// - each counter under its trace label: fast_op slow_op cond_branch uncond_branch call arg_pass ret mem_read
+    ///   mem_write alloc print phi
+    /// - block_cost, then gc
+    /// - bytes_read, then bytes_written
+    ///
+    /// Keys are only ever added at the end. The stats should be the ones the run was given.
+    pub fn to_kv(&self, stats: &ExecStats) -> String {
+        let (status, result, reason) = match (&self.result, &self.stopped) {
+            (Ok(_), Some(stop)) => ("stopped", "none".to_string(), error_name(stop)),
+            (Ok(v), None) => ("ok", v.as_ref().map_or("void".to_string(), |v| v.to_string()), "none".to_string()),
+            (Err(e), _) => ("fail", "none".to_string(), error_name(e))
+        };
+        let mut line = format!("status={} result={} reason={} steps={}", status, result, reason, stats.steps());
+        for (label, n) in stats.counters() {
+            line += &format!(" {}={}", label, n);
+        }
+        line + &format!(" block_cost={} gc={} bytes_read={} bytes_written={}", stats.block_cost, stats.gcs, stats.bytes_read,
+                        stats.bytes_written)
+    }
+}
+// Just an error's (or Stop's) variant name, without its fields
+fn error_name(e: &impl fmt::Debug) -> String {
+    let name = format!("{:?}", e);
+    name.split(|c: char| !c.is_alphanumeric()).next().unwrap_or_default().to_string()
 }
-pub fn run_prog<'a>(prog: &'a IRProgram, tracing: bool, mut cycles: &mut ExecStats, cap:ExecMode) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
 
-    let main = prog.blocks.get("main");
-    if main.is_none() {
-        return Err(RuntimeError::MissingMain);
+// Shared by run_prog and eval_prog: runs main, handing back the final memory state for reporting
+fn exec_main<'a>(prog: &'a IRProgram, opts: &RunOptions, st: &mut RunState<'a>, cycles: &mut ExecStats, cap: ExecMode) -> (Outcome<'a>, Option<(Memory<'a>,Globals<'a>)>) {
+    if !prog.blocks.contains_key("main") {
+        return (Err(RuntimeError::MissingMain), None);
     }
-    let cur_block = main.unwrap();
-    let (mut m, mut globs) = Memory::new(prog,cap);
-    if tracing {
-        println!("Initial Globals:\n{:?}", globs);
+    exec_entry(prog, "main", &InitialHeap::default(), opts, st, cycles, cap)
+}
+
+// Set up the per-program parts of the run state: interned block and variable names, constants, block costs, and the blocks
+// to memoize
+fn prepare_run<'a>(prog: &'a IRProgram, opts: &RunOptions, st: &mut RunState<'a>) -> Result<(),RuntimeError<'a>> {
+    st.blocks = BlockTable::new(prog);
+    for g in prog.globals.iter() {
+        if let GlobalStatic::Const { name, val } = g {
+            st.consts.insert(name, *val);
+        }
+    }
+    for (name, cost) in prog.pragmas.costs.iter() {
+        if let Some((bname,_)) = prog.blocks.get_key_value(name.as_str()) {
+            st.block_costs.insert(bname, *cost);
+        }
+    }
+    if opts.memoize_pure {
+        for name in prog.pragmas.pure.iter() {
+            // Declarations of blocks that don't exist are only worth a validation warning
+            if let Some((bname,_)) = prog.blocks.get_key_value(name.as_str()) {
+                if let Some((at, index)) = purity_violation(prog, bname) {
+                    return Err(RuntimeError::NotPure { bname, at, index });
+                }
+                st.pure_blocks.insert(bname);
+            }
+        }
+    }
+    Ok(())
+}
+
+// How a run ended, given what its entry block's run_code returned and the stack it left. A stopped run has no result,
+// so it comes back as Ok(None), with why it stopped in st.stopped.
+fn entry_outcome<'a>(flow: Result<Flow<'a>,RuntimeError<'a>>, stack: &[Locals<'a>], st: &mut RunState<'a>) -> Outcome<'a> {
+    match flow.map_err(|e| blame_caller(e, stack))? {
+        Flow::Return(vals) => match vals[..] {
+            [] => Ok(None),
+            [v] => Ok(Some(v)),
+            // There's nowhere to bind the rest
+            _ => Err(RuntimeError::WrongReturnCount { expected: 1, got: vals.len() })
+        },
+        Flow::Halt(val) => Ok(Some(val)),
+        Flow::Stop(stop) => {
+            st.stopped = Some(stop);
+            Ok(None)
+        }
+    }
+}
+
+// Carry a run on from a ResumePoint as if it had never stopped there, re-entering each frame at the instruction it
+// was at. Only the outcome comes back; snapshots and the rest accumulate in st as usual.
+fn exec_resumed<'a>(prog: &'a IRProgram, opts: &RunOptions, st: &mut RunState<'a>, point: ResumePoint<'a>) -> Outcome<'a> {
+    prepare_run(prog, opts, st)?;
+    let ResumePoint { frames, mut stack, mut memory, mut globals, mut stats, rng, block_entries, cast_vars, memo, next_snapshot, .. } = point;
+    (st.rng, st.block_entries, st.cast_vars, st.memo, st.next_snapshot) = (rng, block_entries, cast_vars, memo, next_snapshot);
+    let outermost = &prog.blocks[frames[0].0];
+    st.resuming = frames.into_iter().rev().collect();
+    let flow = run_code(&mut Ctx { prog, opts, st, cycles: &mut stats }, outermost, &mut stack, &mut globals, &mut memory);
+    st.flush_trace(opts, &stats);
+    entry_outcome(flow, &stack, st)
+}
+
+// exec_main, but starting from any block, with a heap laid out in advance (see run_entry)
+fn exec_entry<'a>(prog: &'a IRProgram, entry: &'a str, heap: &InitialHeap, opts: &RunOptions, st: &mut RunState<'a>, cycles: &mut ExecStats, cap: ExecMode) -> (Outcome<'a>, Option<(Memory<'a>,Globals<'a>)>) {
+    let cur_block = match prog.blocks.get(entry) {
+        Some(b) => b,
+        None => return (Err(RuntimeError::InvalidBlock { bname: entry }), None)
+    };
+    if let Err(e) = prepare_run(prog, opts, st) {
+        return (Err(e), None);
     }
-    // Run main with an empty variable
+    let (mut m, mut globs) = match Memory::new(prog,cap.with_pragmas(&prog.pragmas),opts.cap_includes_globals,opts.max_address) {
+        Ok(layout) => layout,
+        Err(e) => return (Err(e), None)
+    };
+    m.check_slotmaps = opts.check_slotmaps;
+    m.guard_words = opts.guard_words;
+    m.deterministic = opts.deterministic;
+    m.track_ages = opts.track_ages;
+    m.check_header_init = opts.check_header_init;
+    m.weak_refs = opts.weak_refs;
+    m.track_dead_stores = opts.track_dead_stores;
+    m.track_uninit_fields = opts.track_uninit_fields;
+    m.max_live_objects = opts.max_live_objects;
+    m.fill_word = opts.fill_word;
+    m.check_code_stores = opts.check_code_stores;
+    // A filtered trace is for looking at particular instructions, so leave out the globals
+    if opts.tracing && !opts.trace_filter.is_active() {
+        if opts.deterministic {
+            st.emit_trace(format!("Initial Globals:\n{:?}", globs.iter().collect::<BTreeMap<_,_>>()));
+        } else {
+            st.emit_trace(format!("Initial Globals:\n{:?}", globs));
+        }
+    }
+    let addrs = match m.place_objects(&heap.objects) {
+        Ok(addrs) => addrs,
+        Err(e) => return (Err(e), Some((m, globs)))
+    };
+    let mut entryvars = Locals::new();
+    if !cur_block.accepts_args(heap.args.len()) {
+        return (Err(RuntimeError::BadEntryArity { bname: entry, got: heap.args.len() }), Some((m, globs)));
+    }
+    for (formal, arg) in cur_block.formals.iter().zip(heap.args.iter()) {
+        let val = match arg.resolve(&addrs) {
+            Ok(val) => val,
+            Err(e) => return (Err(e), Some((m, globs)))
+        };
+        entryvars.assign(prog.vars.id(formal), formal, VirtualVal::Data { val });
+    }
+    if let Err(e) = bind_defaults(cur_block, &mut entryvars, heap.args.len(), &globs, prog, opts, st) {
+        return (Err(e), Some((m, globs)));
+    }
+    // Run the entry block (main, usually with no variables at all)
     let mut stack = Vec::new();
-    stack.push(HashMap::new());
-    let fresult = run_code(prog, cur_block, &mut stack, &mut globs, &mut m, tracing, &mut cycles);
+    stack.push(entryvars);
+    let flow = run_code(&mut Ctx { prog, opts, st, cycles }, cur_block, &mut stack, &mut globs, &mut m);
+    let fresult = entry_outcome(flow, &stack, st);
+    // If execution stopped with an error, the instruction responsible is still waiting to be traced
+    st.flush_trace(opts, cycles);
+    let finished = match fresult {
+        Ok(_) => st.stopped.is_none(),
+        Err(RuntimeError::Failed { .. }) => opts.best_effort && opts.continue_past_fail,
+        Err(_) => false
+    };
+    if opts.print_final_globals && finished {
+        st.emit_trace("Final Globals:".to_string());
+        for (name, index, val) in m.global_slots() {
+            st.emit_trace(format!("\t@{}[{}] = {}", name, index, val.map_or("<unset>".to_string(), |v| v.to_string())));
+        }
+    }
+    (fresult, Some((m, globs)))
+}
+
+/// Run a program without printing the outcome or trace, returning a structured result instead
+pub fn eval_prog<'a>(prog: &'a IRProgram, opts: &RunOptions, cycles: &mut ExecStats, cap: ExecMode) -> ExecResult<'a> {
+    eval_with(prog, opts, RunState::new(opts), cycles, cap)
+}
+
+/// A field or argument value in an InitialHeap
+#[derive(Debug,PartialEq,Eq,Clone,Copy)]
+pub enum HeapValue {
+    /// A plain number
+    Int(u64),
+    /// The address of the object at this index in InitialHeap::objects
+    Object(usize),
+}
+impl HeapValue {
+    // The word this stands for, given the addresses the objects were placed at
+    fn resolve<'a>(&self, addrs: &[u64]) -> Result<u64,RuntimeError<'a>> {
+        match self {
+            HeapValue::Int(val) => Ok(*val),
+            HeapValue::Object(idx) =>
+                addrs.get(*idx).copied().ok_or(RuntimeError::UnknownHeapObject { index: *idx, objects: addrs.len() })
+        }
+    }
+}
+
+/// Data for run_entry to set up before the entry block starts, so a single function can be tested on existing data
+/// structures without IR to build them
+#[derive(Debug,PartialEq,Eq,Clone,Default)]
+pub struct InitialHeap {
+    /// Each object's fields, in order; objects are allocated in this order, after the globals
+    pub objects: Vec<Vec<HeapValue>>,
+    /// The entry block's arguments, bound to its formals in order as a call would (so defaults fill in the rest)
+    pub args: Vec<HeapValue>,
+}
+
+/// Like eval_prog, but starting at the named block instead of main, with the given objects already in memory and its
+/// formals bound to the given arguments. A void return comes back as Ok(None), as for eval_prog.
+pub fn run_entry<'a>(prog: &'a IRProgram, entry: &'a str, heap: &InitialHeap, opts: &RunOptions, cycles: &mut ExecStats, cap: ExecMode) -> ExecResult<'a> {
+    let mut st = RunState::new(opts);
+    st.trace = Some(vec![]);
+    let (result, state) = exec_entry(prog, entry, heap, opts, &mut st, cycles, cap);
+    eval_result(st, result, state)
+}
+
+/// An input on which differential_test found the two programs disagreeing
+#[derive(Debug,PartialEq)]
+pub struct Divergence<'a> {
+    /// Index of the input in the list given to differential_test
+    pub input: usize,
+    pub a: Outcome<'a>,
+    pub b: Outcome<'a>,
+    /// Both runs' stats, when asked to compare them and they differ (even if the results agree)
+    pub stats: Option<(ExecStats,ExecStats)>,
+}
+
+/// Run entry in both programs via run_entry on each input, whose numbers are the entry's arguments, and report every
+/// input where they disagree: different return values, one failing and not the other, or different kinds of error
+/// (the same error at a different instruction or address counts as agreeing, since the programs' code differs).
+/// With compare_stats, different ExecStats count too. Runs use the default options and no memory cap. What the
+/// programs print isn't compared; it goes to stdout as usual.
+pub fn differential_test<'a>(a: &'a IRProgram, b: &'a IRProgram, entry: &'a str, inputs: &[Vec<u64>], compare_stats: bool) -> Vec<Divergence<'a>> {
+    let opts = RunOptions::new();
+    let mut divergences = vec![];
+    for (index, input) in inputs.iter().enumerate() {
+        let heap = InitialHeap { objects: vec![], args: input.iter().map(|n| HeapValue::Int(*n)).collect() };
+        let mut stats_a = ExecStats::new();
+        let mut stats_b = ExecStats::new();
+        let res_a = run_entry(a, entry, &heap, &opts, &mut stats_a, ExecMode::Unlimited).result;
+        let res_b = run_entry(b, entry, &heap, &opts, &mut stats_b, ExecMode::Unlimited).result;
+        let agree = match (&res_a, &res_b) {
+            (Ok(x), Ok(y)) => x == y,
+            (Err(x), Err(y)) => std::mem::discriminant(x) == std::mem::discriminant(y),
+            _ => false
+        };
+        let stats = if compare_stats && stats_a != stats_b { Some((stats_a, stats_b)) } else { None };
+        if !agree || stats.is_some() {
+            divergences.push(Divergence { input: index, a: res_a, b: res_b, stats });
+        }
+    }
+    divergences
+}
+
+/// The smallest slot cap under which prog runs from main to completion in GC mode with opts, found by doubling the
+/// cap until the program fits and then binary-searching below that. None if some run fails for any reason other than
+/// running out of memory. What the runs print is captured and thrown away. A `;@ cap` pragma in the program overrides
+/// the cap each run asks for, so for such a program this only tells whether that cap suffices.
+pub fn min_cap(prog: &IRProgram, opts: &RunOptions) -> Option<u64> {
+    let opts = RunOptions { capture_output: true, ..opts.clone() };
+    // Whether the program completes with this cap, or None if it fails some other way
+    let fits = |limit: u64| match eval_prog(prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit }).result {
+        Ok(_) => Some(true),
+        Err(RuntimeError::OutOfMemory) => Some(false),
+        Err(_) => None
+    };
+    // Invariant once found: hi fits, lo doesn't (or lo is 0 and hi is the first cap tried)
+    let mut lo = 0;
+    let mut hi = 1;
+    while !fits(hi)? {
+        lo = hi;
+        hi = hi.checked_mul(2)?;
+    }
+    if lo == 0 && fits(0)? {
+        return Some(0);
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if fits(mid)? {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Some(hi)
+}
+
+/// Like eval_prog, but stopping with Stop::Cancelled as soon as should_cancel returns true. It's asked before every
+/// instruction (including control transfers), so it should be cheap, e.g. checking a flag or a deadline.
+pub fn eval_prog_cancellable<'a>(prog: &'a IRProgram, opts: &RunOptions, cycles: &mut ExecStats, cap: ExecMode, should_cancel: &'a dyn Fn() -> bool) -> ExecResult<'a> {
+    let mut st = RunState::new(opts);
+    st.should_cancel = Some(should_cancel);
+    eval_with(prog, opts, st, cycles, cap)
+}
+
+fn eval_with<'a>(prog: &'a IRProgram, opts: &RunOptions, mut st: RunState<'a>, cycles: &mut ExecStats, cap: ExecMode) -> ExecResult<'a> {
+    st.trace = Some(vec![]);
+    let (result, state) = exec_main(prog, opts, &mut st, cycles, cap);
+    eval_result(st, result, state)
+}
+
+// Package up a finished run for eval_prog and friends
+fn eval_result<'a>(st: RunState<'a>, result: Outcome<'a>, state: Option<(Memory<'a>,Globals<'a>)>) -> ExecResult<'a> {
+    let crash_snapshot = match (&result, &state) {
+        (Err(_), Some((m,globs))) => Some(m.snapshot(globs)),
+        _ => None
+    };
+    let memory = state.map(|(m,_)| m);
+    let dead_stores = memory.as_ref().map(|m| m.dead_stores()).unwrap_or_default();
+    let uninit_fields = memory.as_ref().map(|m| m.uninit_fields()).unwrap_or_default();
+    let mut warnings = st.warnings;
+    warnings.extend(dead_stores.iter().cloned().map(Warning::DeadStore));
+    warnings.extend(uninit_fields.iter().cloned().map(Warning::UninitField));
+    ExecResult {
+        result,
+        stopped: st.stopped,
+        fragmentation: memory.as_ref().and_then(|m| m.last_fragmentation.clone()),
+        memory,
+        trace: st.trace.unwrap_or_default(),
+        trace_blocks: st.trace_blocks,
+        recovered: st.recovered,
+        path: st.path,
+        path_truncated: st.path_truncated,
+        checkpoints: st.checkpoints,
+        crash_snapshot,
+        breakpoint_hits: st.breakpoint_hits,
+        dead_stores,
+        uninit_fields,
+        warnings,
+        snapshots: st.snapshots,
+        peak_live: st.peak_live,
+        output: st.output,
+    }
+}
+
+/// Where a replayed run first stopped matching its recorded trace
+#[derive(Debug,PartialEq)]
+pub struct ReplayMismatch<'a> {
+    /// Index of the first differing trace line
+    pub step: usize,
+    /// Block the replay was executing at that point, if it had entered one
+    pub block: Option<&'a str>,
+    /// The recorded line and the block it was written in, or None if the replay produced more lines than were recorded
+    pub expected: Option<TraceLine>,
+    /// The replayed line, or None if the replay ended early
+    pub actual: Option<String>,
+}
+impl <'a> fmt::Display for ReplayMismatch<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let recorded_block = self.expected.as_ref().and_then(|e| e.block.as_deref());
+        write!(f, "replay diverged at step {}: expected {:?} (block {}), got {:?} (block {})", self.step,
+            self.expected.as_ref().map(|e| &e.line), recorded_block.unwrap_or("<none>"), self.actual, self.block.unwrap_or("<none>"))
+    }
+}
+
+/// Why a replay failed: the recording couldn't be read, or the run didn't match it
+#[derive(Debug,PartialEq)]
+pub enum ReplayError<'a> {
+    BadTrace(JsonError),
+    Mismatch(ReplayMismatch<'a>),
+}
+impl <'a> fmt::Display for ReplayError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::BadTrace(e) => write!(f, "{}", e),
+            ReplayError::Mismatch(m) => write!(f, "{}", m),
+        }
+    }
+}
+
+/// Re-run a program and check it produces exactly a previously recorded JSON trace (ExecResult::trace_json from
+/// eval_prog with tracing on): the same lines, each written in the same block. For catching nondeterminism or locking
+/// in a known-good run. Use the same options and mode as the recording; tracing is turned on regardless.
+pub fn replay<'a>(prog: &'a IRProgram, opts: &RunOptions, cap: ExecMode, recorded: &str) -> Result<(),ReplayError<'a>> {
+    let recorded = from_json_trace(recorded).map_err(ReplayError::BadTrace)?;
+    let opts = RunOptions { tracing: true, ..opts.clone() };
+    let mut st = RunState::new(&opts);
+    st.trace = Some(vec![]);
+    // The outcome is only compared through the trace lines it produced
+    let _ = exec_main(prog, &opts, &mut st, &mut ExecStats::new(), cap);
+    let actual = st.trace.unwrap_or_default();
+    for step in 0..actual.len().max(recorded.len()) {
+        let same = match (actual.get(step), recorded.get(step)) {
+            (Some(line), Some(rec)) => *line == rec.line && st.trace_blocks[step] == rec.block.as_deref(),
+            _ => false
+        };
+        if !same {
+            return Err(ReplayError::Mismatch(ReplayMismatch {
+                step,
+                block: st.trace_blocks.get(step).or(st.trace_blocks.last()).copied().flatten(),
+                expected: recorded.get(step).cloned(),
+                actual: actual.get(step).cloned(),
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// Time-travel debugging by snapshot and resume. Every `every` steps the run is snapshotted, with enough of its state
+/// (the whole stack, memory, globals and stats) to carry on from there later; `back` can land on any of those
+/// boundaries, and `forward` resumes from wherever the debugger is now, so it costs only the steps it covers. Going
+/// back only ever lands on a boundary, never between two: to reach a point in between, go back past it and step
+/// forward. Stepping forward over ground already covered runs the program's prints again.
+pub struct TimeTravel<'a> {
+    prog: &'a IRProgram<'a>,
+    // The caller's options, with snapshot_every set
+    opts: RunOptions,
+    // Snapshot boundaries reached so far, with what it takes to resume from each
+    boundaries: Vec<(Snapshot<'a>,ResumePoint<'a>)>,
+    /// Where the debugger is now
+    pub current: Snapshot<'a>,
+    here: ResumePoint<'a>,
+}
+impl <'a> TimeTravel<'a> {
+    /// Start at the first instruction of main, or fail with whatever kept the run from getting that far
+    pub fn new(prog: &'a IRProgram<'a>, opts: &RunOptions, cap: ExecMode, every: u64) -> Result<TimeTravel<'a>,Outcome<'a>> {
+        let opts = RunOptions { snapshot_every: Some(every.max(1)), ..opts.clone() };
+        let run_opts = RunOptions { stop_at_step: Some(0), ..opts.clone() };
+        let mut st = TimeTravel::run_state(&run_opts);
+        let (outcome, _) = exec_main(prog, &run_opts, &mut st, &mut ExecStats::new(), cap);
+        match (outcome, st.stopped) {
+            (Ok(_), Some(Stop::AtStep { .. })) => {
+                let (current, here) = (st.snapshots.pop().unwrap(), st.resume_points.as_mut().unwrap().pop().unwrap());
+                let mut tt = TimeTravel { prog, opts, boundaries: vec![], current: current.clone(), here: here.clone() };
+                if here.boundary {
+                    tt.boundaries.push((current, here));
+                }
+                Ok(tt)
+            },
+            (outcome, _) => Err(outcome)
+        }
+    }
+    fn run_state(opts: &RunOptions) -> RunState<'a> {
+        let mut st = RunState::new(opts);
+        st.trace = Some(vec![]);
+        st.resume_points = Some(vec![]);
+        st
+    }
+    /// Run on to at least `steps` further on (the first instruction boundary there), resuming from the current
+    /// position. If the program ends first, that outcome is returned and the position stays put.
+    pub fn forward(&mut self, steps: u64) -> Option<Outcome<'a>> {
+        let opts = RunOptions { stop_at_step: Some(self.current.step.saturating_add(steps)), ..self.opts.clone() };
+        let mut st = TimeTravel::run_state(&opts);
+        let outcome = exec_resumed(self.prog, &opts, &mut st, self.here.clone());
+        match (outcome, st.stopped) {
+            (Ok(_), Some(Stop::AtStep { .. })) => {
+                let mut points = st.resume_points.take().unwrap_or_default();
+                let (current, here) = (st.snapshots.pop().unwrap(), points.pop().unwrap());
+                // Boundaries past where we were are about to be taken again, identically
+                self.boundaries.retain(|(s,_)| s.step <= self.current.step);
+                self.boundaries.extend(st.snapshots.into_iter().zip(points));
+                if here.boundary {
+                    self.boundaries.push((current.clone(), here.clone()));
+                }
+                self.current = current;
+                self.here = here;
+                None
+            },
+            (outcome, _) => Some(outcome)
+        }
+    }
+    /// Go back to the nearest snapshot boundary before the current position. Returns false at the start of the run.
+    pub fn back(&mut self) -> bool {
+        match self.boundaries.iter().rev().find(|(s,_)| s.step < self.current.step) {
+            Some((prev, point)) => {
+                self.current = prev.clone();
+                self.here = point.clone();
+                true
+            },
+            None => false
+        }
+    }
+}
+
+/// Wall-clock times for repeated runs of one program, from bench_run
+#[derive(Debug)]
+pub struct BenchReport<'a> {
+    pub iterations: usize,
+    pub min: Duration,
+    pub median: Duration,
+    pub max: Duration,
+    /// ExecStats::steps for one run; every run takes the same number, since runs are deterministic
+    pub steps: u64,
+    /// How the last run ended (the others ended the same way)
+    pub result: Outcome<'a>,
+}
+
+/// Run prog from main iterations times with the default options and no memory cap, timing each run. Every run starts
+/// from fresh memory, globals and stats, so nothing carries over from one to the next. Runs still print whatever the
+/// program prints, every time.
+pub fn bench_run<'a>(prog: &'a IRProgram, iterations: usize) -> BenchReport<'a> {
+    let opts = RunOptions::new();
+    let mut times = Vec::with_capacity(iterations);
+    let mut steps = 0;
+    let mut result = Ok(None);
+    for _ in 0..iterations {
+        let mut cycles = ExecStats::new();
+        let start = Instant::now();
+        let run = eval_prog(prog, &opts, &mut cycles, ExecMode::Unlimited);
+        times.push(start.elapsed());
+        steps = cycles.steps();
+        result = run.result;
+    }
+    times.sort();
+    BenchReport {
+        iterations,
+        min: times.first().copied().unwrap_or_default(),
+        median: times.get(iterations / 2).copied().unwrap_or_default(),
+        max: times.last().copied().unwrap_or_default(),
+        steps,
+        result
+    }
+}
+
+/// Process exit status for the outcome of a run, so shell scripts can branch on it:
+///
+/// | code | meaning |
+/// |------|---------|
+/// | 0 | program returned normally |
+/// | 1 | (not produced here; left for the CLI itself failing, e.g., a parse error) |
+/// | 2 | program executed an explicit `fail`, or an `assertheap` check failed |
+/// | 3 | program ran out of memory |
+/// | 4 | any other runtime error |
+pub fn exit_code<T>(result: &Result<T, RuntimeError>) -> i32 {
+    match result {
+        Ok(_) => 0,
+        Err(RuntimeError::Failed { .. }) | Err(RuntimeError::HeapAssertionFailed { .. }) => 2,
+        Err(RuntimeError::OutOfMemory) | Err(RuntimeError::GCRequired) => 3,
+        Err(_) => 4
+    }
+}
+
+/// A paragraph for students on what an error means and what usually causes it, to print after a crash report
+pub fn explain(err: &RuntimeError) -> &'static str {
+    match err {
+        RuntimeError::AccessingCodeInMemory { .. } =>
+            "A code pointer (a block name) was used as a memory address or offset. Code lives outside memory, so it can't be read or written like an object; usually a vtable or function pointer was passed where the object holding it was meant.",
+        RuntimeError::AccessingDeallocatedAddress { .. } =>
+            "Memory at this address was reclaimed by the garbage collector. Something kept using an old copy of a pointer after a collection moved the object, typically a pointer hidden where the collector can't see it (arithmetic on it, or a field the slotmap doesn't mark).",
+        RuntimeError::AllocationTooLarge { .. } =>
+            "An alloc's size, a literal written in the program, is so large that the object would reach past the configured maximum address (RunOptions::max_address) or the end of memory. Use a smaller size or raise the limit; sizes that merely exceed the memory cap fail with OutOfMemory instead.",
+        RuntimeError::BadCallArity { .. } =>
+            "A call passed a different number of arguments than the callee declares. The receiver counts as the first formal, so a block with formals (this, x) takes the receiver plus one argument.",
+        RuntimeError::BadEntryArity { .. } =>
+            "run_entry was given a different number of arguments than the entry block declares (less any trailing formals with defaults). Pass one InitialHeap argument per formal, in order.",
+        RuntimeError::BadGCField =>
+            "The collector found a code pointer in a field the slotmap says holds an object pointer. Check the slotmap bits: vtable and method-pointer fields should not be marked.",
+        RuntimeError::BadPhiPredecessor { .. } =>
+            "A phi has no entry for the block control actually came from. Every block that can jump to a phi's block needs its own arm, usually missing after adding a new edge to a loop header or join point.",
+        RuntimeError::BlockFuelExceeded { .. } =>
+            "One block ran more times than the run allows any single block to. That's almost always a loop that never ends: check that its condition eventually changes, e.g. that the counter is updated on every path back to the loop header.",
+        RuntimeError::CallToNonEntry { .. } =>
+            "A call targeted a block that isn't a function entry. Calls should go to the first block of a function; the middle of a function can only be reached by jumps that set up its phis.",
+        RuntimeError::CallingNonCode =>
+            "A call's target evaluated to a number rather than a code pointer. Often a method was loaded from the wrong vtable slot, or the object pointer itself was used as the method.",
+        RuntimeError::CodeAddressArithmetic { .. } =>
+            "Arithmetic was done on a code pointer. Block names aren't numbers; compare or call them, but don't add to them. This often means a vtable entry was used where a field value was expected.",
+        RuntimeError::CorruptGCMetadata { .. } =>
+            "An object's GC header (size, forwarding pointer, or slotmap) held something other than a plain number. Usually a write ran off the front of an object, or used a negative offset, and clobbered the header.",
+        RuntimeError::DivisionByZero { .. } =>
+            "A division or divmod had a zero divisor. Check the divisor before dividing, for example with an if that branches to a fail.",
+        RuntimeError::EmptyRandomRange =>
+            "rand was asked for a number in [0, 0), which has no values. The bound must be at least 1.",
+        RuntimeError::Failed { .. } =>
+            "The program ran a fail instruction on purpose, reporting a dynamic check it inserted itself (a non-pointer where an object was needed, a missing field or method, and so on). Look at which check failed and why its input was wrong.",
+        RuntimeError::GCRequired =>
+            "Memory ran out in a mode with a garbage collector, and no collection was run to make room. Programs don't normally see this; it means an allocation policy forgot to collect before giving up.",
+        RuntimeError::GuardZoneAccess { .. } =>
+            "The address lies in the unused gap left in front of an object to catch overruns. Usually an index one past the end of the previous object (an off-by-one), or a negative offset from this one.",
+        RuntimeError::HeapAssertionFailed { .. } =>
+            "An assertheap predicate found the heap breaking the invariant it checks. The bug is in whatever code last changed that data structure: look at the stores since the previous assertion held.",
+        RuntimeError::InvalidBlock { .. } =>
+            "A call named a block that doesn't exist. Check the spelling, and that the block was actually emitted.",
+        RuntimeError::IndirectJumpDisabled =>
+            "The program used jump %x, which is only allowed when code pointer tables are enabled in the run options.",
+        RuntimeError::InvalidBlockInControl { .. } =>
+            "A jump or branch names a block that doesn't exist. Check the spelling, and that every label the generator refers to is actually emitted.",
+        RuntimeError::JumpToNonCode =>
+            "An indirect jump's target was a number rather than a code pointer. Often the wrong slot of a jump table was loaded.",
+        RuntimeError::MissingMain =>
+            "There is no block named main, so there is nowhere to start. Every program needs one.",
+        RuntimeError::NotPure { .. } =>
+            "A block declared ;@ pure can have an effect: it stores, prints, allocates, reads memory through a pointer, or calls something not declared pure. Memoizing its calls could change what the program does, so the run refused to start. Move the effect out of the function or drop the declaration.",
+        RuntimeError::NullPointer =>
+            "Memory was accessed through address 0. A pointer was used before being set to an allocated object, or a null check was missing before a field or method access.",
+        RuntimeError::OffsetOverflow { .. } =>
+            "An element offset was so large that the address it names lies past the end of memory. The offset was probably computed from garbage, such as a pointer used as an index, or a subtraction that went below zero.",
+        RuntimeError::OutOfMemory =>
+            "The program allocated more than the memory cap allows. Without a collector nothing is ever freed, so long loops that allocate run out; with a collector, everything still reachable didn't fit.",
+        RuntimeError::LiveObjectLimitExceeded { .. } =>
+            "The program had more objects alive at once than the run allows. It doesn't matter how small they are; look for structures that keep every object reachable (a list that only grows, a cache never cleared), or reuse objects instead of allocating new ones.",
+        RuntimeError::OverwroteGCHeader { .. } =>
+            "A write landed on an object's GC header instead of one of its fields. Usually a negative offset, or running off the end of the previous object.",
+        RuntimeError::PlacementNotFree { .. } =>
+            "The allocator placed an object over memory that wasn't free: part of a live object, a guard zone, a global, or past the end of the heap. This is a bug in the allocator, not the program; it must only place objects in words it reserved or released.",
+        RuntimeError::UnknownHeapObject { .. } =>
+            "The initial heap given to run_entry refers to an object it doesn't have. HeapValue::Object takes an index into InitialHeap::objects, counting from 0.",
+        RuntimeError::UntraceableHeapField { .. } =>
+            "An object in the initial heap given to run_entry has a reference beyond its 64th field. A slotmap only has bits for the first 64 fields, so the collector couldn't trace it; put references first, or split the object.",
+        RuntimeError::PhiNotAtBlockStart { .. } =>
+            "A phi ran after an ordinary instruction in the same block. Phis choose values based on how control arrived, so they must all come first in their block; this usually means the front end emitted code before a join point's phis.",
+        RuntimeError::PhiInFirstBlock { .. } =>
+            "A phi ran in the first block a function executed, where there is no predecessor to choose an arm by. Phis only belong in blocks reached by jumps.",
+        RuntimeError::UnalignedAccess { .. } =>
+            "Memory is word-addressed: addresses must be multiples of 8 (or of the element size for narrow accesses). This pointer wasn't, often from integer arithmetic on a non-pointer, or adding an index without multiplying by 8.",
+        RuntimeError::UnallocatedAddressRead { .. } =>
+            "A read from an address no object covers. Often an index past the end of an object, or an integer that was never a pointer being used as one.",
+        RuntimeError::UnallocatedAddressWrite { .. } =>
+            "A write to an address no object covers. Often an index past the end of an object, or an integer that was never a pointer being used as one.",
+        RuntimeError::UninitializedVariable { .. } =>
+            "A variable was read before anything assigned it on the path the program took. Check every path to the read, especially the arms of any phi that feeds it, and remember each function call starts with only its formals.",
+        RuntimeError::VariableFromCaller { .. } =>
+            "A function read a variable it never assigned, though the function that called it has one by that name. Locals don't cross call boundaries: each call starts with only its formals, so pass the value as an argument (or return it) instead.",
+        RuntimeError::GlobalTooLarge { .. } =>
+            "A global array declares more slots than could ever be laid out: more than the memory cap, past the configured maximum address (RunOptions::max_address), or more than the largest object an alloc can make. The declared size is a literal in the data section; it's probably a typo.",
+        RuntimeError::GlobalOutOfBounds { .. } =>
+            "A getelt indexed a global past the slots it declares, which would read whatever global comes next. Check the index against the global's size, remembering indices start at 0.",
+        RuntimeError::UndefinedGlobal { .. } =>
+            "An @name refers to a global that the data section never declares. Check the spelling and that the global was emitted.",
+        RuntimeError::UndefinedConst { .. } =>
+            "A $name refers to a constant that the data section never declares with `global const`. Check the spelling and that the constant was emitted.",
+        RuntimeError::ReadFromGCedData =>
+            "Something read memory the collector has already evacuated, through a stale pointer to an object's old location. Pointers the collector can't see (hidden in arithmetic or unmarked fields) aren't updated when their object moves.",
+        RuntimeError::ReturnedDanglingReference { .. } =>
+            "A function returned a pointer to memory the collector has reclaimed, so the caller would be holding a dangling reference. Make sure anything a function returns stays reachable from a variable until it does.",
+        RuntimeError::SlotmapSizeMismatch { .. } =>
+            "An object's slotmap marks fields past the end of the object, or the object is too big for a 64-bit slotmap. Check the slotmap is computed for this object's own field count.",
+        RuntimeError::WriteToGCedData { .. } =>
+            "Something wrote to memory the collector has already evacuated, through a stale pointer to an object's old location.",
+        RuntimeError::WriteToImmutableData =>
+            "A write targeted a global. Globals (vtables, constant tables) are read-only; data that changes belongs in an allocated object.",
+        RuntimeError::WrongReturnCount { .. } =>
+            "A call bound a different number of results than the callee returned. ret a, b needs a call with two destinations; a single-destination call expects one value (or none for ret void).",
+        RuntimeError::StoringCodePtrInTracedField { .. } =>
+            "A code pointer (a block name) went into a field the object's slotmap marks as a pointer, and the collector can only trace pointers to objects. Either the slotmap has the wrong bit set, or a vtable or method pointer was stored in a field meant for an object.",
+        RuntimeError::UseBeforeHeaderInit { .. } =>
+            "The program used an object's fields before storing its slotmap, so a collection at that point would trace it with whatever the header happened to hold. Store the slotmap (at the object's address minus 8) right after alloc, even if it's 0.",
+        RuntimeError::NYI =>
+            "The program used a feature the interpreter doesn't implement yet. Unknown operators are rejected when the program is parsed, so this is something that parsed fine but has no behavior; check the documentation for what's supported.",
+    }
+}
+
+/// Run a program, printing its result or crash report. A main that ends in ret void reports success with no
+/// final result; since this returns a plain value, it comes back as 0 (much like C's main falling off the end).
+/// Use eval_prog to tell the two apart.
+pub fn run_prog<'a>(prog: &'a IRProgram, tracing: bool, cycles: &mut ExecStats, cap:ExecMode) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+    run_prog_with_allocator(prog, tracing, cycles, cap, Box::new(BumpAllocator))
+}
+
+/// Like run_prog, but placing objects with a different allocation policy
+pub fn run_prog_with_allocator<'a>(prog: &'a IRProgram, tracing: bool, cycles: &mut ExecStats, cap:ExecMode, allocator: Box<dyn Allocator<'a> + 'a>) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+    let opts = RunOptions { tracing, ..RunOptions::new() };
+    let mut st = RunState::new(&opts);
+    st.allocator = allocator;
+    report_run(prog, &opts, st, cycles, cap, Verbosity::Result)
+}
+
+/// Like run_prog, but on success also printing the stats and/or final memory, as verbosity asks
+pub fn run_prog_verbose<'a>(prog: &'a IRProgram, tracing: bool, cycles: &mut ExecStats, cap:ExecMode, verbosity: Verbosity) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+    let opts = RunOptions { tracing, ..RunOptions::new() };
+    let st = RunState::new(&opts);
+    report_run(prog, &opts, st, cycles, cap, verbosity)
+}
+
+/// How much run_prog_verbose prints when a program succeeds. A crash always gets the error and a memory dump.
+#[derive(Debug,PartialEq,Eq,PartialOrd,Ord,Clone,Copy)]
+pub enum Verbosity {
+    /// Just the final result, as run_prog prints
+    Result,
+    /// The final result, then the ExecStats
+    Stats,
+    /// The final result, the ExecStats, then a memory dump
+    Memory,
+}
+
+// What follows the final result on success, at the given verbosity
+fn success_details<'a>(m: &Memory<'a>, globs: &Globals<'a>, cycles: &ExecStats, verbosity: Verbosity) -> String {
+    let mut out = String::new();
+    if verbosity >= Verbosity::Stats {
+        writeln!(out, "Execution stats:\n{:?}", cycles).unwrap();
+    }
+    if verbosity >= Verbosity::Memory {
+        out.push_str(&m.dump(globs));
+    }
+    out
+}
+
+// Shared by the run_prog variants: run main and print how it went
+fn report_run<'a>(prog: &'a IRProgram, opts: &RunOptions, mut st: RunState<'a>, cycles: &mut ExecStats, cap:ExecMode, verbosity: Verbosity) -> Result<VirtualVal<'a>,RuntimeError<'a>> {
+    let (fresult, state) = exec_main(prog, opts, &mut st, cycles, cap);
+    let (m, globs) = match &state {
+        None => return fresult.map(|v| v.unwrap_or(VirtualVal::Data { val: 0 })),
+        Some(st) => st
+    };
     match &fresult {
-        Ok(v) => {
+        Ok(None) => {
+            println!("Program finished (main returns void)");
+            print!("{}", success_details(m, globs, cycles, verbosity));
+        },
+        Ok(Some(v)) => {
             println!("Final result: {:?}", v);
+            print!("{}", success_details(m, globs, cycles, verbosity));
         },
         Err(err) => {
             println!("Program crashed with: {:?}", err);
-            m.print(prog, &globs);
+            m.print(prog, globs);
         }
     };
-    fresult
+    fresult.map(|v| v.unwrap_or(VirtualVal::Data { val: 0 }))
+}
+
+#[cfg(test)]
+mod exec_tests {
+    use crate::ir441::exec::*;
+    use crate::ir441::parsing::*;
+
+    #[test]
+    fn check_dump_global_order() {
+        let src = "data:\nglobal array zeta: { 1 }\nglobal array alpha: { 2, 3 }\nglobal array mid: { 4 }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (m,globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        let first = m.dump(&globs);
+        assert!(first.starts_with("Global Addresses:\n\t@zeta -> 32\n\t@alpha -> 40\n\t@mid -> 56\n"));
+        // Fresh layouts get fresh HashMaps; the rendering must not depend on their iteration order
+        for _ in 0..10 {
+            let (m2,globs2) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+            assert_eq!(m2.dump(&globs2), first);
+        }
+    }
+
+    #[test]
+    fn check_dump_boundaries() {
+        let src = "data:\nglobal array g: { 5, 6 }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (mut m,globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        // Without a guard zone the first object sits exactly at first_writable
+        m.guard_words = 0;
+        assert_eq!(m.alloc(1), Ok(48));
+        assert!(m.dump(&globs).ends_with(concat!("Memory Contents:\n\t32: 5\n\t40: 6\n",
+            "\t---------------- <end of globals, start of mutable memory>\n",
+            "\t---------------- <end of GC'ed memory, start of current \"semispace\">\n",
+            "\t48: 0\n")));
+    }
+
+    #[test]
+    fn check_dump_legend() {
+        let src = "data:\nglobal array g: { 5, 6 }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (mut m,globs) = Memory::new(&prog, ExecMode::GC { limit: 20 }, false, None).unwrap();
+        // Three header slots plus two fields, behind a one-word guard
+        m.alloc(2).unwrap();
+        assert!(m.dump(&globs).contains(concat!("Memory Map:\n",
+            "\tglobals   [32, 48) size 2\n",
+            "\tGC'ed     [48, 48) size 0\n",
+            "\tsemispace [48, 96) size 6\n",
+            "\tfree until cap: 15\n",
+            "Memory Contents:\n")));
+        // With no roots nothing survives, and the old semispace becomes reclaimed space
+        let mut stack = vec![Locals::new()];
+        m.gc(&mut stack).unwrap();
+        let snap = m.snapshot(&globs);
+        assert_eq!((snap.first_writable, snap.base, snap.next_alloc, snap.free_slots), (48, 96, 96, Some(20)));
+        assert!(snap.to_string().contains("\tGC'ed     [48, 96) size 6\n\tsemispace [96, 96) size 0\n"));
+        let (m,globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        assert!(m.dump(&globs).contains("\tfree until cap: unlimited\n"));
+    }
+
+    #[test]
+    fn check_hex_dump() {
+        let src = "data:\nglobal array vt: { main, 255 }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (mut m,globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        // One object holding a pointer to itself and a large number
+        let obj = m.alloc(2).unwrap();
+        m.map.insert(obj, VirtualVal::Data { val: obj });
+        m.map.insert(obj + 8, VirtualVal::Data { val: 4096 });
+        let snap = m.snapshot(&globs);
+        assert_eq!(format!("{:#}", snap), concat!("Global Addresses:\n\t@vt -> 0x20\n",
+            "Memory Map:\n\tglobals   [0x20, 0x30) size 2\n\tGC'ed     [0x30, 0x30) size 0\n\tsemispace [0x30, 0x48) size 3\n",
+            "\tfree until cap: unlimited\n",
+            "Memory Contents:\n\t0x20: main\n\t0x28: 0xff\n",
+            "\t---------------- <end of globals, start of mutable memory>\n",
+            "\t---------------- <end of GC'ed memory, start of current \"semispace\">\n",
+            "\t0x38: 0x38\n\t0x40: 0x1000\n"));
+        // Decimal is still the default
+        assert!(snap.to_string().contains("\t32: main\n\t40: 255\n"));
+        assert!(snap.to_string().contains("\t56: 56\n\t64: 4096\n"));
+        // A collected object's old words are marked the same way in hex
+        m.map.insert(obj, VirtualVal::GCTombstone);
+        assert!(format!("{:#}", m.snapshot(&globs)).contains("\t0x38: GCTombstone\n"));
+    }
+
+    #[test]
+    fn check_reachable_count() {
+        let src = "data:\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (mut m,_) = Memory::new(&prog, ExecMode::GC { limit: 100 }, false, None).unwrap();
+        // A list a -> b -> c of two-field nodes whose first field is the next pointer, plus an unrelated d
+        let nodes : Vec<u64> = (0..4).map(|_| m.alloc(2).unwrap()).collect();
+        let (a,b,c,d) = (nodes[0], nodes[1], nodes[2], nodes[3]);
+        for n in nodes.iter() {
+            m.mem_store(n - 8, VirtualVal::Data { val: 1 }).unwrap();
+        }
+        m.mem_store(a, VirtualVal::Data { val: b }).unwrap();
+        m.mem_store(b, VirtualVal::Data { val: c }).unwrap();
+        // d's second field holds a's address, but the slotmap says it's not a pointer
+        m.mem_store(d + 8, VirtualVal::Data { val: a }).unwrap();
+        assert_eq!(m.reachable_count(&[a]), (3, 15));
+        assert_eq!(m.reachable_count(&[c]), (1, 5));
+        assert_eq!(m.reachable_count(&[b, a, b]), (3, 15));
+        assert_eq!(m.reachable_count(&[d]), (1, 5));
+        assert_eq!(m.reachable_count(&[0, 7, a + 8]), (0, 0));
+        // Cycles are counted once
+        m.mem_store(c, VirtualVal::Data { val: a }).unwrap();
+        assert_eq!(m.reachable_count(&[c]), (3, 15));
+        // It's a pure query: the collector keeps exactly what it predicted
+        let mut stack = vec![[("x", VirtualVal::Data { val: a })].into_iter().collect()];
+        m.gc(&mut stack).unwrap();
+        assert_eq!(m.slots_alloced, 15);
+        assert_eq!(m.reachable_count(&[stack[0].read("x").unwrap().as_u64_or_else(|_| ()).unwrap()]), (3, 15));
+        // Without a cap there are no slotmaps to follow
+        let (mut m,_) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        let a = m.alloc(1).unwrap();
+        let b = m.alloc(1).unwrap();
+        m.mem_store(a, VirtualVal::Data { val: b }).unwrap();
+        assert_eq!(m.reachable_count(&[a]), (1, 1));
+    }
+
+    #[test]
+    fn check_success_verbosity() {
+        let src = "data:\nglobal array g: { 5 }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (m,globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        let cycles = ExecStats { rets: 1, ..ExecStats::new() };
+        assert_eq!(success_details(&m, &globs, &cycles, Verbosity::Result), "");
+        let stats = success_details(&m, &globs, &cycles, Verbosity::Stats);
+        assert_eq!(stats, format!("Execution stats:\n{:?}\n", cycles));
+        let full = success_details(&m, &globs, &cycles, Verbosity::Memory);
+        assert_eq!(full, format!("{}{}", stats, m.dump(&globs)));
+        assert!(full.contains("\t@g -> 32\n"));
+    }
+
+    #[test]
+    fn check_run_entry() {
+        let src = "data:\ncode:\nmain:\n  ret 0\nlength(l):\n  if %l then step else empty\nempty:\n  ret 0\nstep:\n  %next = getelt(%l, 1)\n  %rest = call(length, %next)\n  %n = %rest + 1\n  ret %n\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        // A list 10 -> 20 -> 30, each node a (value, next) pair, with the head placed last
+        let heap = InitialHeap {
+            objects: vec![
+                vec![HeapValue::Int(30), HeapValue::Int(0)],
+                vec![HeapValue::Int(20), HeapValue::Object(0)],
+                vec![HeapValue::Int(10), HeapValue::Object(1)],
+            ],
+            args: vec![HeapValue::Object(2)],
+        };
+        for cap in [ExecMode::Unlimited, ExecMode::MemCap { limit: 100 }, ExecMode::GC { limit: 100 }] {
+            let res = run_entry(&prog, "length", &heap, &RunOptions::new(), &mut ExecStats::new(), cap);
+            assert_eq!(res.result, Ok(Some(VirtualVal::Data { val: 3 })));
+        }
+        // With a cap, the slotmaps mark the next fields, so the collector would keep the whole list
+        let res = run_entry(&prog, "length", &heap, &RunOptions::new(), &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        let m = res.memory.unwrap();
+        let head = m.heap_objects().last().unwrap().addr;
+        assert_eq!(m.reachable_count(&[head]), (3, 15));
+        let empty = InitialHeap { objects: vec![], args: vec![HeapValue::Int(0)] };
+        assert_eq!(run_entry(&prog, "length", &empty, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(run_entry(&prog, "length", &InitialHeap::default(), &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result,
+                   Err(RuntimeError::BadEntryArity { bname: "length", got: 0 }));
+        assert_eq!(run_entry(&prog, "size", &heap, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result,
+                   Err(RuntimeError::InvalidBlock { bname: "size" }));
+        // References to objects that aren't there, or that a slotmap couldn't mark, are refused rather than placed
+        let dangling = InitialHeap { objects: vec![vec![HeapValue::Int(1), HeapValue::Object(1)]], args: vec![HeapValue::Object(0)] };
+        assert_eq!(run_entry(&prog, "length", &dangling, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result,
+                   Err(RuntimeError::UnknownHeapObject { index: 1, objects: 1 }));
+        let missing_arg = InitialHeap { objects: vec![], args: vec![HeapValue::Object(0)] };
+        assert_eq!(run_entry(&prog, "length", &missing_arg, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result,
+                   Err(RuntimeError::UnknownHeapObject { index: 0, objects: 0 }));
+        let mut wide = vec![HeapValue::Int(0); 65];
+        wide[64] = HeapValue::Object(0);
+        let wide = InitialHeap { objects: vec![wide], args: vec![HeapValue::Int(0)] };
+        assert_eq!(run_entry(&prog, "length", &wide, &RunOptions::new(), &mut ExecStats::new(), ExecMode::GC { limit: 1000 }).result,
+                   Err(RuntimeError::UntraceableHeapField { object: 0, field: 64 }));
+        assert!(run_entry(&prog, "length", &wide, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result.is_ok());
+    }
+
+    #[test]
+    fn check_differential_test() {
+        let parse = |src: &'static str| parse_program(src.as_bytes()).unwrap().1;
+        let inputs = vec![vec![7, 2], vec![2, 7], vec![4, 4], vec![5, 0]];
+        // Equivalent: the same result by a different route
+        let double_mul = parse("data:\ncode:\nmain:\n  ret 0\nf(x, y):\n  %r = %x * 2\n  ret %r\n");
+        let double_add = parse("data:\ncode:\nmain:\n  ret 0\nf(x, y):\n  %r = %x + %x\n  ret %r\n");
+        assert_eq!(differential_test(&double_mul, &double_add, "f", &inputs, false), vec![]);
+        // ... but not at the same cost
+        let diffs = differential_test(&double_mul, &double_add, "f", &inputs, true);
+        assert_eq!(diffs.len(), 4);
+        assert!(diffs.iter().all(|d| d.a == d.b && d.stats.as_ref().is_some_and(|(a,b)| a.slow_alu_ops == 1 && b.slow_alu_ops == 0)));
+        let min_a = parse("data:\ncode:\nmain:\n  ret 0\nf(x, y):\n  %lt = %x < %y\n  if %lt then first else second\nfirst:\n  ret %x\nsecond:\n  ret %y\n");
+        let min_b = parse("data:\ncode:\nmain:\n  ret 0\nf(x, y):\n  %gt = %y < %x\n  if %gt then second else first\nfirst:\n  ret %x\nsecond:\n  ret %y\n");
+        assert_eq!(differential_test(&min_a, &min_b, "f", &inputs, false), vec![]);
+        // Inequivalent: remainder where the quotient was meant; both dividing by zero still counts as agreeing
+        let quot = parse("data:\ncode:\nmain:\n  ret 0\nf(x, y):\n  %q, %m = divmod(%x, %y)\n  ret %q\n");
+        let rem = parse("data:\ncode:\nmain:\n  ret 0\nf(x, y):\n  %q, %m = divmod(%x, %y)\n  ret %m\n");
+        let diffs = differential_test(&quot, &rem, "f", &inputs, false);
+        assert_eq!(diffs.iter().map(|d| d.input).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!((&diffs[0].a, &diffs[0].b), (&Ok(Some(VirtualVal::Data { val: 3 })), &Ok(Some(VirtualVal::Data { val: 1 }))));
+        // Inequivalent: one side fails where the other returns
+        let guarded = parse("data:\ncode:\nmain:\n  ret 0\nf(x, y):\n  if %y then div else zero\ndiv:\n  %q, %m = divmod(%x, %y)\n  ret %q\nzero:\n  ret 0\n");
+        let diffs = differential_test(&quot, &guarded, "f", &inputs, false);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].input, 3);
+        assert!(matches!(diffs[0].a, Err(RuntimeError::DivisionByZero { .. })));
+        assert_eq!(diffs[0].b, Ok(Some(VirtualVal::Data { val: 0 })));
+    }
+
+    #[test]
+    fn check_phi_placement() {
+        let src = "data:\ncode:\nmain:\n  %a = 1\n  jump next\nnext:\n  %b = phi(main, %a)\n  %c = phi(main, 2)\n  %d = %b + %c\n  ret %d\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 3 })));
+
+        let src = "data:\ncode:\nmain:\n  %a = 1\n  jump next\nnext:\n  %b = phi(main, %a)\n  print(%b)\n  %c = phi(main, 2)\n  ret %c\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let late = &prog.blocks["next"].instrs[2];
+        assert_eq!(eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result, Err(RuntimeError::PhiNotAtBlockStart { instr: late }));
+    }
+
+    #[test]
+    fn check_within_tolerance() {
+        let expected = ExecStats { fast_alu_ops: 100, slow_alu_ops: 100, conditional_branches: 100, unconditional_branches: 100,
+                                   calls: 100, arg_passes: 100, rets: 100, mem_reads: 100, mem_writes: 100, allocs: 100,
+                                   prints: 100, phis: 100, gcs: 100, bytes_read: 100, bytes_written: 100,
+                                   gc_copies_by_size: BTreeMap::from([(2, 100)]), ..ExecStats::new() };
+        assert_eq!(expected.within_tolerance(&expected, 0.0), Ok(()));
+        // Each counter, nudged to the edge of a 10% band and then past it
+        type Counter = fn(&mut ExecStats) -> &mut u64;
+        let nudges : Vec<(&str, Counter)> = vec![
+            ("fast_op", |s| &mut s.fast_alu_ops), ("slow_op", |s| &mut s.slow_alu_ops),
+            ("cond_branch", |s| &mut s.conditional_branches), ("uncond_branch", |s| &mut s.unconditional_branches),
+            ("call", |s| &mut s.calls), ("arg_pass", |s| &mut s.arg_passes), ("ret", |s| &mut s.rets),
+            ("mem_read", |s| &mut s.mem_reads), ("mem_write", |s| &mut s.mem_writes), ("alloc", |s| &mut s.allocs),
+            ("print", |s| &mut s.prints), ("phi", |s| &mut s.phis), ("gc", |s| &mut s.gcs),
+            ("bytes_read", |s| &mut s.bytes_read), ("bytes_written", |s| &mut s.bytes_written),
+            ("gc_copies[2]", |s| s.gc_copies_by_size.get_mut(&2).unwrap()),
+        ];
+        for (label, field) in nudges {
+            for (val, ok) in [(110, true), (90, true), (111, false), (89, false)] {
+                let mut actual = expected.clone();
+                *field(&mut actual) = val;
+                match actual.within_tolerance(&expected, 0.1) {
+                    Ok(()) => assert!(ok, "{} = {} accepted", label, val),
+                    Err(outside) => {
+                        assert!(!ok, "{} = {} rejected", label, val);
+                        assert_eq!(outside, vec![format!("{}: {} is outside 100 \u{b1} 10%", label, val)]);
+                    }
+                }
+            }
+        }
+        // Copies of a size only one run made count against an expected 0, which allows no slack
+        let mut actual = expected.clone();
+        actual.gc_copies_by_size.insert(5, 1);
+        assert_eq!(actual.within_tolerance(&expected, 0.1), Err(vec!["gc_copies[5]: 1 is outside 0 \u{b1} 10%".to_string()]));
+        assert_eq!(expected.within_tolerance(&actual, 0.1), Err(vec!["gc_copies[5]: 0 is outside 1 \u{b1} 10%".to_string()]));
+        assert_eq!(ExecStats { allocs: 1, ..ExecStats::new() }.within_tolerance(&ExecStats::new(), 0.5),
+                   Err(vec!["alloc: 1 is outside 0 \u{b1} 50%".to_string()]));
+    }
+
+    #[test]
+    fn check_variable_from_caller() {
+        // f expects to see main's %x
+        let src = "data:\ncode:\nmain:\n  %x = 5\n  %r = call(f, 0)\n  ret %r\nf(this):\n  %y = %x + 1\n  ret %y\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let res = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(res.result, Err(RuntimeError::VariableFromCaller { name: "x" }));
+        // A name nobody assigned is still a plain uninitialized read, in a callee or in main
+        let src = "data:\ncode:\nmain:\n  %x = 5\n  %r = call(f, 0)\n  ret %r\nf(this):\n  %y = %z + 1\n  ret %y\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let res = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(res.result, Err(RuntimeError::UninitializedVariable { name: "z" }));
+        let src = "data:\ncode:\nmain:\n  ret %x\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let res = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(res.result, Err(RuntimeError::UninitializedVariable { name: "x" }));
+    }
+
+    #[test]
+    fn check_tombstone_operands() {
+        // Run one statement with %t holding a tombstone and %p a live two-field object, and name the error it stops with
+        fn stop(stmt: &str) -> String {
+            let src = format!("data:\ncode:\nmain:\n  {}\n  ret 0\n", stmt);
+            let (_,prog) = parse_program(src.as_bytes()).unwrap();
+            let (mut m, mut globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+            let p = m.alloc(2).unwrap();
+            let locals : Locals = [("t", VirtualVal::GCTombstone), ("p", VirtualVal::Data { val: p })].into_iter().collect();
+            let opts = RunOptions::new();
+            let mut st = RunState::new(&opts);
+            let err = run_code(&mut Ctx { prog: &prog, opts: &opts, st: &mut st, cycles: &mut ExecStats::new() }, &prog.blocks["main"], &mut vec![locals], &mut globs, &mut m).unwrap_err();
+            format!("{:?}", err).split(|c: char| !c.is_alphanumeric()).next().unwrap().to_string()
+        }
+        // A dead pointer being written through is a write; every other dead operand was read
+        let cases = [
+            ("%x = %t + 1", "ReadFromGCedData"), ("%x = 1 + %t", "ReadFromGCedData"),
+            ("%x = getelt(%t, 0)", "ReadFromGCedData"), ("%x = getelt(%p, %t)", "ReadFromGCedData"),
+            ("setelt(%t, 0, 1)", "WriteToGCedData"), ("setelt(%p, %t, 1)", "ReadFromGCedData"), ("setelt(%p, 0, %t)", "ReadFromGCedData"),
+            ("%x = getelt16(%t, 0)", "ReadFromGCedData"), ("%x = getelt16(%p, %t)", "ReadFromGCedData"),
+            ("setelt16(%t, 0, 1)", "WriteToGCedData"), ("setelt16(%p, %t, 1)", "ReadFromGCedData"), ("setelt16(%p, 0, %t)", "ReadFromGCedData"),
+            ("%x = load(%t)", "ReadFromGCedData"),
+            ("store(%t, 1)", "WriteToGCedData"), ("store(%p, %t)", "ReadFromGCedData"),
+            ("memcpy(%t, %p, 1)", "WriteToGCedData"), ("memcpy(%p, %t, 1)", "ReadFromGCedData"), ("memcpy(%p, %p, %t)", "ReadFromGCedData"),
+            ("memset(%t, 0, 1)", "WriteToGCedData"), ("memset(%p, %t, 1)", "ReadFromGCedData"), ("memset(%p, 0, %t)", "ReadFromGCedData"),
+        ];
+        for (stmt, expected) in cases {
+            assert_eq!(stop(stmt), expected, "{}", stmt);
+        }
+    }
+
+    #[test]
+    fn check_interned_names() {
+        // Run main with and without the block table, which should only change how branch targets are found
+        fn run<'a>(prog: &'a IRProgram<'a>, interned: bool) -> (Result<Flow<'a>,RuntimeError<'a>>, ExecStats, Vec<&'a str>) {
+            let (mut m, mut globs) = Memory::new(prog, ExecMode::Unlimited, false, None).unwrap();
+            let opts = RunOptions { record_path: Some(10000), code_pointer_tables: true, ..RunOptions::new() };
+            let mut st = RunState::new(&opts);
+            if interned {
+                st.blocks = BlockTable::new(prog);
+            }
+            let mut cycles = ExecStats::new();
+            let result = run_code(&mut Ctx { prog, opts: &opts, st: &mut st, cycles: &mut cycles }, &prog.blocks["main"], &mut vec![Locals::new()], &mut globs, &mut m);
+            (result, cycles, st.path)
+        }
+        for file in ["examples/countdown.ir", "examples/ifsigned.ir", "examples/basicoo.ir", "examples/jumptable.ir", "examples/path.ir"] {
+            let src = std::fs::read(file).unwrap();
+            let (_,prog) = parse_program(&src[..]).unwrap();
+            let interned = run(&prog, true);
+            assert!(interned.2.len() > 1, "{}", file);
+            assert_eq!(interned, run(&prog, false), "{}", file);
+        }
+
+        // A branch to a block that doesn't exist is still caught when it's taken
+        let src = "data:\ncode:\nmain:\n  if 0 then nowhere else done\ndone:\n  if 1 then nowhere else done\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let table = BlockTable::new(&prog);
+        let main = table.id("main");
+        assert_eq!(table.succ(main, 0).map(|(id,_)| id), None);
+        assert_eq!(table.succ(main, 1).map(|(_,b)| b.name), Some("done"));
+        assert_eq!(table.succ(None, 0).map(|(id,_)| id), None);
+        let (result, _, path) = run(&prog, true);
+        assert_eq!(path, vec!["main", "done"]);
+        assert!(matches!(result, Err(RuntimeError::InvalidBlockInControl { bname: "nowhere", .. })));
+    }
+
+    #[test]
+    fn check_var_slots() {
+        let src = "data:\ncode:\nmain:\n  %a = 1\n  %b = %a + 1\n  ret %b\nf(this, a):\n  ret %a\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        // Ids are dense and shared between blocks using the same name
+        assert_eq!((prog.vars.id("this"), prog.vars.id("a"), prog.vars.id("b"), prog.vars.id("c")), (Some(0), Some(1), Some(2), None));
+        let mut l = Locals::new();
+        assert_eq!(l.read("a"), Err(RuntimeError::UninitializedVariable { name: "a" }));
+        // Set by name from outside, then found and moved into its slot by id
+        l.insert("a", VirtualVal::Data { val: 1 });
+        assert_eq!(l.lookup(prog.vars.id("a"), "a"), Some(&VirtualVal::Data { val: 1 }));
+        assert_eq!(l.assign(prog.vars.id("a"), "a", VirtualVal::Data { val: 2 }), Some(VirtualVal::Data { val: 1 }));
+        assert_eq!((l.len(), l.read("a")), (1, Ok(VirtualVal::Data { val: 2 })));
+        // A name the program never mentions still works, by name
+        l.assign(prog.vars.id("c"), "c", VirtualVal::Data { val: 3 });
+        assert_eq!(l.iter().collect::<Vec<_>>(), vec![("a", &VirtualVal::Data { val: 2 }), ("c", &VirtualVal::Data { val: 3 })]);
+    }
+
+    #[test]
+    fn check_frame_reuse() {
+        // f's frame is reused for g, but g still starts with only its formals
+        let src = concat!("data:\n",
+                          "code:\n",
+                          "main:\n",
+                          "  %a = call(f, 0, 1)\n",
+                          "  %b = call(f, 0, 2)\n",
+                          "  %c = call(g, 0)\n",
+                          "  ret %c\n",
+                          "f(this, n):\n",
+                          "  %x = %n + 4\n",
+                          "  ret %x\n",
+                          "g(this):\n",
+                          "  ret %x\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (mut m, mut globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        let opts = RunOptions::new();
+        let mut st = RunState::new(&opts);
+        let mut stack = vec![Locals::new()];
+        let err = run_code(&mut Ctx { prog: &prog, opts: &opts, st: &mut st, cycles: &mut ExecStats::new() }, &prog.blocks["main"], &mut stack, &mut globs, &mut m).unwrap_err();
+        assert_eq!(err, RuntimeError::UninitializedVariable { name: "x" });
+        assert_eq!(stack[0].get("b"), Some(&VirtualVal::Data { val: 6 }));
+        // g took the one frame f kept handing back, and it stays on the stack since g failed
+        assert!(st.spare_frames.is_empty());
+        assert_eq!(stack.len(), 2);
+    }
+
+    #[test]
+    fn check_bench_run() {
+        let (_,prog) = parse_program(concat!("data:\ncode:\nmain:\n  %n0 = 5\n  jump head\n",
+                                             "head:\n  %n = phi(main, %n0, head, %m)\n  %m = %n - 1\n  if %m then head else done\n",
+                                             "done:\n  ret %n\n").as_bytes()).unwrap();
+        let report = bench_run(&prog, 7);
+        assert_eq!(report.iterations, 7);
+        assert!(report.min <= report.median && report.median <= report.max);
+        assert_eq!(report.result, Ok(Some(VirtualVal::Data { val: 1 })));
+        let mut cycles = ExecStats::new();
+        eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        assert_eq!(report.steps, cycles.steps());
+    }
+    #[test]
+    fn check_time_travel() {
+        // Allocates ten nodes, dropping each for the next, so memory keeps changing and the collector has to run
+        let src = concat!("data:\ncode:\nmain:\n  %cnt = 10\n  %head = 0\n  jump loop\n",
+                          "loop:\n  if %cnt then more else done\n",
+                          "more:\n  %n = alloc(2)\n  %nmap = %n - 8\n  store(%nmap, 2)\n  setelt(%n, 1, 0)\n  setelt(%n, 0, %cnt)\n",
+                          "  %head = %n\n  %cnt = %cnt - 1\n  jump loop\n",
+                          "done:\n  ret %head\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let mut tt = TimeTravel::new(&prog, &RunOptions::new(), ExecMode::GC { limit: 20 }, 10).unwrap();
+        assert_eq!((tt.current.block, tt.current.index, tt.current.step), ("main", 0, 0));
+        assert!(!tt.back());
+
+        assert_eq!(tt.forward(75), None);
+        let there = tt.current.clone();
+        assert!(there.step >= 75);
+        // Back lands on a snapshot boundary, and replaying forward from it gets to exactly the same state
+        assert!(tt.back());
+        let boundary = tt.current.clone();
+        assert!(boundary.step < there.step);
+        assert_ne!(boundary, there);
+        assert_eq!(tt.forward(there.step - boundary.step), None);
+        assert_eq!(tt.current, there);
+        // Several boundaries back, too
+        assert!(tt.back() && tt.back() && tt.back());
+        let earlier = tt.current.step;
+        assert!(earlier < boundary.step);
+        assert_eq!(tt.forward(there.step - earlier), None);
+        assert_eq!(tt.current, there);
+
+        // Stepping off the end reports how the run ended and stays put
+        assert!(matches!(tt.forward(1_000_000), Some(Ok(Some(VirtualVal::Data { .. })))));
+        assert_eq!(tt.current, there);
+
+        // Snapshots without time travel: the first at the start, then at least 20 steps apart
+        let opts = RunOptions { snapshot_every: Some(20), ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 20 });
+        assert!(result.result.is_ok());
+        assert_eq!(result.snapshots[0].step, 0);
+        assert!(result.snapshots.windows(2).all(|w| w[1].step / 20 > w[0].step / 20));
+        assert!(result.snapshots.len() > 5);
+
+        // Snapshots taken inside calls resume inside them, with the callers still waiting, to the same end result
+        let src = concat!("data:\ncode:\nmain:\n  %cnt = 6\n  %sum = 0\n  jump loop\n",
+                          "loop:\n  if %cnt then more else done\n",
+                          "more:\n  %sum = call(add, 0, %sum, %cnt)\n  %cnt = %cnt - 1\n  jump loop\n",
+                          "done:\n  ret %sum\n",
+                          "add(this, a, b):\n  %b2 = call(double, 0, %b)\n  %r = %a + %b2\n  ret %r\n",
+                          "double(this, x):\n  %y = %x + %x\n  ret %y\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let mut tt = TimeTravel::new(&prog, &RunOptions::new(), ExecMode::Unlimited, 3).unwrap();
+        assert_eq!(tt.forward(40), None);
+        let there = tt.current.clone();
+        while tt.current.depth < 3 {
+            assert!(tt.back());
+        }
+        let inside = tt.current.step;
+        assert_eq!(tt.forward(there.step - inside), None);
+        assert_eq!(tt.current, there);
+        let mut straight = TimeTravel::new(&prog, &RunOptions::new(), ExecMode::Unlimited, 3).unwrap();
+        assert_eq!(straight.forward(there.step), None);
+        assert_eq!(straight.current, there);
+        assert!(tt.back());
+        assert_eq!(tt.forward(1_000), Some(Ok(Some(VirtualVal::Data { val: 42 }))));
+    }
+
+    #[test]
+    fn check_allocation_overflow() {
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  ret 0\n".as_bytes()).unwrap();
+        for cap in [ExecMode::Unlimited, ExecMode::GC { limit: 100 }] {
+            let (mut m,_) = Memory::new(&prog, cap.clone(), false, None).unwrap();
+            assert_eq!(m.alloc(u64::MAX), Err(RuntimeError::AllocationTooLarge { slots: u64::MAX }));
+            assert_eq!(m.alloc(u64::MAX / 8), Err(RuntimeError::AllocationTooLarge { slots: u64::MAX / 8 }));
+            // Nothing was taken, so the next allocation lands where it would have anyway
+            let (mut fresh,_) = Memory::new(&prog, cap, false, None).unwrap();
+            assert_eq!(m.alloc(2), fresh.alloc(2));
+        }
+        // A configured ceiling stops allocations well short of wrapping
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  %a = alloc(4)\n  %b = alloc(1000)\n  ret 0\n".as_bytes()).unwrap();
+        let opts = RunOptions { max_address: Some(4096), ..RunOptions::new() };
+        assert_eq!(eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited).result, Err(RuntimeError::AllocationTooLarge { slots: 1000 }));
+        assert_eq!(eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 0 })));
+        // 2^61 words is 2^64 bytes, one past the last address, whichever way offsets are read
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  %a = alloc(1)\n  %x = getelt(%a, 2305843009213693952)\n  ret %x\n".as_bytes()).unwrap();
+        for signed_offsets in [false, true] {
+            let opts = RunOptions { signed_offsets, ..RunOptions::new() };
+            let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited).result;
+            assert!(matches!(result, Err(RuntimeError::OffsetOverflow { offset: 2305843009213693952, .. })), "{:?}", result);
+        }
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  %a = alloc(1)\n  setelt(%a, 18446744073709551615, 7)\n  ret 0\n".as_bytes()).unwrap();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result;
+        assert!(matches!(result, Err(RuntimeError::OffsetOverflow { offset: u64::MAX, .. })));
+    }
+
+    #[test]
+    fn check_global_too_large() {
+        let src = |n: u64| format!("data:\nglobal array small: {{ 1 }}\nglobal array buf[{}]: {{ 1 }}\ncode:\nmain:\n  ret 0\n", n);
+        // Wraps the address space, and is more slots than any alloc could ask for
+        for n in [u64::MAX / 8 + 1, 1 << 32] {
+            let text = src(n);
+            let (_,prog) = parse_program(text.as_bytes()).unwrap();
+            let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result;
+            assert_eq!(result, Err(RuntimeError::GlobalTooLarge { name: "buf", slots: n }));
+        }
+        // Past the cap when globals count against it, or past a configured ceiling
+        let text = src(1000);
+        let (_,prog) = parse_program(text.as_bytes()).unwrap();
+        let total = RunOptions { cap_includes_globals: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &total, &mut ExecStats::new(), ExecMode::GC { limit: 100 }).result;
+        assert_eq!(result, Err(RuntimeError::GlobalTooLarge { name: "buf", slots: 1000 }));
+        let opts = RunOptions { max_address: Some(4096), ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited).result;
+        assert_eq!(result, Err(RuntimeError::GlobalTooLarge { name: "buf", slots: 1000 }));
+        assert_eq!(eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 0 })));
+    }
+
+    #[test]
+    fn check_arith_edges() {
+        let run = |op: &str, a: u64, b: u64| {
+            let src = format!("data:\ncode:\nmain:\n  %z = {} {} {}\n  ret %z\n", a, op, b);
+            let (_,prog) = parse_program(src.as_bytes()).unwrap();
+            match eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result {
+                Ok(Some(VirtualVal::Data { val })) => Ok(val),
+                Err(RuntimeError::DivisionByZero { .. }) => Err("div0"),
+                other => panic!("{:?}", other),
+            }
+        };
+        assert_eq!(run("/", 7, 0), Err("div0"));
+        assert_eq!(run("/", 7, 2), Ok(3));
+        assert_eq!(run("+", u64::MAX, 2), Ok(1));
+        assert_eq!(run("-", 0, 1), Ok(u64::MAX));
+        assert_eq!(run("*", 1 << 63, 2), Ok(0));
+        assert_eq!(run("<<", 1, 63), Ok(1 << 63));
+        assert_eq!(run("<<", 1, 64), Ok(0));
+        assert_eq!(run("<<", 1, u64::MAX), Ok(0));
+        assert_eq!(run(">>", u64::MAX, 64), Ok(0));
+        // % is divmod's second result, and shares its zero check
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  %q, %m = divmod(7, 0)\n  ret %m\n".as_bytes()).unwrap();
+        assert!(matches!(eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result, Err(RuntimeError::DivisionByZero { .. })));
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  %q, %m = divmod(7, 3)\n  ret %m\n".as_bytes()).unwrap();
+        assert_eq!(eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 1 })));
+    }
+
+    #[test]
+    fn check_op_classes() {
+        let src = "data:\ncode:\nmain:\n  %a = 3 << 2\n  %b = %a * 5\n  %c = %b + 1\n  ret %c\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let mut cycles = ExecStats::new();
+        assert_eq!(eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 61 })));
+        assert_eq!((cycles.fast_alu_ops, cycles.slow_alu_ops), (2, 1));
+        // A target where shifts are slow; the other operators keep their usual class
+        let mut opts = RunOptions::new();
+        opts.op_classes.insert(BinOp::Shl, OpClass::Slow);
+        let mut cycles = ExecStats::new();
+        assert_eq!(eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 61 })));
+        assert_eq!((cycles.fast_alu_ops, cycles.slow_alu_ops), (1, 2));
+        assert_eq!(cycles.cycles(&CostModel::new()), 1 + 2*3 + 5);
+        // ...and multiplies are fast
+        opts.op_classes.insert(BinOp::Mul, OpClass::Fast);
+        let mut cycles = ExecStats::new();
+        eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert_eq!((cycles.fast_alu_ops, cycles.slow_alu_ops), (2, 1));
+    }
+
+    #[test]
+    fn check_min_cap() {
+        // Each collection keeps %a's object (4 fields and a 3-slot header) and the junk object %junk still holds (2 and
+        // 3), and the next junk alloc needs room for its 2 fields plus one
+        let src = concat!("data:\ncode:\nmain:\n  %a = alloc(4)\n  %amap = %a - 8\n  store(%amap, 0)\n  %amap = 0\n  %cnt = 10\n  jump loop\n",
+            "loop:\n  if %cnt then more else done\n",
+            "more:\n  %cnt = %cnt - 1\n  %junk = alloc(2)\n  %jmap = %junk - 8\n  store(%jmap, 0)\n  %jmap = 0\n  print(%cnt)\n  jump loop\n",
+            "done:\n  %v = getelt(%a, 0)\n  ret %v\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(min_cap(&prog, &RunOptions::new()), Some(7 + 5 + 3));
+        let run = |limit| eval_prog(&prog, &RunOptions { capture_output: true, ..RunOptions::new() }, &mut ExecStats::new(), ExecMode::GC { limit }).result;
+        assert!(run(15).is_ok());
+        assert_eq!(run(14), Err(RuntimeError::OutOfMemory));
+        // A program that never allocates needs nothing
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  ret 0\n".as_bytes()).unwrap();
+        assert_eq!(min_cap(&prog, &RunOptions::new()), Some(0));
+        // No cap helps a program that fails anyway
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  %a = alloc(2)\n  fail NotANumber\n".as_bytes()).unwrap();
+        assert_eq!(min_cap(&prog, &RunOptions::new()), None);
+    }
+
+    #[test]
+    fn check_peak_live() {
+        // Builds a five-node list (2 fields and a 3-slot header each), then drops it and allocates one more object
+        let src = concat!("data:\ncode:\nmain:\n  %head = 0\n  %cnt = 5\n  jump loop\n",
+            "loop:\n  if %cnt then more else done\n",
+            "more:\n  %n = alloc(2)\n  %nmap = %n - 8\n  store(%nmap, 2)\n  %nmap = 0\n  setelt(%n, 1, %head)\n  %head = %n\n  %cnt = %cnt - 1\n  jump loop\n",
+            "done:\n  %head = 0\n  %n = 0\n  %x = alloc(1)\n  ret 0\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let run = |every| eval_prog(&prog, &RunOptions { sample_live_every: every, ..RunOptions::new() }, &mut ExecStats::new(), ExecMode::MemCap { limit: 100 });
+        assert_eq!(run(None).peak_live, None);
+        let res = run(Some(1));
+        assert!(res.result.is_ok());
+        let peak = res.peak_live.unwrap();
+        assert_eq!(peak, LivePeak { allocated: 5*5 + 4, live: 5*5 });
+        assert!(peak.live < peak.allocated);
+        // Sampling every other alloc sees the list at four nodes, but misses it at five
+        assert_eq!(run(Some(2)).peak_live, Some(LivePeak { allocated: 5*5 + 4, live: 4*5 }));
+    }
+
+    #[test]
+    fn check_block_fuel() {
+        // loop runs 11 times (the last to leave), every other block once
+        let src = concat!("data:\ncode:\nmain:\n  %i = 10\n  jump loop\n",
+            "loop:\n  if %i then body else done\n",
+            "body:\n  %i = %i - 1\n  jump loop\n",
+            "done:\n  ret 0\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let run = |fuel| eval_prog(&prog, &RunOptions { block_fuel: fuel, ..RunOptions::new() }, &mut ExecStats::new(), ExecMode::Unlimited).result;
+        assert_eq!(run(None), Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(run(Some(11)), Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(run(Some(10)), Err(RuntimeError::BlockFuelExceeded { block: "loop", limit: 10 }));
+        // A loop that never ends is caught however long the rest of the program is allowed to run
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  jump spin\nspin:\n  jump spin\n".as_bytes()).unwrap();
+        let res = eval_prog(&prog, &RunOptions { block_fuel: Some(1000), ..RunOptions::new() }, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(res.result, Err(RuntimeError::BlockFuelExceeded { block: "spin", limit: 1000 }));
+    }
+
+    #[test]
+    fn check_eval_expr() {
+        let src = "data:\nglobal const k = 9\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let locals : Locals = [("x", VirtualVal::Data { val: 7 })].into_iter().collect();
+        let globals : Globals = [("g", 32)].into_iter().collect();
+        let eval = |e: IRExpr<'static>| eval_expr(&e, &locals, &globals, &prog);
+        assert_eq!(eval(IRExpr::IntLit { val: 3 }), Ok(VirtualVal::Data { val: 3 }));
+        assert_eq!(eval(IRExpr::Var { id: "x" }), Ok(VirtualVal::Data { val: 7 }));
+        assert_eq!(eval(IRExpr::Var { id: "y" }), Err(RuntimeError::UninitializedVariable { name: "y" }));
+        assert_eq!(eval(IRExpr::GlobalRef { name: "g" }), Ok(VirtualVal::Data { val: 32 }));
+        assert_eq!(eval(IRExpr::GlobalRef { name: "h" }), Err(RuntimeError::UndefinedGlobal { name: "h" }));
+        assert_eq!(eval(IRExpr::ConstRef { id: "k" }), Ok(VirtualVal::Data { val: 9 }));
+        assert_eq!(eval(IRExpr::ConstRef { id: "j" }), Err(RuntimeError::UndefinedConst { name: "j" }));
+        assert_eq!(eval(IRExpr::BlockRef { bname: "main" }), Ok(VirtualVal::CodePtr { val: "main" }));
+        assert_eq!(eval(IRExpr::BlockRef { bname: "nowhere" }), Err(RuntimeError::InvalidBlock { bname: "nowhere" }));
+    }
+
+    #[test]
+    fn check_alloc_accounting() {
+        let src = "data:\nglobal array g: { 1 }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        // No headers in unlimited mode: just the fields, with a guard word before each object
+        let (mut m,_) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        m.alloc(2).unwrap();
+        m.alloc(5).unwrap();
+        assert_eq!(m.slots_alloced, 7);
+        assert_eq!(m.next_alloc, m.base + 8 * (7 + 2));
+        assert!(m.accounting_consistent());
+        // Capped modes add 3 header slots per object, and the collector's copies are counted the same way
+        let (mut m,_) = Memory::new(&prog, ExecMode::GC { limit: 100 }, false, None).unwrap();
+        let kept = m.alloc(2).unwrap();
+        m.alloc(4).unwrap();
+        assert_eq!(m.slots_alloced, 5 + 7);
+        assert!(m.accounting_consistent());
+        let mut stack = vec![[("kept", VirtualVal::Data { val: kept })].into_iter().collect()];
+        m.gc(&mut stack).unwrap();
+        assert_eq!(m.slots_alloced, 5);
+        assert!(m.accounting_consistent());
+    }
+
+    #[test]
+    fn check_global_forward_refs() {
+        let src = "data:\nglobal array a: { @b, 1 }\nglobal array b: { @a }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (m,globs) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        assert_eq!(globs["a"], 32);
+        assert_eq!(globs["b"], 48);
+        assert_eq!(m.map[&32], VirtualVal::Data { val: 48 });
+        assert_eq!(m.map[&48], VirtualVal::Data { val: 32 });
+
+        let src = "data:\nglobal array a: { @nope }\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::UndefinedGlobal { name: "nope" }));
+    }
+
+    #[test]
+    fn check_explanations() {
+        let instr = IRStatement::Print { out: IRExpr::IntLit { val: 0 } };
+        let ctrl = ControlXfer::RetVoid;
+        let val = VirtualVal::Data { val: 0 };
+        // One of each variant; explain's match has no catch-all, so a new variant can't be added without an explanation
+        let all = [
+            RuntimeError::AccessingCodeInMemory { bname: "b" }, RuntimeError::AccessingDeallocatedAddress { addr: 8 },
+            RuntimeError::AllocationTooLarge { slots: u64::MAX }, RuntimeError::OffsetOverflow { base: 64, offset: u64::MAX },
+            RuntimeError::BadCallArity { instr: &instr }, RuntimeError::BadEntryArity { bname: "f", got: 2 }, RuntimeError::BadGCField,
+            RuntimeError::BadPhiPredecessor { instr: &instr, actual_predecessor: "b" }, RuntimeError::CallToNonEntry { bname: "b" },
+            RuntimeError::CallingNonCode, RuntimeError::CodeAddressArithmetic { bname: "b" }, RuntimeError::CorruptGCMetadata { val },
+            RuntimeError::DivisionByZero { instr: &instr }, RuntimeError::EmptyRandomRange, RuntimeError::Failed { reason: Reason::NotANumber },
+            RuntimeError::HeapAssertionFailed { msg: "sorted", bname: "check" },
+            RuntimeError::GCRequired, RuntimeError::GuardZoneAccess { addr: 8 }, RuntimeError::InvalidBlock { bname: "b" },
+            RuntimeError::IndirectJumpDisabled, RuntimeError::InvalidBlockInControl { instr: &ctrl, bname: "b" }, RuntimeError::JumpToNonCode,
+            RuntimeError::MissingMain, RuntimeError::NotPure { bname: "f", at: "g", index: 0 }, RuntimeError::NullPointer, RuntimeError::OutOfMemory, RuntimeError::LiveObjectLimitExceeded { limit: 4 }, RuntimeError::OverwroteGCHeader { addr: 8 }, RuntimeError::PlacementNotFree { addr: 8 },
+            RuntimeError::UnknownHeapObject { index: 3, objects: 2 }, RuntimeError::UntraceableHeapField { object: 0, field: 64 }, RuntimeError::BlockFuelExceeded { block: "main", limit: 3 },
+            RuntimeError::PhiInFirstBlock { instr: &instr }, RuntimeError::PhiNotAtBlockStart { instr: &instr }, RuntimeError::UnalignedAccess { addr: 3 },
+            RuntimeError::UnallocatedAddressRead { addr: 8 }, RuntimeError::UnallocatedAddressWrite { addr: 8 },
+            RuntimeError::UninitializedVariable { name: "x" }, RuntimeError::VariableFromCaller { name: "x" }, RuntimeError::GlobalOutOfBounds { name: "g", index: 1, slots: 1 },
+            RuntimeError::GlobalTooLarge { name: "g", slots: u64::MAX },
+            RuntimeError::UndefinedGlobal { name: "g" }, RuntimeError::UndefinedConst { name: "k" }, RuntimeError::ReadFromGCedData, RuntimeError::ReturnedDanglingReference { addr: 8 },
+            RuntimeError::SlotmapSizeMismatch { addr: 8, slots: 1, slotmap: 2 }, RuntimeError::WriteToGCedData { addr: 8, val },
+            RuntimeError::WriteToImmutableData, RuntimeError::WrongReturnCount { expected: 1, got: 2 },
+            RuntimeError::UseBeforeHeaderInit { addr: 8 },
+            RuntimeError::StoringCodePtrInTracedField { addr: 64, bname: "main" }, RuntimeError::NYI,
+        ];
+        for e in all.iter() {
+            assert!(!explain(e).is_empty(), "{:?}", e);
+        }
+        assert!(explain(&RuntimeError::UnalignedAccess { addr: 3 }).contains("multiples of 8"));
+        assert!(!explain(&RuntimeError::NYI).contains("unsupported operator"));
+    }
+
+    #[test]
+    fn check_object_ages() {
+        let src = "data:\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let (mut m,_) = Memory::new(&prog, ExecMode::GC { limit: 100 }, false, None).unwrap();
+        m.track_ages = true;
+        let old = m.alloc(2).unwrap();
+        m.alloc(1).unwrap();
+        assert_eq!(m.heap_objects().iter().map(|o| o.age).collect::<Vec<_>>(), vec![Some(0), Some(0)]);
+        let mut stack = vec![[("old", VirtualVal::Data { val: old })].into_iter().collect()];
+        m.gc(&mut stack).unwrap();
+        let young = m.alloc(1).unwrap();
+        stack[0].insert("young", VirtualVal::Data { val: young });
+        m.gc(&mut stack).unwrap();
+        // The garbage is gone, and each survivor kept its birth through both moves
+        let objs = m.heap_objects();
+        assert_eq!(objs.len(), 2);
+        let old_now = stack[0].read("old").unwrap().as_u64_or_else(|_| RuntimeError::NYI).unwrap();
+        let young_now = stack[0].read("young").unwrap().as_u64_or_else(|_| RuntimeError::NYI).unwrap();
+        assert_eq!(objs.iter().find(|o| o.addr == old_now), Some(&HeapObject { addr: old_now, fields: 2, born: Some(0), age: Some(2) }));
+        assert_eq!(objs.iter().find(|o| o.addr == young_now), Some(&HeapObject { addr: young_now, fields: 1, born: Some(1), age: Some(1) }));
+
+        // Without tracking, objects are still listed but have no age
+        let (mut m,_) = Memory::new(&prog, ExecMode::Unlimited, false, None).unwrap();
+        let a = m.alloc(3).unwrap();
+        assert_eq!(m.heap_objects(), vec![HeapObject { addr: a, fields: 3, born: None, age: None }]);
+    }
 }
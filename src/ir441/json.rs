@@ -0,0 +1,435 @@
+// JSON rendering of a parsed IRProgram, for looking at exactly what a front end produced, and of a run's trace.
+//
+// Unlike the binary encoding the program rendering is one-way: it's meant for people and tools to read, not for
+// loading back. A trace can be read back (from_json_trace), so a recorded run can be replayed (exec::replay).
+// The top level is an object with "pragmas", "globals", and "blocks" (sorted by name, so output is deterministic).
+// Every global, expression, statement, and control transfer is an object whose "kind" names its variant (statements and
+// control transfers use the InstrKind names), followed by its fields under their names in nodes.rs. Integers are
+// written as bare JSON numbers, so consumers that parse numbers as doubles lose precision above 2^53.
+use std::fmt;
+use crate::ir441::nodes::*;
+
+enum Json<'a> {
+    Null,
+    Num(u64),
+    Str(&'a str),
+    Arr(Vec<Json<'a>>),
+    Obj(Vec<(&'static str, Json<'a>)>),
+}
+impl <'a> Json<'a> {
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Num(n) => out.push_str(&n.to_string()),
+            Json::Str(s) => put_str(out, s),
+            Json::Arr(elts) => {
+                out.push('[');
+                for (idx,e) in elts.iter().enumerate() {
+                    if idx > 0 {
+                        out.push(',');
+                    }
+                    e.write(out);
+                }
+                out.push(']');
+            },
+            Json::Obj(fields) => {
+                out.push('{');
+                for (idx,(k,v)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        out.push(',');
+                    }
+                    put_str(out, k);
+                    out.push(':');
+                    v.write(out);
+                }
+                out.push('}');
+            },
+        }
+    }
+}
+
+fn put_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+pub fn to_json_ast(prog: &IRProgram) -> String {
+    let mut out = String::new();
+    program(prog).write(&mut out);
+    out
+}
+
+fn program<'a>(prog: &'a IRProgram) -> Json<'a> {
+    let mut names : Vec<&&str> = prog.blocks.keys().collect();
+    names.sort();
+    Json::Obj(vec![
+        ("pragmas", pragmas(&prog.pragmas)),
+        ("globals", Json::Arr(prog.globals.iter().map(|g| match g {
+            GlobalStatic::Array { name, vals, slots } => Json::Obj(vec![
+                ("kind", Json::Str("Array")),
+                ("name", Json::Str(name)),
+                ("vals", exprs(vals.iter())),
+                ("slots", slots.map_or(Json::Null, Json::Num)),
+            ]),
+            GlobalStatic::Const { name, val } => Json::Obj(vec![
+                ("kind", Json::Str("Const")),
+                ("name", Json::Str(name)),
+                ("val", Json::Num(*val)),
+            ]),
+        }).collect())),
+        ("blocks", Json::Arr(names.into_iter().map(|n| block(&prog.blocks[*n])).collect())),
+    ])
+}
+
+fn pragmas(p: &Pragmas) -> Json<'_> {
+    Json::Obj(vec![
+        ("mode", match p.mode {
+            None => Json::Null,
+            Some(PragmaMode::Unlimited) => Json::Str("unlimited"),
+            Some(PragmaMode::MemCap) => Json::Str("memcap"),
+            Some(PragmaMode::GC) => Json::Str("gc"),
+            Some(PragmaMode::LoggingGC) => Json::Str("logginggc"),
+        }),
+        ("cap", p.cap.map_or(Json::Null, Json::Num)),
+        ("unknown", Json::Arr(p.unknown.iter().map(|u| Json::Str(u)).collect())),
+        ("pure", Json::Arr(p.pure.iter().map(|n| Json::Str(n)).collect())),
+        ("costs", Json::Arr(p.costs.iter().map(|(n,c)| Json::Obj(vec![("block", Json::Str(n)), ("cycles", Json::Num(*c))])).collect())),
+    ])
+}
+
+/// One line of a run's trace, with the block the run was in when it wrote it (None before it entered main)
+#[derive(Debug,PartialEq,Clone)]
+pub struct TraceLine {
+    pub block: Option<String>,
+    pub line: String,
+}
+
+/// A trace as a JSON array with an object per line, e.g. [{"block":"main","line":"%a: <unset> => 413"}]. blocks
+/// runs parallel to trace, as in ExecResult.
+pub fn to_json_trace(trace: &[String], blocks: &[Option<&str>]) -> String {
+    let mut out = String::new();
+    Json::Arr(trace.iter().zip(blocks.iter()).map(|(line,block)| Json::Obj(vec![
+        ("block", block.map_or(Json::Null, Json::Str)),
+        ("line", Json::Str(line)),
+    ])).collect()).write(&mut out);
+    out
+}
+
+/// Why some text isn't a trace as to_json_trace writes them, and the byte offset where reading it gave up
+#[derive(Debug,PartialEq)]
+pub struct JsonError {
+    pub pos: usize,
+    pub msg: &'static str,
+}
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bad JSON trace at byte {}: {}", self.pos, self.msg)
+    }
+}
+
+/// Read back a trace written by to_json_trace. An object's "block" may be left out, meaning null; other fields aren't
+/// allowed.
+pub fn from_json_trace(src: &str) -> Result<Vec<TraceLine>,JsonError> {
+    let mut r = Reader { src: src.as_bytes(), pos: 0 };
+    let mut lines = vec![];
+    r.expect(b'[', "expected [")?;
+    if !r.eat(b']') {
+        loop {
+            lines.push(r.trace_line()?);
+            if r.eat(b']') {
+                break;
+            }
+            r.expect(b',', "expected , or ]")?;
+        }
+    }
+    r.skip_ws();
+    if r.pos < r.src.len() {
+        return r.fail("text after the trace");
+    }
+    Ok(lines)
+}
+
+// Just enough of a JSON reader for what to_json_trace writes
+struct Reader<'s> {
+    src: &'s [u8],
+    pos: usize,
+}
+impl <'s> Reader<'s> {
+    fn fail<T>(&self, msg: &'static str) -> Result<T,JsonError> {
+        Err(JsonError { pos: self.pos, msg })
+    }
+    fn skip_ws(&mut self) {
+        while matches!(self.src.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_ws();
+        let found = self.src.get(self.pos) == Some(&c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+    fn expect(&mut self, c: u8, msg: &'static str) -> Result<(),JsonError> {
+        if self.eat(c) { Ok(()) } else { self.fail(msg) }
+    }
+    fn trace_line(&mut self) -> Result<TraceLine,JsonError> {
+        self.expect(b'{', "expected an object for a trace line")?;
+        let (mut block, mut line) = (None, None);
+        if !self.eat(b'}') {
+            loop {
+                self.skip_ws();
+                let at = self.pos;
+                let key = self.string()?;
+                self.expect(b':', "expected :")?;
+                match key.as_str() {
+                    "block" => block = self.string_or_null()?,
+                    "line" => line = Some(self.string()?),
+                    _ => return Err(JsonError { pos: at, msg: "unexpected field in a trace line" }),
+                }
+                if self.eat(b'}') {
+                    break;
+                }
+                self.expect(b',', "expected , or }")?;
+            }
+        }
+        match line {
+            Some(line) => Ok(TraceLine { block, line }),
+            None => self.fail("trace line without a \"line\""),
+        }
+    }
+    fn string_or_null(&mut self) -> Result<Option<String>,JsonError> {
+        self.skip_ws();
+        if self.src[self.pos..].starts_with(b"null") {
+            self.pos += 4;
+            Ok(None)
+        } else {
+            self.string().map(Some)
+        }
+    }
+    fn string(&mut self) -> Result<String,JsonError> {
+        self.expect(b'"', "expected a string")?;
+        let mut out = String::new();
+        loop {
+            // Copy runs of plain characters across whole; they end on an ASCII byte, so they're whole UTF-8
+            let start = self.pos;
+            while !matches!(self.src.get(self.pos), None | Some(b'"' | b'\\')) {
+                self.pos += 1;
+            }
+            out.push_str(std::str::from_utf8(&self.src[start..self.pos]).unwrap());
+            match self.src.get(self.pos) {
+                None => return self.fail("unterminated string"),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                },
+                _ => {}
+            }
+            let c = match self.src.get(self.pos + 1) {
+                Some(b'"') => '"',
+                Some(b'\\') => '\\',
+                Some(b'/') => '/',
+                Some(b'n') => '\n',
+                Some(b't') => '\t',
+                Some(b'r') => '\r',
+                Some(b'b') => '\u{8}',
+                Some(b'f') => '\u{c}',
+                Some(b'u') => {
+                    let code = self.src.get(self.pos + 2..self.pos + 6)
+                        .and_then(|h| u32::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok())
+                        .and_then(char::from_u32);
+                    match code {
+                        Some(c) => {
+                            self.pos += 4;
+                            c
+                        },
+                        None => return self.fail("bad \\u escape"),
+                    }
+                },
+                _ => return self.fail("bad escape"),
+            };
+            self.pos += 2;
+            out.push(c);
+        }
+    }
+}
+
+fn block<'a>(b: &'a BasicBlock) -> Json<'a> {
+    Json::Obj(vec![
+        ("name", Json::Str(b.name)),
+        ("formals", names(b.formals.iter())),
+        ("defaults", exprs(b.defaults.iter())),
+        ("instrs", Json::Arr(b.instrs.iter().map(stmt).collect())),
+        ("next", control(&b.next)),
+    ])
+}
+
+fn names<'a, I: Iterator<Item=&'a &'a str>>(ns: I) -> Json<'a> {
+    Json::Arr(ns.map(|n| Json::Str(n)).collect())
+}
+
+fn exprs<'a, I: Iterator<Item=&'a IRExpr<'a>>>(es: I) -> Json<'a> {
+    Json::Arr(es.map(expr).collect())
+}
+
+fn expr<'a>(e: &'a IRExpr) -> Json<'a> {
+    match e {
+        IRExpr::IntLit { val } => Json::Obj(vec![("kind", Json::Str("IntLit")), ("val", Json::Num(*val))]),
+        IRExpr::GlobalRef { name } => Json::Obj(vec![("kind", Json::Str("GlobalRef")), ("name", Json::Str(name))]),
+        IRExpr::Var { id } => Json::Obj(vec![("kind", Json::Str("Var")), ("id", Json::Str(id))]),
+        IRExpr::BlockRef { bname } => Json::Obj(vec![("kind", Json::Str("BlockRef")), ("bname", Json::Str(bname))]),
+        IRExpr::ConstRef { id } => Json::Obj(vec![("kind", Json::Str("ConstRef")), ("id", Json::Str(id))]),
+    }
+}
+
+fn stmt<'a>(i: &'a IRStatement) -> Json<'a> {
+    let mut fields : Vec<(&'static str, Json<'a>)> = match i {
+        IRStatement::VarAssign { lhs, rhs } => vec![("lhs", Json::Str(lhs)), ("rhs", expr(rhs))],
+        IRStatement::Op { lhs, arg1, op, arg2 } =>
+            vec![("lhs", Json::Str(lhs)), ("arg1", expr(arg1)), ("op", Json::Str(op.symbol())), ("arg2", expr(arg2))],
+        IRStatement::Call { lhs, code, receiver, args } =>
+            vec![("lhs", Json::Str(lhs)), ("code", expr(code)), ("receiver", expr(receiver)), ("args", exprs(args.iter()))],
+        IRStatement::CallMulti { lhs, code, receiver, args } =>
+            vec![("lhs", names(lhs.iter())), ("code", expr(code)), ("receiver", expr(receiver)), ("args", exprs(args.iter()))],
+        IRStatement::Phi { lhs, opts } =>
+            vec![("lhs", Json::Str(lhs)),
+                 ("opts", Json::Arr(opts.iter().map(|(bname,src)| Json::Obj(vec![("block", Json::Str(bname)), ("src", expr(src))])).collect()))],
+        IRStatement::Alloc { lhs, slots } => vec![("lhs", Json::Str(lhs)), ("slots", Json::Num(u64::from(*slots)))],
+        IRStatement::Rand { lhs, bound } => vec![("lhs", Json::Str(lhs)), ("bound", expr(bound))],
+        IRStatement::Print { out } => vec![("out", expr(out))],
+        IRStatement::GetElt { lhs, base, offset } => vec![("lhs", Json::Str(lhs)), ("base", expr(base)), ("offset", expr(offset))],
+        IRStatement::SetElt { base, offset, val } => vec![("base", expr(base)), ("offset", expr(offset)), ("val", expr(val))],
+        IRStatement::GetEltNarrow { lhs, bits, base, offset } =>
+            vec![("lhs", Json::Str(lhs)), ("bits", Json::Num(u64::from(*bits))), ("base", expr(base)), ("offset", expr(offset))],
+        IRStatement::SetEltNarrow { bits, base, offset, val } =>
+            vec![("bits", Json::Num(u64::from(*bits))), ("base", expr(base)), ("offset", expr(offset)), ("val", expr(val))],
+        IRStatement::Load { lhs, base } => vec![("lhs", Json::Str(lhs)), ("base", expr(base))],
+        IRStatement::Store { base, val } => vec![("base", expr(base)), ("val", expr(val))],
+        IRStatement::MemCopy { dst, src, slots } => vec![("dst", expr(dst)), ("src", expr(src)), ("slots", expr(slots))],
+        IRStatement::MemFill { dst, val, slots } => vec![("dst", expr(dst)), ("val", expr(val)), ("slots", expr(slots))],
+        IRStatement::DivMod { quot, rem, arg1, arg2 } =>
+            vec![("quot", Json::Str(quot)), ("rem", Json::Str(rem)), ("arg1", expr(arg1)), ("arg2", expr(arg2))],
+        IRStatement::Checkpoint { label } => vec![("label", Json::Str(label))],
+        IRStatement::PtrToInt { lhs, ptr } => vec![("lhs", Json::Str(lhs)), ("ptr", expr(ptr))],
+        IRStatement::IntToPtr { lhs, int } => vec![("lhs", Json::Str(lhs)), ("int", expr(int))],
+        IRStatement::ReadCycles { lhs } => vec![("lhs", Json::Str(lhs))],
+        IRStatement::AssertHeap { msg, block, receiver } => vec![("msg", Json::Str(msg)), ("block", Json::Str(block)), ("receiver", expr(receiver))],
+        IRStatement::Halt { val } => vec![("val", expr(val))],
+        IRStatement::Cas { result, base, expected, new } =>
+            vec![("result", Json::Str(result)), ("base", expr(base)), ("expected", expr(expected)), ("new", expr(new))],
+    };
+    fields.insert(0, ("kind", Json::Str(kind_name(i.kind()))));
+    Json::Obj(fields)
+}
+
+fn control<'a>(c: &'a ControlXfer) -> Json<'a> {
+    let (kind, mut fields) : (&'static str, Vec<(&'static str, Json<'a>)>) = match c {
+        ControlXfer::Jump { block } => ("Jump", vec![("block", Json::Str(block))]),
+        ControlXfer::JumpIndirect { target } => ("JumpIndirect", vec![("target", expr(target))]),
+        ControlXfer::If { cond, tblock, fblock } =>
+            ("If", vec![("cond", expr(cond)), ("tblock", Json::Str(tblock)), ("fblock", Json::Str(fblock))]),
+        ControlXfer::IfSigned { cond, neg_block, zero_block, pos_block } =>
+            ("IfSigned", vec![("cond", expr(cond)), ("neg_block", Json::Str(neg_block)),
+                              ("zero_block", Json::Str(zero_block)), ("pos_block", Json::Str(pos_block))]),
+        ControlXfer::Ret { val } => ("Ret", vec![("val", expr(val))]),
+        ControlXfer::RetMulti { vals } => ("RetMulti", vec![("vals", exprs(vals.iter()))]),
+        ControlXfer::RetVoid => ("RetVoid", vec![]),
+        ControlXfer::Fail { reason } => ("Fail", vec![("reason", Json::Str(match reason {
+            Reason::NotAPointer => "NotAPointer",
+            Reason::NotANumber => "NotANumber",
+            Reason::NoSuchField => "NoSuchField",
+            Reason::NoSuchMethod => "NoSuchMethod",
+        }))]),
+    };
+    fields.insert(0, ("kind", Json::Str(kind)));
+    Json::Obj(fields)
+}
+
+fn kind_name(k: InstrKind) -> &'static str {
+    match k {
+        InstrKind::VarAssign => "VarAssign", InstrKind::Op => "Op", InstrKind::Call => "Call", InstrKind::CallMulti => "CallMulti",
+        InstrKind::Phi => "Phi", InstrKind::Alloc => "Alloc", InstrKind::Rand => "Rand", InstrKind::Print => "Print",
+        InstrKind::GetElt => "GetElt", InstrKind::SetElt => "SetElt", InstrKind::GetEltNarrow => "GetEltNarrow",
+        InstrKind::SetEltNarrow => "SetEltNarrow", InstrKind::Load => "Load", InstrKind::Store => "Store",
+        InstrKind::MemCopy => "MemCopy", InstrKind::MemFill => "MemFill", InstrKind::DivMod => "DivMod",
+        InstrKind::Checkpoint => "Checkpoint", InstrKind::PtrToInt => "PtrToInt", InstrKind::IntToPtr => "IntToPtr",
+        InstrKind::ReadCycles => "ReadCycles", InstrKind::AssertHeap => "AssertHeap", InstrKind::Halt => "Halt", InstrKind::Cas => "Cas",
+        InstrKind::Jump => "Jump", InstrKind::JumpIndirect => "JumpIndirect", InstrKind::If => "If",
+        InstrKind::IfSigned => "IfSigned", InstrKind::Ret => "Ret", InstrKind::RetMulti => "RetMulti",
+        InstrKind::RetVoid => "RetVoid", InstrKind::Fail => "Fail",
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use crate::ir441::json::*;
+    use crate::ir441::parsing::*;
+
+    #[test]
+    fn check_json_ast() {
+        let src = concat!(";@ mode gc\n",
+                          "data:\n",
+                          "global array tbl[2]: { f }\n",
+                          "code:\n",
+                          "main:\n",
+                          "  %a = alloc(2)\n",
+                          "  %x = call(@tbl, %a, 7)\n",
+                          "  ret %x\n",
+                          "f(this, n):\n",
+                          "  %y = %n + 1\n",
+                          "  if %y then done else main\n",
+                          "done:\n",
+                          "  fail NotANumber\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(to_json_ast(&prog), concat!(
+            r#"{"pragmas":{"mode":"gc","cap":null,"unknown":[],"pure":[],"costs":[]},"#,
+            r#""globals":[{"kind":"Array","name":"tbl","vals":[{"kind":"BlockRef","bname":"f"}],"slots":2}],"#,
+            r#""blocks":["#,
+            r#"{"name":"done","formals":[],"defaults":[],"instrs":[],"next":{"kind":"Fail","reason":"NotANumber"}},"#,
+            r#"{"name":"f","formals":["this","n"],"defaults":[],"instrs":["#,
+            r#"{"kind":"Op","lhs":"y","arg1":{"kind":"Var","id":"n"},"op":"+","arg2":{"kind":"IntLit","val":1}}],"#,
+            r#""next":{"kind":"If","cond":{"kind":"Var","id":"y"},"tblock":"done","fblock":"main"}},"#,
+            r#"{"name":"main","formals":[],"defaults":[],"instrs":["#,
+            r#"{"kind":"Alloc","lhs":"a","slots":2},"#,
+            r#"{"kind":"Call","lhs":"x","code":{"kind":"GlobalRef","name":"tbl"},"receiver":{"kind":"Var","id":"a"},"args":[{"kind":"IntLit","val":7}]}],"#,
+            r#""next":{"kind":"Ret","val":{"kind":"Var","id":"x"}}}]}"#));
+
+        let mut out = String::new();
+        put_str(&mut out, "a\"b\\c\n\u{1}");
+        assert_eq!(out, r#""a\"b\\c\n\u0001""#);
+    }
+
+    #[test]
+    fn check_json_trace() {
+        let trace = vec!["Final Globals:".to_string(), "\t@s[0] = \"é\\\u{1}\"".to_string()];
+        let json = to_json_trace(&trace, &[None, Some("main")]);
+        assert_eq!(json, r#"[{"block":null,"line":"Final Globals:"},{"block":"main","line":"\t@s[0] = \"é\\\u0001\""}]"#);
+        assert_eq!(from_json_trace(&json), Ok(vec![
+            TraceLine { block: None, line: trace[0].clone() },
+            TraceLine { block: Some("main".to_string()), line: trace[1].clone() },
+        ]));
+        // Whitespace between tokens, fields in either order, block left out
+        assert_eq!(from_json_trace(" [ { \"line\" : \"x\\/y\" , \"block\":\"f\" }, {\"line\":\"z\"} ]\n"), Ok(vec![
+            TraceLine { block: Some("f".to_string()), line: "x/y".to_string() },
+            TraceLine { block: None, line: "z".to_string() },
+        ]));
+        assert_eq!(from_json_trace("[]"), Ok(vec![]));
+        assert_eq!(from_json_trace(r#"[{"line":3}]"#), Err(JsonError { pos: 9, msg: "expected a string" }));
+        assert_eq!(from_json_trace(r#"[{"block":"main"}]"#), Err(JsonError { pos: 17, msg: "trace line without a \"line\"" }));
+        assert_eq!(from_json_trace(r#"[{"line":"a","step":1}]"#), Err(JsonError { pos: 13, msg: "unexpected field in a trace line" }));
+        assert_eq!(from_json_trace(r#"[{"line":"a\q"}]"#), Err(JsonError { pos: 11, msg: "bad escape" }));
+        assert_eq!(from_json_trace(r#"[{"line":"a"}] x"#), Err(JsonError { pos: 15, msg: "text after the trace" }));
+        assert_eq!(from_json_trace(r#"[{"line":"a"#), Err(JsonError { pos: 11, msg: "unterminated string" }));
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::{HashMap,HashSet};
+
+use crate::ir441::nodes::*;
+
+#[derive(Debug,PartialEq)]
+pub enum LinkError<'a> {
+    DuplicateBlock { name: &'a str },
+    DuplicateGlobal { name: &'a str },
+    MissingMain,
+}
+
+// Merge separately-parsed programs into one. Blocks and globals share a single namespace each across all inputs,
+// so any name defined twice is an error, and exactly one input may define main. The linked program runs the way
+// the input defining main asks to (its pragmas); other inputs' pragmas are dropped, except that every input's
+// pure declarations and block costs are kept, since they describe that input's own blocks.
+pub fn link_programs<'a>(programs: Vec<IRProgram<'a>>) -> Result<IRProgram<'a>,LinkError<'a>> {
+    let mut globals = vec![];
+    let mut global_names = HashSet::new();
+    let mut blocks = HashMap::new();
+    let mut pragmas = Pragmas::default();
+    let mut pure = vec![];
+    let mut costs = vec![];
+    for p in programs {
+        pure.extend(p.pragmas.pure.iter().cloned());
+        costs.extend(p.pragmas.costs.iter().cloned());
+        if p.blocks.contains_key("main") {
+            pragmas = p.pragmas;
+        }
+        for g in p.globals {
+            let name = g.name();
+            if !global_names.insert(name) {
+                return Err(LinkError::DuplicateGlobal { name });
+            }
+            globals.push(g);
+        }
+        for (name,b) in p.blocks {
+            if blocks.contains_key(name) {
+                return Err(LinkError::DuplicateBlock { name });
+            }
+            blocks.insert(name, b);
+        }
+    }
+    if !blocks.contains_key("main") {
+        return Err(LinkError::MissingMain);
+    }
+    pragmas.pure = pure;
+    pragmas.costs = costs;
+    Ok(IRProgram { globals, vars: VarIds::new(&blocks), blocks, pragmas })
+}
+
+#[cfg(test)]
+mod link_tests {
+    use crate::ir441::link::*;
+    use crate::ir441::exec::*;
+    use crate::ir441::parsing::*;
+
+    fn parse(src: &str) -> IRProgram<'_> {
+        parse_program(src.as_bytes()).unwrap().1
+    }
+
+    #[test]
+    fn check_clean_link() {
+        let main_src = "data:\nglobal array vtbl: { helper }\ncode:\nmain:\n  %1 = call(helper, 20)\n  ret %1\n";
+        let lib_src = "data:\nglobal array consts: { 22 }\ncode:\nhelper(x):\n  %1 = load(@consts)\n  %2 = %x + %1\n  ret %2\n";
+        let linked = link_programs(vec![parse(main_src), parse(lib_src)]).unwrap();
+        assert_eq!(linked.globals.len(), 2);
+        assert!(linked.blocks.contains_key("main"));
+        assert!(linked.blocks.contains_key("helper"));
+        let result = eval_prog(&linked, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 42 })));
+    }
+
+    #[test]
+    fn check_duplicate_symbols() {
+        let a = "data:\nglobal array g: { 1 }\ncode:\nmain:\n  ret 0\nhelper(x):\n  ret 1\n";
+        let dup_block = "data:\ncode:\nhelper(x):\n  ret 2\n";
+        let dup_global = "data:\nglobal array g: { 2 }\ncode:\nother(x):\n  ret 2\n";
+        let no_main = "data:\ncode:\nother(x):\n  ret 2\n";
+        assert_eq!(link_programs(vec![parse(a), parse(dup_block)]), Err(LinkError::DuplicateBlock { name: "helper" }));
+        assert_eq!(link_programs(vec![parse(a), parse(dup_global)]), Err(LinkError::DuplicateGlobal { name: "g" }));
+        assert_eq!(link_programs(vec![parse(a), parse(a)]), Err(LinkError::DuplicateGlobal { name: "g" }));
+        assert_eq!(link_programs(vec![parse(no_main)]), Err(LinkError::MissingMain));
+    }
+}
@@ -1,3 +1,9 @@
 pub mod nodes;
 pub mod parsing;
-pub mod exec;
\ No newline at end of file
+pub mod exec;
+pub mod link;
+pub mod binary;
+pub mod owned;
+pub mod validate;
+pub mod builder;
+pub mod json;
@@ -3,7 +3,7 @@ use std::collections::{HashMap};
 
 // We will model an architecture where code and data live in separate address spaces
 // We don't need to have a global pointer because those actually get flattened into memory
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum VirtualVal<'a> {
     Data { val: u64 },
     CodePtr { val: &'a str },
@@ -27,7 +27,7 @@ impl <'a> VirtualVal<'a> {
     }
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone,Copy)]
 pub enum Reason {
     NotAPointer,
     NotANumber,
@@ -51,6 +51,8 @@ pub enum IRExpr<'a> {
     GlobalRef { name: &'a str },
     Var { id: &'a str },
     BlockRef { bname: &'a str },
+    // A named constant from the data section (`$K`), looked up when evaluated rather than written into each use
+    ConstRef { id: &'a str },
 }
 impl <'a> fmt::Display for IRExpr<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -59,22 +61,102 @@ impl <'a> fmt::Display for IRExpr<'a> {
             IRExpr::GlobalRef { name } => write!(f, "@{}", name),
             IRExpr::BlockRef { bname } => write!(f, "{}", bname),
             IRExpr::Var { id } => write!(f, "%{}", id),
+            IRExpr::ConstRef { id } => write!(f, "${}", id),
+        }
+    }
+}
+
+// The binary operators of `%x = a op b`. Keeping these as an enum rather than the source text means the
+// interpreter's match is checked for exhaustiveness, and an unknown operator is rejected by the parser.
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+    Lt,
+    Gt,
+    Eq,
+}
+impl BinOp {
+    pub const ALL: [BinOp; 12] = [BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div, BinOp::Shl, BinOp::Shr,
+                                  BinOp::And, BinOp::Or, BinOp::Xor, BinOp::Lt, BinOp::Gt, BinOp::Eq];
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Xor => "^",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::Eq => "==",
         }
     }
+    pub fn from_symbol(s: &str) -> Option<BinOp> {
+        BinOp::ALL.iter().copied().find(|op| op.symbol() == s)
+    }
+}
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
 }
 
 #[derive(Debug,PartialEq)]
 pub enum IRStatement<'a> {
     VarAssign { lhs: &'a str, rhs: IRExpr<'a> },
-    Op { lhs: &'a str, arg1: IRExpr<'a>, op: &'a str, arg2: IRExpr<'a> },
+    Op { lhs: &'a str, arg1: IRExpr<'a>, op: BinOp, arg2: IRExpr<'a> },
     Call { lhs: &'a str, code: IRExpr<'a>, receiver: IRExpr<'a>, args: Vec<IRExpr<'a>> },
+    // Call a block that returns several values (ret a, b, ...), binding them in order
+    CallMulti { lhs: Vec<&'a str>, code: IRExpr<'a>, receiver: IRExpr<'a>, args: Vec<IRExpr<'a>> },
     Phi { lhs: &'a str, opts: Vec<(&'a str, IRExpr<'a>)> },
     Alloc { lhs: &'a str, slots: u32 },
+    // Uniform-ish value in [0, bound) from the run's seeded generator
+    Rand { lhs: &'a str, bound: IRExpr<'a> },
     Print { out: IRExpr<'a> },
     GetElt { lhs: &'a str, base: IRExpr<'a>, offset: IRExpr<'a> },
     SetElt { base: IRExpr<'a>, offset: IRExpr<'a>, val: IRExpr<'a> },
+    // Elements of 16 or 32 bits packed into words (little-endian), e.g. UTF-16 text or RGBA pixels.
+    // Reads zero-extend; writes keep only the low bits of val and leave the rest of the word alone.
+    GetEltNarrow { lhs: &'a str, bits: u32, base: IRExpr<'a>, offset: IRExpr<'a> },
+    SetEltNarrow { bits: u32, base: IRExpr<'a>, offset: IRExpr<'a>, val: IRExpr<'a> },
     Load { lhs: &'a str, base: IRExpr<'a> },
-    Store { base: IRExpr<'a>, val: IRExpr<'a> }
+    Store { base: IRExpr<'a>, val: IRExpr<'a> },
+    // Copy slots words from src to dst. Overlapping ranges behave like memmove.
+    MemCopy { dst: IRExpr<'a>, src: IRExpr<'a>, slots: IRExpr<'a> },
+    // Write val into slots consecutive words starting at dst
+    MemFill { dst: IRExpr<'a>, val: IRExpr<'a>, slots: IRExpr<'a> },
+    // Unsigned quotient and remainder from a single divide
+    DivMod { quot: &'a str, rem: &'a str, arg1: IRExpr<'a>, arg2: IRExpr<'a> },
+    // Free marker that snapshots the stats so far under label
+    Checkpoint { label: &'a str },
+    // Explicit casts between pointers and integers. Both just copy the value, but mark the conversion as intended.
+    // The collector still only updates locals holding an object's exact address (and slotmap-marked fields), so an
+    // integer derived from a pointer by arithmetic is not moved with its object, and casting it back afterwards dangles.
+    PtrToInt { lhs: &'a str, ptr: IRExpr<'a> },
+    IntToPtr { lhs: &'a str, int: IRExpr<'a> },
+    // The run's step count so far (ExecStats::steps, including this instruction's own fast op), so a program can time
+    // a region of itself by subtracting two readings
+    ReadCycles { lhs: &'a str },
+    // Call block with receiver (typically the root of the structure) and no other arguments, to check an invariant over
+    // the heap as it stands; fails with msg if it returns 0. The check costs what a call does, but leaves no result behind.
+    AssertHeap { msg: &'a str, block: &'a str, receiver: IRExpr<'a> },
+    // End the whole program on the spot, however deep in calls, as if main had returned val. Free, like a checkpoint,
+    // so the stats are exactly the cost of getting here.
+    Halt { val: IRExpr<'a> },
+    // Compare-and-swap: if the word at base holds expected, replace it with new and set result to 1; otherwise leave it
+    // and set result to 0. There's only one thread, so it can't fail spuriously, but it has the shape of the real thing.
+    Cas { result: &'a str, base: IRExpr<'a>, expected: IRExpr<'a>, new: IRExpr<'a> },
 }
 
 impl <'a> fmt::Display for IRStatement<'a> {
@@ -84,10 +166,23 @@ impl <'a> fmt::Display for IRStatement<'a> {
             IRStatement::Op { lhs, arg1, op, arg2 } => write!(f, "%{} = {} {} {}", lhs, arg1, op, arg2),
             IRStatement::Alloc { lhs, slots } => write!(f, "%{} = alloc({})", lhs, slots),
             IRStatement::Print { out } => write!(f, "print({})", out),
+            IRStatement::Rand { lhs, bound } => write!(f, "%{} = rand({})", lhs, bound),
             IRStatement::GetElt { lhs, base, offset } => write!(f, "%{} = getelt({}, {})", lhs, base, offset),
             IRStatement::SetElt { base, offset, val } => write!(f, "setelt({}, {}, {})", base, offset, val),
+            IRStatement::GetEltNarrow { lhs, bits, base, offset } => write!(f, "%{} = getelt{}({}, {})", lhs, bits, base, offset),
+            IRStatement::SetEltNarrow { bits, base, offset, val } => write!(f, "setelt{}({}, {}, {})", bits, base, offset, val),
             IRStatement::Load { lhs, base } => write!(f, "%{} = load({})", lhs, base),
             IRStatement::Store { base, val } => write!(f, "store({}, {})", base, val),
+            IRStatement::MemCopy { dst, src, slots } => write!(f, "memcpy({}, {}, {})", dst, src, slots),
+            IRStatement::MemFill { dst, val, slots } => write!(f, "memset({}, {}, {})", dst, val, slots),
+            IRStatement::DivMod { quot, rem, arg1, arg2 } => write!(f, "%{}, %{} = divmod({}, {})", quot, rem, arg1, arg2),
+            IRStatement::Checkpoint { label } => write!(f, "checkpoint({})", label),
+            IRStatement::PtrToInt { lhs, ptr } => write!(f, "%{} = ptrtoint({})", lhs, ptr),
+            IRStatement::IntToPtr { lhs, int } => write!(f, "%{} = inttoptr({})", lhs, int),
+            IRStatement::ReadCycles { lhs } => write!(f, "%{} = readcycles()", lhs),
+            IRStatement::AssertHeap { msg, block, receiver } => write!(f, "assertheap({}, {}, \"{}\")", block, receiver, msg),
+            IRStatement::Halt { val } => write!(f, "halt({})", val),
+            IRStatement::Cas { result, base, expected, new } => write!(f, "%{} = cas({}, {}, {})", result, base, expected, new),
             IRStatement::Call { lhs, code, receiver, args } => {
                 write!(f, "%{} = call({}, {}", lhs, code, receiver)?;
                 for elt in args {
@@ -96,6 +191,15 @@ impl <'a> fmt::Display for IRStatement<'a> {
                 }
                 write!(f,")")
             }
+            IRStatement::CallMulti { lhs, code, receiver, args } => {
+                let dests : Vec<String> = lhs.iter().map(|x| format!("%{}", x)).collect();
+                write!(f, "{} = call({}, {}", dests.join(", "), code, receiver)?;
+                for elt in args {
+                    write!(f, ", ")?;
+                    elt.fmt(f)?;
+                }
+                write!(f,")")
+            }
             IRStatement::Phi { lhs, opts } => {
                 write!(f, "%{} = call(", lhs)?;
                 for (bname,src) in opts {
@@ -108,26 +212,120 @@ impl <'a> fmt::Display for IRStatement<'a> {
     }
 }
 
+/// The kind of an instruction (statement or control transfer) with its operands stripped, for filtering and tallying
+#[derive(Debug,PartialEq,Eq,Hash,Clone,Copy)]
+pub enum InstrKind {
+    VarAssign, Op, Call, CallMulti, Phi, Alloc, Rand, Print, GetElt, SetElt, GetEltNarrow, SetEltNarrow, Load, Store, MemCopy, MemFill, DivMod, Checkpoint, PtrToInt, IntToPtr, ReadCycles, AssertHeap, Halt, Cas,
+    Jump, JumpIndirect, If, IfSigned, Ret, RetMulti, RetVoid, Fail
+}
+impl <'a> IRStatement<'a> {
+    pub fn kind(&self) -> InstrKind {
+        match self {
+            IRStatement::VarAssign { .. } => InstrKind::VarAssign,
+            IRStatement::Op { .. } => InstrKind::Op,
+            IRStatement::Call { .. } => InstrKind::Call,
+            IRStatement::CallMulti { .. } => InstrKind::CallMulti,
+            IRStatement::Phi { .. } => InstrKind::Phi,
+            IRStatement::Alloc { .. } => InstrKind::Alloc,
+            IRStatement::Rand { .. } => InstrKind::Rand,
+            IRStatement::Print { .. } => InstrKind::Print,
+            IRStatement::GetElt { .. } => InstrKind::GetElt,
+            IRStatement::SetElt { .. } => InstrKind::SetElt,
+            IRStatement::GetEltNarrow { .. } => InstrKind::GetEltNarrow,
+            IRStatement::SetEltNarrow { .. } => InstrKind::SetEltNarrow,
+            IRStatement::Load { .. } => InstrKind::Load,
+            IRStatement::Store { .. } => InstrKind::Store,
+            IRStatement::MemCopy { .. } => InstrKind::MemCopy,
+            IRStatement::MemFill { .. } => InstrKind::MemFill,
+            IRStatement::DivMod { .. } => InstrKind::DivMod,
+            IRStatement::Checkpoint { .. } => InstrKind::Checkpoint,
+            IRStatement::PtrToInt { .. } => InstrKind::PtrToInt,
+            IRStatement::IntToPtr { .. } => InstrKind::IntToPtr,
+            IRStatement::ReadCycles { .. } => InstrKind::ReadCycles,
+            IRStatement::AssertHeap { .. } => InstrKind::AssertHeap,
+            IRStatement::Halt { .. } => InstrKind::Halt,
+            IRStatement::Cas { .. } => InstrKind::Cas,
+        }
+    }
+    /// Local variables the statement assigns
+    pub fn defs(&self) -> Vec<&'a str> {
+        match self {
+            IRStatement::VarAssign { lhs, .. } | IRStatement::Op { lhs, .. } | IRStatement::Call { lhs, .. }
+            | IRStatement::Phi { lhs, .. } | IRStatement::Alloc { lhs, .. } | IRStatement::Rand { lhs, .. }
+            | IRStatement::GetElt { lhs, .. } | IRStatement::GetEltNarrow { lhs, .. } | IRStatement::Load { lhs, .. }
+            | IRStatement::PtrToInt { lhs, .. } | IRStatement::IntToPtr { lhs, .. } | IRStatement::ReadCycles { lhs } | IRStatement::Cas { result: lhs, .. } => vec![*lhs],
+            IRStatement::DivMod { quot, rem, .. } => vec![*quot, *rem],
+            IRStatement::CallMulti { lhs, .. } => lhs.clone(),
+            IRStatement::Print { .. } | IRStatement::SetElt { .. } | IRStatement::SetEltNarrow { .. } | IRStatement::Store { .. }
+            | IRStatement::MemCopy { .. } | IRStatement::MemFill { .. } | IRStatement::Checkpoint { .. } | IRStatement::AssertHeap { .. }
+            | IRStatement::Halt { .. } => vec![],
+        }
+    }
+    /// Expressions the statement evaluates, left to right (for a phi, every arm's source)
+    pub fn exprs(&self) -> Vec<&IRExpr<'a>> {
+        match self {
+            IRStatement::VarAssign { rhs, .. } => vec![rhs],
+            IRStatement::Op { arg1, arg2, .. } | IRStatement::DivMod { arg1, arg2, .. } => vec![arg1, arg2],
+            IRStatement::Call { code, receiver, args, .. } | IRStatement::CallMulti { code, receiver, args, .. } =>
+                [code, receiver].into_iter().chain(args.iter()).collect(),
+            IRStatement::Phi { opts, .. } => opts.iter().map(|(_,src)| src).collect(),
+            IRStatement::Alloc { .. } | IRStatement::Checkpoint { .. } | IRStatement::ReadCycles { .. } => vec![],
+            IRStatement::AssertHeap { receiver, .. } => vec![receiver],
+            IRStatement::Rand { bound, .. } => vec![bound],
+            IRStatement::Print { out } => vec![out],
+            IRStatement::Halt { val } => vec![val],
+            IRStatement::GetElt { base, offset, .. } | IRStatement::GetEltNarrow { base, offset, .. } => vec![base, offset],
+            IRStatement::SetElt { base, offset, val } | IRStatement::SetEltNarrow { base, offset, val, .. } => vec![base, offset, val],
+            IRStatement::Load { base, .. } => vec![base],
+            IRStatement::Store { base, val } => vec![base, val],
+            IRStatement::Cas { base, expected, new, .. } => vec![base, expected, new],
+            IRStatement::MemCopy { dst, src, slots } => vec![dst, src, slots],
+            IRStatement::MemFill { dst, val, slots } => vec![dst, val, slots],
+            IRStatement::PtrToInt { ptr, .. } => vec![ptr],
+            IRStatement::IntToPtr { int, .. } => vec![int],
+        }
+    }
+}
+
 #[derive(Debug,PartialEq)]
 pub enum ControlXfer<'a> {
     Jump { block: &'a str },
+    // Jump to a code pointer computed at run time, e.g. loaded from a jump table (RunOptions::code_pointer_tables)
+    JumpIndirect { target: IRExpr<'a> },
     If { cond: IRExpr<'a>, tblock: &'a str, fblock: &'a str },
+    // Three-way branch on the sign of cond, interpreted as an i64
+    IfSigned { cond: IRExpr<'a>, neg_block: &'a str, zero_block: &'a str, pos_block: &'a str },
     Ret { val: IRExpr<'a> },
+    // Return several values at once, for a caller binding them with CallMulti
+    RetMulti { vals: Vec<IRExpr<'a>> },
+    // Return without a value. The caller's destination variable is left unassigned.
+    RetVoid,
     Fail { reason: Reason }
 }
 impl <'a> fmt::Display for ControlXfer<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ControlXfer::Jump { block } => write!(f, "jump {}", block),
+            ControlXfer::JumpIndirect { target } => write!(f, "jump {}", target),
             ControlXfer::If { cond, tblock, fblock } => {
                 write!(f, "if ")?;
                 cond.fmt(f)?;
                 write!(f, " then {} else {}", tblock, fblock)
             },
+            ControlXfer::IfSigned { cond, neg_block, zero_block, pos_block } => {
+                write!(f, "ifsigned ")?;
+                cond.fmt(f)?;
+                write!(f, " neg {} zero {} pos {}", neg_block, zero_block, pos_block)
+            },
             ControlXfer::Ret { val } => {
                 write!(f, "ret ")?;
                 val.fmt(f)
             },
+            ControlXfer::RetMulti { vals } => {
+                let vals : Vec<String> = vals.iter().map(|v| v.to_string()).collect();
+                write!(f, "ret {}", vals.join(", "))
+            },
+            ControlXfer::RetVoid => write!(f, "ret void"),
             ControlXfer::Fail { reason } => {
                 write!(f,"fail ")?;
                 reason.fmt(f)
@@ -136,13 +334,50 @@ impl <'a> fmt::Display for ControlXfer<'a> {
     }
 }
 
+impl <'a> ControlXfer<'a> {
+    pub fn kind(&self) -> InstrKind {
+        match self {
+            ControlXfer::Jump { .. } => InstrKind::Jump,
+            ControlXfer::JumpIndirect { .. } => InstrKind::JumpIndirect,
+            ControlXfer::If { .. } => InstrKind::If,
+            ControlXfer::IfSigned { .. } => InstrKind::IfSigned,
+            ControlXfer::Ret { .. } => InstrKind::Ret,
+            ControlXfer::RetMulti { .. } => InstrKind::RetMulti,
+            ControlXfer::RetVoid => InstrKind::RetVoid,
+            ControlXfer::Fail { .. } => InstrKind::Fail,
+        }
+    }
+    /// Expressions the transfer evaluates
+    pub fn exprs(&self) -> Vec<&IRExpr<'a>> {
+        match self {
+            ControlXfer::JumpIndirect { target } => vec![target],
+            ControlXfer::If { cond, .. } | ControlXfer::IfSigned { cond, .. } => vec![cond],
+            ControlXfer::Ret { val } => vec![val],
+            ControlXfer::RetMulti { vals } => vals.iter().collect(),
+            ControlXfer::Jump { .. } | ControlXfer::RetVoid | ControlXfer::Fail { .. } => vec![],
+        }
+    }
+}
+
 #[derive(Debug,PartialEq)]
 pub struct BasicBlock<'a> {
     pub name: &'a str,
     pub formals: Vec<&'a str>,
+    // Defaults for the last defaults.len() formals (`f(this, n = 1):`), bound when a call leaves them off
+    pub defaults: Vec<IRExpr<'a>>,
     pub instrs: Vec<IRStatement<'a>>,
     pub next: ControlXfer<'a>
 }
+impl <'a> BasicBlock<'a> {
+    // Formals a call must supply (the receiver counts as one)
+    pub fn required_formals(&self) -> usize {
+        self.formals.len() - self.defaults.len()
+    }
+    // Whether a call passing n values, receiver included, binds every formal
+    pub fn accepts_args(&self, n: usize) -> bool {
+        n >= self.required_formals() && n <= self.formals.len()
+    }
+}
 impl <'a> fmt::Display for BasicBlock<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.formals.len() == 0 {
@@ -150,11 +385,14 @@ impl <'a> fmt::Display for BasicBlock<'a> {
         } else {
             write!(f, "{}(",self.name)?;
             let mut first = true;
-            for arg in &self.formals {
+            for (idx,arg) in self.formals.iter().enumerate() {
                 if !first {
                     write!(f, ", ")?;
                 }
                 write!(f, "{}", arg)?;
+                if idx >= self.required_formals() {
+                    write!(f, " = {}", self.defaults[idx - self.required_formals()])?;
+                }
                 first = false;
             }
             writeln!(f, "):")?;
@@ -170,16 +408,104 @@ impl <'a> fmt::Display for BasicBlock<'a> {
     }
 }
 
+// Global initializers are restricted expressions: IntLit, BlockRef (a code pointer), or GlobalRef (another global's address).
+// An array can declare more slots than it has initializers (`global array buf[8]: { 1, 2 }`); the rest are reserved
+// but never hold a value, so reading them is an error.
+// A constant (`global const K = 1000`) takes no memory; code reads it with `$K`, the constant pool of a real machine.
 #[derive(Debug,PartialEq)]
 pub enum GlobalStatic<'a> {
-    Array { name: &'a str, vals: Vec<VirtualVal<'a>> }
+    Array { name: &'a str, vals: Vec<IRExpr<'a>>, slots: Option<u64> },
+    Const { name: &'a str, val: u64 },
+}
+impl <'a> GlobalStatic<'a> {
+    pub fn name(&self) -> &'a str {
+        match self {
+            GlobalStatic::Array { name, .. } | GlobalStatic::Const { name, .. } => name
+        }
+    }
+}
+
+// Memory modes a program can ask for with a `;@ mode` pragma; mirrors exec::ExecMode without the limits
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum PragmaMode {
+    Unlimited,
+    MemCap,
+    GC,
+    LoggingGC,
+}
+
+// Run settings declared by `;@` lines at the top of a file, before `data:`, e.g.
+//     ;@ mode gc
+//     ;@ cap 64
+// so a test program can say how it expects to be run. A `;@` line anywhere after `data:` is a parse error.
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Pragmas {
+    pub mode: Option<PragmaMode>,
+    pub cap: Option<u64>,
+    // Pragma lines we didn't understand, kept verbatim so they can be reported as warnings
+    pub unknown: Vec<String>,
+    // Blocks declared `;@ pure f g`, whose calls may be memoized once validate::purity_violation clears them
+    pub pure: Vec<String>,
+    // Blocks given a fixed extra cost by `;@ cost f 1000`, charged in cycles each time control enters them, e.g. to
+    // stand in for a runtime library routine the program only stubs out
+    pub costs: Vec<(String,u64)>,
 }
 
 #[derive(Debug,PartialEq)]
 pub struct IRProgram<'a> {
     pub globals: Vec<GlobalStatic<'a>>,
-    pub blocks: HashMap<&'a str, BasicBlock<'a>>
+    pub blocks: HashMap<&'a str, BasicBlock<'a>>,
+    pub pragmas: Pragmas,
+    // Built from blocks whenever a program is made (VarIds::new), so it's stale if blocks are changed afterwards
+    pub vars: VarIds<'a>,
+}
+impl <'a> IRProgram<'a> {
+    // Value of the constant declared as name, if there is one
+    pub fn constant(&self, name: &str) -> Option<u64> {
+        self.globals.iter().find_map(|g| match g {
+            GlobalStatic::Const { name: n, val } if *n == name => Some(*val),
+            _ => None
+        })
+    }
+}
+/// Identifies a variable within one program (see VarIds)
+pub type VarId = u32;
+
+/// Every variable name a program mentions, as a formal, a definition, or an operand, numbered densely from 0 in order
+/// of first mention (taking blocks in name order), so an interpreter can keep a frame's variables in a Vec
+#[derive(Debug,PartialEq,Default)]
+pub struct VarIds<'a> {
+    ids: HashMap<&'a str,VarId>,
 }
+impl <'a> VarIds<'a> {
+    pub fn new(blocks: &HashMap<&'a str, BasicBlock<'a>>) -> VarIds<'a> {
+        let mut ids = HashMap::new();
+        let mut blocks : Vec<&BasicBlock<'a>> = blocks.values().collect();
+        blocks.sort_by_key(|b| b.name);
+        for b in blocks {
+            let exprs = b.defaults.iter().chain(b.instrs.iter().flat_map(|i| i.exprs())).chain(b.next.exprs());
+            let used = exprs.filter_map(|e| match e {
+                IRExpr::Var { id } => Some(*id),
+                _ => None
+            });
+            for name in b.formals.iter().copied().chain(b.instrs.iter().flat_map(|i| i.defs())).chain(used) {
+                let next = ids.len() as VarId;
+                ids.entry(name).or_insert(next);
+            }
+        }
+        VarIds { ids }
+    }
+    pub fn id(&self, name: &str) -> Option<VarId> {
+        self.ids.get(name).copied()
+    }
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
 impl <'a> fmt::Display for IRProgram<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for (_,b) in &self.blocks {
@@ -0,0 +1,255 @@
+// Self-contained mirror of the IR. The node types in nodes.rs borrow their identifiers from the source text,
+// which keeps parsing cheap but ties every program to its input buffer. OwnedIRProgram holds its own Strings,
+// so it can outlive the text it was parsed from; call view() to get an ordinary IRProgram borrowing from it
+// whenever you want to run or inspect it.
+use std::collections::{HashMap};
+
+use crate::ir441::nodes::*;
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum OwnedExpr {
+    IntLit { val: u64 },
+    GlobalRef { name: String },
+    Var { id: String },
+    BlockRef { bname: String },
+    ConstRef { id: String },
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum OwnedStatement {
+    VarAssign { lhs: String, rhs: OwnedExpr },
+    Op { lhs: String, arg1: OwnedExpr, op: BinOp, arg2: OwnedExpr },
+    Call { lhs: String, code: OwnedExpr, receiver: OwnedExpr, args: Vec<OwnedExpr> },
+    CallMulti { lhs: Vec<String>, code: OwnedExpr, receiver: OwnedExpr, args: Vec<OwnedExpr> },
+    Phi { lhs: String, opts: Vec<(String, OwnedExpr)> },
+    Alloc { lhs: String, slots: u32 },
+    Rand { lhs: String, bound: OwnedExpr },
+    Print { out: OwnedExpr },
+    GetElt { lhs: String, base: OwnedExpr, offset: OwnedExpr },
+    SetElt { base: OwnedExpr, offset: OwnedExpr, val: OwnedExpr },
+    GetEltNarrow { lhs: String, bits: u32, base: OwnedExpr, offset: OwnedExpr },
+    SetEltNarrow { bits: u32, base: OwnedExpr, offset: OwnedExpr, val: OwnedExpr },
+    Load { lhs: String, base: OwnedExpr },
+    Store { base: OwnedExpr, val: OwnedExpr },
+    MemCopy { dst: OwnedExpr, src: OwnedExpr, slots: OwnedExpr },
+    MemFill { dst: OwnedExpr, val: OwnedExpr, slots: OwnedExpr },
+    DivMod { quot: String, rem: String, arg1: OwnedExpr, arg2: OwnedExpr },
+    Checkpoint { label: String },
+    PtrToInt { lhs: String, ptr: OwnedExpr },
+    IntToPtr { lhs: String, int: OwnedExpr },
+    ReadCycles { lhs: String },
+    AssertHeap { msg: String, block: String, receiver: OwnedExpr },
+    Halt { val: OwnedExpr },
+    Cas { result: String, base: OwnedExpr, expected: OwnedExpr, new: OwnedExpr },
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum OwnedControl {
+    Jump { block: String },
+    JumpIndirect { target: OwnedExpr },
+    If { cond: OwnedExpr, tblock: String, fblock: String },
+    IfSigned { cond: OwnedExpr, neg_block: String, zero_block: String, pos_block: String },
+    Ret { val: OwnedExpr },
+    RetMulti { vals: Vec<OwnedExpr> },
+    RetVoid,
+    Fail { reason: Reason },
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct OwnedBlock {
+    pub name: String,
+    pub formals: Vec<String>,
+    pub defaults: Vec<OwnedExpr>,
+    pub instrs: Vec<OwnedStatement>,
+    pub next: OwnedControl,
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub enum OwnedGlobal {
+    Array { name: String, vals: Vec<OwnedExpr>, slots: Option<u64> },
+    Const { name: String, val: u64 },
+}
+
+#[derive(Debug,PartialEq,Clone)]
+pub struct OwnedIRProgram {
+    pub globals: Vec<OwnedGlobal>,
+    pub blocks: HashMap<String, OwnedBlock>,
+    pub pragmas: Pragmas,
+}
+
+impl <'a> IRProgram<'a> {
+    pub fn to_owned(&self) -> OwnedIRProgram {
+        OwnedIRProgram {
+            globals: self.globals.iter().map(|g| match g {
+                GlobalStatic::Array { name, vals, slots } =>
+                    OwnedGlobal::Array { name: name.to_string(), vals: vals.iter().map(own_expr).collect(), slots: *slots },
+                GlobalStatic::Const { name, val } => OwnedGlobal::Const { name: name.to_string(), val: *val },
+            }).collect(),
+            blocks: self.blocks.iter().map(|(name,b)| (name.to_string(), OwnedBlock {
+                name: b.name.to_string(),
+                formals: b.formals.iter().map(|f| f.to_string()).collect(),
+                defaults: b.defaults.iter().map(own_expr).collect(),
+                instrs: b.instrs.iter().map(own_stmt).collect(),
+                next: own_control(&b.next),
+            })).collect(),
+            pragmas: self.pragmas.clone(),
+        }
+    }
+}
+
+impl OwnedIRProgram {
+    // Borrow this program as a regular IRProgram, e.g. to hand to run_prog
+    pub fn view(&self) -> IRProgram<'_> {
+        let blocks = self.blocks.iter().map(|(name,b)| (name.as_str(), BasicBlock {
+            name: &b.name,
+            formals: b.formals.iter().map(|f| f.as_str()).collect(),
+            defaults: b.defaults.iter().map(view_expr).collect(),
+            instrs: b.instrs.iter().map(view_stmt).collect(),
+            next: view_control(&b.next),
+        })).collect();
+        IRProgram {
+            vars: VarIds::new(&blocks),
+            globals: self.globals.iter().map(|g| match g {
+                OwnedGlobal::Array { name, vals, slots } =>
+                    GlobalStatic::Array { name, vals: vals.iter().map(view_expr).collect(), slots: *slots },
+                OwnedGlobal::Const { name, val } => GlobalStatic::Const { name, val: *val },
+            }).collect(),
+            blocks,
+            pragmas: self.pragmas.clone(),
+        }
+    }
+}
+
+fn own_expr(e: &IRExpr) -> OwnedExpr {
+    match e {
+        IRExpr::IntLit { val } => OwnedExpr::IntLit { val: *val },
+        IRExpr::GlobalRef { name } => OwnedExpr::GlobalRef { name: name.to_string() },
+        IRExpr::Var { id } => OwnedExpr::Var { id: id.to_string() },
+        IRExpr::BlockRef { bname } => OwnedExpr::BlockRef { bname: bname.to_string() },
+        IRExpr::ConstRef { id } => OwnedExpr::ConstRef { id: id.to_string() },
+    }
+}
+fn view_expr(e: &OwnedExpr) -> IRExpr<'_> {
+    match e {
+        OwnedExpr::IntLit { val } => IRExpr::IntLit { val: *val },
+        OwnedExpr::GlobalRef { name } => IRExpr::GlobalRef { name },
+        OwnedExpr::Var { id } => IRExpr::Var { id },
+        OwnedExpr::BlockRef { bname } => IRExpr::BlockRef { bname },
+        OwnedExpr::ConstRef { id } => IRExpr::ConstRef { id },
+    }
+}
+
+fn own_stmt(i: &IRStatement) -> OwnedStatement {
+    match i {
+        IRStatement::VarAssign { lhs, rhs } => OwnedStatement::VarAssign { lhs: lhs.to_string(), rhs: own_expr(rhs) },
+        IRStatement::Op { lhs, arg1, op, arg2 } => OwnedStatement::Op { lhs: lhs.to_string(), arg1: own_expr(arg1), op: *op, arg2: own_expr(arg2) },
+        IRStatement::Call { lhs, code, receiver, args } => OwnedStatement::Call { lhs: lhs.to_string(), code: own_expr(code), receiver: own_expr(receiver), args: args.iter().map(own_expr).collect() },
+        IRStatement::CallMulti { lhs, code, receiver, args } => OwnedStatement::CallMulti { lhs: lhs.iter().map(|l| l.to_string()).collect(), code: own_expr(code), receiver: own_expr(receiver), args: args.iter().map(own_expr).collect() },
+        IRStatement::Phi { lhs, opts } => OwnedStatement::Phi { lhs: lhs.to_string(), opts: opts.iter().map(|(b,e)| (b.to_string(), own_expr(e))).collect() },
+        IRStatement::Alloc { lhs, slots } => OwnedStatement::Alloc { lhs: lhs.to_string(), slots: *slots },
+        IRStatement::Rand { lhs, bound } => OwnedStatement::Rand { lhs: lhs.to_string(), bound: own_expr(bound) },
+        IRStatement::Print { out } => OwnedStatement::Print { out: own_expr(out) },
+        IRStatement::GetElt { lhs, base, offset } => OwnedStatement::GetElt { lhs: lhs.to_string(), base: own_expr(base), offset: own_expr(offset) },
+        IRStatement::SetElt { base, offset, val } => OwnedStatement::SetElt { base: own_expr(base), offset: own_expr(offset), val: own_expr(val) },
+        IRStatement::GetEltNarrow { lhs, bits, base, offset } => OwnedStatement::GetEltNarrow { lhs: lhs.to_string(), bits: *bits, base: own_expr(base), offset: own_expr(offset) },
+        IRStatement::SetEltNarrow { bits, base, offset, val } => OwnedStatement::SetEltNarrow { bits: *bits, base: own_expr(base), offset: own_expr(offset), val: own_expr(val) },
+        IRStatement::Load { lhs, base } => OwnedStatement::Load { lhs: lhs.to_string(), base: own_expr(base) },
+        IRStatement::Store { base, val } => OwnedStatement::Store { base: own_expr(base), val: own_expr(val) },
+        IRStatement::MemCopy { dst, src, slots } => OwnedStatement::MemCopy { dst: own_expr(dst), src: own_expr(src), slots: own_expr(slots) },
+        IRStatement::MemFill { dst, val, slots } => OwnedStatement::MemFill { dst: own_expr(dst), val: own_expr(val), slots: own_expr(slots) },
+        IRStatement::DivMod { quot, rem, arg1, arg2 } => OwnedStatement::DivMod { quot: quot.to_string(), rem: rem.to_string(), arg1: own_expr(arg1), arg2: own_expr(arg2) },
+        IRStatement::Checkpoint { label } => OwnedStatement::Checkpoint { label: label.to_string() },
+        IRStatement::PtrToInt { lhs, ptr } => OwnedStatement::PtrToInt { lhs: lhs.to_string(), ptr: own_expr(ptr) },
+        IRStatement::IntToPtr { lhs, int } => OwnedStatement::IntToPtr { lhs: lhs.to_string(), int: own_expr(int) },
+        IRStatement::ReadCycles { lhs } => OwnedStatement::ReadCycles { lhs: lhs.to_string() },
+        IRStatement::AssertHeap { msg, block, receiver } => OwnedStatement::AssertHeap { msg: msg.to_string(), block: block.to_string(), receiver: own_expr(receiver) },
+        IRStatement::Halt { val } => OwnedStatement::Halt { val: own_expr(val) },
+        IRStatement::Cas { result, base, expected, new } => OwnedStatement::Cas { result: result.to_string(), base: own_expr(base), expected: own_expr(expected), new: own_expr(new) },
+    }
+}
+fn view_stmt(i: &OwnedStatement) -> IRStatement<'_> {
+    match i {
+        OwnedStatement::VarAssign { lhs, rhs } => IRStatement::VarAssign { lhs, rhs: view_expr(rhs) },
+        OwnedStatement::Op { lhs, arg1, op, arg2 } => IRStatement::Op { lhs, arg1: view_expr(arg1), op: *op, arg2: view_expr(arg2) },
+        OwnedStatement::Call { lhs, code, receiver, args } => IRStatement::Call { lhs, code: view_expr(code), receiver: view_expr(receiver), args: args.iter().map(view_expr).collect() },
+        OwnedStatement::CallMulti { lhs, code, receiver, args } => IRStatement::CallMulti { lhs: lhs.iter().map(|l| l.as_str()).collect(), code: view_expr(code), receiver: view_expr(receiver), args: args.iter().map(view_expr).collect() },
+        OwnedStatement::Phi { lhs, opts } => IRStatement::Phi { lhs, opts: opts.iter().map(|(b,e)| (b.as_str(), view_expr(e))).collect() },
+        OwnedStatement::Alloc { lhs, slots } => IRStatement::Alloc { lhs, slots: *slots },
+        OwnedStatement::Rand { lhs, bound } => IRStatement::Rand { lhs, bound: view_expr(bound) },
+        OwnedStatement::Print { out } => IRStatement::Print { out: view_expr(out) },
+        OwnedStatement::GetElt { lhs, base, offset } => IRStatement::GetElt { lhs, base: view_expr(base), offset: view_expr(offset) },
+        OwnedStatement::SetElt { base, offset, val } => IRStatement::SetElt { base: view_expr(base), offset: view_expr(offset), val: view_expr(val) },
+        OwnedStatement::GetEltNarrow { lhs, bits, base, offset } => IRStatement::GetEltNarrow { lhs, bits: *bits, base: view_expr(base), offset: view_expr(offset) },
+        OwnedStatement::SetEltNarrow { bits, base, offset, val } => IRStatement::SetEltNarrow { bits: *bits, base: view_expr(base), offset: view_expr(offset), val: view_expr(val) },
+        OwnedStatement::Load { lhs, base } => IRStatement::Load { lhs, base: view_expr(base) },
+        OwnedStatement::Store { base, val } => IRStatement::Store { base: view_expr(base), val: view_expr(val) },
+        OwnedStatement::MemCopy { dst, src, slots } => IRStatement::MemCopy { dst: view_expr(dst), src: view_expr(src), slots: view_expr(slots) },
+        OwnedStatement::MemFill { dst, val, slots } => IRStatement::MemFill { dst: view_expr(dst), val: view_expr(val), slots: view_expr(slots) },
+        OwnedStatement::DivMod { quot, rem, arg1, arg2 } => IRStatement::DivMod { quot, rem, arg1: view_expr(arg1), arg2: view_expr(arg2) },
+        OwnedStatement::Checkpoint { label } => IRStatement::Checkpoint { label },
+        OwnedStatement::PtrToInt { lhs, ptr } => IRStatement::PtrToInt { lhs, ptr: view_expr(ptr) },
+        OwnedStatement::IntToPtr { lhs, int } => IRStatement::IntToPtr { lhs, int: view_expr(int) },
+        OwnedStatement::ReadCycles { lhs } => IRStatement::ReadCycles { lhs },
+        OwnedStatement::AssertHeap { msg, block, receiver } => IRStatement::AssertHeap { msg, block, receiver: view_expr(receiver) },
+        OwnedStatement::Halt { val } => IRStatement::Halt { val: view_expr(val) },
+        OwnedStatement::Cas { result, base, expected, new } => IRStatement::Cas { result, base: view_expr(base), expected: view_expr(expected), new: view_expr(new) },
+    }
+}
+
+fn own_control(c: &ControlXfer) -> OwnedControl {
+    match c {
+        ControlXfer::Jump { block } => OwnedControl::Jump { block: block.to_string() },
+        ControlXfer::JumpIndirect { target } => OwnedControl::JumpIndirect { target: own_expr(target) },
+        ControlXfer::If { cond, tblock, fblock } => OwnedControl::If { cond: own_expr(cond), tblock: tblock.to_string(), fblock: fblock.to_string() },
+        ControlXfer::IfSigned { cond, neg_block, zero_block, pos_block } =>
+            OwnedControl::IfSigned { cond: own_expr(cond), neg_block: neg_block.to_string(), zero_block: zero_block.to_string(), pos_block: pos_block.to_string() },
+        ControlXfer::Ret { val } => OwnedControl::Ret { val: own_expr(val) },
+        ControlXfer::RetMulti { vals } => OwnedControl::RetMulti { vals: vals.iter().map(own_expr).collect() },
+        ControlXfer::RetVoid => OwnedControl::RetVoid,
+        ControlXfer::Fail { reason } => OwnedControl::Fail { reason: *reason },
+    }
+}
+fn view_control(c: &OwnedControl) -> ControlXfer<'_> {
+    match c {
+        OwnedControl::Jump { block } => ControlXfer::Jump { block },
+        OwnedControl::JumpIndirect { target } => ControlXfer::JumpIndirect { target: view_expr(target) },
+        OwnedControl::If { cond, tblock, fblock } => ControlXfer::If { cond: view_expr(cond), tblock, fblock },
+        OwnedControl::IfSigned { cond, neg_block, zero_block, pos_block } => ControlXfer::IfSigned { cond: view_expr(cond), neg_block, zero_block, pos_block },
+        OwnedControl::Ret { val } => ControlXfer::Ret { val: view_expr(val) },
+        OwnedControl::RetMulti { vals } => ControlXfer::RetMulti { vals: vals.iter().map(view_expr).collect() },
+        OwnedControl::RetVoid => ControlXfer::RetVoid,
+        OwnedControl::Fail { reason } => ControlXfer::Fail { reason: *reason },
+    }
+}
+
+#[cfg(test)]
+mod owned_tests {
+    use crate::ir441::owned::*;
+    use crate::ir441::parsing::*;
+    use crate::ir441::exec::*;
+
+    // Parse from a buffer that is dropped before returning, so the result can't be borrowing from it
+    fn load_owned(path: &str) -> OwnedIRProgram {
+        let src = std::fs::read(path).unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        prog.to_owned()
+    }
+
+    #[test]
+    fn check_owned_executes_identically() {
+        for (path,mode) in [("examples/basicoo.ir", ExecMode::Unlimited), ("examples/gctest3.ir", ExecMode::GC { limit: 100 })] {
+            let src = std::fs::read(path).unwrap();
+            let (_,prog) = parse_program(&src[..]).unwrap();
+            let mut borrowed_stats = ExecStats::new();
+            let borrowed_result = run_prog(&prog, false, &mut borrowed_stats, mode.clone()).map(|v| v.to_string());
+
+            let owned = load_owned(path);
+            let view = owned.view();
+            assert_eq!(view, prog);
+            let mut owned_stats = ExecStats::new();
+            let owned_result = run_prog(&view, false, &mut owned_stats, mode).map(|v| v.to_string());
+            assert_eq!(owned_result, borrowed_result);
+            assert_eq!(owned_stats, borrowed_stats);
+        }
+    }
+}
@@ -4,11 +4,12 @@ use std::collections::{HashMap};
 
 use crate::ir441::nodes::*;
 use nom::{IResult,Finish};
+use nom::error::ErrorKind;
 use nom::bytes::complete::{tag,is_not};
 use nom::branch::{alt};
 use nom::character::complete::{digit1,alpha1,multispace1, multispace0,alphanumeric1};
 use nom::sequence::{tuple,pair};
-use nom::combinator::{recognize,all_consuming,opt};
+use nom::combinator::{recognize,all_consuming,opt,verify,value};
 use nom::multi::{many0,separated_list1,separated_list0};
 
 use std::str::{from_utf8};
@@ -23,21 +24,21 @@ pub fn identifier(input: &[u8]) -> IResult<&[u8], &str> {
       )
     )(input).map(|(rest,m)| (rest,from_utf8(m).unwrap()))
   }
-pub fn parse_op(i: &[u8]) -> IResult<&[u8], &str> {
+pub fn parse_op(i: &[u8]) -> IResult<&[u8], BinOp> {
     alt((
-        tag("<<"),
-        tag(">>"),
-        tag("+"),
-        tag("-"),
-        tag("*"),
-        tag("/"),
-        tag("|"),
-        tag("&"),
-        tag("^"),
-        tag("<"),
-        tag(">"),
-        tag("==")
-    ))(i).map(|(rest,op)| (rest, from_utf8(op).unwrap()))
+        value(BinOp::Shl, tag("<<")),
+        value(BinOp::Shr, tag(">>")),
+        value(BinOp::Add, tag("+")),
+        value(BinOp::Sub, tag("-")),
+        value(BinOp::Mul, tag("*")),
+        value(BinOp::Div, tag("/")),
+        value(BinOp::Or, tag("|")),
+        value(BinOp::And, tag("&")),
+        value(BinOp::Xor, tag("^")),
+        value(BinOp::Lt, tag("<")),
+        value(BinOp::Gt, tag(">")),
+        value(BinOp::Eq, tag("=="))
+    ))(i)
 }
 pub fn parse_register_name(i: &[u8]) -> IResult<&[u8], &str> {
     alphanumeric1(i).map(|(rest,id)| (rest, from_utf8(id).unwrap()))
@@ -47,10 +48,29 @@ pub fn parse_ir_expr(i: &[u8]) -> IResult<&[u8], IRExpr> {
     alt((
         |i| tuple((tag("@"),identifier))(i).map(|(rest,(_,id))| (rest,IRExpr::GlobalRef { name : id })),
         |i| tuple((tag("%"),parse_register_name))(i).map(|(rest,(_,id))| (rest,IRExpr::Var { id: id })),
+        |i| tuple((tag("$"),identifier))(i).map(|(rest,(_,id))| (rest,IRExpr::ConstRef { id })),
         |i| identifier(i).map(|(rest,id)| (rest,IRExpr::BlockRef { bname: id })),
-        |i| digit1(i).map(|(rest,n)| (rest,IRExpr::IntLit { val: from_utf8(n).unwrap().parse::<u64>().unwrap() }))
+        |i| parse_int_lit(i).map(|(rest,n)| (rest,IRExpr::IntLit { val: n }))
     ))(i)
 }
+// Literals cover the full u64 range. One that doesn't fit is a hard failure pointing at the token, so the parser
+// doesn't backtrack and report something confusing further along.
+pub fn parse_int_lit(i: &[u8]) -> IResult<&[u8], u64> {
+    let (rest,n) = digit1(i)?;
+    match from_utf8(n).unwrap().parse::<u64>() {
+        Ok(val) => Ok((rest,val)),
+        Err(_) => Err(nom::Err::Failure(nom::error::Error::new(i, ErrorKind::TooLarge)))
+    }
+}
+
+// Alloc sizes are 32 bits; a bigger one fails the same way as a literal too big for 64
+fn parse_alloc_size(i: &[u8]) -> IResult<&[u8], u32> {
+    let (rest,n) = parse_int_lit(i)?;
+    match u32::try_from(n) {
+        Ok(slots) => Ok((rest,slots)),
+        Err(_) => Err(nom::Err::Failure(nom::error::Error::new(i, ErrorKind::TooLarge)))
+    }
+}
 
 pub fn parse_reason(i: &[u8]) -> IResult<&[u8], Reason> {
     let (i,_) = multispace0(i)?;
@@ -61,6 +81,10 @@ pub fn parse_reason(i: &[u8]) -> IResult<&[u8], Reason> {
         |i| tag("NoSuchField")(i).map(|(rest,_)| (rest, Reason::NoSuchField))
     ))(i)
 }
+// A double-quoted message, with no escapes; it can't contain a quote or run past the end of the line
+pub fn parse_message(i: &[u8]) -> IResult<&[u8], &str> {
+    tuple((tag("\""),opt(is_not("\"\n")),tag("\"")))(i).map(|(rest,(_,m,_))| (rest, m.map_or("", |m| from_utf8(m).unwrap())))
+}
 pub fn parse_arg_list(i: &[u8]) -> IResult<&[u8], Vec<IRExpr>> {
     alt((|i| tuple((tag(","),multispace0,separated_list0(tuple((multispace0,tag(","),multispace0)),parse_ir_expr),multispace0,tag(")")))(i).map(|(rest,(_,_,v,_,_))| (rest,v) ),
          |i| tag(")")(i).map(|(rest,_)| (rest, Vec::new()))
@@ -75,6 +99,17 @@ pub fn parse_phi_arg_list(i: &[u8]) -> IResult<&[u8], Vec<(&str,IRExpr)>> {
     ))(i)
 }
 
+// Element width suffix for getelt/setelt on sub-word elements
+fn parse_elt_bits(i: &[u8]) -> IResult<&[u8], u32> {
+    alt((tag("16"),tag("32")))(i).map(|(rest,b)| (rest,from_utf8(b).unwrap().parse::<u32>().unwrap()))
+}
+
+// Two or more destination registers, as in %q, %r = ...
+fn parse_dest_list(i: &[u8]) -> IResult<&[u8], Vec<&str>> {
+    verify(separated_list1(tuple((multispace0,tag(","),multispace0)), |x| tuple((tag("%"),parse_register_name))(x).map(|(rest,(_,r))| (rest,r))),
+           |ds: &Vec<&str>| ds.len() > 1)(i)
+}
+
 pub fn parse_ir_statement(i: &[u8]) -> IResult<&[u8], IRStatement> {
     // TODO: Very sensitive to ordering. Should reject input that results in parsing a blockname phi or alloc
     let (i,_) = multispace0(i)?;
@@ -103,11 +138,20 @@ pub fn parse_ir_statement(i: &[u8]) -> IResult<&[u8], IRStatement> {
     let (i,_) = multispace0(i)?;
     alt((
         |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("phi("),multispace0,parse_phi_arg_list))(i).map(|(rest,(_,l,_,_,_,_,_,a1))| (rest,IRStatement::Phi { lhs: l, opts: a1 })),
-        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("load("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,l,_,_,_,_,_,a1,_,_))| (rest,IRStatement::Load { lhs: l, base: a1 })),
+        // Loads and compare-and-swaps share a branch to stay within alt's limit
+        |i| alt((
+            |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("load("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,l,_,_,_,_,_,a1,_,_))| (rest,IRStatement::Load { lhs: l, base: a1 })),
+            |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("cas("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,r,_,_,_,_,_,b,_,_,_,e,_,_,_,n,_,_))| (rest,IRStatement::Cas { result: r, base: b, expected: e, new: n })),
+        ))(i),
         |i| tuple((tag("store("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,_,base,_,_,_,v,_,_))| (rest,IRStatement::Store { base: base , val: v })),
-        |i| tuple((tag("setelt("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,_,base,_,_,_,off,_,_,_,v,_,_))| (rest,IRStatement::SetElt { base: base, offset: off, val: v })),
-        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("getelt("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,lhs,_,_,_,_,_,base,_,_,_,off,_,_))| (rest,IRStatement::GetElt { lhs: lhs, base: base, offset: off })),
-        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("load("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,l,_,_,_,_,_,a1,_,_))| (rest,IRStatement::Load { lhs: l, base: a1 })),
+        |i| tuple((tag("setelt"),opt(parse_elt_bits),tag("("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,bits,_,_,base,_,_,_,off,_,_,_,v,_,_))| (rest,match bits {
+            None => IRStatement::SetElt { base: base, offset: off, val: v },
+            Some(bits) => IRStatement::SetEltNarrow { bits, base, offset: off, val: v }
+        })),
+        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("getelt"),opt(parse_elt_bits),tag("("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,lhs,_,_,_,_,bits,_,_,base,_,_,_,off,_,_))| (rest,match bits {
+            None => IRStatement::GetElt { lhs: lhs, base: base, offset: off },
+            Some(bits) => IRStatement::GetEltNarrow { lhs, bits, base, offset: off }
+        })),
         |i| tuple((tag("%"),
                    parse_register_name,
                    tuple((multispace1,tag("="),multispace1)),
@@ -118,12 +162,32 @@ pub fn parse_ir_statement(i: &[u8]) -> IResult<&[u8], IRStatement> {
                    multispace0,
                    parse_arg_list, // TODO: check handling of that first comma before the varargs part
             ))(i).map(|(rest,(_,l,_,_,cd,_,rcv,_,args))| (rest,IRStatement::Call { lhs: l, code: cd, receiver: rcv, args: args })),
-        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("alloc("),digit1,tag(")")))(i).map(
-            |(rest,(_,l,_,_,_,_,d,_))| (rest,IRStatement::Alloc { lhs: l, slots: from_utf8(d).unwrap().parse::<u32>().unwrap() })),
+        |i| tuple((parse_dest_list,
+                   tuple((multispace1,tag("="),multispace1)),
+                   tag("call("),
+                   parse_ir_expr,
+                   tuple((multispace0,tag(","),multispace0)),
+                   parse_ir_expr,
+                   multispace0,
+                   parse_arg_list,
+            ))(i).map(|(rest,(ls,_,_,cd,_,rcv,_,args))| (rest,IRStatement::CallMulti { lhs: ls, code: cd, receiver: rcv, args })),
+        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("alloc("),parse_alloc_size,tag(")")))(i).map(
+            |(rest,(_,l,_,_,_,_,slots,_))| (rest,IRStatement::Alloc { lhs: l, slots })),
+        |i| tuple((tag("%"),parse_register_name,tag(","),multispace0,tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("divmod("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(
+            |(rest,(_,q,_,_,_,r,_,_,_,_,_,a1,_,_,_,a2,_,_))| (rest,IRStatement::DivMod { quot: q, rem: r, arg1: a1, arg2: a2 })),
+        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("ptrtoint("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,l,_,_,_,_,_,p,_,_))| (rest,IRStatement::PtrToInt { lhs: l, ptr: p })),
+        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("inttoptr("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,l,_,_,_,_,_,n,_,_))| (rest,IRStatement::IntToPtr { lhs: l, int: n })),
+        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("readcycles("),multispace0,tag(")")))(i).map(|(rest,(_,l,_,_,_,_,_,_))| (rest,IRStatement::ReadCycles { lhs: l })),
+        |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,tag("rand("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,l,_,_,_,_,_,b,_,_))| (rest,IRStatement::Rand { lhs: l, bound: b })),
         |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,parse_ir_expr,multispace1,parse_op,multispace1,parse_ir_expr))(i).map(|(rest,(_,l,_,_,_,a1,_,o,_,a2))| (rest,IRStatement::Op { lhs: l, arg1: a1, op: o, arg2: a2 })),
         |i| tuple((tag("%"),parse_register_name,multispace1,tag("="),multispace1,parse_ir_expr))(i).map(|(rest,(_,l,_,_,_,a1))| (rest,IRStatement::VarAssign { lhs: l, rhs: a1 })),
         // print
-        |i| tuple((tag("print("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,_,e,_,_))| (rest, IRStatement::Print { out: e}))
+        |i| tuple((tag("print("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,_,e,_,_))| (rest, IRStatement::Print { out: e})),
+        |i| tuple((tag("halt("),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,_,e,_,_))| (rest, IRStatement::Halt { val: e })),
+        |i| tuple((tag("checkpoint("),multispace0,identifier,multispace0,tag(")")))(i).map(|(rest,(_,_,l,_,_))| (rest, IRStatement::Checkpoint { label: l })),
+        |i| tuple((tag("assertheap("),multispace0,identifier,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_message,multispace0,tag(")")))(i).map(|(rest,(_,_,b,_,_,_,r,_,_,_,m,_,_))| (rest, IRStatement::AssertHeap { msg: m, block: b, receiver: r })),
+        |i| tuple((tag("memcpy("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,_,d,_,_,_,s,_,_,_,n,_,_))| (rest,IRStatement::MemCopy { dst: d, src: s, slots: n })),
+        |i| tuple((tag("memset("),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(","),multispace0,parse_ir_expr,multispace0,tag(")")))(i).map(|(rest,(_,_,d,_,_,_,v,_,_,_,n,_,_))| (rest,IRStatement::MemFill { dst: d, val: v, slots: n }))
     ))(i)
 }
 pub fn parse_ir_statements(i: &[u8]) -> IResult<&[u8], Vec<IRStatement>> {
@@ -139,8 +203,13 @@ pub fn parse_ir_statements(i: &[u8]) -> IResult<&[u8], Vec<IRStatement>> {
 pub fn parse_control(i: &[u8]) -> IResult<&[u8], ControlXfer> {
     let (i,_) = multispace0(i)?;
     alt((
+        |i| tuple((tag("jump"),multispace1,tag("%"),parse_register_name))(i).map(|(rest,(_,_,_,id))| (rest,ControlXfer::JumpIndirect { target: IRExpr::Var { id } })),
         |i| tuple((tag("jump"),multispace1,identifier))(i).map(|(rest,(_,_,n))| (rest,ControlXfer::Jump { block: n})),
+        |i| tuple((tag("ifsigned"),multispace1,parse_ir_expr,multispace1,tag("neg"),multispace1,identifier,multispace1,tag("zero"),multispace1,identifier,multispace1,tag("pos"),multispace1,identifier))(i).map(|(rest,(_,_,c,_,_,_,n,_,_,_,z,_,_,_,p))| (rest,ControlXfer::IfSigned { cond: c, neg_block: n, zero_block: z, pos_block: p})),
         |i| tuple((tag("if"),multispace1,parse_ir_expr,multispace1,tag("then"),multispace1,identifier,multispace1,tag("else"),multispace1,identifier))(i).map(|(rest,(_,_,b,_,_,_,t,_,_,_,f))| (rest,ControlXfer::If { cond: b, tblock: t, fblock: f})),
+        // Before plain ret, which would otherwise read void as a block name
+        |i| tuple((tag("ret"),multispace1,verify(identifier, |id: &str| id == "void")))(i).map(|(rest,_)| (rest,ControlXfer::RetVoid)),
+        |i| tuple((tag("ret"),multispace1,verify(separated_list1(tuple((multispace0,tag(","),multispace0)),parse_ir_expr), |vs: &Vec<IRExpr>| vs.len() > 1)))(i).map(|(rest,(_,_,vs))| (rest,ControlXfer::RetMulti { vals: vs })),
         |i| tuple((tag("ret"),multispace1,parse_ir_expr))(i).map(|(rest,(_,_,n))| (rest,ControlXfer::Ret { val: n})),
         |i| tuple((tag("fail"),multispace1,parse_reason))(i).map(|(rest,(_,_,r))| (rest, ControlXfer::Fail { reason: r}))
     ))(i)
@@ -154,36 +223,86 @@ pub fn parse_block_arg(i: &[u8]) -> IResult<&[u8], &str> {
         |x| tuple((tag("%"),identifier))(x).map(|(rst,(_,id))| (rst,(id)))
     ))(i)
 }
-pub fn parse_opt_block_arg_list(i: &[u8]) -> IResult<&[u8], Vec<&str>> {
+// A formal, optionally with a default: `n` or `n = 5`
+pub fn parse_formal(i: &[u8]) -> IResult<&[u8], (&str,Option<IRExpr<'_>>)> {
+    tuple((parse_block_arg, opt(tuple((multispace0,tag("="),parse_ir_expr)))))(i).map(|(rest,(name,d))| (rest,(name,d.map(|(_,_,e)| e))))
+}
+// Formals and the defaults of the trailing ones. Once one formal has a default, the rest must too.
+pub fn parse_opt_block_arg_list(i: &[u8]) -> IResult<&[u8], (Vec<&str>,Vec<IRExpr<'_>>)> {
     alt((
-        |x| tuple((tag(":"),opt(tag("\r")),tag("\n")))(x).map(|(rest,_)| (rest, vec![])),
-        |x| tuple((tag("("), multispace0, separated_list0(tuple((multispace0,tag(","),multispace0)),parse_block_arg), multispace0, tag("):"), opt(tag("\r")), tag("\n")))(x).map(|(rest,(_,_,args,_,_,_,_))| (rest,args))
+        |x| tuple((tag(":"),opt(tag("\r")),tag("\n")))(x).map(|(rest,_)| (rest, (vec![],vec![]))),
+        |x| verify(tuple((tag("("), multispace0, separated_list0(tuple((multispace0,tag(","),multispace0)),parse_formal), multispace0, tag("):"), opt(tag("\r")), tag("\n"))),
+                   |(_,_,args,_,_,_,_)| args.iter().skip_while(|(_,d)| d.is_none()).all(|(_,d)| d.is_some()))(x)
+            .map(|(rest,(_,_,args,_,_,_,_))| (rest, args.into_iter().fold((vec![],vec![]), |(mut names,mut defaults),(n,d)| {
+                names.push(n);
+                defaults.extend(d);
+                (names,defaults)
+            })))
     ))(i)
 }
 pub fn parse_basic_block(i: &[u8]) -> IResult<&[u8], BasicBlock> {
     let (i,_) = multispace0(i)?;
     tuple((
         identifier, parse_opt_block_arg_list, parse_ir_statements, parse_control
-    ))(i).map(|(rest,(name,formals,prims,ctrl))| (rest,BasicBlock { name: name, instrs: prims, next: ctrl, formals: formals}))
+    ))(i).map(|(rest,(name,(formals,defaults),prims,ctrl))| (rest,BasicBlock { name: name, instrs: prims, next: ctrl, formals: formals, defaults }))
 }
 
-pub fn parse_array_elt(i: &[u8]) -> IResult<&[u8], VirtualVal> {
+pub fn parse_array_elt(i: &[u8]) -> IResult<&[u8], IRExpr> {
     alt((
-        |i| identifier(i).map(|(rest,x)| (rest,VirtualVal::CodePtr { val: x })),
-        |i| digit1(i).map(|(rest,x)| (rest,VirtualVal::Data { val: from_utf8(x).unwrap().parse::<u64>().unwrap() }))
+        |i| tuple((tag("@"),identifier))(i).map(|(rest,(_,x))| (rest,IRExpr::GlobalRef { name: x })),
+        |i| identifier(i).map(|(rest,x)| (rest,IRExpr::BlockRef { bname: x })),
+        |i| parse_int_lit(i).map(|(rest,x)| (rest,IRExpr::IntLit { val: x }))
     ))(i)
 }
-pub fn parse_array_body(i: &[u8]) -> IResult<&[u8], Vec<VirtualVal>> {
+pub fn parse_array_body(i: &[u8]) -> IResult<&[u8], Vec<IRExpr>> {
     tuple((multispace0,separated_list0(tuple((multispace0,tag(","),multispace0)),parse_array_elt),multispace0,tag("}")))(i).map(|(rest,(_,v,_,_))| (rest,v) )
 }
 pub fn parse_global(i: &[u8]) -> IResult<&[u8], GlobalStatic> {
+    alt((parse_global_array, parse_global_const))(i)
+}
+pub fn parse_global_const(i: &[u8]) -> IResult<&[u8], GlobalStatic<'_>> {
     tuple((
+        tuple((multispace0,tag("global"), multispace1, tag("const"), multispace1)),
+        identifier,
+        tuple((multispace0,tag("="), multispace0)),
+        parse_int_lit,
+        multispace0
+    ))(i).map(|(rest,(_,name,_,val,_))| (rest,GlobalStatic::Const { name, val }))
+}
+pub fn parse_global_array(i: &[u8]) -> IResult<&[u8], GlobalStatic<'_>> {
+    verify(tuple((
         tuple((multispace0,tag("global"), multispace1, tag("array"), multispace0)),
         identifier,
+        opt(tuple((tag("["),digit1,tag("]")))),
         tuple((multispace0,tag(":"), multispace0, tag("{"))),
         parse_array_body,
         multispace0
-    ))(i).map(|(rest,(_,name,_,vs,_))| (rest,GlobalStatic::Array {name: name, vals: vs}))
+    )), |(_,_,len,_,vs,_)| len.is_none_or(|(_,d,_)| from_utf8(d).unwrap().parse::<u64>().is_ok_and(|n| n >= vs.len() as u64))
+    )(i).map(|(rest,(_,name,len,_,vs,_))| (rest,GlobalStatic::Array {name: name, vals: vs, slots: len.map(|(_,d,_)| from_utf8(d).unwrap().parse::<u64>().unwrap())}))
+}
+
+/// A readable message for an error from parse_program over src, with the line and column where it happened
+pub fn describe_parse_error(src: &[u8], err: &nom::error::Error<&[u8]>) -> String {
+    let offset = src.len() - err.input.len();
+    let line = src[..offset].iter().filter(|c| **c == b'\n').count() + 1;
+    let column = offset - src[..offset].iter().rposition(|c| *c == b'\n').map_or(0, |p| p + 1) + 1;
+    match err.code {
+        ErrorKind::TooLarge => {
+            let (_,token) = digit1::<_,nom::error::Error<&[u8]>>(err.input).unwrap_or((b"",err.input));
+            let token = String::from_utf8_lossy(token);
+            // Only alloc sizes are narrower than 64 bits, so a token that fits in 64 was one of those
+            let bits = if token.parse::<u64>().is_ok() { 32 } else { 64 };
+            format!("line {}, column {}: integer literal {} does not fit in {} bits", line, column, token, bits)
+        },
+        ErrorKind::Verify if err.input.starts_with(b";@") => {
+            let rest = String::from_utf8_lossy(err.input);
+            format!("line {}, column {}: pragmas must come before data: {}", line, column, rest.lines().next().unwrap_or("").trim_end())
+        },
+        _ => {
+            let rest = String::from_utf8_lossy(err.input);
+            format!("line {}, column {}: could not parse: {}", line, column, rest.lines().next().unwrap_or(""))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -197,6 +316,7 @@ mod parsing_tests {
         assert_eq!(parse_ir_expr("%asdf".as_bytes()), Ok((empty, IRExpr::Var { id : "asdf" })));
         assert_eq!(parse_ir_expr("@v3".as_bytes()), Ok((empty, IRExpr::GlobalRef { name : "v3" })));
         assert_eq!(parse_ir_expr("@asdf".as_bytes()), Ok((empty, IRExpr::GlobalRef { name : "asdf" })));
+        assert_eq!(parse_ir_expr("$SCALE".as_bytes()), Ok((empty, IRExpr::ConstRef { id : "SCALE" })));
         assert_eq!(parse_ir_expr("v3".as_bytes()), Ok((empty, IRExpr::BlockRef { bname : "v3" })));
         assert_eq!(parse_ir_expr("asdf".as_bytes()), Ok((empty, IRExpr::BlockRef { bname : "asdf" })));
         assert_eq!(parse_ir_expr("3".as_bytes()), Ok((empty, IRExpr::IntLit { val : 3 })));
@@ -260,27 +380,130 @@ mod parsing_tests {
         assert_eq!(parse_ir_statement("  %v  =   phi( bb1 , %q , bb3 , 5 )".as_bytes()), 
                    Ok((empty, IRStatement::Phi { lhs: "v", opts: vec![("bb1",IRExpr::Var{id:"q"}), ("bb3",IRExpr::IntLit{val:5})]})));
 
-        assert_eq!(parse_ir_statement("%v = 3 + 4".as_bytes()), Ok((empty, IRStatement::Op { lhs: "v", arg1: IRExpr::IntLit { val : 3}, op: "+", arg2: IRExpr::IntLit { val:4}})));
-        assert_eq!(parse_ir_statement("\t %v   =  %q   * 4".as_bytes()), Ok((empty, IRStatement::Op { lhs: "v", arg1: IRExpr::Var { id: "q"}, op: "*", arg2: IRExpr::IntLit { val:4}})));
+        assert_eq!(parse_ir_statement("memcpy(%d, %s, 4)".as_bytes()), Ok((empty, IRStatement::MemCopy { dst: IRExpr::Var { id: "d" }, src: IRExpr::Var { id: "s" }, slots: IRExpr::IntLit { val: 4 }})));
+        assert_eq!(parse_ir_statement("memset(%d, 7, %n)".as_bytes()), Ok((empty, IRStatement::MemFill { dst: IRExpr::Var { id: "d" }, val: IRExpr::IntLit { val: 7 }, slots: IRExpr::Var { id: "n" }})));
+        assert_eq!(parse_ir_statement("%r = rand(%n)".as_bytes()), Ok((empty, IRStatement::Rand { lhs: "r", bound: IRExpr::Var { id: "n" }})));
+        assert_eq!(parse_ir_statement("%i = ptrtoint(%p)".as_bytes()), Ok((empty, IRStatement::PtrToInt { lhs: "i", ptr: IRExpr::Var { id: "p" }})));
+        assert_eq!(parse_ir_statement("%p = inttoptr(%i)".as_bytes()), Ok((empty, IRStatement::IntToPtr { lhs: "p", int: IRExpr::Var { id: "i" }})));
+        assert_eq!(parse_ir_statement("%t = readcycles()".as_bytes()), Ok((empty, IRStatement::ReadCycles { lhs: "t" })));
+        assert_eq!(parse_ir_statement("checkpoint(loop_done)".as_bytes()), Ok((empty, IRStatement::Checkpoint { label: "loop_done" })));
+        assert_eq!(parse_ir_statement("assertheap(sorted, %head, \"list out of order\")".as_bytes()),
+                   Ok((empty, IRStatement::AssertHeap { msg: "list out of order", block: "sorted", receiver: IRExpr::Var { id: "head" } })));
+        assert_eq!(parse_ir_statement("halt(%total)".as_bytes()),
+                   Ok((empty, IRStatement::Halt { val: IRExpr::Var { id: "total" } })));
+        assert_eq!(parse_ir_statement("%ok = cas(%p, 0, %me)".as_bytes()),
+                   Ok((empty, IRStatement::Cas { result: "ok", base: IRExpr::Var { id: "p" }, expected: IRExpr::IntLit { val: 0 }, new: IRExpr::Var { id: "me" } })));
+        assert_eq!(parse_ir_statement("%q, %r = divmod(%n, 10)".as_bytes()), Ok((empty, IRStatement::DivMod { quot: "q", rem: "r", arg1: IRExpr::Var { id: "n" }, arg2: IRExpr::IntLit { val: 10 }})));
+        assert_eq!(parse_ir_statement("%c = getelt16(%s, %i)".as_bytes()), Ok((empty, IRStatement::GetEltNarrow { lhs: "c", bits: 16, base: IRExpr::Var { id: "s" }, offset: IRExpr::Var { id: "i" }})));
+        assert_eq!(parse_ir_statement("setelt32(%px, 3, 255)".as_bytes()), Ok((empty, IRStatement::SetEltNarrow { bits: 32, base: IRExpr::Var { id: "px" }, offset: IRExpr::IntLit { val: 3 }, val: IRExpr::IntLit { val: 255 }})));
+        assert_eq!(parse_ir_statement("%q, %r = call(@f, 0, %n)".as_bytes()), Ok((empty, IRStatement::CallMulti { lhs: vec!["q", "r"], code: IRExpr::GlobalRef { name: "f" }, receiver: IRExpr::IntLit { val: 0 }, args: vec![IRExpr::Var { id: "n" }]})));
+
+        assert_eq!(parse_ir_statement("%v = 3 + 4".as_bytes()), Ok((empty, IRStatement::Op { lhs: "v", arg1: IRExpr::IntLit { val : 3}, op: BinOp::Add, arg2: IRExpr::IntLit { val:4}})));
+        assert_eq!(parse_ir_statement("\t %v   =  %q   * 4".as_bytes()), Ok((empty, IRStatement::Op { lhs: "v", arg1: IRExpr::Var { id: "q"}, op: BinOp::Mul, arg2: IRExpr::IntLit { val:4}})));
 
 
         assert_eq!(parse_ir_statements("\t %v   =  %q   * 4\nprint( %v )".as_bytes()), 
-            Ok((empty, vec![IRStatement::Op { lhs: "v", arg1: IRExpr::Var { id: "q"}, op: "*", arg2: IRExpr::IntLit { val:4}},
+            Ok((empty, vec![IRStatement::Op { lhs: "v", arg1: IRExpr::Var { id: "q"}, op: BinOp::Mul, arg2: IRExpr::IntLit { val:4}},
                             IRStatement::Print { out: IRExpr::Var { id: "v"}}
             ])));
         //assert_eq!(parse_ir_statements("\t %v   =  %q   * 4     \nprint( %v )".as_bytes()), 
-        //    Ok((empty, vec![IRStatement::Op { lhs: "v", arg1: IRExpr::Var { id: "q"}, op: "*", arg2: IRExpr::IntLit { val:4}},
+        //    Ok((empty, vec![IRStatement::Op { lhs: "v", arg1: IRExpr::Var { id: "q"}, op: BinOp::Mul, arg2: IRExpr::IntLit { val:4}},
         //                    IRStatement::Print { out: IRExpr::Var { id: "v"}}
         //    ])));
     }
 
+    #[test]
+    fn check_pragmas() {
+        let src = ";@ cap 64\n  ;@ mode gc-logging\r\n;@ frobnicate 3\n;@ cap lots\n;@ pure f g\n;@ pure\n;@ pure h\n;@ cost f 100\n;@ cost g\ndata:\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(prog.pragmas, Pragmas { mode: Some(PragmaMode::LoggingGC), cap: Some(64),
+                                           unknown: vec!["frobnicate 3".to_string(), "cap lots".to_string(), "pure".to_string(), "cost g".to_string()],
+                                           pure: vec!["f".to_string(), "g".to_string(), "h".to_string()],
+                                           costs: vec![("f".to_string(), 100)] });
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  ret 0\n".as_bytes()).unwrap();
+        assert_eq!(prog.pragmas, Pragmas::default());
+    }
+
+    #[test]
+    fn check_misplaced_pragmas() {
+        let describe = |src: &str| match parse_program(src.as_bytes()) {
+            Err(nom::Err::Failure(e)) => describe_parse_error(src.as_bytes(), &e),
+            other => panic!("expected a failure, got {:?}", other)
+        };
+        assert_eq!(describe(";@ mode gc\ndata:\n;@ cap 64\ncode:\nmain:\n  ret 0\n"),
+            "line 3, column 1: pragmas must come before data: ;@ cap 64");
+        assert_eq!(describe("data:\ncode:\nmain:\n  ;@ mode gc\r\n  ret 0\n"),
+            "line 4, column 3: pragmas must come before data: ;@ mode gc");
+        // Only a line that starts with ;@ is a pragma
+        assert!(parse_program("data:\ncode:\nmain:\n  # not ;@ a pragma\n  %x = 1\n  ret %x\n".as_bytes()).is_ok());
+    }
+
     #[test]
     fn check_control() {
         let empty : &[u8] = b"";
         assert_eq!(parse_control("\tjump loophead".as_bytes()).finish().map_err(|nom::error::Error { input: x, code: _}| from_utf8(x).unwrap()),
             Ok((empty, ControlXfer::Jump { block: "loophead" })));
+        assert_eq!(parse_control("\tjump %target".as_bytes()).finish().map_err(|nom::error::Error { input: x, code: _}| from_utf8(x).unwrap()),
+            Ok((empty, ControlXfer::JumpIndirect { target: IRExpr::Var { id: "target" } })));
         assert_eq!(parse_control("\tret 0".as_bytes()).finish().map_err(|nom::error::Error { input: x, code: _}| from_utf8(x).unwrap()),
             Ok((empty, ControlXfer::Ret { val: IRExpr::IntLit { val: 0 } })));
+        assert_eq!(parse_control("\tret void".as_bytes()).finish().map_err(|nom::error::Error { input: x, code: _}| from_utf8(x).unwrap()),
+            Ok((empty, ControlXfer::RetVoid)));
+        assert_eq!(parse_control("\tret %q, 3".as_bytes()).finish().map_err(|nom::error::Error { input: x, code: _}| from_utf8(x).unwrap()),
+            Ok((empty, ControlXfer::RetMulti { vals: vec![IRExpr::Var { id: "q" }, IRExpr::IntLit { val: 3 }] })));
+        assert_eq!(parse_control("\tret voidish".as_bytes()).finish().map_err(|nom::error::Error { input: x, code: _}| from_utf8(x).unwrap()),
+            Ok((empty, ControlXfer::Ret { val: IRExpr::BlockRef { bname: "voidish" } })));
+        assert_eq!(parse_control("\tifsigned %x neg bneg zero bzero pos bpos".as_bytes()).finish().map_err(|nom::error::Error { input: x, code: _}| from_utf8(x).unwrap()),
+            Ok((empty, ControlXfer::IfSigned { cond: IRExpr::Var { id: "x" }, neg_block: "bneg", zero_block: "bzero", pos_block: "bpos" })));
+    }
+
+    #[test]
+    fn check_operators() {
+        let empty : &[u8] = b"";
+        for op in BinOp::ALL {
+            assert_eq!(parse_op(op.symbol().as_bytes()), Ok((empty, op)));
+        }
+        // Longer symbols win over their prefixes
+        assert_eq!(parse_op("<< 2".as_bytes()), Ok((" 2".as_bytes(), BinOp::Shl)));
+        assert!(parse_op("%".as_bytes()).is_err());
+        assert!(parse_op("!=".as_bytes()).is_err());
+        // An unknown operator is rejected by the parser rather than surfacing when the statement runs
+        assert_eq!(parse_ir_statement("%v = 3 % 4".as_bytes()), Ok((" % 4".as_bytes(), IRStatement::VarAssign { lhs: "v", rhs: IRExpr::IntLit { val: 3 }})));
+        assert!(parse_basic_block("main:\n\t%v = 3 % 4\n\tret %v".as_bytes()).is_err());
+        assert!(parse_basic_block("main:\n\t%v = 3 != 4\n\tret %v".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn check_int_literals() {
+        let empty : &[u8] = b"";
+        assert_eq!(parse_ir_expr("18446744073709551615".as_bytes()), Ok((empty, IRExpr::IntLit { val: u64::MAX })));
+        assert_eq!(parse_ir_expr(" 18446744073709551616".as_bytes()),
+            Err(nom::Err::Failure(nom::error::Error::new("18446744073709551616".as_bytes(), ErrorKind::TooLarge))));
+        let src = "data:\ncode:\nmain:\n  %x = 18446744073709551615\n  %y = %x + 99999999999999999999\n  ret %y\n";
+        let err = parse_program(src.as_bytes()).finish().unwrap_err();
+        assert_eq!(describe_parse_error(src.as_bytes(), &err), "line 5, column 13: integer literal 99999999999999999999 does not fit in 64 bits");
+        let src = "data:\nglobal array g: { 1, 123456789012345678901234 }\ncode:\nmain:\n  ret 0\n";
+        let err = parse_program(src.as_bytes()).finish().unwrap_err();
+        assert_eq!(describe_parse_error(src.as_bytes(), &err), "line 2, column 22: integer literal 123456789012345678901234 does not fit in 64 bits");
+        // Alloc sizes only have 32 bits
+        let src = "data:\ncode:\nmain:\n  %a = alloc(99999999999)\n  ret 0\n";
+        let err = parse_program(src.as_bytes()).finish().unwrap_err();
+        assert_eq!(describe_parse_error(src.as_bytes(), &err), "line 4, column 14: integer literal 99999999999 does not fit in 32 bits");
+        let src = "data:\ncode:\nmain:\n  %a = alloc(99999999999999999999)\n  ret 0\n";
+        let err = parse_program(src.as_bytes()).finish().unwrap_err();
+        assert_eq!(describe_parse_error(src.as_bytes(), &err), "line 4, column 14: integer literal 99999999999999999999 does not fit in 64 bits");
+        assert!(parse_ir_statement(b"%a = alloc(4294967295)").is_ok());
+    }
+
+    #[test]
+    fn check_default_formals() {
+        let (_,b) = parse_basic_block("f(this, a, b = 2, c = @g):\n\tret %a".as_bytes()).unwrap();
+        assert_eq!(b.formals, vec!["this", "a", "b", "c"]);
+        assert_eq!(b.defaults, vec![IRExpr::IntLit { val: 2 }, IRExpr::GlobalRef { name: "g" }]);
+        assert_eq!((b.required_formals(), b.accepts_args(1), b.accepts_args(2), b.accepts_args(4), b.accepts_args(5)), (2, false, true, true, false));
+        assert!(b.to_string().starts_with("f(this, a, b = 2, c = @g):\n"));
+        // Only trailing formals can have defaults
+        assert!(parse_basic_block("f(this, a = 1, b):\n\tret %a".as_bytes()).is_err());
     }
 
     #[test]
@@ -290,6 +513,7 @@ mod parsing_tests {
             Ok((empty, BasicBlock {
                         name: "main",
                         formals: vec![],
+                        defaults: vec![],
                 instrs: vec![IRStatement::VarAssign { lhs: "1", rhs: IRExpr::IntLit { val : 10}}],
                 next: ControlXfer::Ret { val: IRExpr::IntLit { val:0 } }
             })));
@@ -297,6 +521,7 @@ mod parsing_tests {
             Ok((empty, BasicBlock {
                         name: "mB",
                         formals: vec!["this"],
+                        defaults: vec![],
                 instrs: vec![],
                 next: ControlXfer::Ret { val: IRExpr::IntLit { val:0 } }
             })));
@@ -305,6 +530,7 @@ mod parsing_tests {
             Ok((empty, BasicBlock {
                         name: "main",
                         formals: vec![],
+                        defaults: vec![],
                 instrs: vec![IRStatement::VarAssign { lhs: "1", rhs: IRExpr::IntLit { val : 10}}],
                 next: ControlXfer::Ret { val: IRExpr::IntLit { val:0 } }
             })));
@@ -312,6 +538,7 @@ mod parsing_tests {
             Ok((empty, BasicBlock {
                         name: "mB",
                         formals: vec!["this"],
+                        defaults: vec![],
                 instrs: vec![],
                 next: ControlXfer::Ret { val: IRExpr::IntLit { val:0 } }
             })));
@@ -321,12 +548,64 @@ mod parsing_tests {
     fn check_global() {
         let empty : &[u8] = b"";
         assert_eq!(parse_global("global array vtblA: { mA }\n".as_bytes()),
-            Ok((empty, GlobalStatic::Array { name: "vtblA", vals: vec![VirtualVal::CodePtr{val:"mA"}]})));
+            Ok((empty, GlobalStatic::Array { name: "vtblA", vals: vec![IRExpr::BlockRef{bname:"mA"}], slots: None})));
+        assert_eq!(parse_global("global array tbl: { @vtblA, 3 }\n".as_bytes()),
+            Ok((empty, GlobalStatic::Array { name: "tbl", vals: vec![IRExpr::GlobalRef{name:"vtblA"}, IRExpr::IntLit{val:3}], slots: None})));
+        assert_eq!(parse_global("global array buf[4]: { 1, 2 }\n".as_bytes()),
+            Ok((empty, GlobalStatic::Array { name: "buf", vals: vec![IRExpr::IntLit{val:1}, IRExpr::IntLit{val:2}], slots: Some(4)})));
+        // Can't declare fewer slots than initializers
+        assert!(parse_global("global array buf[1]: { 1, 2 }\n".as_bytes()).is_err());
+        assert_eq!(parse_global("global const SCALE = 1000\n".as_bytes()),
+            Ok((empty, GlobalStatic::Const { name: "SCALE", val: 1000 })));
+        assert!(parse_global("global const SCALE = %x\n".as_bytes()).is_err());
     }
 }
 
 
+// One `;@ ...` line. Anything we can't make sense of is kept as unknown rather than rejected, since
+// pragmas only tune how a program is run. Pragmas may only appear at the top of a file, before `data:`.
+pub fn parse_pragma_line(i: &[u8]) -> IResult<&[u8], &str> {
+    tuple((multispace0,tag(";@"),opt(is_not("\r\n")),opt(tag("\r")),tag("\n")))(i)
+        .map(|(rest,(_,_,body,_,_))| (rest,from_utf8(body.unwrap_or(b"")).unwrap().trim()))
+}
+pub fn parse_pragmas(i: &[u8]) -> IResult<&[u8], Pragmas> {
+    let (rest,lines) = many0(parse_pragma_line)(i)?;
+    let mut pragmas = Pragmas::default();
+    for line in lines {
+        let words : Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["cap", n] if n.parse::<u64>().is_ok() => pragmas.cap = n.parse().ok(),
+            ["mode", "unlimited"] => pragmas.mode = Some(PragmaMode::Unlimited),
+            ["mode", "memcap"] => pragmas.mode = Some(PragmaMode::MemCap),
+            ["mode", "gc"] => pragmas.mode = Some(PragmaMode::GC),
+            ["mode", "gc-logging"] => pragmas.mode = Some(PragmaMode::LoggingGC),
+            ["pure", ref names @ ..] if !names.is_empty() => pragmas.pure.extend(names.iter().map(|n| n.to_string())),
+            ["cost", name, n] if n.parse::<u64>().is_ok() => pragmas.costs.push((name.to_string(), n.parse().unwrap())),
+            _ => pragmas.unknown.push(line.to_string())
+        }
+    }
+    Ok((rest,pragmas))
+}
+
+// The first line after the pragmas that is itself a pragma, from its `;@` on. Left alone it would just fail to parse
+// as a global or statement, so parse_program reports it as misplaced instead (see describe_parse_error).
+fn misplaced_pragma(i: &[u8]) -> Option<&[u8]> {
+    let mut start = 0;
+    for line in i.split(|c| *c == b'\n') {
+        let indent = line.iter().take_while(|c| c.is_ascii_whitespace()).count();
+        if line[indent..].starts_with(b";@") {
+            return Some(&i[start + indent..]);
+        }
+        start += line.len() + 1;
+    }
+    None
+}
+
 pub fn parse_program(i: &[u8]) -> IResult<&[u8], IRProgram> {
+    let (i,pragmas) = parse_pragmas(i)?;
+    if let Some(at) = misplaced_pragma(i) {
+        return Err(nom::Err::Failure(nom::error::Error::new(at, ErrorKind::Verify)));
+    }
     let (rst,_) = tuple((multispace0,tag("data:"),opt(tag("\r")),tag("\n")))(i)?;
     let mut globals = vec![];
     let mut last_global_parse = parse_global(rst);
@@ -337,6 +616,9 @@ pub fn parse_program(i: &[u8]) -> IResult<&[u8], IRProgram> {
         globals.push(g);
         last_global_parse = parse_global(remaining);
     }
+    if let Err(nom::Err::Failure(e)) = last_global_parse {
+        return Err(nom::Err::Failure(e));
+    }
     match last_global_parse.finish() {
         Err(nom::error::Error{ input: postglobals, code: _}) => {
             // TODO: figure out what happens with 
@@ -351,6 +633,9 @@ pub fn parse_program(i: &[u8]) -> IResult<&[u8], IRProgram> {
                 //println!("last_block_parse={:?}", &last_block_parse);
             }
             //println!("last_block_parse={:?}", &last_block_parse);
+            if let Err(nom::Err::Failure(e)) = last_block_parse {
+                return Err(nom::Err::Failure(e));
+            }
             match last_block_parse.finish() {
                 Err(nom::error::Error{ input: postcode, code: _}) => {
                     //println!("postcode={}", from_utf8(postcode).unwrap());
@@ -361,7 +646,7 @@ pub fn parse_program(i: &[u8]) -> IResult<&[u8], IRProgram> {
                             while let Some(b) = blocks.pop() {
                                 bs.insert(b.name, b);
                             }
-                            Ok(("".as_bytes(), IRProgram { globals: globals, blocks: bs}))
+                            Ok(("".as_bytes(), IRProgram { globals: globals, vars: VarIds::new(&bs), blocks: bs, pragmas }))
                         },
                         Err(nom::error::Error { input: x, code: _}) => {
                             panic!("Leftover text after last parsed block: {}", from_utf8(x).unwrap())
@@ -0,0 +1,462 @@
+use std::collections::{HashMap,HashSet};
+use std::fmt;
+
+use crate::ir441::nodes::*;
+
+// Static problems with a program that can be found without running it. Errors mean the program is malformed;
+// warnings are things that are legal but probably not what was meant.
+#[derive(Debug,PartialEq)]
+pub enum ValidationIssue<'a> {
+    NoMain,
+    UnknownPragma { text: String },
+    PhiAfterNonPhi { block: &'a str, index: usize },
+    MissingSuccessor { block: &'a str, target: &'a str },
+    // A phi arm reads a variable that some path to the end of its predecessor never assigns
+    PhiSourceNotLive { block: &'a str, predecessor: &'a str, name: &'a str },
+    // No jump or branch leads to the block, it isn't a function entry, and nothing takes its address
+    UnreachableBlock { block: &'a str },
+    // ValidateOptions::unreferenced_blocks: nothing names the block at all, as a jump or branch target, a code
+    // pointer (which includes call targets), or an assertheap check, so even a function entry can't be reached
+    UnreferencedBlock { block: &'a str },
+    // A `;@ pure` block that can have an effect: instruction `index` of `at` (instrs.len() for its control transfer)
+    NotPure { block: &'a str, at: &'a str, index: usize },
+    // A `;@ pure` declaration naming a block that doesn't exist
+    NoSuchPureBlock { name: String },
+    // A `;@ cost` declaration naming a block that doesn't exist
+    NoSuchCostBlock { name: String },
+}
+impl <'a> ValidationIssue<'a> {
+    pub fn is_error(&self) -> bool {
+        match self {
+            ValidationIssue::NoMain => false,
+            ValidationIssue::UnknownPragma { .. } => false,
+            ValidationIssue::PhiAfterNonPhi { .. } => true,
+            ValidationIssue::MissingSuccessor { .. } => true,
+            // Only fails if that path is taken, and the analysis can't see through indirect jumps
+            ValidationIssue::PhiSourceNotLive { .. } => false,
+            ValidationIssue::UnreachableBlock { .. } => false,
+            ValidationIssue::UnreferencedBlock { .. } => false,
+            ValidationIssue::NotPure { .. } => true,
+            ValidationIssue::NoSuchPureBlock { .. } => false,
+            ValidationIssue::NoSuchCostBlock { .. } => false,
+        }
+    }
+}
+impl <'a> fmt::Display for ValidationIssue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::NoMain => write!(f, "WARNING: No main block found"),
+            ValidationIssue::UnknownPragma { text } => write!(f, "WARNING: ignoring unknown pragma: ;@ {}", text),
+            ValidationIssue::PhiAfterNonPhi { block, index } =>
+                write!(f, "ERROR: phi instruction after non-phis in basic block {} (instruction {})", block, index),
+            ValidationIssue::MissingSuccessor { block, target } =>
+                write!(f, "ERROR: next block |{}| in block {} does not exist!", target, block),
+            ValidationIssue::PhiSourceNotLive { block, predecessor, name } =>
+                write!(f, "WARNING: phi in block {} reads %{} from {}, which may not have assigned it", block, name, predecessor),
+            ValidationIssue::UnreachableBlock { block } => write!(f, "WARNING: basic block {} can never run", block),
+            ValidationIssue::UnreferencedBlock { block } => write!(f, "WARNING: basic block {} is never referenced", block),
+            ValidationIssue::NotPure { block, at, index } =>
+                write!(f, "ERROR: block {} is declared pure, but instruction {} of block {} has an effect", block, index, at),
+            ValidationIssue::NoSuchPureBlock { name } => write!(f, "WARNING: ignoring pure declaration of missing block {}", name),
+            ValidationIssue::NoSuchCostBlock { name } => write!(f, "WARNING: ignoring cost declaration of missing block {}", name),
+        }
+    }
+}
+
+// Checks beyond the defaults. Start from ValidateOptions::new() and turn on what you want.
+#[derive(Debug,Clone,Default)]
+pub struct ValidateOptions {
+    // Report blocks other than main that nothing refers to (UnreferencedBlock), function entries included, instead
+    // of assuming any function might be called from outside. Such blocks are usually dead front-end output.
+    pub unreferenced_blocks: bool,
+}
+impl ValidateOptions {
+    pub fn new() -> ValidateOptions {
+        ValidateOptions { unreferenced_blocks: false }
+    }
+}
+
+// Check a whole program. Blocks are visited in name order so the report is stable from run to run.
+pub fn validate<'a>(prog: &IRProgram<'a>) -> Vec<ValidationIssue<'a>> {
+    validate_with(prog, &ValidateOptions::new())
+}
+
+// Like validate, with extra checks turned on by opts
+pub fn validate_with<'a>(prog: &IRProgram<'a>, opts: &ValidateOptions) -> Vec<ValidationIssue<'a>> {
+    let mut issues = vec![];
+    if !prog.blocks.contains_key("main") {
+        issues.push(ValidationIssue::NoMain);
+    }
+    for p in prog.pragmas.unknown.iter() {
+        issues.push(ValidationIssue::UnknownPragma { text: p.clone() });
+    }
+    let mut names : Vec<&&'a str> = prog.blocks.keys().collect();
+    names.sort();
+    for n in names {
+        let b = &prog.blocks[*n];
+        let mut past_phis = false;
+        for (index,i) in b.instrs.iter().enumerate() {
+            match i {
+                IRStatement::Phi { .. } => {
+                    if past_phis {
+                        issues.push(ValidationIssue::PhiAfterNonPhi { block: b.name, index });
+                    }
+                },
+                _ => past_phis = true
+            }
+        }
+        for target in successors(b) {
+            if !prog.blocks.contains_key(target) {
+                issues.push(ValidationIssue::MissingSuccessor { block: b.name, target });
+            }
+        }
+    }
+    check_phi_sources(prog, &mut issues);
+    check_reachable(prog, opts, &mut issues);
+    check_pure(prog, &mut issues);
+    check_costs(prog, &mut issues);
+    issues
+}
+
+fn successors<'a>(b: &BasicBlock<'a>) -> Vec<&'a str> {
+    match &b.next {
+        ControlXfer::Jump { block } => vec![*block],
+        ControlXfer::If { tblock, fblock, .. } => vec![*tblock, *fblock],
+        ControlXfer::IfSigned { neg_block, zero_block, pos_block, .. } => vec![*neg_block, *zero_block, *pos_block],
+        // Indirect targets are only known at run time
+        ControlXfer::JumpIndirect { .. } | ControlXfer::Ret { .. } | ControlXfer::RetMulti { .. } | ControlXfer::RetVoid | ControlXfer::Fail { .. } => vec![]
+    }
+}
+
+// Variables certainly assigned on every path to the end of each block (None: no path is known, so anything goes).
+// Function entries (main, and blocks with formals) start with just their formals; other blocks get what all their
+// predecessors agree on. A block with no known predecessors is dead or reached by an indirect jump, so we assume nothing.
+fn defined_at_exit<'a>(prog: &IRProgram<'a>) -> HashMap<&'a str,Option<HashSet<&'a str>>> {
+    let mut preds : HashMap<&'a str,Vec<&'a str>> = HashMap::new();
+    for b in prog.blocks.values() {
+        for target in successors(b) {
+            preds.entry(target).or_default().push(b.name);
+        }
+    }
+    let mut out : HashMap<&'a str,Option<HashSet<&'a str>>> = prog.blocks.keys().map(|n| (*n, None)).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in prog.blocks.values() {
+            let is_entry = b.name == "main" || !b.formals.is_empty();
+            let mut defined : Option<HashSet<&'a str>> = if is_entry { Some(b.formals.iter().copied().collect()) } else { None };
+            for p in preds.get(b.name).into_iter().flatten() {
+                if let Some(pdefs) = &out[p] {
+                    defined = Some(match defined {
+                        None => pdefs.clone(),
+                        Some(d) => d.intersection(pdefs).copied().collect()
+                    });
+                }
+            }
+            let defined = defined.map(|mut d| {
+                d.extend(b.instrs.iter().flat_map(|i| i.defs()));
+                d
+            });
+            if out[b.name] != defined {
+                out.insert(b.name, defined);
+                changed = true;
+            }
+        }
+    }
+    out
+}
+
+fn check_phi_sources<'a>(prog: &IRProgram<'a>, issues: &mut Vec<ValidationIssue<'a>>) {
+    let defined = defined_at_exit(prog);
+    let mut names : Vec<&&'a str> = prog.blocks.keys().collect();
+    names.sort();
+    for n in names {
+        let b = &prog.blocks[*n];
+        for i in b.instrs.iter() {
+            if let IRStatement::Phi { opts, .. } = i {
+                for (predecessor, src) in opts {
+                    if let (IRExpr::Var { id }, Some(Some(defs))) = (src, defined.get(predecessor)) {
+                        if !defs.contains(id) {
+                            issues.push(ValidationIssue::PhiSourceNotLive { block: b.name, predecessor, name: id });
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Blocks named as code pointers anywhere in the program, in code or in global initializers
+fn code_pointers<'a>(prog: &IRProgram<'a>) -> HashSet<&'a str> {
+    let mut ptrs = HashSet::new();
+    let mut exprs : Vec<&IRExpr<'a>> = vec![];
+    for b in prog.blocks.values() {
+        exprs.extend(b.instrs.iter().flat_map(|i| i.exprs()));
+        exprs.extend(b.next.exprs());
+        exprs.extend(b.defaults.iter());
+    }
+    for g in prog.globals.iter() {
+        if let GlobalStatic::Array { vals, .. } = g {
+            exprs.extend(vals.iter());
+        }
+    }
+    for e in exprs {
+        if let IRExpr::BlockRef { bname } = e {
+            ptrs.insert(*bname);
+        }
+    }
+    ptrs
+}
+
+// Blocks control can start in: main, function entries (blocks with formals), and any block named as a code pointer,
+// since a call or indirect jump could go there
+fn roots<'a>(prog: &IRProgram<'a>) -> HashSet<&'a str> {
+    let mut roots = code_pointers(prog);
+    roots.extend(prog.blocks.values().filter(|b| b.name == "main" || !b.formals.is_empty()).map(|b| b.name));
+    roots
+}
+
+// Blocks something names: jump and branch targets, code pointers, and the blocks assertheap calls
+fn referenced<'a>(prog: &IRProgram<'a>) -> HashSet<&'a str> {
+    let mut refs = callable(prog);
+    for b in prog.blocks.values() {
+        refs.extend(successors(b));
+    }
+    refs
+}
+
+// Blocks control can start in when function entries only count if something can call them: main, code pointers, and
+// the blocks assertheap calls
+fn callable<'a>(prog: &IRProgram<'a>) -> HashSet<&'a str> {
+    let mut blocks = code_pointers(prog);
+    blocks.insert("main");
+    for b in prog.blocks.values() {
+        for i in b.instrs.iter() {
+            if let IRStatement::AssertHeap { block, .. } = i {
+                blocks.insert(*block);
+            }
+        }
+    }
+    blocks
+}
+
+// A block nothing names is reported as unreferenced if asked for; otherwise, or if it is named, as unreachable when
+// no path from a root leads to it. When asked for unreferenced blocks, function entries are only roots if something
+// can call them, so what only an unreferenced function jumps to is unreachable too.
+fn check_reachable<'a>(prog: &IRProgram<'a>, opts: &ValidateOptions, issues: &mut Vec<ValidationIssue<'a>>) {
+    let mut seen : HashSet<&'a str> = HashSet::new();
+    let roots = if opts.unreferenced_blocks { callable(prog) } else { roots(prog) };
+    let mut worklist : Vec<&'a str> = roots.into_iter().collect();
+    while let Some(n) = worklist.pop() {
+        if let Some(b) = prog.blocks.get(n) {
+            if seen.insert(b.name) {
+                worklist.extend(successors(b));
+            }
+        }
+    }
+    let refs = if opts.unreferenced_blocks { Some(referenced(prog)) } else { None };
+    let mut names : Vec<&&'a str> = prog.blocks.keys().collect();
+    names.sort();
+    for n in names {
+        let block = prog.blocks[*n].name;
+        if block != "main" && refs.as_ref().is_some_and(|r| !r.contains(block)) {
+            issues.push(ValidationIssue::UnreferencedBlock { block });
+        } else if !seen.contains(block) {
+            issues.push(ValidationIssue::UnreachableBlock { block });
+        }
+    }
+}
+
+fn check_pure<'a>(prog: &IRProgram<'a>, issues: &mut Vec<ValidationIssue<'a>>) {
+    for name in prog.pragmas.pure.iter() {
+        match prog.blocks.get_key_value(name.as_str()) {
+            None => issues.push(ValidationIssue::NoSuchPureBlock { name: name.clone() }),
+            Some((block,_)) => if let Some((at, index)) = purity_violation(prog, block) {
+                issues.push(ValidationIssue::NotPure { block, at, index });
+            }
+        }
+    }
+}
+
+fn check_costs<'a>(prog: &IRProgram<'a>, issues: &mut Vec<ValidationIssue<'a>>) {
+    for (name,_) in prog.pragmas.costs.iter() {
+        if !prog.blocks.contains_key(name.as_str()) {
+            issues.push(ValidationIssue::NoSuchCostBlock { name: name.clone() });
+        }
+    }
+}
+
+// Whether a function declared `;@ pure` really is, so a call's result depends only on its arguments: the first
+// instruction (block, index) reachable from its entry by jumps and branches that stores, prints, allocates, draws a
+// random number, reads the clock or memory other than a global, or calls or jumps anywhere not itself declared pure.
+// Reading globals is fine since they're immutable. A missing block is reported as its own entry, index 0.
+pub fn purity_violation<'a>(prog: &IRProgram<'a>, bname: &'a str) -> Option<(&'a str,usize)> {
+    let declared = |e: &IRExpr| matches!(e, IRExpr::BlockRef { bname } if prog.pragmas.pure.iter().any(|p| p == bname));
+    let mut seen : HashSet<&'a str> = HashSet::new();
+    let mut worklist = vec![bname];
+    let mut names = vec![];
+    while let Some(n) = worklist.pop() {
+        let b = match prog.blocks.get(n) {
+            Some(b) => b,
+            None => return Some((n, 0))
+        };
+        if seen.insert(b.name) {
+            names.push(b.name);
+            worklist.extend(successors(b));
+        }
+    }
+    // Report the same instruction however the walk happened to go
+    names.sort();
+    for n in names {
+        let b = &prog.blocks[n];
+        for (index,i) in b.instrs.iter().enumerate() {
+            let ok = match i {
+                IRStatement::VarAssign { .. } | IRStatement::Op { .. } | IRStatement::Phi { .. } | IRStatement::DivMod { .. }
+                | IRStatement::PtrToInt { .. } | IRStatement::IntToPtr { .. } => true,
+                IRStatement::GetElt { base, .. } | IRStatement::GetEltNarrow { base, .. } | IRStatement::Load { base, .. } =>
+                    matches!(base, IRExpr::GlobalRef { .. }),
+                IRStatement::Call { code, .. } | IRStatement::CallMulti { code, .. } => declared(code),
+                IRStatement::Alloc { .. } | IRStatement::Rand { .. } | IRStatement::Print { .. } | IRStatement::SetElt { .. }
+                | IRStatement::SetEltNarrow { .. } | IRStatement::Store { .. } | IRStatement::MemCopy { .. } | IRStatement::MemFill { .. }
+                | IRStatement::Checkpoint { .. } | IRStatement::ReadCycles { .. } | IRStatement::AssertHeap { .. }
+                | IRStatement::Halt { .. } | IRStatement::Cas { .. } => false,
+            };
+            if !ok {
+                return Some((b.name, index));
+            }
+        }
+        if let ControlXfer::JumpIndirect { .. } = b.next {
+            return Some((b.name, b.instrs.len()));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod validate_tests {
+    use crate::ir441::validate::*;
+    use crate::ir441::parsing::*;
+
+    #[test]
+    fn check_issues() {
+        let src = ";@ bogus\ndata:\ncode:\nb(x):\n  %1 = 1\n  %2 = phi(a, 1)\n  jump nowhere\na(x):\n  if %x then b else gone\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let issues = validate(&prog);
+        assert_eq!(issues, vec![
+            ValidationIssue::NoMain,
+            ValidationIssue::UnknownPragma { text: "bogus".to_string() },
+            ValidationIssue::MissingSuccessor { block: "a", target: "gone" },
+            ValidationIssue::PhiAfterNonPhi { block: "b", index: 1 },
+            ValidationIssue::MissingSuccessor { block: "b", target: "nowhere" },
+        ]);
+        assert_eq!(issues.iter().filter(|i| i.is_error()).count(), 3);
+
+        let src = std::fs::read("examples/basicoo.ir").unwrap();
+        let (_,prog) = parse_program(&src[..]).unwrap();
+        assert_eq!(validate(&prog), vec![]);
+    }
+
+    #[test]
+    fn check_phi_sources() {
+        // %x comes from the loop itself, %n0 from main; the %y arm's predecessor only sometimes assigns it
+        let src = "data:\ncode:\nmain:\n  %n0 = 3\n  if %n0 then maybe else loop\nmaybe:\n  %y = 1\n  jump loop\n\
+                   loop:\n  %x = phi(main, %n0, maybe, %n0, loop, %x1)\n  %z = phi(main, 0, maybe, %y, loop, %y)\n  %x1 = %x - 1\n  if %x1 then loop else done\n\
+                   done:\n  ret %z\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let issues = validate(&prog);
+        assert_eq!(issues, vec![ValidationIssue::PhiSourceNotLive { block: "loop", predecessor: "loop", name: "y" }]);
+        assert!(!issues[0].is_error());
+
+        // Formals count as assigned at a function's entry
+        let src = "data:\ncode:\nmain:\n  ret 0\nf(this, n):\n  jump g\ng:\n  %m = phi(f, %n)\n  ret %m\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(validate(&prog), vec![]);
+    }
+
+    #[test]
+    fn check_misplaced_phi() {
+        let src = "data:\ncode:\nmain:\n  %a = 1\n  jump next\nnext:\n  %b = phi(main, %a)\n  print(%b)\n  %c = phi(main, %a)\n  ret %c\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(validate(&prog), vec![ValidationIssue::PhiAfterNonPhi { block: "next", index: 2 }]);
+    }
+
+    #[test]
+    fn check_unreachable_blocks() {
+        // orphan and its successor are dead; f is a function, and handler and tbl_target are reachable as code pointers
+        let src = "data:\nglobal array tbl: { tbl_target }\ncode:\nmain:\n  %h = handler\n  ret 0\nf(this):\n  jump g\ng:\n  ret 1\n\
+                   handler:\n  ret 2\ntbl_target:\n  ret 3\norphan:\n  jump orphan2\norphan2:\n  ret 4\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let issues = validate(&prog);
+        assert_eq!(issues, vec![
+            ValidationIssue::UnreachableBlock { block: "orphan" },
+            ValidationIssue::UnreachableBlock { block: "orphan2" },
+        ]);
+        assert!(issues.iter().all(|i| !i.is_error()));
+        assert_eq!(issues[0].to_string(), "WARNING: basic block orphan can never run");
+    }
+
+    #[test]
+    fn check_unreferenced_blocks() {
+        // orphan is named by nothing, nor is the function f. orphan2 and g are named, by orphan's jump and f's, so
+        // they're not unreferenced, but they're unreachable, since only those blocks lead to them. handler, check, and
+        // tbl_target are named as a code pointer, by assertheap, and by a global, so they're fine.
+        let src = "data:\nglobal array tbl: { tbl_target }\ncode:\nmain:\n  %h = handler\n  assertheap(check, 0, \"bad\")\n  ret 0\n\
+                   f(this):\n  jump g\ng:\n  ret 1\nhandler:\n  ret 2\ncheck(this):\n  ret 1\ntbl_target:\n  ret 3\n\
+                   orphan:\n  jump orphan2\norphan2:\n  ret 4\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let opts = ValidateOptions { unreferenced_blocks: true };
+        let issues = validate_with(&prog, &opts);
+        assert_eq!(issues, vec![
+            ValidationIssue::UnreferencedBlock { block: "f" },
+            ValidationIssue::UnreachableBlock { block: "g" },
+            ValidationIssue::UnreferencedBlock { block: "orphan" },
+            ValidationIssue::UnreachableBlock { block: "orphan2" },
+        ]);
+        assert!(issues.iter().all(|i| !i.is_error()));
+        assert_eq!(issues[2].to_string(), "WARNING: basic block orphan is never referenced");
+        // Off by default, when functions count as entry points
+        assert_eq!(validate(&prog), vec![
+            ValidationIssue::UnreachableBlock { block: "orphan" },
+            ValidationIssue::UnreachableBlock { block: "orphan2" },
+        ]);
+    }
+
+    #[test]
+    fn check_pure_declarations() {
+        // fib recurses through itself and reads a global table; noisy prints; caller calls something undeclared
+        let src = concat!(";@ pure fib noisy caller ghost\n",
+                          "data:\nglobal array base: { 0, 1 }\ncode:\n",
+                          "main:\n  %a = call(fib, 0, 10)\n  %b = call(noisy, 0)\n  %c = call(caller, 0)\n  ret %a\n",
+                          "fib(this, n):\n  %small = %n < 2\n  if %small then small else big\n",
+                          "small:\n  %v = getelt(@base, %n)\n  ret %v\n",
+                          "big:\n  %n1 = %n - 1\n  %n2 = %n - 2\n  %a = call(fib, 0, %n1)\n  %b = call(fib, 0, %n2)\n  %s = %a + %b\n  ret %s\n",
+                          "noisy(this):\n  jump loud\nloud:\n  %x = 1\n  print(%x)\n  ret %x\n",
+                          "caller(this):\n  %r = call(helper, 0)\n  ret %r\n",
+                          "helper(this):\n  ret 1\n");
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(purity_violation(&prog, "fib"), None);
+        assert_eq!(purity_violation(&prog, "helper"), None);
+        let issues = validate(&prog);
+        assert_eq!(issues, vec![
+            ValidationIssue::NotPure { block: "noisy", at: "loud", index: 1 },
+            ValidationIssue::NotPure { block: "caller", at: "caller", index: 0 },
+            ValidationIssue::NoSuchPureBlock { name: "ghost".to_string() },
+        ]);
+        assert_eq!(issues.iter().filter(|i| i.is_error()).count(), 2);
+        assert_eq!(issues[0].to_string(), "ERROR: block noisy is declared pure, but instruction 1 of block loud has an effect");
+
+        // Reading memory through a pointer could see a store made between calls
+        let src = ";@ pure first\ndata:\ncode:\nmain:\n  ret 0\nfirst(this, p):\n  %x = getelt(%p, 0)\n  ret %x\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        assert_eq!(purity_violation(&prog, "first"), Some(("first", 0)));
+    }
+
+    #[test]
+    fn check_cost_declarations() {
+        let src = ";@ cost main 10\n;@ cost ghost 5\ndata:\ncode:\nmain:\n  ret 0\n";
+        let (_,prog) = parse_program(src.as_bytes()).unwrap();
+        let issues = validate(&prog);
+        assert_eq!(issues, vec![ValidationIssue::NoSuchCostBlock { name: "ghost".to_string() }]);
+        assert!(!issues[0].is_error());
+    }
+}
@@ -0,0 +1,3 @@
+// The interpreter proper, as a library so other tools can drive it directly; main.rs is a thin CLI on top
+extern crate nom;
+pub mod ir441;
@@ -1,18 +1,18 @@
 // We'll just hack it all together in one file for now
+// (the interpreter itself lives in the library half of the crate, see lib.rs)
 extern crate nom;
-mod ir441;
 
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, BufReader};
 use std::path::Path;
 
-use std::str::{from_utf8};
 use nom::{Finish};
 
-use crate::ir441::nodes::*;
-use crate::ir441::parsing::*;
-use crate::ir441::exec::*;
+use ir441::ir441::nodes::*;
+use ir441::ir441::parsing::*;
+use ir441::ir441::exec::*;
+use ir441::ir441::validate::*;
 
 
 
@@ -20,43 +20,74 @@ use crate::ir441::exec::*;
 
 
 fn check_warnings(prog: &IRProgram) {
-    if !prog.blocks.contains_key("main") {
-        println!("WARNING: No main block found");
-    }
-    for (_,b) in prog.blocks.iter() {
-        let mut past_phis = false;
-        for i in b.instrs.iter() {
-            // Check that all phi targets exist
-            match i {
-                IRStatement::Phi { .. } => {
-                    if past_phis {
-                        println!("ERROR: phi instruction after non-phis in basic block {}: {:?}", b.name, i);
-                    }
-                },
-                _ => (past_phis = true)
-            }
+    for issue in validate(prog) {
+        println!("{}", issue);
+    }
+}
+
+// How far apart the debugger's snapshots are, and so how far each `back` goes
+const DEBUG_SNAPSHOT_STEPS: u64 = 50;
+
+fn show_position(prog: &IRProgram, at: &Snapshot) {
+    let b = &prog.blocks[at.block];
+    let instr = b.instrs.get(at.index).map_or_else(|| b.next.to_string(), |i| i.to_string());
+    println!("step {} at {}:{} (depth {}): {}", at.step, at.block, at.index, at.depth, instr);
+    let mut vars : Vec<(&str,&VirtualVal)> = at.locals.iter().collect();
+    vars.sort_by_key(|(x,_)| *x);
+    for (x,v) in vars {
+        println!("\t%{} = {}", x, v);
+    }
+}
+
+// The debug subcommand: step through a run and back again, one command per line on stdin
+//     step [n]   run at least n more steps (default 1)
+//     back       return to the previous snapshot, taken every DEBUG_SNAPSHOT_STEPS steps; step forward from there
+//     where      show the next instruction and the current frame's variables
+//     mem        dump memory, in hex
+//     quit
+// Stepping forward after going back runs that stretch again, so the program's own output repeats. The program has
+// to come from a file, since stdin carries the commands.
+fn debug_session(prog: &IRProgram) -> Result<(),Box<dyn std::error::Error>> {
+    let mut tt = match TimeTravel::new(prog, &RunOptions::new(), ExecMode::Unlimited, DEBUG_SNAPSHOT_STEPS) {
+        Ok(tt) => tt,
+        Err(outcome) => {
+            println!("Program ended before its first instruction: {:?}", outcome);
+            return Ok(());
         }
-        match &b.next {
-            ControlXfer::If { cond: _, tblock:t, fblock:f } => {
-                if !prog.blocks.contains_key(t) {
-                    println!("ERROR: next block |{}| in block {} does not exist!", t, b.name);
-                }
-                if !prog.blocks.contains_key(f) {
-                    println!("ERROR: next block |{}| in block {} does not exist!", f, b.name);
-                }
-            }
-            ControlXfer::Jump { block:l } => {
-                if !prog.blocks.contains_key(l) {
-                    println!("ERROR: next block |{}| in block {} does not exist!", l, b.name);
-                }
+    };
+    show_position(prog, &tt.current);
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let words : Vec<&str> = line.split_whitespace().collect();
+        let steps = match words[..] {
+            ["step"] | ["s"] => Some(1),
+            ["step", n] | ["s", n] => match n.parse() {
+                Ok(n) => Some(n),
+                Err(_) => { println!("Not a step count: {}", n); continue }
+            },
+            _ => None
+        };
+        if let Some(n) = steps {
+            match tt.forward(n) {
+                None => show_position(prog, &tt.current),
+                Some(outcome) => println!("Program ended first: {:?}", outcome)
             }
-            _ => ()
+            continue;
+        }
+        match words[..] {
+            [] => (),
+            ["back"] | ["b"] => if tt.back() { show_position(prog, &tt.current) } else { println!("Already at the start") },
+            ["where"] | ["w"] => show_position(prog, &tt.current),
+            ["mem"] => println!("{:#}", tt.current.memory),
+            ["quit"] | ["q"] => break,
+            _ => println!("Commands: step [n], back, where, mem, quit")
         }
     }
+    Ok(())
 }
 
 fn main() -> Result<(),Box<dyn std::error::Error>> {
-    let cmd = std::env::args().nth(1).expect("need subcommand [check|exec|trace|perf]");
+    let cmd = std::env::args().nth(1).expect("need subcommand [check|exec|exec-explain|exec-verbose|exec-fixedmem|exec-gc|exec-gc-logging|trace|trace-json|perf|summary|kv|debug]");
     let txt = std::env::args().nth(2);
     let mut reader: Box<dyn BufRead> = match txt {
         None => Box::new(BufReader::new(io::stdin())),
@@ -72,10 +103,11 @@ fn main() -> Result<(),Box<dyn std::error::Error>> {
 
     let mut bytes : Vec<u8> = vec![];
     reader.read_to_end(&mut bytes)?; 
-    let (_leftover,prog) = parse_program(&bytes[..]).finish().map_err(|nom::error::Error { input, code: _ }| from_utf8(input).unwrap())?;
+    let (_leftover,prog) = parse_program(&bytes[..]).finish().map_err(|e| describe_parse_error(&bytes, &e))?;
     let cmd_str = cmd.as_str();
 
     let mut cycles = ExecStats::new();
+    let mut status = 0;
 
     if cmd_str == "check" {
         println!("Parsed: {}", prog);
@@ -83,33 +115,69 @@ fn main() -> Result<(),Box<dyn std::error::Error>> {
     } else if cmd_str == "exec" {
         println!("Parsed: {}", prog);
         check_warnings(&prog);
-        let _fresult = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        status = exit_code(&run_prog(&prog, false, &mut cycles, ExecMode::Unlimited));
     } else if cmd_str == "exec-fixedmem" {
         println!("Parsed: {}", prog);
         check_warnings(&prog);
-        let _fresult = run_prog(&prog, true, &mut cycles, ExecMode::MemCap {limit:100});
+        status = exit_code(&run_prog(&prog, true, &mut cycles, ExecMode::MemCap {limit:100}));
     } else if cmd_str == "exec-gc" {
         println!("Parsed: {}", prog);
         check_warnings(&prog);
-        let _fresult = run_prog(&prog, false, &mut cycles, ExecMode::GC {limit:100});
+        status = exit_code(&run_prog(&prog, false, &mut cycles, ExecMode::GC {limit:100}));
     } else if cmd_str == "exec-gc-logging" {
         println!("Parsed: {}", prog);
         check_warnings(&prog);
-        let _fresult = run_prog(&prog, false, &mut cycles, ExecMode::LoggingGC {limit:100});
+        status = exit_code(&run_prog(&prog, false, &mut cycles, ExecMode::LoggingGC {limit:100}));
     } else if cmd_str == "trace" {
         println!("Parsed: {}", prog);
         check_warnings(&prog);
-        let _ = run_prog(&prog, true, &mut cycles, ExecMode::Unlimited);
+        status = exit_code(&run_prog(&prog, true, &mut cycles, ExecMode::Unlimited));
+    } else if cmd_str == "trace-json" {
+        // The trace alone, as JSON, for recording a run to replay against later
+        let opts = RunOptions { tracing: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        println!("{}", result.trace_json());
+        status = exit_code(&result.result);
     } else if cmd_str == "perf" {
         println!("Parsed: {}", prog);
         check_warnings(&prog);
-        let _ = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        status = exit_code(&run_prog(&prog, false, &mut cycles, ExecMode::Unlimited));
         println!("Execution stats:\n{:?}", cycles);
+    } else if cmd_str == "exec-verbose" {
+        // For grading final heap state: stats and memory even when the run succeeds
+        println!("Parsed: {}", prog);
+        check_warnings(&prog);
+        status = exit_code(&run_prog_verbose(&prog, false, &mut cycles, ExecMode::Unlimited, Verbosity::Memory));
+    } else if cmd_str == "exec-explain" {
+        // For students: follow a crash report with what the error usually means
+        println!("Parsed: {}", prog);
+        check_warnings(&prog);
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        if let Err(e) = &result {
+            println!("{}", explain(e));
+        }
+        status = exit_code(&result);
+    } else if cmd_str == "summary" {
+        // Just the one line per run, for grepping through logs of many runs
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        println!("{}", result.summary(&cycles));
+        status = exit_code(&result.result);
+    } else if cmd_str == "kv" {
+        // The outcome and every stat on one line, for CI logs
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        println!("{}", result.to_kv(&cycles));
+        status = exit_code(&result.result);
+    } else if cmd_str == "debug" {
+        check_warnings(&prog);
+        debug_session(&prog)?;
     } else {
         println!("Unsupported command (possibly not-yet-implemented): {}", cmd);
-        panic!("Usage: ir441 (check|exec|exec-fixedmem|exec-gc|exec-gc-logging|trace|perf)");
+        panic!("Usage: ir441 (check|exec|exec-explain|exec-verbose|exec-fixedmem|exec-gc|exec-gc-logging|trace|trace-json|perf|summary|kv|debug)");
     }
     
+    if status != 0 {
+        std::process::exit(status);
+    }
     Ok(())
 }
 
@@ -118,10 +186,11 @@ mod systests {
     use std::path::Path;
     use std::fs::File;
     use std::io::{BufReader,BufRead};
-    use crate::ir441::nodes::*;
-    use crate::ir441::parsing::*;
-    use crate::ir441::exec::*;
-    use std::str::{from_utf8};
+    use ir441::ir441::nodes::*;
+    use ir441::ir441::parsing::*;
+    use ir441::ir441::exec::*;
+    use ir441::ir441::json::{to_json_trace,TraceLine};
+    use std::collections::BTreeMap;
     use nom::{Finish};
 
     fn load_program(filepath: &str) -> Result<Vec<u8>,Box<dyn std::error::Error>> {
@@ -137,9 +206,31 @@ mod systests {
         Ok(bytes)
     }
     fn parse(bytes:&Vec<u8>) -> Result<IRProgram,Box<dyn std::error::Error>> {
-        let (_leftover,prog) = parse_program(&bytes[..]).finish().map_err(|nom::error::Error { input, code: _ }| from_utf8(input).unwrap())?;
+        let (_leftover,prog) = parse_program(&bytes[..]).finish().map_err(|e| describe_parse_error(&bytes, &e))?;
         Ok(prog)
     }
+    // A program that runs setup in main, then allocates rounds objects of junk fields each, dropping every one as the
+    // next is made, to force collections in a capped mode; then it runs done, which gets its own block and whatever
+    // blocks follow it
+    fn churn_prog(setup: &str, rounds: u64, junk: u64, done: &str) -> Vec<u8> {
+        format!(concat!("data:\ncode:\nmain:\n{}  %cnt = {}\n  jump loop\n",
+            "loop:\n  if %cnt then more else done\n",
+            "more:\n  %cnt = %cnt - 1\n  %junk = alloc({})\n  jump loop\n",
+            "done:\n{}"), setup, rounds, junk, done).into_bytes()
+    }
+    #[test]
+    fn check_churn_prog() -> Result<(),Box<dyn std::error::Error>>{
+        // Each round's junk is garbage by the next, so the collector reclaims it rather than running out
+        for (rounds, junk, limit, gcs) in [(10, 10, 100, 1), (20, 10, 100, 3), (40, 0, 30, 4)] {
+            let bytes = churn_prog("", rounds, junk, "  ret 0\n");
+            let prog = parse(&bytes)?;
+            let mut cycles = ExecStats::new();
+            let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::GC { limit });
+            assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+            assert_eq!((cycles.allocs, cycles.gcs), (rounds, gcs));
+        }
+        Ok(())
+    }
     #[test]
     fn check_trivial() -> Result<(),Box<dyn std::error::Error>>{
         let bytes = load_program("examples/trivial.ir")?;
@@ -153,7 +244,7 @@ mod systests {
     fn check_countdown() -> Result<(),Box<dyn std::error::Error>>{
         let bytes = load_program("examples/countdown.ir")?;
         let prog = parse(&bytes)?;
-        let mut cycles = ExecStats { allocs: 0, calls: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, prints: 0, rets: 0 };
+        let mut cycles = ExecStats { allocs: 0, calls: 0, arg_passes: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, bytes_read: 0, bytes_written: 0, prints: 0, rets: 0, gcs: 0, gc_copies_by_size: BTreeMap::new(), block_cost: 0 };
         let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
         assert_eq!(result,Ok(VirtualVal::Data { val: 0 }));
         Ok(())
@@ -162,7 +253,7 @@ mod systests {
     fn check_basicoo() -> Result<(),Box<dyn std::error::Error>>{
         let bytes = load_program("examples/basicoo.ir")?;
         let prog = parse(&bytes)?;
-        let mut cycles = ExecStats { allocs: 0, calls: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, prints: 0, rets: 0 };
+        let mut cycles = ExecStats { allocs: 0, calls: 0, arg_passes: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, bytes_read: 0, bytes_written: 0, prints: 0, rets: 0, gcs: 0, gc_copies_by_size: BTreeMap::new(), block_cost: 0 };
         let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
         assert_eq!(result,Ok(VirtualVal::Data { val: 3 }));
         Ok(())
@@ -171,16 +262,181 @@ mod systests {
     fn check_gctest1() -> Result<(),Box<dyn std::error::Error>>{
         let bytes = load_program("examples/gctest1.ir")?;
         let prog = parse(&bytes)?;
-        let mut cycles = ExecStats { allocs: 0, calls: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, prints: 0, rets: 0 };
+        let mut cycles = ExecStats { allocs: 0, calls: 0, arg_passes: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, bytes_read: 0, bytes_written: 0, prints: 0, rets: 0, gcs: 0, gc_copies_by_size: BTreeMap::new(), block_cost: 0 };
         let result = run_prog(&prog, false, &mut cycles, ExecMode::GC { limit: 100 });
         assert_eq!(result,Ok(VirtualVal::Data { val: 0 }));
         Ok(())
     }
     #[test]
+    fn check_header_init() -> Result<(),Box<dyn std::error::Error>>{
+        let mut opts = RunOptions::new();
+        opts.check_header_init = true;
+        // gctest1 fills in its objects before storing their slotmaps
+        let bytes = load_program("examples/gctest1.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::UseBeforeHeaderInit { addr: 64 }));
+        // Reads count too, and there's no header to initialize without GC
+        let bytes = b"data:\ncode:\nmain:\n  %o = alloc(1)\n  %x = getelt(%o, 0)\n  ret %x\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::UseBeforeHeaderInit { addr: 64 }));
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        // Slotmaps stored first, including a 0 for the object without pointers; survivors stay initialized when moved
+        let bytes = churn_prog(concat!("  %a = alloc(2)\n  %amap = %a - 8\n  store(%amap, 1)\n",
+            "  %b = alloc(1)\n  %bmap = %b - 8\n  store(%bmap, 0)\n",
+            "  setelt(%a, 0, %b)\n  setelt(%b, 0, 7)\n  %amap = 0\n  %bmap = 0\n"),
+            10, 10, "  %b2 = getelt(%a, 0)\n  %v = getelt(%b2, 0)\n  ret %v\n");
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 7 })));
+        Ok(())
+    }
+    #[test]
+    fn check_code_stores() -> Result<(),Box<dyn std::error::Error>>{
+        // %a's first field is marked as a pointer but gets a code pointer; allocating junk then forces a collection
+        let program = |setup: &str| churn_prog(&format!("  %a = alloc(2)\n  %amap = %a - 8\n{}  %amap = 0\n", setup),
+            10, 10, "  %f = getelt(%a, 0)\n  ret 0\nhelper(this):\n  ret 0\n");
+        let bytes = program("  store(%amap, 1)\n  setelt(%a, 0, helper)\n");
+        let prog = parse(&bytes)?;
+        // Unchecked, the mistake only shows up once the collector gets to the field
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::BadGCField));
+        let opts = RunOptions { check_code_stores: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::StoringCodePtrInTracedField { addr: 64, bname: "helper" }));
+        // Storing the slotmap after the code pointer is caught at the slotmap
+        let bytes = program("  setelt(%a, 0, helper)\n  store(%amap, 1)\n");
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::StoringCodePtrInTracedField { addr: 64, bname: "helper" }));
+        // Without a collector nothing traces the field, either way round
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::MemCap { limit: 1000 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        // A code pointer in a field the collector copies blindly is fine
+        let bytes = program("  store(%amap, 2)\n  setelt(%a, 0, helper)\n");
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        Ok(())
+    }
+    #[test]
+    fn check_weak_refs() -> Result<(),Box<dyn std::error::Error>>{
+        // holder's only field is weak (slotmap bit 32); target is also kept in %t unless the program drops it.
+        // Enough allocation follows to force a couple of collections before main reads the weak field.
+        let program = |drop_target: bool| churn_prog(&format!(concat!(
+            "  %holder = alloc(1)\n  %hmap = %holder - 8\n  store(%hmap, 4294967296)\n",
+            "  %t = alloc(1)\n  %tmap = %t - 8\n  store(%tmap, 0)\n  setelt(%t, 0, 99)\n",
+            "  setelt(%holder, 0, %t)\n  %hmap = 0\n  %tmap = 0\n{}"), if drop_target { "  %t = 0\n" } else { "" }),
+            10, 10, concat!("  %w = getelt(%holder, 0)\n  if %w then alive else dead\n",
+            "alive:\n  %same = %w == %t\n  %v = getelt(%w, 0)\n  %r = %v + %same\n  ret %r\n",
+            "dead:\n  ret 0\n"));
+        let mut opts = RunOptions::new();
+        opts.weak_refs = true;
+        opts.check_slotmaps = true;
+        // Only the weak reference is left, so the target is collected and the field nulled
+        let bytes = program(true);
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        // With %t still live, the weak field follows the target to its new address
+        let bytes = program(false);
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 100 })));
+        // Without weak references bit 32 is just past the object, so the field is copied blindly and left dangling
+        let bytes = program(true);
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::ReadFromGCedData));
+        Ok(())
+    }
+    #[test]
+    fn check_dead_stores() -> Result<(),Box<dyn std::error::Error>>{
+        let mut opts = RunOptions::new();
+        opts.track_dead_stores = true;
+        // Field 0 is written twice and read once, so the first write was wasted; field 1 is never read at all
+        let bytes = concat!("data:\ncode:\nmain:\n  %o = alloc(2)\n  setelt(%o, 0, 1)\n  jump second\n",
+            "second:\n  setelt(%o, 0, 2)\n  setelt(%o, 1, 5)\n  %x = getelt(%o, 0)\n  ret %x\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 2 })));
+        let o = 40;
+        assert_eq!(result.dead_stores, vec![
+            DeadStore { addr: o, block: "main", instr: &prog.blocks["main"].instrs[1], overwritten: true },
+            DeadStore { addr: o + 8, block: "second", instr: &prog.blocks["second"].instrs[1], overwritten: false },
+        ]);
+        // Off by default
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.dead_stores.is_empty());
+        // Unread values follow their object when it moves; slotmap stores aren't tracked
+        let bytes = churn_prog(concat!("  %a = alloc(2)\n  %amap = %a - 8\n  store(%amap, 1)\n",
+            "  %b = alloc(1)\n  %bmap = %b - 8\n  store(%bmap, 0)\n",
+            "  setelt(%a, 0, %b)\n  setelt(%a, 1, 3)\n  setelt(%b, 0, 7)\n  %b = 0\n  %amap = 0\n  %bmap = 0\n",
+            "  %dead = alloc(1)\n  setelt(%dead, 0, 8)\n  %dead = 0\n"),
+            10, 10, "  %b2 = getelt(%a, 0)\n  %v = getelt(%b2, 0)\n  ret %v\n");
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 7 })));
+        let dead : Vec<(String,bool)> = result.dead_stores.iter().map(|d| (d.instr.to_string(), d.overwritten)).collect();
+        // The store into %dead's object goes when the object does; a's second field outlives it
+        assert_eq!(dead, vec![("setelt(%dead, 0, 8)".to_string(), false), ("setelt(%a, 1, 3)".to_string(), false)]);
+        let a = result.memory.as_ref().unwrap().heap_objects().iter().find(|o| o.fields == 2).unwrap().addr;
+        assert_eq!(result.dead_stores[1].addr, a + 8);
+        Ok(())
+    }
+    #[test]
+    fn check_uninit_fields() -> Result<(),Box<dyn std::error::Error>>{
+        let mut opts = RunOptions::new();
+        opts.track_uninit_fields = true;
+        // Field 1 is read but never written; a narrow write to field 3 still initializes it
+        let bytes = concat!("data:\ncode:\nmain:\n  %o = alloc(4)\n  setelt(%o, 0, 1)\n  setelt(%o, 2, 5)\n",
+            "  setelt32(%o, 6, 6)\n  %x = getelt(%o, 1)\n  ret %x\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.result.is_ok());
+        assert_eq!(result.uninit_fields, vec![UninitField { object: 40, field: 1 }]);
+        // Off by default
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.uninit_fields.is_empty());
+        // A garbage object is reported when it's collected; a survivor's unwritten field follows it when it moves
+        let bytes = churn_prog(concat!("  %a = alloc(2)\n  %amap = %a - 8\n  store(%amap, 0)\n  setelt(%a, 0, 3)\n  %amap = 0\n",
+            "  %dead = alloc(3)\n  setelt(%dead, 0, 8)\n  setelt(%dead, 1, 8)\n  %dead = 0\n"),
+            10, 0, "  %v = getelt(%a, 0)\n  ret %v\n");
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 30 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 3 })));
+        let a = result.memory.as_ref().unwrap().heap_objects().iter().find(|o| o.fields == 2).unwrap().addr;
+        assert_eq!(result.uninit_fields.len(), 2);
+        assert_eq!(result.uninit_fields[0].field, 2);
+        assert_ne!(result.uninit_fields[0].object, a);
+        assert_eq!(result.uninit_fields[1], UninitField { object: a, field: 1 });
+        Ok(())
+    }
+    #[test]
+    fn check_gc_copies_by_size() -> Result<(),Box<dyn std::error::Error>>{
+        // Objects of one, two, and three fields all stay live while ten-field garbage forces collections
+        let bytes = churn_prog(concat!("  %a = alloc(1)\n  %amap = %a - 8\n  store(%amap, 0)\n",
+            "  %b = alloc(2)\n  %bmap = %b - 8\n  store(%bmap, 0)\n",
+            "  %c = alloc(3)\n  %cmap = %c - 8\n  store(%cmap, 0)\n",
+            "  %amap = 0\n  %bmap = 0\n  %cmap = 0\n"), 20, 10, "  ret 0\n");
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::GC { limit: 100 });
+        assert_eq!(result, Ok(VirtualVal::Data { val: 0 }));
+        assert_eq!(cycles.gcs, 3);
+        // The survivors move at every collection, and so does whichever piece of garbage %junk still holds
+        assert_eq!(cycles.gc_copies_by_size, [(1, 3), (2, 3), (3, 3), (10, 3)].into_iter().collect());
+        assert!(cycles.to_string().ends_with("gc: 3\ngc_copies_by_size: 3x1 fields 3x2 fields 3x3 fields 3x10 fields\n"));
+        assert!(ExecStats::new().to_string().ends_with("gc_copies_by_size: none\n"));
+        Ok(())
+    }
+    #[test]
     fn check_gctest2() -> Result<(),Box<dyn std::error::Error>>{
         let bytes = load_program("examples/gctest2.ir")?;
         let prog = parse(&bytes)?;
-        let mut cycles = ExecStats { allocs: 0, calls: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, prints: 0, rets: 0 };
+        let mut cycles = ExecStats { allocs: 0, calls: 0, arg_passes: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, bytes_read: 0, bytes_written: 0, prints: 0, rets: 0, gcs: 0, gc_copies_by_size: BTreeMap::new(), block_cost: 0 };
         let result = run_prog(&prog, false, &mut cycles, ExecMode::GC { limit: 100 });
         assert_eq!(result,Ok(VirtualVal::Data { val: 0 }));
         Ok(())
@@ -189,9 +445,1324 @@ mod systests {
     fn check_gctest3() -> Result<(),Box<dyn std::error::Error>>{
         let bytes = load_program("examples/gctest3.ir")?;
         let prog = parse(&bytes)?;
-        let mut cycles = ExecStats { allocs: 0, calls: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, prints: 0, rets: 0 };
+        let mut cycles = ExecStats { allocs: 0, calls: 0, arg_passes: 0, fast_alu_ops: 0, slow_alu_ops: 0, phis: 0, conditional_branches: 0, unconditional_branches: 0, mem_reads: 0, mem_writes: 0, bytes_read: 0, bytes_written: 0, prints: 0, rets: 0, gcs: 0, gc_copies_by_size: BTreeMap::new(), block_cost: 0 };
         let result = run_prog(&prog, false, &mut cycles, ExecMode::GC { limit: 100 });
         assert_eq!(result,Ok(VirtualVal::Data { val: 4096 }));
         Ok(())
     }
-}
\ No newline at end of file
+    #[test]
+    fn check_ifsigned() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/ifsigned.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        // negative -> 1, zero -> 2, positive -> 3
+        assert_eq!(result,Ok(VirtualVal::Data { val: 123 }));
+        assert_eq!(cycles.conditional_branches, 3);
+        Ok(())
+    }
+    #[test]
+    fn check_fragmentation() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/fragmentation.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::GC { limit: 21 });
+        assert_eq!(result.result,Ok(Some(VirtualVal::Data { val: 0 })));
+        let report = result.fragmentation.expect("expected a collection to run");
+        // Each object is a guard word, 3 metadata slots, and 1 field; %a, %c, and %e survive
+        assert_eq!(report.live_objects, 3);
+        assert_eq!(report.gaps.iter().map(|(_,n)| *n).collect::<Vec<u64>>(), vec![1, 6, 6]);
+        assert_eq!(report.free_slots, 13);
+        assert_eq!(report.largest_free_run, 6);
+        Ok(())
+    }
+    #[test]
+    fn check_rand() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/rand.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let opts = RunOptions { seed: 42, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        // SplitMix64 seeded with 42 yields 13679457532755275413, 2949826092126892291, 5139283748462763858
+        assert_eq!(result.result,Ok(Some(VirtualVal::Data { val: 413291858 })));
+        // Same seed, same sequence
+        let again = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(again.result, result.result);
+        Ok(())
+    }
+    #[test]
+    fn check_heaps_equal() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes_a = load_program("examples/list_a.ir")?;
+        let bytes_b = load_program("examples/list_b.ir")?;
+        let bytes_c = load_program("examples/list_cyclic.ir")?;
+        let (prog_a, prog_b, prog_c) = (parse(&bytes_a)?, parse(&bytes_b)?, parse(&bytes_c)?);
+        let run_a = eval_prog(&prog_a, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        let run_b = eval_prog(&prog_b, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        let run_c = eval_prog(&prog_c, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        let (root_a, root_b, root_c) = (run_a.result.unwrap().unwrap(), run_b.result.unwrap().unwrap(), run_c.result.unwrap().unwrap());
+        // Same shape at different addresses
+        assert_ne!(root_a, root_b);
+        let (mem_a, mem_b, mem_c) = (run_a.memory.unwrap(), run_b.memory.unwrap(), run_c.memory.unwrap());
+        assert!(heaps_equal(&mem_a, &[root_a], &mem_b, &[root_b]));
+        assert!(heaps_equal(&mem_b, &[root_b], &mem_a, &[root_a]));
+        assert!(!heaps_equal(&mem_a, &[root_a], &mem_c, &[root_c]));
+        // Roots must correspond pairwise
+        assert!(!heaps_equal(&mem_a, &[root_a], &mem_b, &[root_b, root_b]));
+        Ok(())
+    }
+    #[test]
+    fn check_arg_passing_cost() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/callargs.ir")?;
+        let prog = parse(&bytes)?;
+        // Amortized by default: no per-argument charges at all
+        let mut amortized = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut amortized, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 15 })));
+        assert_eq!(amortized.arg_passes, 0);
+        // Receiver-only call moves 1 value, the 5-argument call moves 6
+        let mut charged = ExecStats::new();
+        let opts = RunOptions { charge_arg_passing: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut charged, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 15 })));
+        assert_eq!(charged.calls, 2);
+        assert_eq!(charged.arg_passes, 7);
+        assert_eq!(charged.fast_alu_ops, amortized.fast_alu_ops + 7);
+        // Defaulted formals the caller leaves off aren't passed: scale takes 3, but is called with 3, 2, then 1 values
+        let bytes = load_program("examples/defaults.ir")?;
+        let prog = parse(&bytes)?;
+        let mut charged = ExecStats::new();
+        assert!(eval_prog(&prog, &opts, &mut charged, ExecMode::Unlimited).result.is_ok());
+        assert_eq!(charged.arg_passes, 6);
+        Ok(())
+    }
+    #[test]
+    fn check_call_to_non_entry() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/midcall.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { entry_blocks: Some(["sum".to_string()].into_iter().collect()), ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::CallToNonEntry { bname: "loop" }));
+
+        // Declaring the real entries doesn't disturb a well-formed program
+        let bytes = load_program("examples/callargs.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { entry_blocks: Some(["noargs".to_string(), "fiveargs".to_string()].into_iter().collect()), ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 15 })));
+        Ok(())
+    }
+    #[test]
+    fn check_trace_filter() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/list_a.ir")?;
+        let prog = parse(&bytes)?;
+        let filter = TraceFilter { kinds: Some([InstrKind::Alloc].into_iter().collect()), ..TraceFilter::new() };
+        let opts = RunOptions { tracing: true, trace_filter: filter, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.result.is_ok());
+        assert_eq!(result.trace, vec!["Executing: %n3 = alloc(2)    [alloc, +10 cycles]", "Executing: %n2 = alloc(2)    [alloc, +10 cycles]", "Executing: %n1 = alloc(2)    [alloc, +10 cycles]"]);
+
+        let bytes = load_program("examples/callargs.ir")?;
+        let prog = parse(&bytes)?;
+        let filter = TraceFilter { blocks: Some(["fiveargs".to_string()].into_iter().collect()), ..TraceFilter::new() };
+        let opts = RunOptions { tracing: true, trace_filter: filter, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.trace.len(), 5);
+        assert_eq!(result.trace[4], "Transfering via: ret %4    [ret, +5 cycles]");
+        Ok(())
+    }
+    #[test]
+    fn check_trace_first_entries() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/countdown.ir")?;
+        let prog = parse(&bytes)?;
+        let filter = TraceFilter { first_entries: Some(2), ..TraceFilter::new() };
+        let opts = RunOptions { tracing: true, trace_filter: filter, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.result.is_ok());
+        // Ten trips around the loop, but only the first two of each block are shown, then one note per block
+        assert_eq!(result.trace.iter().filter(|l| l.starts_with("Executing: %2 = %cnt - 1")).count(), 2);
+        let notes : Vec<&String> = result.trace.iter().filter(|l| l.starts_with("...")).collect();
+        assert_eq!(notes, vec!["... loophead suppressed after 2 entries", "... body suppressed after 2 entries"]);
+        // finish runs once, so it's traced in full
+        assert_eq!(result.trace.last().unwrap(), "Transfering via: ret 0    [ret, +5 cycles]");
+        Ok(())
+    }
+    #[test]
+    fn check_best_effort() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/besteffort.ir")?;
+        let prog = parse(&bytes)?;
+        // Strict by default: stops at the first mistake
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::UninitializedVariable { name: "x" }));
+        assert!(!result.is_degraded());
+
+        let opts = RunOptions { best_effort: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 1 })));
+        assert!(result.is_degraded());
+        assert_eq!(result.recovered, vec![
+            RuntimeError::UninitializedVariable { name: "x" },
+            RuntimeError::UnallocatedAddressRead { addr: 4096 },
+            RuntimeError::UninitializedVariable { name: "y" },
+        ]);
+
+        // Non-recoverable errors still stop a best-effort run
+        let bytes = load_program("examples/fail.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(matches!(result.result, Err(RuntimeError::Failed { .. })));
+        Ok(())
+    }
+    #[test]
+    fn check_continue_past_fail() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = concat!("data:\n",
+                            "global array total: { 5 }\n",
+                            "code:\n",
+                            "main:\n",
+                            "  %p = alloc(2)\n",
+                            "  setelt(%p, 1, 7)\n",
+                            "  %x = getelt(%p, 1)\n",
+                            "  print(%x)\n",
+                            "  %r = call(work, %p, %u)\n",
+                            "  ret %r\n",
+                            "work(this, n):\n",
+                            "  print(%n)\n",
+                            "  fail NotANumber\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { best_effort: true, continue_past_fail: true, print_final_globals: true, ..RunOptions::new() };
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        // Still a failure, not something recovered from, but the run is wrapped up normally
+        assert_eq!(result.result, Err(RuntimeError::Failed { reason: Reason::NotANumber }));
+        assert_eq!(result.recovered, vec![RuntimeError::UninitializedVariable { name: "u" }]);
+        assert_eq!(result.trace, vec!["WARNING: main recovered from UninitializedVariable { name: \"u\" }, reading 0",
+                                      "Final Globals:", "\t@total[0] = 5"]);
+        assert_eq!(result.warnings, vec![
+            Warning::Recovered { error: RuntimeError::UninitializedVariable { name: "u" }, block: "main" },
+        ]);
+        // The work done before the failure is all accounted for
+        assert_eq!((cycles.allocs, cycles.prints, cycles.calls), (1, 2, 1));
+        assert!(result.crash_snapshot.is_some());
+
+        // Without the option, best-effort mode stops at fail as before
+        let opts = RunOptions { best_effort: true, print_final_globals: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::Failed { reason: Reason::NotANumber }));
+        assert_eq!(result.recovered, vec![RuntimeError::UninitializedVariable { name: "u" }]);
+        assert_eq!(result.trace, vec!["WARNING: main recovered from UninitializedVariable { name: \"u\" }, reading 0"]);
+        Ok(())
+    }
+    #[test]
+    fn check_assert_heap() -> Result<(),Box<dyn std::error::Error>>{
+        // A three-node list (value, next), checked for ascending order before returning
+        let program = |middle: u64| format!(concat!("data:\ncode:\nmain:\n",
+            "  %c = alloc(2)\n  setelt(%c, 0, 3)\n  setelt(%c, 1, 0)\n",
+            "  %b = alloc(2)\n  setelt(%b, 0, {})\n  setelt(%b, 1, %c)\n",
+            "  %a = alloc(2)\n  setelt(%a, 0, 1)\n  setelt(%a, 1, %b)\n",
+            "  assertheap(sorted, %a, \"list out of order\")\n  ret 0\n",
+            "sorted(this):\n  jump walk\n",
+            "walk:\n  %n = phi(sorted, %this, step, %next)\n  %next = getelt(%n, 1)\n  if %next then step else yes\n",
+            "step:\n  %x = getelt(%n, 0)\n  %y = getelt(%next, 0)\n  %bad = %y < %x\n  if %bad then no else walk\n",
+            "yes:\n  ret 1\n",
+            "no:\n  ret 0\n"), middle).into_bytes();
+        let bytes = program(2);
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        // The check is charged as the call it is
+        assert_eq!(cycles.calls, 1);
+        // argument passing included
+        let mut charged = ExecStats::new();
+        let opts = RunOptions { charge_arg_passing: true, ..RunOptions::new() };
+        assert_eq!(eval_prog(&prog, &opts, &mut charged, ExecMode::Unlimited).result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(charged.arg_passes, 1);
+        // and a predicate can't hand back a reference any more than a call can
+        let bytes = String::from_utf8(load_program("examples/dangling.ir")?)?.replace("%p = call(dangle, 0)", "assertheap(dangle, 0, \"leaks\")").into_bytes();
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { check_returned_refs: true, ..RunOptions::new() };
+        assert!(matches!(eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited).result, Err(RuntimeError::ReturnedDanglingReference { .. })));
+
+        let bytes = program(5);
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::HeapAssertionFailed { msg: "list out of order", bname: "sorted" }));
+        assert_eq!(exit_code(&result.result), 2);
+        Ok(())
+    }
+    #[test]
+    fn check_capture_output() -> Result<(),Box<dyn std::error::Error>>{
+        let opts = RunOptions { capture_output: true, ..RunOptions::new() };
+        let bytes = load_program("examples/countdown.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(result.output.as_deref(), Some("10\n9\n8\n7\n6\n5\n4\n3\n2\n1\n"));
+        // Whatever was printed before a crash is kept, code pointers included
+        let bytes = b"data:\ncode:\nmain:\n  print(main)\n  print(42)\n  %x = %u + 1\n  print(%x)\n  ret 0\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::UninitializedVariable { name: "u" }));
+        assert_eq!(result.output.as_deref(), Some("main\n42\n"));
+        // Off by default
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.output, None);
+        Ok(())
+    }
+    #[test]
+    fn check_block_costs() -> Result<(),Box<dyn std::error::Error>>{
+        // syscall stands in for a runtime routine that would really take far longer than its one instruction
+        let body = concat!("data:\ncode:\n",
+                           "main:\n  %a = call(syscall, 0, 1)\n  %b = call(syscall, 0, 2)\n  %c = call(syscall, 0, 3)\n  ret %c\n",
+                           "syscall(this, n):\n  ret %n\n");
+        let weighted = format!(";@ cost syscall 500\n{}", body).into_bytes();
+        let prog = parse(&weighted)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 3 })));
+        assert_eq!(cycles.block_cost, 1500);
+        let total = cycles.cycles(&CostModel::new());
+        assert!(cycles.block_cost * 2 > total);
+        assert!(cycles.to_string().contains("\nblock_cost: 1500\n"));
+        // Only the cycle total changes
+        let plain = body.as_bytes().to_vec();
+        let prog_plain = parse(&plain)?;
+        let mut plain_cycles = ExecStats::new();
+        eval_prog(&prog_plain, &RunOptions::new(), &mut plain_cycles, ExecMode::Unlimited);
+        assert_eq!(plain_cycles.steps(), cycles.steps());
+        assert_eq!(plain_cycles.cycles(&CostModel::new()) + 1500, total);
+        // Costs near u64::MAX pin the total at the maximum instead of overflowing
+        let huge = format!(";@ cost syscall {}\n{}", u64::MAX / 2, body).into_bytes();
+        let prog = parse(&huge)?;
+        let mut cycles = ExecStats::new();
+        assert!(eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited).result.is_ok());
+        assert_eq!(cycles.block_cost, u64::MAX);
+        assert_eq!(cycles.cycles(&CostModel::new()), u64::MAX);
+        Ok(())
+    }
+    #[test]
+    fn check_trace_costs() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/costs.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { tracing: true, ..RunOptions::new() };
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 21 })));
+        assert_eq!(result.trace[1..], [
+            "Executing: %p = alloc(2)    [alloc, +10 cycles]",
+            "Executing: setelt(%p, 1, 7)    [fast_op, slow_op, mem_write, +8 cycles]",
+            "Executing: %x = getelt(%p, 1)    [fast_op, slow_op, mem_read, +8 cycles]",
+            "Executing: %y = %x * 3    [slow_op, +3 cycles]",
+            "Executing: print(%y)    [print, +1 cycles]",
+            "Transfering via: ret %y    [ret, +5 cycles]",
+        ]);
+        // The annotations account for every cycle of the run
+        assert_eq!(cycles.cycles(&CostModel::new()), 10 + 8 + 8 + 3 + 1 + 5);
+        Ok(())
+    }
+    #[test]
+    fn check_void_main() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/voidmain.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(None));
+        assert_eq!(cycles.prints, 2);
+        assert_eq!(cycles.rets, 2);
+        // run_prog has no way to say "nothing", so a void main succeeds with 0
+        let result = run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result, Ok(VirtualVal::Data { val: 0 }));
+        assert_eq!(exit_code(&result), 0);
+
+        // Value-returning mains are unaffected
+        let bytes = load_program("examples/trivial.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 23 })));
+        Ok(())
+    }
+    #[test]
+    fn check_pointer_confusion() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/ptrconfusion.ir")?;
+        let prog = parse(&bytes)?;
+        // Best-effort so the bad loads don't stop the run before every warning is seen
+        let opts = RunOptions { best_effort: true, warn_pointer_confusion: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(result.trace, vec![
+            "WARNING: pointer 40 used in arithmetic: %q = %p * 2",
+            "WARNING: integer 8 used as a pointer: %x = load(%i)",
+            "WARNING: loop recovered from UnallocatedAddressRead { addr: 8 }, reading 0",
+            "WARNING: integer 16 used as a pointer: %x = load(%i)",
+            "WARNING: loop recovered from UnallocatedAddressRead { addr: 16 }, reading 0",
+            "WARNING: integer 24 used as a pointer: %x = load(%i)",
+            "WARNING: loop recovered from UnallocatedAddressRead { addr: 24 }, reading 0",
+        ]);
+        // Off by default, leaving only the best-effort notices
+        let opts = RunOptions { best_effort: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.warnings.iter().all(|w| matches!(w, Warning::Recovered { .. })));
+        assert_eq!(result.warnings.len(), 3);
+        // Ordinary pointer use, including header arithmetic, raises nothing
+        let bytes = load_program("examples/gctest1.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { warn_pointer_confusion: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert!(result.result.is_ok());
+        assert!(result.trace.is_empty());
+        Ok(())
+    }
+    #[test]
+    fn check_pointer_ordering() -> Result<(),Box<dyn std::error::Error>>{
+        // Only %lt orders two objects: == is fine, and so is comparing an object with a plain number
+        let bytes = concat!("data:\ncode:\nmain:\n  %a = alloc(1)\n  %b = alloc(1)\n",
+            "  %lt = %a < %b\n  %eq = %a == %b\n  %big = %a > 5\n  ret %lt\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { warn_pointer_ordering: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 1 })));
+        let objects = result.memory.as_ref().unwrap().heap_objects();
+        let (a, b) = (objects[0].addr, objects[1].addr);
+        assert_eq!(result.warnings, vec![Warning::PointerOrdering { left: a, right: b, block: "main", instr: &prog.blocks["main"].instrs[2] }]);
+        assert_eq!(result.warnings[0].to_string(), format!("pointers {} and {} compared by address order: %lt = %a < %b", a, b));
+        assert!(result.trace.iter().any(|l| l.starts_with("WARNING: pointers")));
+        // Off by default
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.warnings.is_empty());
+        Ok(())
+    }
+    #[test]
+    fn check_warnings() -> Result<(),Box<dyn std::error::Error>>{
+        let opts = RunOptions { warn_pointer_confusion: true, track_dead_stores: true, track_uninit_fields: true, ..RunOptions::new() };
+        let bytes = concat!("data:\ncode:\nmain:\n  %o = alloc(2)\n  setelt(%o, 0, 5)\n  jump next\n",
+            "next:\n  %h = %o * 3\n  ret 7\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        // None of them stop the run
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 7 })));
+        let o = 40;
+        assert_eq!(result.warnings, vec![
+            Warning::PointerArithmetic { ptr: o, block: "next", instr: &prog.blocks["next"].instrs[0] },
+            Warning::DeadStore(DeadStore { addr: o, block: "main", instr: &prog.blocks["main"].instrs[1], overwritten: false }),
+            Warning::UninitField(UninitField { object: o, field: 1 }),
+        ]);
+        assert_eq!(result.trace, vec!["WARNING: pointer 40 used in arithmetic: %h = %o * 3"]);
+        assert_eq!(result.warnings[2].to_string(), "field 1 of the object at 40 was never written");
+        // Nothing is checked by default
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.warnings.is_empty());
+        Ok(())
+    }
+    #[test]
+    fn check_casts() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/casts.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { warn_pointer_confusion: true, ..RunOptions::new() };
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 9 })));
+        // Only the uncast multiply is flagged
+        assert_eq!(result.trace, vec!["WARNING: pointer 40 used in arithmetic: %oops = %p * 31"]);
+        // Casts cost a register copy each, beside the tagging ops and the two element address adds
+        assert_eq!(cycles.fast_alu_ops, 2 + 2 + 2);
+        // Code addresses still can't be cast
+        let src = b"data:\ncode:\nmain:\n  %i = ptrtoint(main)\n  ret %i\n".to_vec();
+        let prog = parse(&src)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::CodeAddressArithmetic { bname: "main" }));
+        Ok(())
+    }
+    #[test]
+    fn check_trace_assignments() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/checkpoint.ir")?;
+        let prog = parse(&bytes)?;
+        let only_ops = TraceFilter { kinds: Some([InstrKind::VarAssign, InstrKind::Op].into_iter().collect()), ..TraceFilter::new() };
+        let opts = RunOptions { tracing: true, trace_assignments: true, trace_filter: only_ops, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.trace, vec![
+            "Executing: %1 = 3    [fast_op, +1 cycles]",
+            "%1: <unset> => 3",
+            "Executing: %2 = %cnt - 1    [fast_op, +1 cycles]",
+            "%2: <unset> => 2",
+            "Executing: %2 = %cnt - 1    [fast_op, +1 cycles]",
+            "%2: 2 => 1",
+            "Executing: %2 = %cnt - 1    [fast_op, +1 cycles]",
+            "%2: 1 => 0",
+        ]);
+        // Plain tracing leaves them out
+        let opts = RunOptions { trace_assignments: false, ..opts };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.trace.len(), 4);
+        Ok(())
+    }
+    #[test]
+    fn check_replay() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/rand.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { tracing: true, trace_assignments: true, seed: 42, ..RunOptions::new() };
+        let recording = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        let recorded = recording.trace_json();
+        assert_eq!(replay(&prog, &opts, ExecMode::Unlimited, &recorded), Ok(()));
+        // A different seed changes the very first random draw (line 0 is the globals, 1 the rand itself)
+        let reseeded = RunOptions { seed: 7, ..opts.clone() };
+        let Err(ReplayError::Mismatch(mismatch)) = replay(&prog, &reseeded, ExecMode::Unlimited, &recorded) else { panic!("reseeded replay matched") };
+        assert_eq!((mismatch.step, mismatch.block), (2, Some("main")));
+        assert_eq!(mismatch.expected, Some(TraceLine { block: Some("main".to_string()), line: "%a: <unset> => 413".to_string() }));
+        assert_ne!(mismatch.actual.as_ref(), mismatch.expected.as_ref().map(|e| &e.line));
+        // A recording cut short shows up as an extra replayed line
+        let short = to_json_trace(&recording.trace[..4], &recording.trace_blocks[..4]);
+        let Err(ReplayError::Mismatch(mismatch)) = replay(&prog, &opts, ExecMode::Unlimited, &short) else { panic!("short replay matched") };
+        assert_eq!((mismatch.step, mismatch.expected, mismatch.actual), (4, None, Some(recording.trace[4].clone())));
+        // The same line written in another block is a divergence too
+        let moved = recorded.replacen("\"block\":\"main\"", "\"block\":\"elsewhere\"", 1);
+        let Err(ReplayError::Mismatch(mismatch)) = replay(&prog, &opts, ExecMode::Unlimited, &moved) else { panic!("moved replay matched") };
+        assert_eq!(mismatch.expected.and_then(|e| e.block), Some("elsewhere".to_string()));
+        assert_eq!(mismatch.actual.as_ref(), Some(&recording.trace[mismatch.step]));
+        // And a recording that isn't a JSON trace is reported as such rather than as a divergence
+        assert!(matches!(replay(&prog, &opts, ExecMode::Unlimited, "%a: <unset> => 413"), Err(ReplayError::BadTrace(_))));
+        Ok(())
+    }
+    #[test]
+    fn check_jump_table() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/jumptable.ir")?;
+        let prog = parse(&bytes)?;
+        let tables = RunOptions { code_pointer_tables: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &tables, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 7891 })));
+        // Off by default
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::IndirectJumpDisabled));
+        // Arithmetic on code pointers stays forbidden, and data can't be jumped to
+        for (src, err) in [("data:\ncode:\nmain:\n  %f = main + 8\n  ret %f\n", RuntimeError::CodeAddressArithmetic { bname: "main" }),
+                           ("data:\ncode:\nmain:\n  %f = 8\n  jump %f\n", RuntimeError::JumpToNonCode)] {
+            let bytes = src.as_bytes().to_vec();
+            let prog = parse(&bytes)?;
+            let result = eval_prog(&prog, &tables, &mut ExecStats::new(), ExecMode::Unlimited);
+            assert_eq!(result.result, Err(err));
+        }
+        Ok(())
+    }
+    #[test]
+    fn check_block_path() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/path.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.path.is_empty());
+
+        let opts = RunOptions { record_path: Some(100), ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(result.path, vec!["main", "loophead", "body", "odd", "loophead", "body", "even",
+                                     "loophead", "body", "odd", "loophead", "finish"]);
+        assert!(!result.path_truncated);
+
+        let opts = RunOptions { record_path: Some(4), ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.path, vec!["main", "loophead", "body", "odd"]);
+        assert!(result.path_truncated);
+        Ok(())
+    }
+    #[test]
+    fn check_pragmas() -> Result<(),Box<dyn std::error::Error>>{
+        // A cap alone turns an unlimited run into a capped one
+        let bytes = load_program("examples/pragma_cap.ir")?;
+        let prog = parse(&bytes)?;
+        assert_eq!(prog.pragmas, Pragmas { mode: None, cap: Some(20), unknown: vec![], pure: vec![], costs: vec![] });
+        let result = run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result, Err(RuntimeError::OutOfMemory));
+
+        // The program needs a collector to finish within its cap, and asks for one
+        let bytes = load_program("examples/pragma_gc.ir")?;
+        let prog = parse(&bytes)?;
+        assert_eq!(prog.pragmas.mode, Some(PragmaMode::GC));
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::MemCap { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert!(result.fragmentation.is_some());
+        Ok(())
+    }
+    #[test]
+    fn check_gc_header_guard() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/header_clobber.ir")?;
+        let prog = parse(&bytes)?;
+        let guarded = RunOptions { guard_gc_headers: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &guarded, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::OverwroteGCHeader { addr: 88 }));
+        // Unguarded, the write goes through and nothing notices yet
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        // Setting slotmaps with store is how programs are meant to use the header
+        let bytes = load_program("examples/gctest1.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &guarded, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        Ok(())
+    }
+    #[test]
+    fn check_slotmap_sizes() -> Result<(),Box<dyn std::error::Error>>{
+        let checked = RunOptions { check_slotmaps: true, ..RunOptions::new() };
+        let cases = [("examples/slotmap_over.ir", RuntimeError::SlotmapSizeMismatch { addr: 64, slots: 2, slotmap: 4 }),
+                     ("examples/slotmap_under.ir", RuntimeError::SlotmapSizeMismatch { addr: 64, slots: 70, slotmap: 1 }),
+                     ("examples/slotmap_gc.ir", RuntimeError::SlotmapSizeMismatch { addr: 64, slots: 2, slotmap: 4 })];
+        for (path, err) in cases {
+            let bytes = load_program(path)?;
+            let prog = parse(&bytes)?;
+            let result = eval_prog(&prog, &checked, &mut ExecStats::new(), ExecMode::Unlimited);
+            // Not checked by default
+            let unchecked = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+            assert_ne!(unchecked.result, result.result, "{}", path);
+            assert_eq!(result.result, Err(err), "{}", path);
+        }
+        // Well-formed slotmaps pass
+        let bytes = load_program("examples/gctest1.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &checked, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        Ok(())
+    }
+    #[test]
+    fn check_guard_zones() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/offbyone.ir")?;
+        let prog = parse(&bytes)?;
+        // %a is at 40 with its fields at 40 and 48; the word after it is the guard in front of %b
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::GuardZoneAccess { addr: 56 }));
+        // Without guards the read silently lands in %b
+        let packed = RunOptions { guard_words: 0, ..RunOptions::new() };
+        let result = eval_prog(&prog, &packed, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 7 })));
+        // With wider guards %a moves to 48, and the read hits the start of %b's two-word guard
+        let wide = RunOptions { guard_words: 2, ..RunOptions::new() };
+        let result = eval_prog(&prog, &wide, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::GuardZoneAccess { addr: 64 }));
+        Ok(())
+    }
+    #[test]
+    fn check_guards_disabled() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = b"data:\ncode:\nmain:\n  %a = alloc(1)\n  %b = alloc(3)\n  %c = alloc(2)\n  ret 0\n".to_vec();
+        let prog = parse(&bytes)?;
+        let packed = RunOptions { guard_words: 0, ..RunOptions::new() };
+        for (cap, header) in [(ExecMode::Unlimited, 0), (ExecMode::MemCap { limit: 15 }, 3), (ExecMode::GC { limit: 100 }, 3)] {
+            let result = eval_prog(&prog, &packed, &mut ExecStats::new(), cap);
+            assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+            let objects = result.memory.unwrap().heap_objects();
+            // Each object's header starts right where the previous object's last field ends
+            let addrs : Vec<u64> = objects.iter().map(|o| o.addr).collect();
+            assert_eq!(addrs, vec![32 + 8 * header, 32 + 8 * (2 * header + 1), 32 + 8 * (3 * header + 4)]);
+        }
+        Ok(())
+    }
+    #[test]
+    fn check_final_globals() -> Result<(),Box<dyn std::error::Error>>{
+        // Globals are read-only, so their final values are the initial ones: numbers, code pointers, other globals'
+        // addresses, and (for @buf's reserved tail) zeros
+        let bytes = concat!("data:\nglobal array tbl: { 7, main, @buf }\nglobal array none: { }\nglobal array buf[3]: { 1 }\n",
+            "code:\nmain:\n  %b = @buf\n  %x = load(%b)\n  ret %x\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { print_final_globals: true, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 1 })));
+        assert_eq!(result.trace, vec!["Final Globals:", "\t@tbl[0] = 7", "\t@tbl[1] = main", "\t@tbl[2] = 56",
+                                      "\t@buf[0] = 1", "\t@buf[1] = 0", "\t@buf[2] = 0"]);
+        // Nothing when the run fails
+        let bytes = b"data:\nglobal array tbl: { 7 }\ncode:\nmain:\n  %b = @tbl\n  store(%b, 1)\n  ret 0\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::WriteToImmutableData));
+        assert!(result.trace.is_empty());
+        Ok(())
+    }
+    #[test]
+    fn check_max_live_objects() -> Result<(),Box<dyn std::error::Error>>{
+        // Ten one-field objects, each allocated in turn; with link set, each points to the one before, so all stay alive
+        let program = |link: bool| format!(concat!("data:\ncode:\nmain:\n  %cnt = 10\n  %head = 0\n  jump loop\n",
+            "loop:\n  if %cnt then more else done\n",
+            "more:\n  %n = alloc(1)\n  %nmap = %n - 8\n  store(%nmap, {})\n  setelt(%n, 0, %head)\n  %nmap = 0\n  {}\n  %cnt = %cnt - 1\n  jump loop\n",
+            "done:\n  ret 0\n"), if link { 1 } else { 0 }, if link { "%head = %n" } else { "%head = 0" }).into_bytes();
+        let capped = RunOptions { max_live_objects: Some(5), ..RunOptions::new() };
+        // The collector frees the garbage when the limit is reached, so only the chain runs into it
+        let bytes = program(false);
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &capped, &mut cycles, ExecMode::GC { limit: 1000 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert_eq!(cycles.gcs, 2);
+        assert!(result.memory.unwrap().live_objects() <= 5);
+        let bytes = program(true);
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &capped, &mut cycles, ExecMode::GC { limit: 1000 });
+        assert_eq!(result.result, Err(RuntimeError::LiveObjectLimitExceeded { limit: 5 }));
+        assert_eq!(cycles.allocs, 5);
+        // Without a collector nothing is ever reclaimed, so even garbage counts
+        let bytes = program(false);
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &capped, &mut ExecStats::new(), ExecMode::MemCap { limit: 1000 });
+        assert_eq!(result.result, Err(RuntimeError::LiveObjectLimitExceeded { limit: 5 }));
+        // Slots aren't the issue: the same chain fits without the object limit
+        let bytes = program(true);
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::GC { limit: 1000 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        Ok(())
+    }
+    #[test]
+    fn check_fill_word() -> Result<(),Box<dyn std::error::Error>>{
+        // Write one field and stop, so the crash dump shows the whole object
+        let bytes = b"data:\ncode:\nmain:\n  %p = alloc(3)\n  setelt(%p, 1, 5)\n  fail NotANumber\n".to_vec();
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { fill_word: 0xDEADBEEF, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::Failed { reason: Reason::NotANumber }));
+        // The two unwritten fields still hold the pattern; the header (size 6, no forwarding, empty slotmap) is as usual
+        let snapshot = result.crash_snapshot.unwrap();
+        // The object is the last thing allocated, ending at next_alloc
+        let p = snapshot.next_alloc - 24;
+        let words : Vec<u64> = (p - 24..p + 24).step_by(8).map(|a| match snapshot.contents.get(&a) {
+            Some(VirtualVal::Data { val }) => *val,
+            other => panic!("{:?} at {}", other, a)
+        }).collect();
+        assert_eq!(words, vec![6, 0, 0, 0xDEADBEEF, 5, 0xDEADBEEF]);
+        let dump = format!("{:#}", snapshot);
+        assert_eq!(dump.matches("0xdeadbeef").count(), 2, "{}", dump);
+
+        // A traced field the program hasn't written yet isn't a pointer: the collector copies the fill word as is
+        let bytes = concat!("data:\ncode:\nmain:\n  %q = alloc(1)\n  %p = alloc(2)\n  %pmap = %p - 8\n  store(%pmap, 3)\n",
+            "  %pmap = 0\n  setelt(%p, 0, %q)\n  %j = alloc(4)\n  %j = 0\n  %j = alloc(4)\n  %f = getelt(%p, 1)\n  ret %f\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::GC { limit: 20 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0xDEADBEEF })));
+        assert_eq!(cycles.gcs, 1);
+
+        // Fresh objects are zeroed by default
+        let bytes = b"data:\ncode:\nmain:\n  %p = alloc(2)\n  %a = getelt(%p, 0)\n  ret %a\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        let opts = RunOptions { fill_word: 0xDEADBEEF, ..RunOptions::new() };
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0xDEADBEEF })));
+        Ok(())
+    }
+    #[test]
+    fn check_signed_offsets() -> Result<(),Box<dyn std::error::Error>>{
+        let signed = RunOptions { signed_offsets: true, ..RunOptions::new() };
+        // There are no negative literals, so -1 is written as 2^64 - 1. It reads the stored slotmap, then clears it.
+        let bytes = concat!("data:\ncode:\nmain:\n  %p = alloc(2)\n  %pmap = %p - 8\n  store(%pmap, 2)\n  %pmap = 0\n",
+            "  %m1 = 18446744073709551615\n  %before = getelt(%p, %m1)\n  setelt(%p, %m1, 0)\n  %after = getelt(%p, %m1)\n",
+            "  %r = %before * 10\n  %r = %r + %after\n  ret %r\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &signed, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 20 })));
+        // Going back past the header lands in the guard zone, which is still checked
+        let bytes = b"data:\ncode:\nmain:\n  %p = alloc(2)\n  %m4 = 18446744073709551612\n  %x = getelt(%p, %m4)\n  ret %x\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &signed, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Err(RuntimeError::GuardZoneAccess { addr: 32 }));
+        // Narrow elements count back in their own size: -2 halfwords is bits 32-47 of the slotmap word
+        let bytes = concat!("data:\ncode:\nmain:\n  %p = alloc(1)\n  %pmap = %p - 8\n  store(%pmap, 12884901888)\n  %pmap = 0\n",
+            "  %m2 = 18446744073709551614\n  %x = getelt16(%p, %m2)\n  ret %x\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &signed, &mut ExecStats::new(), ExecMode::GC { limit: 100 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 3 })));
+        Ok(())
+    }
+    #[test]
+    fn check_memoize_pure() -> Result<(),Box<dyn std::error::Error>>{
+        let fib = concat!(";@ pure fib\n",
+                          "data:\n",
+                          "code:\n",
+                          "main:\n",
+                          "  %r = call(fib, 0, 20)\n",
+                          "  %again = call(twice, 0, 5)\n",
+                          "  %s = %r + %again\n",
+                          "  ret %s\n",
+                          "fib(this, n):\n",
+                          "  %small = %n < 2\n",
+                          "  if %small then base else rec\n",
+                          "base:\n",
+                          "  ret %n\n",
+                          "rec:\n",
+                          "  %n1 = %n - 1\n",
+                          "  %n2 = %n - 2\n",
+                          "  %a = call(fib, 0, %n1)\n",
+                          "  %b = call(fib, 0, %n2)\n",
+                          "  %s = %a + %b\n",
+                          "  ret %s\n",
+                          // Not declared pure, so both calls to dbl run
+                          "twice(this, x):\n",
+                          "  %a = call(dbl, 0, %x)\n",
+                          "  %b = call(dbl, 0, %x)\n",
+                          "  %s = %a + %b\n",
+                          "  ret %s\n",
+                          "dbl(this, x):\n",
+                          "  %d = %x * 2\n",
+                          "  ret %d\n").as_bytes().to_vec();
+        let prog = parse(&fib)?;
+        let entries = |path: &Vec<&str>, b: &str| path.iter().filter(|p| **p == b).count();
+
+        let opts = RunOptions { record_path: Some(100000), ..RunOptions::new() };
+        let mut plain = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut plain, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 6765 + 20 })));
+        assert_eq!(entries(&result.path, "fib"), 21891);
+
+        let opts = RunOptions { memoize_pure: true, ..opts };
+        let mut memoized = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut memoized, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 6765 + 20 })));
+        // Once per distinct argument, 0 through 20; every other call is answered from the cache
+        assert_eq!(entries(&result.path, "fib"), 21);
+        assert_eq!(entries(&result.path, "dbl"), 2);
+        assert!(memoized.steps() * 100 < plain.steps());
+
+        // A pure declaration is checked before anything runs
+        let impure = String::from_utf8(fib)?.replace(";@ pure fib", ";@ pure fib dbl twice").replace("  %d = %x * 2\n", "  %d = %x * 2\n  print(%d)\n").into_bytes();
+        let prog = parse(&impure)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::NotPure { bname: "dbl", at: "dbl", index: 1 }));
+        assert_eq!(cycles.steps(), 0);
+        // Without memoization the declaration is only a validation error
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.result.is_ok());
+        Ok(())
+    }
+    #[test]
+    fn check_returned_refs() -> Result<(),Box<dyn std::error::Error>>{
+        let checked = RunOptions { check_returned_refs: true, ..RunOptions::new() };
+        // Returning a freshly allocated object is fine, even across a collection
+        let bytes = load_program("examples/retlocal.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &checked, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 5 })));
+        // hide's object (at 64) is reclaimed before dangle rebuilds and returns a pointer to it
+        let bytes = load_program("examples/dangling.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &checked, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::ReturnedDanglingReference { addr: 64 }));
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        Ok(())
+    }
+    #[test]
+    fn check_cap_includes_globals() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/bigglobals.ir")?;
+        let prog = parse(&bytes)?;
+        let total = RunOptions { cap_includes_globals: true, ..RunOptions::new() };
+        // By default only the heap is capped, so 20 words of globals go unnoticed
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::MemCap { limit: 10 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 20 })));
+        // Globals leave 4 slots, too few for the allocation and its header
+        let result = eval_prog(&prog, &total, &mut ExecStats::new(), ExecMode::MemCap { limit: 24 });
+        assert_eq!(result.result, Err(RuntimeError::OutOfMemory));
+        let result = eval_prog(&prog, &total, &mut ExecStats::new(), ExecMode::MemCap { limit: 30 });
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 20 })));
+        // Globals alone don't fit, so main never starts
+        let result = eval_prog(&prog, &total, &mut ExecStats::new(), ExecMode::MemCap { limit: 10 });
+        assert_eq!(result.result, Err(RuntimeError::OutOfMemory));
+        assert!(result.memory.is_none());
+        Ok(())
+    }
+    #[test]
+    fn check_crash_snapshot() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/nullcrash.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::NullPointer));
+        let snap = result.crash_snapshot.expect("crash should capture memory");
+        assert_eq!(snap.globals, vec![("g", 32)]);
+        assert_eq!((snap.first_writable, snap.base, snap.next_alloc), (40, 40, 64));
+        let contents : Vec<(u64,VirtualVal)> = snap.contents.into_iter().collect();
+        assert_eq!(contents, vec![(32, VirtualVal::Data { val: 7 }), (48, VirtualVal::Data { val: 5 }), (56, VirtualVal::Data { val: 0 })]);
+        // Successful runs don't take one
+        let bytes = load_program("examples/globalrefs.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert!(result.crash_snapshot.is_none());
+        Ok(())
+    }
+    #[test]
+    fn check_globalrefs() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/globalrefs.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result,Ok(VirtualVal::Data { val: 43 }));
+        Ok(())
+    }
+    #[test]
+    fn check_short_global() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/shortglobal.ir")?;
+        let prog = parse(&bytes)?;
+        // Slots past the initializers read as 0, but indexing past the declared size doesn't reach the next global
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::GlobalOutOfBounds { name: "buf", index: 4, slots: 4 }));
+        let snap = result.crash_snapshot.unwrap();
+        // The reserved slots still push later globals along
+        assert_eq!(snap.globals, vec![("buf", 32), ("after", 64)]);
+        let memory = result.memory.unwrap();
+        let slots : Vec<_> = memory.global_slots().into_iter().filter(|(name,_,_)| *name == "buf").map(|(_,_,v)| v).collect();
+        assert_eq!(slots, [10, 20, 0, 0].iter().map(|val| Some(VirtualVal::Data { val: *val })).collect::<Vec<_>>());
+        Ok(())
+    }
+    #[test]
+    fn check_memcpy() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/memcpy.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result,Ok(VirtualVal::Data { val: 11232344 }));
+        // 4 initializing setelts, 4 + 3 + 3 copied words, 8 getelts
+        assert_eq!(cycles.mem_writes, 4 + 10);
+        assert_eq!(cycles.mem_reads, 10 + 8);
+        Ok(())
+    }
+    #[test]
+    fn check_consts() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/consts.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { capture_output: true, ..RunOptions::new() };
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 4 })));
+        assert_eq!(result.output.as_deref(), Some("3004\n"));
+        // By default constants cost no more than immediates
+        assert_eq!(cycles.mem_reads, 1);
+        // Charged as loads, there's one more read for each use of a constant
+        let opts = RunOptions { charge_const_loads: true, ..RunOptions::new() };
+        let mut cycles = ExecStats::new();
+        assert!(eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited).result.is_ok());
+        assert_eq!(cycles.mem_reads, 4);
+        assert_eq!(cycles.bytes_read, 32);
+        // A phi reads only the arm for the edge it came in on, so only that constant is charged
+        let bytes = concat!("data:\nglobal const A = 1\nglobal const B = 2\ncode:\nmain:\n  jump l\n",
+            "l:\n  %x = phi(main, $A, m, $B)\n  ret %x\nm:\n  ret 0\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 1 })));
+        assert_eq!(cycles.mem_reads, 1);
+        let bytes = b"data:\nglobal const K = 1\ncode:\nmain:\n  %x = $K + $J\n  ret %x\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::UndefinedConst { name: "J" }));
+        Ok(())
+    }
+    #[test]
+    fn check_halt() -> Result<(),Box<dyn std::error::Error>>{
+        // g halts two calls deep; nothing after the halt runs, in g or in its callers
+        let bytes = concat!("data:\ncode:\nmain:\n  %a = call(f, 0, 5)\n  print(%a)\n  ret 99\n",
+            "f(this, n):\n  %x = call(g, 0, %n)\n  print(%x)\n  ret %x\n",
+            "g(this, n):\n  %y = %n * 2\n  halt(%y)\n  print(%y)\n  ret 0\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { capture_output: true, ..RunOptions::new() };
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 10 })));
+        assert_eq!(result.output.as_deref(), Some(""));
+        // The stats stop at the halt, which is free: two calls and the multiply, and no returns
+        assert_eq!((cycles.calls, cycles.slow_alu_ops, cycles.rets, cycles.prints), (2, 1, 0, 0));
+        assert_eq!(cycles.steps(), 3);
+        assert_eq!(exit_code(&result.result), 0);
+        Ok(())
+    }
+    #[test]
+    fn check_cas() -> Result<(),Box<dyn std::error::Error>>{
+        // The first cas finds the 5 it expects and swaps in 7; the second expects 5 again, so it fails and writes nothing
+        let bytes = concat!("data:\ncode:\nmain:\n  %o = alloc(1)\n  setelt(%o, 0, 5)\n",
+            "  %won = cas(%o, 5, 7)\n  %a = load(%o)\n  %lost = cas(%o, 5, 9)\n  %b = load(%o)\n",
+            "  print(%won)\n  print(%a)\n  print(%lost)\n  print(%b)\n  ret 0\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        assert_eq!(prog.blocks["main"].instrs[2].to_string(), "%won = cas(%o, 5, 7)");
+        let opts = RunOptions { capture_output: true, ..RunOptions::new() };
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        assert!(result.result.is_ok());
+        assert_eq!(result.output.as_deref(), Some("1\n7\n0\n7\n"));
+        // Each cas reads; only the one that swapped writes, besides the setelt
+        assert_eq!((cycles.mem_reads, cycles.mem_writes), (4, 2));
+        // Code isn't memory a cas can swap in
+        let (_,prog) = parse_program("data:\ncode:\nmain:\n  %r = cas(main, 0, 1)\n  ret %r\n".as_bytes()).unwrap();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::AccessingCodeInMemory { bname: "main" }));
+        Ok(())
+    }
+    #[test]
+    fn check_divmod() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/divmod.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        // 47 = 9*5 + 2, 40 = 5*8 + 0
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 9250 })));
+        // One slow op per divmod, plus the three multiplies
+        assert_eq!(cycles.slow_alu_ops, 2 + 3);
+        let bytes = load_program("examples/divmod_zero.ir")?;
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::DivisionByZero { instr: &prog.blocks["main"].instrs[1] }));
+        Ok(())
+    }
+    #[test]
+    fn check_checkpoints() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/checkpoint.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        let labels : Vec<&str> = result.checkpoints.iter().map(|(l,_)| *l).collect();
+        assert_eq!(labels, vec!["start", "done"]);
+        // Only the initial copy precedes the first checkpoint
+        assert_eq!(result.checkpoints[0].1, ExecStats { fast_alu_ops: 1, ..ExecStats::new() });
+        // Between them: the jump in, 4 loop tests (phi + branch) and 3 trips through the body (decrement + jump back)
+        let phase = result.checkpoints[1].1.since(&result.checkpoints[0].1);
+        assert_eq!(phase, ExecStats { phis: 4, conditional_branches: 4, fast_alu_ops: 3, unconditional_branches: 1 + 3, ..ExecStats::new() });
+        // Checkpoints themselves are free
+        assert_eq!(cycles.since(&result.checkpoints[1].1), ExecStats { rets: 1, ..ExecStats::new() });
+        Ok(())
+    }
+    #[test]
+    fn check_read_cycles() -> Result<(),Box<dyn std::error::Error>>{
+        // time(n) runs checkpoint.ir's countdown loop and returns how many steps it took, as measured from inside
+        let bytes = concat!("data:\ncode:\nmain:\n  %three = call(time, 0, 3)\n  %five = call(time, 0, 5)\n  %hi = %three * 1000\n  %both = %hi + %five\n  ret %both\n",
+            "time(this, n):\n  %t0 = readcycles()\n  jump loophead\n",
+            "loophead:\n  %cnt = phi(time, %n, body, %2)\n  if %cnt then body else finish\n",
+            "body:\n  %2 = %cnt - 1\n  jump loophead\n",
+            "finish:\n  %t1 = readcycles()\n  %d = %t1 - %t0\n  ret %d\n").as_bytes().to_vec();
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited);
+        // The jump in, n + 1 loop tests (phi + branch), n trips through the body (decrement + jump back), and the second
+        // read's own fast op: 16 steps for 3 trips, and 4 more per extra trip
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 16024 })));
+        // Each read is a single fast op, like the subtraction after it
+        assert_eq!(cycles.fast_alu_ops, 2 * (2 + 1) + 3 + 5 + 1);
+        Ok(())
+    }
+    #[test]
+    fn check_instruction_breakpoints() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/countdown.ir")?;
+        let prog = parse(&bytes)?;
+        let mut opts = RunOptions::new();
+        // The decrement, and loophead's branch (its only statement is the phi, so index 1 is the control transfer)
+        opts.breakpoints.insert(Breakpoint::parse("body:1").unwrap());
+        opts.breakpoints.insert(Breakpoint::new("loophead", 1));
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        let decrements : Vec<(u64,VirtualVal)> = result.breakpoint_hits.iter().filter(|h| h.block == "body").map(|h| (h.step, h.locals.read("cnt").unwrap())).collect();
+        // Five steps in (copy, jump, phi, branch, print), then five more per trip round the loop
+        let expected : Vec<(u64,VirtualVal)> = (0..10).map(|k| (5 + 5*k, VirtualVal::Data { val: 10 - k })).collect();
+        assert_eq!(decrements, expected);
+        assert!(result.breakpoint_hits.iter().all(|h| h.index == 1));
+        assert_eq!(result.breakpoint_hits.iter().filter(|h| h.block == "loophead").count(), 11);
+        assert_eq!((result.breakpoint_hits[0].block, result.breakpoint_hits[0].step), ("loophead", 3));
+        // Traced runs show each hit in line with the instructions
+        opts.tracing = true;
+        opts.breakpoints = [Breakpoint::new("body", 1)].into_iter().collect();
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        let at = result.trace.iter().position(|l| l == "Breakpoint body:1 at step 5").unwrap();
+        assert!(result.trace[at-1].starts_with("Executing: print(%cnt)"));
+        assert!(result.trace[at+1].starts_with("Executing: %2 = %cnt - 1"));
+        assert_eq!(Breakpoint::parse("loop_body:3"), Some(Breakpoint::new("loop_body", 3)));
+        assert_eq!(Breakpoint::parse("loop_body"), None);
+        assert_eq!(Breakpoint::parse(":3"), None);
+        assert_eq!(Breakpoint::parse("b:x"), None);
+        Ok(())
+    }
+    #[test]
+    fn check_run_until() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/countdown.ir")?;
+        let prog = parse(&bytes)?;
+        let mut opts = RunOptions::new();
+        // Copy, jump, and the phi setting %cnt to 10; then five steps per trip, each setting it one lower
+        opts.run_until = Some(("cnt".to_string(), 7));
+        let mut cycles = ExecStats::new();
+        let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+        // Stopping isn't failing, but there's no result either
+        assert_eq!((result.result, result.stopped), (Ok(None), Some(Stop::AtCondition { name: "cnt", step: 18 })));
+        assert_eq!(cycles.steps(), 18);
+        // The decrement gets there first, two steps earlier
+        opts.run_until = Some(("2".to_string(), 7));
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.stopped, Some(Stop::AtCondition { name: "2", step: 16 }));
+        // A value never assigned lets the run finish
+        opts.run_until = Some(("cnt".to_string(), 11));
+        let result = eval_prog(&prog, &opts, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        Ok(())
+    }
+    #[test]
+    fn check_memset() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/memset.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result,Ok(VirtualVal::Data { val: 18 }));
+        assert_eq!(cycles.mem_writes, 5);
+
+        let bytes = load_program("examples/memset_global.ir")?;
+        let prog = parse(&bytes)?;
+        let result = run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result,Err(RuntimeError::WriteToImmutableData));
+        Ok(())
+    }
+    #[test]
+    fn check_bytes_moved() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/memset.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        assert!(result.is_ok());
+        // memset of 5 words, then 2 getelts
+        assert_eq!(cycles.bytes_written, 40);
+        assert_eq!(cycles.bytes_read, 16);
+
+        let bytes = load_program("examples/memcpy.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        assert!(result.is_ok());
+        assert_eq!(cycles.bytes_written, 8 * cycles.mem_writes);
+        assert_eq!(cycles.bytes_read, 8 * 18);
+        Ok(())
+    }
+    #[test]
+    fn check_exec_modes() -> Result<(),Box<dyn std::error::Error>>{
+        // 20 objects of 2 fields (5 slots each with headers), only one live at a time
+        let bytes = load_program("examples/churn.ir")?;
+        let prog = parse(&bytes)?;
+        let run = |mode| eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), mode);
+        let unlimited = run(ExecMode::Unlimited);
+        assert_eq!(unlimited.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert!(unlimited.fragmentation.is_none());
+        // A cap without a collector runs out
+        assert_eq!(run(ExecMode::MemCap { limit: 50 }).result, Err(RuntimeError::OutOfMemory));
+        // The same cap with a collector reclaims the garbage and finishes
+        let gc = run(ExecMode::GC { limit: 50 });
+        assert_eq!(gc.result, Ok(Some(VirtualVal::Data { val: 0 })));
+        assert!(gc.fragmentation.is_some());
+        // ...but can't help when a single object doesn't fit
+        assert_eq!(run(ExecMode::GC { limit: 2 }).result, Err(RuntimeError::OutOfMemory));
+        Ok(())
+    }
+    #[test]
+    fn check_summary() -> Result<(),Box<dyn std::error::Error>>{
+        let summarize = |path, mode| -> Result<String,Box<dyn std::error::Error>> {
+            let bytes = load_program(path)?;
+            let prog = parse(&bytes)?;
+            let mut cycles = ExecStats::new();
+            let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, mode);
+            Ok(result.summary(&cycles))
+        };
+        assert_eq!(summarize("examples/trivial.ir", ExecMode::Unlimited)?, "OK result=23 steps=4 allocs=0 gc=0");
+        assert_eq!(summarize("examples/churn.ir", ExecMode::Unlimited)?, "OK result=0 steps=83 allocs=20 gc=0");
+        // An allocation that needed a collection first counts once it succeeds, so collecting doesn't change the count
+        assert_eq!(summarize("examples/churn.ir", ExecMode::GC { limit: 50 })?, "OK result=0 steps=83 allocs=20 gc=2");
+        assert_eq!(summarize("examples/voidmain.ir", ExecMode::Unlimited)?, "OK result=void steps=5 allocs=0 gc=0");
+        assert_eq!(summarize("examples/nullcrash.ir", ExecMode::Unlimited)?, "FAIL reason=NullPointer steps=8");
+        Ok(())
+    }
+    #[test]
+    fn check_kv() -> Result<(),Box<dyn std::error::Error>>{
+        let kv = |path, mode| -> Result<String,Box<dyn std::error::Error>> {
+            let bytes = load_program(path)?;
+            let prog = parse(&bytes)?;
+            let mut cycles = ExecStats::new();
+            let result = eval_prog(&prog, &RunOptions::new(), &mut cycles, mode);
+            Ok(result.to_kv(&cycles))
+        };
+        assert_eq!(kv("examples/churn.ir", ExecMode::GC { limit: 50 })?,
+                   "status=ok result=0 reason=none steps=83 fast_op=21 slow_op=0 cond_branch=20 uncond_branch=1 call=0 arg_pass=0 ret=1 mem_read=0 mem_write=0 alloc=20 print=0 phi=20 block_cost=0 gc=2 bytes_read=0 bytes_written=0");
+        assert_eq!(kv("examples/nullcrash.ir", ExecMode::Unlimited)?,
+                   "status=fail result=none reason=NullPointer steps=8 fast_op=3 slow_op=2 cond_branch=0 uncond_branch=0 call=0 arg_pass=0 ret=0 mem_read=1 mem_write=1 alloc=1 print=0 phi=0 block_cost=0 gc=0 bytes_read=8 bytes_written=8");
+        Ok(())
+    }
+    // First-fit over the runs it has released, sweeping every unreachable object back into them before growing the heap
+    struct FreeListAllocator {
+        free: Vec<(u64,u64)>
+    }
+    impl FreeListAllocator {
+        fn place<'a>(&mut self, mem: &mut Memory<'a>, n: u64) -> Option<Result<u64,RuntimeError<'a>>> {
+            let words = mem.object_words(n);
+            let i = self.free.iter().position(|(_, len)| *len >= words)?;
+            let (start, len) = self.free.remove(i);
+            if len > words {
+                self.free.push((start + 8 * words, len - words));
+            }
+            Some(mem.place_object(start, n))
+        }
+    }
+    impl <'a> Allocator<'a> for FreeListAllocator {
+        fn alloc(&mut self, mem: &mut Memory<'a>, stack: &mut Vec<Locals<'a>>, n: u64) -> Result<u64,RuntimeError<'a>> {
+            if let Some(result) = self.place(mem, n) {
+                return result;
+            }
+            let live = mem.reachable_objects(&stack_roots(stack));
+            for obj in mem.heap_objects() {
+                if !live.contains(&obj.addr) {
+                    self.free.push(mem.release_object(obj.addr)?);
+                }
+            }
+            if let Some(result) = self.place(mem, n) {
+                return result;
+            }
+            let start = mem.reserve_words(mem.object_words(n))?;
+            mem.place_object(start, n)
+        }
+    }
+    #[test]
+    fn check_custom_allocator() -> Result<(),Box<dyn std::error::Error>>{
+        // Each object is garbage once the next is allocated, so with 10 slots the bump allocator runs out on the third
+        let bytes = load_program("examples/churn.ir")?;
+        let prog = parse(&bytes)?;
+        let cap = || ExecMode::MemCap { limit: 10 };
+        assert_eq!(run_prog(&prog, false, &mut ExecStats::new(), cap()), Err(RuntimeError::OutOfMemory));
+        // while reusing the dead ones' space, all 20 fit
+        let mut cycles = ExecStats::new();
+        let result = run_prog_with_allocator(&prog, false, &mut cycles, cap(), Box::new(FreeListAllocator { free: vec![] }));
+        assert_eq!(result, Ok(VirtualVal::Data { val: 0 }));
+        assert_eq!(cycles.allocs, 20);
+        // The second object goes exactly where the first, no longer referenced, was
+        let bytes = b"data:\ncode:\nmain:\n  %a = alloc(1)\n  %d = %a + 1\n  %a = 0\n  %b = alloc(1)\n  %e = %b + 1\n  %r = %e - %d\n  ret %r\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = run_prog_with_allocator(&prog, false, &mut ExecStats::new(), cap(), Box::new(FreeListAllocator { free: vec![] }));
+        assert_eq!(result, Ok(VirtualVal::Data { val: 0 }));
+        // Placing over memory that isn't free is refused
+        let bytes = b"data:\ncode:\nmain:\n  %a = alloc(1)\n  ret 0\n".to_vec();
+        let prog = parse(&bytes)?;
+        struct Overlapping;
+        impl <'a> Allocator<'a> for Overlapping {
+            fn alloc(&mut self, mem: &mut Memory<'a>, _stack: &mut Vec<Locals<'a>>, n: u64) -> Result<u64,RuntimeError<'a>> {
+                let start = mem.reserve_words(1)?;
+                mem.place_object(start, n)
+            }
+        }
+        let result = run_prog_with_allocator(&prog, false, &mut ExecStats::new(), cap(), Box::new(Overlapping));
+        assert!(matches!(result, Err(RuntimeError::PlacementNotFree { .. })));
+        Ok(())
+    }
+    #[test]
+    fn check_default_arguments() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/defaults.ir")?;
+        let prog = parse(&bytes)?;
+        // 3 * 10, then 3 * 3 with factor defaulting to x, then 1 * 1 with both defaulted
+        let result = run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result, Ok(VirtualVal::Data { val: 3091 }));
+        // Required formals still have to be passed, and there's still a limit
+        let bytes = b"data:\ncode:\nmain:\n  %x = call(f, 0)\n  ret %x\nf(this, a, b = 2):\n  ret %b\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::BadCallArity { instr: &prog.blocks["main"].instrs[0] }));
+        let bytes = b"data:\ncode:\nmain:\n  %x = call(f, 0, 1, 2, 3)\n  ret %x\nf(this, a, b = 2):\n  ret %b\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::BadCallArity { instr: &prog.blocks["main"].instrs[0] }));
+        // Defaults are evaluated in the callee, so the caller's variables aren't visible
+        let bytes = b"data:\ncode:\nmain:\n  %y = 5\n  %x = call(f, 0)\n  ret %x\nf(this, b = %y):\n  ret %b\n".to_vec();
+        let prog = parse(&bytes)?;
+        let result = eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited);
+        assert_eq!(result.result, Err(RuntimeError::UninitializedVariable { name: "y" }));
+        Ok(())
+    }
+    #[test]
+    fn check_multiple_returns() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/multiret.ir")?;
+        let prog = parse(&bytes)?;
+        let mut cycles = ExecStats::new();
+        let result = run_prog(&prog, false, &mut cycles, ExecMode::Unlimited);
+        assert_eq!(result, Ok(VirtualVal::Data { val: 407 }));
+        // Two rets, and the second returned value costs a fast op on top of the + and -
+        assert_eq!(cycles.rets, 2);
+        assert_eq!(cycles.fast_alu_ops, 3);
+
+        // The number of values returned has to match the number of destinations
+        let bytes = b"data:\ncode:\nmain:\n  %x = call(pair, 0)\n  ret %x\npair(this):\n  ret 1, 2\n".to_vec();
+        let prog = parse(&bytes)?;
+        assert_eq!(run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited), Err(RuntimeError::WrongReturnCount { expected: 1, got: 2 }));
+        let bytes = b"data:\ncode:\nmain:\n  %x, %y, %z = call(pair, 0)\n  ret %x\npair(this):\n  ret 1, 2\n".to_vec();
+        let prog = parse(&bytes)?;
+        assert_eq!(run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited), Err(RuntimeError::WrongReturnCount { expected: 3, got: 2 }));
+        Ok(())
+    }
+    #[test]
+    fn check_deterministic() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/manyroots.ir")?;
+        let prog = parse(&bytes)?;
+        let opts = RunOptions { tracing: true, deterministic: true, ..RunOptions::new() };
+        let run = || {
+            let mut cycles = ExecStats::new();
+            let result = eval_prog(&prog, &opts, &mut cycles, ExecMode::Unlimited);
+            (result, cycles)
+        };
+        let (first, first_stats) = run();
+        assert!(first.result.is_ok());
+        assert!(first.fragmentation.is_some());
+        assert_eq!(first.trace[0], "Initial Globals:\n{\"alpha\": 40, \"mid\": 48, \"zeta\": 32}");
+        for _ in 0..5 {
+            let (again, again_stats) = run();
+            assert_eq!(again.result, first.result);
+            assert_eq!(again.trace.join("\n").as_bytes(), first.trace.join("\n").as_bytes());
+            assert_eq!(again_stats, first_stats);
+        }
+        Ok(())
+    }
+    #[test]
+    fn check_narrow_elements() -> Result<(),Box<dyn std::error::Error>>{
+        let bytes = load_program("examples/narrow.ir")?;
+        let prog = parse(&bytes)?;
+        // 104 + 105, plus word 1 holding just element 4
+        assert_eq!(run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited), Ok(VirtualVal::Data { val: 104 + 105 + 105 }));
+
+        // Values and errors rendered as text, since both borrow from the program
+        let run = |body: &str| -> Result<Result<String,String>,Box<dyn std::error::Error>> {
+            let bytes = format!("data:\ncode:\nmain:\n  %a = alloc(2)\n{}", body).into_bytes();
+            let prog = parse(&bytes)?;
+            Ok(run_prog(&prog, false, &mut ExecStats::new(), ExecMode::Unlimited).map(|v| v.to_string()).map_err(|e| format!("{:?}", e)))
+        };
+        // A 32-bit element is half a word; element 1 is the high half of word 0
+        assert_eq!(run("  setelt(%a, 0, 3)\n  setelt32(%a, 1, 7)\n  %w = getelt(%a, 0)\n  ret %w\n")?, Ok(((7u64 << 32) + 3).to_string()));
+        assert_eq!(run("  setelt(%a, 0, 30064771075)\n  %e = getelt32(%a, 1)\n  ret %e\n")?, Ok("7".to_string()));
+        // Writes keep only the low bits, and reads zero-extend
+        assert_eq!(run("  setelt16(%a, 5, 65537)\n  %e = getelt16(%a, 5)\n  %w = getelt(%a, 1)\n  %r = %e + %w\n  ret %r\n")?, Ok((1 + (1 << 16)).to_string()));
+        assert_eq!(run("  setelt(%a, 1, 18446744073709551615)\n  %e = getelt16(%a, 7)\n  ret %e\n")?, Ok("65535".to_string()));
+        // Past the end of the object is still past the end
+        assert_eq!(run("  %e = getelt16(%a, 8)\n  ret %e\n")?, Err("UnallocatedAddressRead { addr: 56 }".to_string()));
+        Ok(())
+    }
+    #[test]
+    fn check_cancellation() -> Result<(),Box<dyn std::error::Error>>{
+        // countdown takes far more than 10 instructions
+        let bytes = load_program("examples/countdown.ir")?;
+        let prog = parse(&bytes)?;
+        let asked = std::cell::Cell::new(0);
+        let after_ten = || { asked.set(asked.get() + 1); asked.get() > 10 };
+        let mut cycles = ExecStats::new();
+        let result = eval_prog_cancellable(&prog, &RunOptions::new(), &mut cycles, ExecMode::Unlimited, &after_ten);
+        assert_eq!((result.result.clone(), result.stopped), (Ok(None), Some(Stop::Cancelled)));
+        assert_eq!(result.summary(&cycles), "STOPPED reason=Cancelled steps=10");
+        assert_eq!(asked.get(), 11);
+        // Exactly the ten instructions before the cancellation ran
+        assert_eq!(cycles.fast_alu_ops + cycles.conditional_branches + cycles.unconditional_branches + cycles.phis + cycles.prints, 10);
+
+        // A callback that never fires changes nothing
+        let never = || false;
+        let result = eval_prog_cancellable(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited, &never);
+        assert_eq!(result.result, eval_prog(&prog, &RunOptions::new(), &mut ExecStats::new(), ExecMode::Unlimited).result);
+        Ok(())
+    }
+    #[test]
+    fn check_exit_codes() -> Result<(),Box<dyn std::error::Error>>{
+        let cases = [("examples/trivial.ir", ExecMode::Unlimited, 0),
+                     ("examples/fail.ir", ExecMode::Unlimited, 2),
+                     ("examples/oom.ir", ExecMode::MemCap { limit: 100 }, 3),
+                     ("examples/memset_global.ir", ExecMode::Unlimited, 4)];
+        for (path, mode, code) in cases {
+            let bytes = load_program(path)?;
+            let prog = parse(&bytes)?;
+            let result = run_prog(&prog, false, &mut ExecStats::new(), mode);
+            assert_eq!(exit_code(&result), code, "{}", path);
+        }
+        Ok(())
+    }
+}
+